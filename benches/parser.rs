@@ -0,0 +1,67 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use criterion::{criterion_group, criterion_main, Benchmark, Criterion, Throughput};
+use rogcat::parser::{Parser, Threadtime};
+
+const THREADTIME: &str =
+    "03-01 02:19:45.207  1000  1001 I ActivityManager: Start proc 3142:com.example/u0a96 for activity";
+const MINDROID: &str = "D/ServiceManager(711): Service MediaPlayer has been started";
+const BUGREPORT: &str = "[ro.build.version.sdk]: [28]";
+const TEXT: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit";
+
+const LINES: usize = 1000;
+
+fn bench(name: &str, line: &str) -> Benchmark {
+    let lines = vec![line.to_owned(); LINES];
+    Benchmark::new(name, move |b| {
+        b.iter(|| {
+            let mut parser = Parser::default();
+            for line in &lines {
+                criterion::black_box(parser.parse_line(line));
+            }
+        })
+    })
+    .throughput(Throughput::Elements(LINES as u32))
+}
+
+/// Parse threadtime lines into slices without building records
+fn borrowed() -> Benchmark {
+    let lines = vec![THREADTIME.to_owned(); LINES];
+    Benchmark::new("threadtime", move |b| {
+        b.iter(|| {
+            for line in &lines {
+                criterion::black_box(Threadtime::parse(line));
+            }
+        })
+    })
+    .throughput(Throughput::Elements(LINES as u32))
+}
+
+fn parse(c: &mut Criterion) {
+    c.bench("borrowed", borrowed());
+    c.bench("parse_line", bench("threadtime", THREADTIME));
+    c.bench("parse_line", bench("mindroid", MINDROID));
+    c.bench("parse_line", bench("bugreport", BUGREPORT));
+    c.bench("parse_line", bench("unparseable", TEXT));
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,202 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Client of the adb server protocol used with `--native-adb`. Requests are
+//! sent to the server as hex encoded length and payload. The server answers
+//! with OKAY or FAIL and a message. After selecting a device with
+//! `host:transport` the connection carries the output of the service e.g
+//! `exec:logcat`. No adb process is spawned.
+
+use crate::{
+    adbserver,
+    utils::{self, Device},
+};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+/// Timeout of requests to the server
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Device of a adb server
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub addr: SocketAddr,
+    /// Any device if None. The server fails if more than one is connected
+    pub serial: Option<String>,
+}
+
+/// Address of the server if `--native-adb` is passed. Defaults to the local
+/// server on `ANDROID_ADB_SERVER_PORT` or 5037
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Result<Option<SocketAddr>, Error> {
+    if !args.is_present("native_adb") {
+        return Ok(None);
+    }
+    match args.value_of("native_adb") {
+        Some(addr) => addr
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut a| a.next())
+            .map(Some)
+            .ok_or_else(|| format_err!("Invalid adb server address {}", addr)),
+        None => Ok(Some(SocketAddr::from((
+            Ipv4Addr::LOCALHOST,
+            adbserver::env_port(),
+        )))),
+    }
+}
+
+fn connect(addr: &SocketAddr) -> Result<TcpStream, Error> {
+    let stream = TcpStream::connect_timeout(addr, TIMEOUT).map_err(|e| {
+        format_err!(
+            "No adb server on {}: {}. Start it with adb start-server",
+            addr,
+            e
+        )
+    })?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+fn send(stream: &mut TcpStream, request: &str) -> Result<(), Error> {
+    stream
+        .write_all(format!("{:04x}{}", request.len(), request).as_bytes())
+        .map_err(|e| format_err!("Failed to send {}: {}", request, e))
+}
+
+/// Read a hex encoded length and the data that follows
+fn read_data(stream: &mut TcpStream) -> Result<Vec<u8>, Error> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = std::str::from_utf8(&len)
+        .ok()
+        .and_then(|l| usize::from_str_radix(l, 16).ok())
+        .ok_or_else(|| format_err!("Invalid length {:?}", len))?;
+    let mut data = vec![0u8; len];
+    stream.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Read the answer to a request. FAIL is returned as error with the message
+/// of the server
+fn status(stream: &mut TcpStream) -> Result<(), Error> {
+    let mut status = [0u8; 4];
+    stream.read_exact(&mut status)?;
+    match &status {
+        b"OKAY" => Ok(()),
+        b"FAIL" => Err(format_err!(
+            "{}",
+            String::from_utf8_lossy(&read_data(stream)?)
+        )),
+        _ => Err(format_err!(
+            "Unexpected response {}",
+            String::from_utf8_lossy(&status)
+        )),
+    }
+}
+
+/// Connect to the service of the target device. The inner error is the
+/// refusal of the service
+fn service(target: &Target, service: &str) -> Result<Result<TcpStream, Error>, Error> {
+    let mut stream = connect(&target.addr)?;
+    let transport = match target.serial {
+        Some(ref serial) => format!("host:transport:{}", serial),
+        None => "host:transport-any".to_owned(),
+    };
+    send(&mut stream, &transport)?;
+    status(&mut stream).map_err(|e| format_err!("Failed to select device: {}", e))?;
+    send(&mut stream, service)?;
+    if let Err(e) = status(&mut stream) {
+        return Ok(Err(format_err!("Failed to run {}: {}", service, e)));
+    }
+    stream.set_read_timeout(None)?;
+    Ok(Ok(stream))
+}
+
+/// Run command on the target device and return the connection carrying its
+/// output. Devices without `exec` (Android 4) run the command in a shell
+pub fn exec(target: &Target, command: &str) -> Result<TcpStream, Error> {
+    match service(target, &format!("exec:{}", command))? {
+        Ok(stream) => Ok(stream),
+        Err(_) => service(target, &format!("shell:{}", command))?,
+    }
+}
+
+/// Run command on the target device and collect its output
+pub fn output(target: &Target, command: &str) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    exec(target, command)?.read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// Devices of the server that are online
+pub fn devices(addr: &SocketAddr) -> Result<Vec<Device>, Error> {
+    let mut stream = connect(addr)?;
+    send(&mut stream, "host:devices-l")?;
+    status(&mut stream).map_err(|e| format_err!("Failed to list devices: {}", e))?;
+    let devices = read_data(&mut stream)?;
+    Ok(utils::parse_devices(&String::from_utf8_lossy(&devices)))
+}
+
+#[test]
+fn protocol() {
+    use std::{net::TcpListener, thread};
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let target = Target {
+        addr: listener.local_addr().unwrap(),
+        serial: Some("A1".to_owned()),
+    };
+    let server = thread::spawn(move || {
+        let request = |stream: &mut TcpStream| {
+            let data = read_data(stream).unwrap();
+            String::from_utf8(data).unwrap()
+        };
+        // Device A1 supports exec
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_eq!(request(&mut stream), "host:transport:A1");
+        stream.write_all(b"OKAY").unwrap();
+        assert_eq!(request(&mut stream), "exec:echo hi");
+        stream.write_all(b"OKAYhi\n").unwrap();
+        drop(stream);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_eq!(request(&mut stream), "host:devices-l");
+        let devices = "A1\tdevice usb:1-1 model:Pixel_4\nB2\toffline\n";
+        write!(stream, "OKAY{:04x}{}", devices.len(), devices).unwrap();
+        drop(stream);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_eq!(request(&mut stream), "host:transport:A1");
+        stream.write_all(b"FAIL0015device 'A1' not found").unwrap();
+    });
+
+    assert_eq!(output(&target, "echo hi").unwrap(), b"hi\n");
+    let devices = devices(&target.addr).unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].model, Some("Pixel 4".to_owned()));
+    let error = exec(&target, "echo hi").unwrap_err().to_string();
+    server.join().unwrap();
+    assert_eq!(error, "Failed to select device: device 'A1' not found");
+}
@@ -0,0 +1,220 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Management of the local adb server. A missing server is started and a
+//! server of a different adb version than the client is restarted before
+//! adb is used for the first time. Older adb versions print the messages of
+//! a server starting on demand to stdout where they end up in the log.
+
+use failure::{format_err, Error};
+use std::{
+    env,
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    process::{Command, Output, Stdio},
+    str::from_utf8,
+    sync::Once,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Port of the adb server if not configured
+const DEFAULT_PORT: u16 = 5037;
+
+/// Timeout of the connection to the server
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Timeout of adb commands run before logcat
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+static ENSURE: Once = Once::new();
+
+/// Port of the server from `ANDROID_ADB_SERVER_PORT` or the default
+pub(crate) fn env_port() -> u16 {
+    env::var("ANDROID_ADB_SERVER_PORT")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(DEFAULT_PORT)
+}
+
+/// Port of the server from `-P` or `ANDROID_ADB_SERVER_PORT`
+fn port(adb: &[String]) -> u16 {
+    adb.iter()
+        .position(|a| a == "-P")
+        .and_then(|n| adb.get(n + 1))
+        .and_then(|p| p.parse().ok())
+        .unwrap_or_else(env_port)
+}
+
+/// Request the version of the server
+fn version(stream: &mut TcpStream) -> Result<u32, Error> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.write_all(b"000chost:version")?;
+    // OKAY followed by the hex encoded length and version
+    let mut response = [0u8; 12];
+    stream.read_exact(&mut response)?;
+    match from_utf8(&response) {
+        Ok(r) if r.starts_with("OKAY") => u32::from_str_radix(&r[8..], 16)
+            .map_err(|e| format_err!("Invalid version {}: {}", &r[8..], e)),
+        _ => Err(format_err!("Unexpected response")),
+    }
+}
+
+/// Version of the server listening on `port`. None if no server is listening
+fn server_version(port: u16) -> Option<Result<u32, Error>> {
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT).ok()?;
+    Some(version(&mut stream))
+}
+
+/// Read pipe to the end on a thread. A full pipe would block the command
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut buffer).ok();
+        }
+        buffer
+    })
+}
+
+/// Run a adb command and collect its output. None if the command fails to
+/// start or doesn't exit within a timeout e.g a unreachable server
+pub fn output(command: &mut Command) -> Option<Output> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().ok()? {
+            break status;
+        }
+        if start.elapsed() > COMMAND_TIMEOUT {
+            child.kill().ok();
+            child.wait().ok();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    Some(Output {
+        status,
+        stdout: stdout.join().ok()?,
+        stderr: stderr.join().ok()?,
+    })
+}
+
+/// Version of the adb client e.g 41 of "Android Debug Bridge version 1.0.41"
+fn client_version(adb: &[String]) -> Option<u32> {
+    let output = output(Command::new(&adb[0]).arg("version"))?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()?
+        .strip_prefix("Android Debug Bridge version ")?
+        .rsplit('.')
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn run(adb: &[String], command: &str) -> Result<(), Error> {
+    let output = output(Command::new(&adb[0]).args(&adb[1..]).arg(command))
+        .ok_or_else(|| format_err!("adb {} timed out", command))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format_err!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+fn start(adb: &[String]) -> Result<(), Error> {
+    match server_version(port(adb)) {
+        Some(Ok(server)) => match client_version(adb) {
+            Some(client) if client != server => {
+                eprintln!(
+                    "Restarting adb server version {} for adb version {}",
+                    server, client
+                );
+                run(adb, "kill-server")?;
+            }
+            _ => return Ok(()),
+        },
+        Some(Err(e)) => {
+            eprintln!("Restarting unresponsive adb server: {}", e);
+            run(adb, "kill-server")?;
+        }
+        None => (),
+    }
+    run(adb, "start-server")
+}
+
+/// Start or restart the local server once. Servers on other hosts (`-H`)
+/// or reached by a socket (`-L`) are not managed
+pub fn ensure(adb: &[String]) {
+    if adb.iter().any(|a| a == "-H" || a == "-L") {
+        return;
+    }
+    ENSURE.call_once(|| {
+        if let Err(e) = start(adb) {
+            eprintln!("Failed to start adb server: {}", e);
+        }
+    });
+}
+
+#[test]
+fn server_version_request() {
+    use std::{net::TcpListener, thread};
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0u8; 16];
+        stream.read_exact(&mut request).unwrap();
+        assert_eq!(&request, b"000chost:version");
+        stream.write_all(b"OKAY00040029").unwrap();
+    });
+    assert_eq!(server_version(port).unwrap().unwrap(), 41);
+    server.join().unwrap();
+
+    let adb = ["adb", "-P", "5038"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(self::port(&adb), 5038);
+}
+
+#[cfg(unix)]
+#[test]
+fn large_output() {
+    // More than a pipe buffer of output must not block the command
+    let start = Instant::now();
+    let output = output(Command::new("sh").args(&["-c", "head -c 1000000 /dev/zero"])).unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout.len(), 1_000_000);
+    assert!(start.elapsed() < COMMAND_TIMEOUT);
+}
@@ -0,0 +1,318 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Latency between pairs of records with `rogcat analyze latency`. A pair
+//! is a start record matching `TAG:REGEX` followed by a end record. If both
+//! regexes capture a group, e.g a request id, records are paired by the
+//! captured value. Otherwise each end is paired with the last start.
+
+use crate::LogSink;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend};
+use regex::Regex;
+use rogcat::record::Record;
+use serde_json::json;
+use std::collections::HashMap;
+
+/// Upper bounds of the histogram buckets in milliseconds
+const BUCKETS: &[u64] = &[
+    1, 2, 5, 10, 20, 50, 100, 200, 500, 1_000, 2_000, 5_000, 10_000, 20_000, 50_000,
+];
+
+/// Record matcher of a `TAG:REGEX` argument. The regex applies to the message
+#[derive(Debug)]
+struct Matcher {
+    tag: Regex,
+    message: Regex,
+}
+
+impl Matcher {
+    fn parse(s: &str) -> Result<Matcher, Error> {
+        let (tag, message) = match s.find(':') {
+            Some(n) => (&s[..n], &s[n + 1..]),
+            None => (s, ""),
+        };
+        let regex =
+            |r: &str| Regex::new(r).map_err(|e| format_err!("Invalid regex string: {}: {}", r, e));
+        Ok(Matcher {
+            tag: regex(tag)?,
+            message: regex(message)?,
+        })
+    }
+
+    /// Pairing key of a matching record
+    fn key(&self, record: &Record) -> Option<String> {
+        if !self.tag.is_match(&record.tag) {
+            return None;
+        }
+        self.message
+            .captures(&record.message)
+            .map(|c| c.get(1).map(|m| m.as_str().to_owned()).unwrap_or_default())
+    }
+}
+
+/// Latencies of one pair of matchers
+#[derive(Debug)]
+struct Latency {
+    name: String,
+    start: Matcher,
+    end: Matcher,
+    /// Unpaired starts by key in milliseconds
+    pending: HashMap<String, i64>,
+    samples: Vec<u64>,
+}
+
+impl Latency {
+    fn add(&mut self, record: &Record) {
+        let time = match record.timestamp {
+            Some(ref timestamp) => {
+                let mut tm = timestamp.tm;
+                tm.tm_utcoff = 0;
+                let spec = tm.to_timespec();
+                spec.sec * 1000 + i64::from(spec.nsec) / 1_000_000
+            }
+            None => return,
+        };
+        // A record matching both ends a pair before it starts the next one
+        if let Some(key) = self.end.key(record) {
+            if let Some(start) = self.pending.remove(&key) {
+                self.samples.push((time - start).max(0) as u64);
+            }
+        }
+        if let Some(key) = self.start.key(record) {
+            self.pending.insert(key, time);
+        }
+    }
+
+    /// Sample at percentile p of sorted samples
+    fn percentile(sorted: &[u64], p: usize) -> u64 {
+        let index = (sorted.len() * p).div_ceil(100).max(1) - 1;
+        sorted[index.min(sorted.len() - 1)]
+    }
+
+    fn histogram(sorted: &[u64]) -> Vec<(String, usize)> {
+        let mut buckets = BUCKETS
+            .iter()
+            .map(|b| (format!("<={}ms", b), 0))
+            .collect::<Vec<_>>();
+        buckets.push((format!(">{}ms", BUCKETS[BUCKETS.len() - 1]), 0));
+        for sample in sorted {
+            let index = BUCKETS
+                .iter()
+                .position(|b| sample <= b)
+                .unwrap_or(BUCKETS.len());
+            buckets[index].1 += 1;
+        }
+        // Leading and trailing empty buckets are omitted
+        let first = buckets.iter().position(|b| b.1 > 0).unwrap_or(0);
+        let last = buckets.iter().rposition(|b| b.1 > 0).unwrap_or(0);
+        buckets.drain(first..=last).collect()
+    }
+
+    /// Count, minimum, mean, percentiles and maximum
+    fn summary(&self) -> Option<Vec<(&'static str, f64)>> {
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        let (min, max) = (*sorted.first()?, *sorted.last()?);
+        let mean = sorted.iter().sum::<u64>() as f64 / sorted.len() as f64;
+        Some(vec![
+            ("min", min as f64),
+            ("mean", mean),
+            ("p50", Latency::percentile(&sorted, 50) as f64),
+            ("p90", Latency::percentile(&sorted, 90) as f64),
+            ("p99", Latency::percentile(&sorted, 99) as f64),
+            ("max", max as f64),
+        ])
+    }
+}
+
+struct Analysis {
+    json: bool,
+    latencies: Vec<Latency>,
+    printed: bool,
+}
+
+pub fn try_from<'a>(args: &ArgMatches<'a>) -> Result<LogSink, Error> {
+    let between = args
+        .values_of("between")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let latencies = between
+        .chunks(2)
+        .map(|pair| {
+            Ok(Latency {
+                name: format!("{} -> {}", pair[0], pair[1]),
+                start: Matcher::parse(pair[0])?,
+                end: Matcher::parse(pair[1])?,
+                pending: HashMap::new(),
+                samples: vec![],
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(Box::new(AnalysisSink(Analysis {
+        json: args.is_present("json"),
+        latencies,
+        printed: false,
+    })))
+}
+
+impl Analysis {
+    fn print_json(&self) -> Result<(), Error> {
+        let latencies = self
+            .latencies
+            .iter()
+            .map(|l| {
+                let mut sorted = l.samples.clone();
+                sorted.sort_unstable();
+                let mut value = json!({
+                    "between": l.name,
+                    "pairs": l.samples.len(),
+                    "unpaired": l.pending.len(),
+                    "histogram": Latency::histogram(&sorted)
+                        .into_iter()
+                        .map(|(b, c)| json!({ "bucket": b, "pairs": c }))
+                        .collect::<Vec<_>>(),
+                });
+                for (name, ms) in l.summary().unwrap_or_default() {
+                    value[name] = json!(ms);
+                }
+                value
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&latencies)?);
+        Ok(())
+    }
+
+    fn print_table(&self) {
+        for (i, latency) in self.latencies.iter().enumerate() {
+            if i != 0 {
+                println!();
+            }
+            println!("{}", latency.name);
+            println!(
+                "  Pairs: {} ({} unpaired starts)",
+                latency.samples.len(),
+                latency.pending.len()
+            );
+            let summary = match latency.summary() {
+                Some(summary) => summary,
+                None => continue,
+            };
+            let summary = summary
+                .iter()
+                .map(|(name, ms)| format!("{} {:.1}ms", name, ms))
+                .collect::<Vec<_>>();
+            println!("  {}", summary.join("  "));
+
+            let mut sorted = latency.samples.clone();
+            sorted.sort_unstable();
+            let histogram = Latency::histogram(&sorted);
+            let peak = histogram.iter().map(|(_, c)| *c).max().unwrap_or(1);
+            for (bucket, count) in histogram {
+                let bar = "#".repeat((count * 40).div_ceil(peak));
+                println!("  {:>9}  {:>8} {}", bucket, count, bar.trim_end());
+            }
+        }
+    }
+
+    fn print(&mut self) -> Result<(), Error> {
+        if self.printed {
+            return Ok(());
+        }
+        self.printed = true;
+        if self.json {
+            self.print_json()
+        } else {
+            self.print_table();
+            Ok(())
+        }
+    }
+}
+
+/// Prints the analysis at the end of the stream or when dropped on
+/// cancellation
+struct AnalysisSink(Analysis);
+
+impl Sink for AnalysisSink {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        for latency in &mut self.0.latencies {
+            latency.add(&record);
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.0.print().map(Async::Ready)
+    }
+}
+
+impl Drop for AnalysisSink {
+    fn drop(&mut self) {
+        self.0.print().ok();
+    }
+}
+
+#[test]
+fn latency() {
+    let mut parser = rogcat::parser::Parser::default();
+    let mut latency = Latency {
+        name: String::new(),
+        start: Matcher::parse("Net:send (\\d+)").unwrap(),
+        end: Matcher::parse("Net:recv (\\d+)").unwrap(),
+        pending: HashMap::new(),
+        samples: vec![],
+    };
+    for line in &[
+        "01-02 03:04:05.000  1  1 I Net: send 1",
+        "01-02 03:04:05.010  1  1 I Net: send 2",
+        "01-02 03:04:05.030  1  1 I Net: recv 2",
+        "01-02 03:04:05.100  1  1 I Other: recv 1",
+        "01-02 03:04:05.250  1  1 I Net: recv 1",
+        "01-02 03:04:05.300  1  1 I Net: send 3",
+    ] {
+        latency.add(&parser.parse_line(line).unwrap());
+    }
+    assert_eq!(latency.samples, vec![20, 250]);
+    assert_eq!(latency.pending.len(), 1);
+    let summary = latency.summary().unwrap();
+    assert_eq!(summary[0], ("min", 20.0));
+    assert_eq!(summary[1], ("mean", 135.0));
+    assert_eq!(summary[2], ("p50", 20.0));
+    assert_eq!(summary[5], ("max", 250.0));
+    assert_eq!(
+        Latency::histogram(&[20, 250]),
+        vec![
+            ("<=20ms".to_owned(), 1),
+            ("<=50ms".to_owned(), 0),
+            ("<=100ms".to_owned(), 0),
+            ("<=200ms".to_owned(), 0),
+            ("<=500ms".to_owned(), 1)
+        ]
+    );
+}
@@ -0,0 +1,287 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Decoder for the binary output of `logcat -B`. The stream is a sequence
+//! of `logger_entry` structs followed by the payload.
+
+use crate::{
+    events::{EventTags, Value},
+    record::{Level, Record, Timestamp},
+};
+use bytes::{Buf, BytesMut, IntoBuf};
+use std::{io, sync::Arc};
+use time::{at, strftime, Timespec};
+use tokio::codec::Decoder;
+
+/// Size of the version 1 header which has no header size field
+const HEADER_SIZE_V1: usize = 20;
+/// Header size of version 4 entries which carry a lid
+const HEADER_SIZE_V3: usize = 24;
+/// Upper bound for a payload as defined by LOGGER_ENTRY_MAX_PAYLOAD
+const MAX_PAYLOAD: usize = 4068;
+/// Log id of the events buffer
+const LOG_ID_EVENTS: u32 = 2;
+/// Log id of the security buffer
+const LOG_ID_SECURITY: u32 = 6;
+/// Buffer names indexed by log id
+const BUFFERS: [&str; 8] = [
+    "main", "radio", "events", "system", "crash", "stats", "security", "kernel",
+];
+
+/// Codec for `logger_entry` structs
+#[derive(Clone, Debug, Default)]
+pub struct LogcatBinaryCodec {
+    /// Names and fields of binary events
+    tags: Option<Arc<EventTags>>,
+}
+
+impl LogcatBinaryCodec {
+    pub fn new() -> LogcatBinaryCodec {
+        LogcatBinaryCodec::default()
+    }
+
+    pub fn with_tags(tags: Arc<EventTags>) -> LogcatBinaryCodec {
+        LogcatBinaryCodec { tags: Some(tags) }
+    }
+}
+
+fn level(priority: u8) -> Level {
+    match priority {
+        2 => Level::Verbose,
+        3 => Level::Debug,
+        4 => Level::Info,
+        5 => Level::Warn,
+        6 => Level::Error,
+        7 => Level::Fatal,
+        8 => Level::Assert,
+        _ => Level::None,
+    }
+}
+
+/// Decode a single entry. `entry` contains header and payload
+fn decode_entry(entry: &[u8], header_size: usize, tags: Option<&EventTags>) -> Record {
+    let mut header = entry[4..header_size].into_buf();
+    let pid = header.get_i32_le();
+    let tid = header.get_u32_le();
+    let sec = header.get_u32_le();
+    let nsec = header.get_u32_le();
+    let lid = if header_size >= HEADER_SIZE_V3 {
+        Some(header.get_u32_le())
+    } else {
+        None
+    };
+    let payload = &entry[header_size..];
+
+    let mut tm = at(Timespec::new(i64::from(sec), nsec as i32));
+    tm.tm_nsec = nsec as i32;
+
+    let (level, tag, message) = match lid {
+        Some(LOG_ID_EVENTS) | Some(LOG_ID_SECURITY) => {
+            // Binary events start with a 32 bit tag number
+            let (number, data) = if payload.len() >= 4 {
+                (Some(payload[..4].into_buf().get_u32_le()), &payload[4..])
+            } else {
+                (None, payload)
+            };
+            let mut rest = data;
+            let value = Value::decode(&mut rest).filter(|_| rest.is_empty());
+            let described = match (tags, number, &value) {
+                (Some(tags), Some(number), Some(value)) => tags.describe(number, value),
+                _ => None,
+            };
+            let (tag, message) = match (described, value) {
+                (Some(described), _) => described,
+                (None, Some(value)) => (
+                    number.map(|n| n.to_string()).unwrap_or_default(),
+                    value.to_string(),
+                ),
+                // Undecodable payloads are dumped as hex
+                (None, None) => (
+                    number.map(|n| n.to_string()).unwrap_or_default(),
+                    data.iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<Vec<String>>()
+                        .join(" "),
+                ),
+            };
+            (Level::Info, tag, message)
+        }
+        _ => {
+            let priority = payload.first().cloned().unwrap_or(0);
+            let mut text = payload
+                .get(1..)
+                .unwrap_or(&[])
+                .split(|b| *b == 0)
+                .map(String::from_utf8_lossy);
+            let tag = text.next().unwrap_or_default().trim().to_owned();
+            let message = text.next().unwrap_or_default().trim_end().to_owned();
+            (level(priority), tag, message)
+        }
+    };
+
+    let mut ts = strftime("%m-%d %H:%M:%S.%f", &tm).unwrap_or_default();
+    ts.truncate(18);
+    let raw = format!(
+        "{} {:>5} {:>5} {} {}: {}",
+        ts, pid, tid, level, tag, message
+    );
+
+    Record {
+        timestamp: Some(Timestamp::new(tm)),
+        message,
+        level,
+        tag,
+        process: pid.to_string(),
+        thread: tid.to_string(),
+        raw,
+        buffer: lid
+            .and_then(|lid| BUFFERS.get(lid as usize))
+            .map(|b| (*b).to_owned()),
+        ..Default::default()
+    }
+}
+
+impl Decoder for LogcatBinaryCodec {
+    type Item = Record;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Record>, io::Error> {
+        if buf.len() < 4 {
+            return Ok(None);
+        }
+
+        let mut header = buf[..4].into_buf();
+        let len = header.get_u16_le() as usize;
+        let header_size = match header.get_u16_le() as usize {
+            0 => HEADER_SIZE_V1,
+            n => n,
+        };
+
+        if header_size < HEADER_SIZE_V1 || len > MAX_PAYLOAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Invalid logger entry with header size {} and length {}",
+                    header_size, len
+                ),
+            ));
+        }
+
+        if buf.len() < header_size + len {
+            return Ok(None);
+        }
+
+        let entry = buf.split_to(header_size + len);
+        Ok(Some(decode_entry(
+            &entry,
+            header_size,
+            self.tags.as_deref(),
+        )))
+    }
+}
+
+#[cfg(test)]
+fn entry(header_size: u16, lid: u32, payload: &[u8]) -> BytesMut {
+    use bytes::BufMut;
+    let mut buf = BytesMut::with_capacity(64);
+    buf.put_u16_le(payload.len() as u16);
+    buf.put_u16_le(header_size);
+    buf.put_i32_le(123);
+    buf.put_u32_le(456);
+    buf.put_u32_le(1_500_000_000);
+    buf.put_u32_le(42_000_000);
+    if header_size as usize >= HEADER_SIZE_V3 {
+        buf.put_u32_le(lid);
+    }
+    if header_size == 28 {
+        buf.put_u32_le(1000);
+    }
+    buf.put_slice(payload);
+    buf
+}
+
+#[test]
+fn decode_v1() {
+    let mut buf = entry(0, 0, b"\x04Tag\0Message\n\0");
+    let r = LogcatBinaryCodec::new().decode(&mut buf).unwrap().unwrap();
+    assert!(buf.is_empty());
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.tag, "Tag");
+    assert_eq!(r.message, "Message");
+    assert_eq!(r.process, "123");
+    assert_eq!(r.thread, "456");
+    assert_eq!(r.timestamp.unwrap().tm_nsec, 42_000_000);
+}
+
+#[test]
+fn decode_v4() {
+    let mut buf = entry(28, 0, b"\x06Tag\0Error\0");
+    buf.extend_from_slice(&entry(28, 0, b"\x03Other\0Debug\0"));
+    let r = LogcatBinaryCodec::new().decode(&mut buf).unwrap().unwrap();
+    assert_eq!(r.level, Level::Error);
+    assert_eq!(r.tag, "Tag");
+    assert_eq!(r.message, "Error");
+    let r = LogcatBinaryCodec::new().decode(&mut buf).unwrap().unwrap();
+    assert_eq!(r.level, Level::Debug);
+    assert_eq!(r.tag, "Other");
+    assert!(LogcatBinaryCodec::new().decode(&mut buf).unwrap().is_none());
+}
+
+#[test]
+fn decode_event() {
+    let mut buf = entry(24, LOG_ID_EVENTS, b"\xa0\x0f\0\0\x01\x02");
+    let r = LogcatBinaryCodec::new().decode(&mut buf).unwrap().unwrap();
+    assert_eq!(r.tag, "4000");
+    assert_eq!(r.message, "01 02");
+}
+
+#[test]
+fn decode_event_list() {
+    let payload = b"\xa0\x0f\0\0\x03\x03\x00\x2a\0\0\0\x02\x03\0\0\0foo\x03\x01\x01\xff\xff\xff\xff\xff\xff\xff\xff";
+    let mut buf = entry(24, LOG_ID_EVENTS, payload);
+    let r = LogcatBinaryCodec::new().decode(&mut buf).unwrap().unwrap();
+    assert_eq!(r.tag, "4000");
+    assert_eq!(r.message, "[42,foo,[-1]]");
+    assert_eq!(r.buffer, Some("events".to_owned()));
+
+    let tags = EventTags::parse("4000 sample (count|1|1),(name|3),(list|3)");
+    let mut buf = entry(24, LOG_ID_EVENTS, payload);
+    let r = LogcatBinaryCodec::with_tags(Arc::new(tags))
+        .decode(&mut buf)
+        .unwrap()
+        .unwrap();
+    assert_eq!(r.tag, "sample");
+    assert_eq!(r.message, "[count=42,name=foo,list=[-1]]");
+}
+
+#[test]
+fn decode_partial() {
+    let mut buf = entry(24, 0, b"\x04Tag\0Message\0");
+    let mut partial = buf.split_to(10);
+    assert!(LogcatBinaryCodec::new()
+        .decode(&mut partial)
+        .unwrap()
+        .is_none());
+    partial.unsplit(buf);
+    assert!(LogcatBinaryCodec::new()
+        .decode(&mut partial)
+        .unwrap()
+        .is_some());
+}
@@ -0,0 +1,150 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Capture a device across reboots. Each boot cycle is written to a new
+//! numbered file annotated with the boot reason of the device.
+
+use crate::{session::HEADER_PREFIX, utils::device_cmd};
+use clap::{value_t, ArgMatches};
+use failure::{format_err, Error};
+use std::{
+    fs::{DirBuilder, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    process::{exit, Command, Stdio},
+};
+use time::{now, strftime};
+
+/// Changes with every boot of the device
+const BOOT_ID: &str = "/proc/sys/kernel/random/boot_id";
+
+/// Filename of boot cycle n started at date
+fn filename(n: usize, date: &str) -> String {
+    format!("boot-{:03}-{}.log", n, date)
+}
+
+/// Comment lines at the top of the file of a boot cycle
+fn header(n: usize, date: &str, reason: &str) -> String {
+    let reason = if reason.is_empty() { "unknown" } else { reason };
+    format!(
+        "{p}boot: {}\n{p}start: {}\n{p}boot-reason: {}\n",
+        n,
+        date,
+        reason,
+        p = HEADER_PREFIX
+    )
+}
+
+/// Run adb with args and return the trimmed output
+fn adb(adb: &[String], args: &[&str]) -> Result<String, Error> {
+    let output = Command::new(&adb[0])
+        .args(&adb[1..])
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format_err!("Failed to run adb: {}", e))?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Capture boot cycles until interrupted or the number of cycles is reached
+pub fn run<'a>(args: &ArgMatches<'a>, bootloop: &ArgMatches<'a>) {
+    if let Err(e) = capture(args, bootloop) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+    exit(0);
+}
+
+fn capture<'a>(args: &ArgMatches<'a>, bootloop: &ArgMatches<'a>) -> Result<(), Error> {
+    let dir = PathBuf::from(bootloop.value_of("directory").unwrap_or("."));
+    if !dir.is_dir() {
+        DirBuilder::new().recursive(true).create(&dir)?;
+    }
+    let cycles = if bootloop.is_present("cycles") {
+        Some(value_t!(bootloop, "cycles", usize)?)
+    } else {
+        None
+    };
+    let cmd = device_cmd(args)?;
+
+    let mut boot_id = String::new();
+    let mut cycle = 0;
+    let mut file: Option<(PathBuf, bool)> = None;
+    loop {
+        adb(&cmd, &["wait-for-device"])?;
+        let id = adb(&cmd, &["shell", "cat", BOOT_ID])?;
+
+        // Logcat exits on reboots but also if adb or the connection dies
+        let rebooted = file.is_none() || id != boot_id;
+        if rebooted {
+            if let Some((ref path, _)) = file {
+                eprintln!("Boot {} written to {}", cycle, path.display());
+            }
+            if cycles == Some(cycle) {
+                return Ok(());
+            }
+            cycle += 1;
+            boot_id = id;
+            let reason = adb(&cmd, &["shell", "getprop", "ro.boot.bootreason"])?;
+            let date = strftime("%Y-%m-%d_%H-%M-%S", &now())?;
+            let path = dir.join(filename(cycle, &date));
+            File::create(&path)
+                .and_then(|mut f| f.write_all(header(cycle, &date, &reason).as_bytes()))
+                .map_err(|e| format_err!("Failed to write {}: {}", path.display(), e))?;
+            eprintln!(
+                "Boot {} ({}) capturing to {}",
+                cycle,
+                reason,
+                path.display()
+            );
+            file = Some((path, true));
+        }
+
+        let (path, whole) = file.clone().expect("Missing boot file");
+        let output = OpenOptions::new().append(true).open(&path)?;
+        let mut logcat = cmd.clone();
+        logcat.extend(["logcat", "-v", "threadtime"].iter().map(|s| s.to_string()));
+        // Resume at the end of the buffers if the device did not reboot
+        if !whole {
+            logcat.extend(["-T", "1"].iter().map(|s| s.to_string()));
+        }
+        Command::new(&logcat[0])
+            .args(&logcat[1..])
+            .stdin(Stdio::null())
+            .stdout(output)
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|e| format_err!("Failed to run adb: {}", e))?;
+        file = Some((path, false));
+    }
+}
+
+#[test]
+fn boot_file() {
+    assert_eq!(
+        filename(7, "2019-03-01_02-19-45"),
+        "boot-007-2019-03-01_02-19-45.log"
+    );
+    let header = header(2, "2019-03-01_02-19-45", "");
+    assert_eq!(header.lines().count(), 3);
+    assert!(header.lines().all(|l| l.starts_with(HEADER_PREFIX)));
+    assert!(header.ends_with("boot-reason: unknown\n"));
+}
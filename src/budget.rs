@@ -0,0 +1,140 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Stop conditions of unattended captures. The stream ends after
+//! `--max-records` records or before the raw lines of the records exceed
+//! `--max-bytes`.
+
+use crate::{filewriter, RecordStream};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{Async, Poll, Stream};
+use rogcat::record::Record;
+
+struct Budget {
+    records: RecordStream,
+    max_records: Option<usize>,
+    max_bytes: Option<usize>,
+    count: usize,
+    bytes: usize,
+    done: bool,
+}
+
+impl Budget {
+    /// Account record and return the exceeded limit if the stream ends
+    fn account(&mut self, record: &Record) -> Option<String> {
+        // Records are written as raw line with a newline
+        let bytes = self.bytes + record.raw.len() + 1;
+        if let Some(max) = self.max_bytes.filter(|max| bytes > *max) {
+            return Some(format!("--max-bytes {}", max));
+        }
+        self.count += 1;
+        self.bytes = bytes;
+        match self.max_records {
+            Some(max) if self.count >= max => Some(format!("--max-records {}", max)),
+            _ => None,
+        }
+    }
+}
+
+impl Stream for Budget {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+        let record = match self.records.poll()? {
+            Async::Ready(Some(record)) => record,
+            r => return Ok(r),
+        };
+        let count = self.count;
+        match self.account(&record) {
+            Some(limit) => {
+                self.done = true;
+                eprintln!(
+                    "Stopped at {} after {} records and {} bytes",
+                    limit, self.count, self.bytes
+                );
+                // A record exceeding the byte budget is dropped
+                Ok(Async::Ready(if self.count > count {
+                    Some(record)
+                } else {
+                    None
+                }))
+            }
+            None => Ok(Async::Ready(Some(record))),
+        }
+    }
+}
+
+/// Wrap records if a record or byte budget is set
+pub fn from_args<'a>(args: &ArgMatches<'a>, records: RecordStream) -> Result<RecordStream, Error> {
+    let limit = |name: &str| {
+        args.value_of(name)
+            .map(|v| match filewriter::count(v) {
+                Some(n) if n > 0 => Ok(n),
+                _ => Err(format_err!("Invalid --{} {}", name.replace('_', "-"), v)),
+            })
+            .transpose()
+    };
+    let max_records = limit("max_records")?;
+    let max_bytes = limit("max_bytes")?;
+    if max_records.is_none() && max_bytes.is_none() {
+        return Ok(records);
+    }
+    Ok(Box::new(Budget {
+        records,
+        max_records,
+        max_bytes,
+        count: 0,
+        bytes: 0,
+        done: false,
+    }))
+}
+
+#[test]
+fn budget() {
+    let record = |raw: &str| Record {
+        raw: raw.into(),
+        ..Default::default()
+    };
+    let mut budget = Budget {
+        records: Box::new(futures::stream::empty()),
+        max_records: Some(3),
+        max_bytes: Some(10),
+        count: 0,
+        bytes: 0,
+        done: false,
+    };
+    assert_eq!(budget.account(&record("abc")), None);
+    assert_eq!(budget.account(&record("abc")), None);
+    assert_eq!(
+        budget.account(&record("abc")),
+        Some("--max-bytes 10".to_owned())
+    );
+    assert_eq!((budget.count, budget.bytes), (2, 8));
+    budget.max_bytes = None;
+    assert_eq!(
+        budget.account(&record("abc")),
+        Some("--max-records 3".to_owned())
+    );
+}
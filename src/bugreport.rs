@@ -0,0 +1,159 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Extract sections of a bugreport. Reports are read from the plain text
+//! dump or the zip archive of `adb bugreport`. Sections start with a header
+//! like `------ SYSTEM LOG (logcat -v threadtime -d *:v) ------` and end at
+//! the next line starting with `------ `.
+
+use crate::{LogStream, StreamData};
+use clap::ArgMatches;
+use failure::{err_msg, format_err, Error};
+use futures::stream::iter_ok;
+use std::{
+    borrow::ToOwned,
+    fs::{read, File},
+    io::Read,
+    path::Path,
+};
+use zip::ZipArchive;
+
+/// Directory of ANR traces in bugreport archives
+const ANR_DIR: &str = "FS/data/anr/";
+
+/// Titles of the sections by name
+fn titles(section: &str) -> Result<&'static [&'static str], Error> {
+    match section {
+        "logcat" => Ok(&["SYSTEM LOG"]),
+        "events" => Ok(&["EVENT LOG"]),
+        "radio" => Ok(&["RADIO LOG"]),
+        "kernel" => Ok(&["KERNEL LOG", "LAST KMSG"]),
+        "anr" => Ok(&["VM TRACES"]),
+        _ => Err(format_err!("Unknown bugreport section {}", section)),
+    }
+}
+
+/// Lines of the sections whose title starts with one of titles
+fn extract<'a>(report: &'a str, titles: &[&str]) -> Vec<&'a str> {
+    let mut lines = vec![];
+    let mut inside = false;
+    for line in report.lines() {
+        if let Some(header) = line.strip_prefix("------ ") {
+            inside = titles.iter().any(|t| header.starts_with(t));
+        } else if inside {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String, Error> {
+    let mut entry = archive.by_name(name)?;
+    let mut content = vec![];
+    entry.read_to_end(&mut content)?;
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// The main text of a bugreport and the ANR traces of archives
+fn read_report(path: &Path) -> Result<(String, Vec<String>), Error> {
+    let content =
+        read(path).map_err(|e| format_err!("Failed to read {}: {}", path.display(), e))?;
+    if !content.starts_with(b"PK") {
+        return Ok((String::from_utf8_lossy(&content).into_owned(), vec![]));
+    }
+
+    let mut archive = ZipArchive::new(File::open(path)?)
+        .map_err(|e| format_err!("Failed to open {}: {}", path.display(), e))?;
+    let names = (0..archive.len())
+        .map(|i| archive.by_index(i).map(|e| e.name().to_owned()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The name of the report is stored in main_entry.txt
+    let main = match read_entry(&mut archive, "main_entry.txt") {
+        Ok(main) => main.trim().to_owned(),
+        Err(_) => names
+            .iter()
+            .find(|n| n.starts_with("bugreport") && n.ends_with(".txt"))
+            .cloned()
+            .ok_or_else(|| err_msg("Failed to find the report in the archive"))?,
+    };
+    let report = read_entry(&mut archive, &main)?;
+    let traces = names
+        .iter()
+        .filter(|n| n.starts_with(ANR_DIR) && !n.ends_with('/'))
+        .map(|n| read_entry(&mut archive, n))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((report, traces))
+}
+
+/// Stream the lines of the section of the bugreport selected with `--extract`
+pub fn extract_from_args<'a>(args: &ArgMatches<'a>) -> Result<LogStream, Error> {
+    let section = args
+        .value_of("extract")
+        .ok_or_else(|| err_msg("Missing section"))?;
+    let titles = titles(section)?;
+    let file = args
+        .value_of("file")
+        .ok_or_else(|| err_msg("Missing bugreport file"))?;
+    let (report, traces) = read_report(Path::new(file))?;
+
+    let mut lines = extract(&report, titles)
+        .into_iter()
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    // Recent Android versions place the traces in the archive only
+    if section == "anr" {
+        lines.extend(traces.iter().flat_map(|t| t.lines().map(ToOwned::to_owned)));
+    }
+    if lines.is_empty() {
+        return Err(format_err!("No {} section found in {}", section, file));
+    }
+    Ok(Box::new(iter_ok(lines.into_iter().map(StreamData::Line))))
+}
+
+#[test]
+fn extract_sections() {
+    let report = "== dumpstate: 2019-03-01 02:19:45\n\
+                  ------ SYSTEM LOG (logcat -v threadtime -v printable -v uid -d *:v) ------\n\
+                  --------- beginning of main\n\
+                  03-01 02:19:45.207  1000  1000  1001 I Tag: hello\n\
+                  ------ 0.640s was the duration of 'SYSTEM LOG' ------\n\
+                  ------ EVENT LOG (logcat -b events -v threadtime -d *:v) ------\n\
+                  03-01 02:19:45.208  1000  1000  1001 I am_proc_start: [0,42]\n\
+                  ------ KERNEL LOG (dmesg) ------\n\
+                  <6>[    0.000000] Booting Linux\n\
+                  ------ LAST KMSG (/proc/last_kmsg) ------\n\
+                  <6>[    1.000000] last\n\
+                  ------ VM TRACES AT LAST ANR (/data/anr/traces.txt) ------\n";
+    assert_eq!(
+        extract(report, titles("logcat").unwrap()),
+        vec![
+            "--------- beginning of main",
+            "03-01 02:19:45.207  1000  1000  1001 I Tag: hello"
+        ]
+    );
+    assert_eq!(extract(report, titles("events").unwrap()).len(), 1);
+    assert_eq!(
+        extract(report, titles("kernel").unwrap()),
+        vec!["<6>[    0.000000] Booting Linux", "<6>[    1.000000] last"]
+    );
+    assert!(extract(report, titles("anr").unwrap()).is_empty());
+    assert!(titles("foo").is_err());
+}
@@ -18,10 +18,10 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::utils;
+use crate::{index, transform, utils};
 use clap::{crate_authors, crate_name, crate_version, App, AppSettings, Arg, SubCommand};
 use lazy_static::lazy_static;
-use rogcat::record::Level;
+use rogcat::{record::Level, template};
 
 lazy_static! {
     static ref ABOUT: String = {
@@ -39,38 +39,159 @@ pub fn cli() -> App<'static, 'static> {
         .version(crate_version!())
         .author(crate_authors!())
         .about(ABOUT.as_str())
+        .arg(Arg::with_name("adb_args")
+             .long("adb-args")
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .value_name("ARGS")
+             .help("Arguments passed to each adb invocation e.g \"-H 10.0.0.2 -P 5037\". Overrides the configuration key adb_args"))
+        .arg(Arg::with_name("adb_path")
+             .long("adb-path")
+             .takes_value(true)
+             .value_name("PATH")
+             .help("Path of the adb binary. Defaults to adb in PATH. Overrides the configuration key adb_path"))
+        .arg(Arg::with_name("all_devices")
+             .long("all-devices")
+             .conflicts_with_all(&["input", "COMMAND"])
+             .help("Capture the logs of all connected devices. Records are prefixed with the device serial"))
+        .arg(Arg::with_name("after_context")
+             .short("A")
+             .long("after-context")
+             .takes_value(true)
+             .help("Show this number of records after each match"))
+        .arg(Arg::with_name("before_context")
+             .short("B")
+             .long("before-context")
+             .takes_value(true)
+             .help("Show this number of records before each match"))
+        .arg(Arg::with_name("binary")
+             .long("binary")
+             .conflicts_with_all(&["input", "COMMAND"])
+             .help("Read the binary logcat format (logcat -B) instead of text. Timestamps and ids are exact"))
         .arg(Arg::with_name("buffer")
              .short("b")
              .long("buffer")
              .multiple(true)
              .takes_value(true)
+             .use_delimiter(true)
              .conflicts_with_all(&["input", "COMMAND"])
-             .help("Select specific logd buffers. Defaults to main, events, kernel and crash"))
+             .help("Select specific logd buffers e.g main,system,crash. Defaults to main, events, kernel and crash"))
+        .arg(Arg::with_name("capture_crashes")
+             .long("capture-crashes")
+             .help("Pull ANR traces and tombstones from the device when reported. Files are saved next to the output file"))
+        .arg(Arg::with_name("clock_skew")
+             .long("clock-skew")
+             .takes_value(true)
+             .value_name("THRESHOLD")
+             .help("Estimate the offset of the device clock from the arrival time of records and warn if it exceeds \
+                    the threshold e.g --clock-skew 2s"))
         .arg(Arg::with_name("color")
              .long("color")
              .takes_value(true)
              .possible_values(&["auto", "always", "never"])
              .conflicts_with_all(&["highlight", "output"])
-             .help("Terminal coloring option"))
+             .help("Terminal coloring option. Auto disables colors if stdout is not a terminal or NO_COLOR is set. Always emits escape sequences into pipes"))
+        .arg(Arg::with_name("color_mode")
+             .long("color-mode")
+             .takes_value(true)
+             .possible_values(&["line", "level", "tag", "off"])
+             .help("Color the entire line by level, only the level and message (default), level and message by tag or nothing"))
+        .arg(Arg::with_name("context")
+             .short("C")
+             .long("context")
+             .takes_value(true)
+             .help("Show this number of records before and after each match. Context records are dimmed"))
+        .arg(Arg::with_name("columns")
+             .long("columns")
+             .takes_value(true)
+             .conflicts_with("output")
+             .help("Columns of the human format e.g \"time,level,tag:20,msg\". Available are buffer, device, time, tag, name, pid, tid, level and msg. \
+                    Append :WIDTH to set a width"))
+        .arg(Arg::with_name("compress")
+             .long("compress")
+             .takes_value(true)
+             .requires("output")
+             .possible_values(&["gzip"])
+             .help("Compress output files. Output files with the extension gz are compressed without this option"))
+        .arg(Arg::with_name("correct_clock")
+             .long("correct-clock")
+             .conflicts_with_all(&["host_timestamps", "input"])
+             .help("Shift the device timestamps by the estimated clock offset. Warns about offsets above --clock-skew \
+                    or 1s. The first records are corrected by the estimate of the first 10s"))
+        .arg(Arg::with_name("dedupe")
+             .long("dedupe")
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .possible_values(&["exact", "fuzzy"])
+             .help("Collapse runs of consecutive records with the same tag and message into one line annotated with the \
+                    number of repeats. 'fuzzy' ignores numbers in messages. Defaults to exact"))
+        .arg(Arg::with_name("device_model")
+             .long("device-model")
+             .takes_value(true)
+             .conflicts_with_all(&["all_devices", "input", "COMMAND", "serial"])
+             .help("Capture the device with a model matching this regex e.g 'Pixel 4'"))
+        .arg(Arg::with_name("dividers")
+             .long("dividers")
+             .conflicts_with_all(&["input", "COMMAND", "binary"])
+             .help("Let logcat announce every switch between buffers to tag all records with their buffer. Needs \
+                    Android 7 or later"))
         .arg(Arg::with_name("dump")
              .short("d")
              .long("dump")
              .conflicts_with_all(&["input", "COMMAND", "restart"])
              .help("Dump the log and then exit (don't block)"))
+        .arg(Arg::with_name("event_tags")
+             .long("event-tags")
+             .takes_value(true)
+             .requires("binary")
+             .help("Event tag database used to decode binary events. Defaults to /system/etc/event-log-tags pulled from the device"))
+        .arg(Arg::with_name("exit_on")
+             .long("exit-on")
+             .takes_value(true)
+             .multiple(true)
+             .help("Stop after the first record with a message matching this regex e.g 'Test run complete'"))
+        .arg(Arg::with_name("fail_on")
+             .long("fail-on")
+             .takes_value(true)
+             .multiple(true)
+             .help("Stop with a non zero exit code after the first record with a message matching this regex e.g 'FATAL EXCEPTION'"))
+        .arg(Arg::with_name("filter")
+             .long("filter")
+             .takes_value(true)
+             .multiple(true)
+             .help("Filter expression e.g '(tag == \"ActivityManager\" && level >= W) || message ~ \"ANR\"'. \
+                    Fields are tag, message, level, process, thread, device and raw. Operators are ==, !=, ~ (regex), !~, \
+                    <, <=, >, >= (level only), &&, || and !"))
+        .arg(Arg::with_name("follow")
+             .long("follow")
+             .requires("input")
+             .help("Tail the input files like tail -F and merge their records by timestamp. Records are tagged with the file name in the device column"))
         .arg(Arg::with_name("format")
              .long("format")
              .short("f")
              .takes_value(true)
-             .possible_values(&["csv", "html", "human", "json", "raw"]).help("Output format. Defaults to human on stdout and raw on file output"))
+             .possible_values(&["csv", "html", "human", "json", "msgpack", "protobuf", "raw", "trace"]).help("Output format. Defaults to human on stdout and raw on file output"))
         .arg(Arg::with_name("filename_format")
              .long("filename-format")
              .short("a")
              .takes_value(true)
              .requires("output")
-             .possible_values(&["single", "enumerate", "date"])
+             .possible_values(&["single", "enumerate", "date", "template"])
              .help( "Select a format for output file names. By passing 'single' the filename provided with the '-o' option is used (default).\
                     'enumerate' appends a file sequence number after the filename passed with '-o' option whenever a new file is created \
-                    (see 'records-per-file' option). 'date' will prefix the output filename with the current local date when a new file is created"))
+                    (see 'records-per-file' option). 'date' will prefix the output filename with the current local date when a new file is created. \
+                    'template' expands strftime specifiers like %F or %H and {index} in the filename passed with '-o'"))
+        .arg(Arg::with_name("forward")
+             .long("forward")
+             .takes_value(true)
+             .help("Send records to a remote collector e.g tcp://host:5514, udp://host:5514 or syslog://host:514?proto=tcp in addition to the output"))
+        .arg(Arg::with_name("forward_format")
+             .long("forward-format")
+             .takes_value(true)
+             .requires("forward")
+             .possible_values(&["csv", "json", "msgpack", "protobuf", "raw"])
+             .help("Format of forwarded records. Defaults to json. Syslog forwards are RFC 5424 messages"))
         .arg(Arg::with_name("head")
              .short("H")
              .long("head")
@@ -84,17 +205,66 @@ pub fn cli() -> App<'static, 'static> {
              .multiple(true)
              .conflicts_with_all(&["output"])
              .help( "Highlight messages that match this pattern in RE2. The prefix '!' inverts the match"))
+        .arg(Arg::with_name("index")
+             .long("index")
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .value_name("RECORDS")
+             .requires("output")
+             .conflicts_with("split_by")
+             .help("Write a seekable index next to each output file with the offset of every Nth record. Defaults to 1000 records. \
+                    See replay --since"))
         .arg(Arg::with_name("input")
              .short("i")
              .long("input")
              .takes_value(true)
              .multiple(true)
-             .help( "Read from file instead of command. Use 'serial://COM0@115200,8N1 or similiar for reading a serial port"))
+             .help( "Read from file instead of command. Use serial:///dev/ttyUSB0?baud=115200 or serial://COM0@115200,8N1 for reading a serial port. \
+                    Pass tcp://ADDR:PORT or udp://ADDR:PORT to listen for lines sent over the network. \
+                    Other sources are file:///PATH, stdin:, adb://[SERIAL] and can://DEVICE"))
+        .arg(Arg::with_name("join_stacktraces")
+             .long("join-stacktraces")
+             .help("Join the lines of Java and Kotlin stack traces into one multi-line record"))
+        .arg(Arg::with_name("jobs")
+             .short("j")
+             .long("jobs")
+             .takes_value(true)
+             .value_name("N")
+             .conflicts_with_all(&["after_context", "before_context", "context"])
+             .help("Parse, filter and encode output files on N threads. Meant for reprocessing captures. Record order is preserved. \
+                    Filters of profiles are not reloaded"))
+        .arg(Arg::with_name("keep")
+             .long("keep")
+             .takes_value(true)
+             .requires("output")
+             .help("Keep only the last n files written. Older ones are removed"))
         .arg(Arg::with_name("level")
              .short("l")
              .long("level")
              .takes_value(true)
              .possible_values(Level::values()).help("Minimum level"))
+        .arg(Arg::with_name("loki")
+             .long("loki")
+             .takes_value(true)
+             .value_name("URL")
+             .help("Push records to Grafana Loki e.g http://loki:3100 in addition to the output"))
+        .arg(Arg::with_name("max_bytes")
+             .long("max-bytes")
+             .takes_value(true)
+             .value_name("SIZE")
+             .help("Stop before the raw lines of the records exceed a size e.g --max-bytes 500M. Use k, M, G suffixes or \
+                    a plain number"))
+        .arg(Arg::with_name("max_file_size")
+             .long("max-file-size")
+             .takes_value(true)
+             .requires("output")
+             .help("Start a new file after n bytes. Use k, M, G suffixes or a plain number"))
+        .arg(Arg::with_name("max_records")
+             .long("max-records")
+             .takes_value(true)
+             .value_name("N")
+             .help("Stop after n records and print a summary. Use k, M, G suffixes or a plain number"))
         .arg(Arg::with_name("message")
              .short("m")
              .long("message")
@@ -115,16 +285,81 @@ pub fn cli() -> App<'static, 'static> {
              .long("hide-timestamp")
              .conflicts_with("output")
              .help("Hide timestamp in terminal output"))
+        .arg(Arg::with_name("host_timestamps")
+             .long("host-timestamps")
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .possible_values(&["keep"])
+             .help("Stamp records with the host time of their arrival instead of the device time. With \
+                    --host-timestamps=keep the device time is kept in the field device_time"))
+        .arg(Arg::with_name("native_adb")
+             .long("native-adb")
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .value_name("HOST:PORT")
+             .conflicts_with_all(&["input", "COMMAND", "all_devices", "transport", "wait_for_boot"])
+             .help("Read logcat directly from the adb server socket instead of spawning adb. \
+                    Defaults to 127.0.0.1 and ANDROID_ADB_SERVER_PORT or 5037"))
+        .arg(Arg::with_name("notify")
+             .long("notify")
+             .takes_value(true)
+             .multiple(true)
+             .help("Show a desktop notification for records with tag or message matching this pattern in RE2. Notifications are rate limited"))
+        .arg(Arg::with_name("no_wrap")
+             .long("no-wrap")
+             .conflicts_with("wrap")
+             .help("Truncate long messages with a ellipsis instead of wrapping"))
+        .arg(Arg::with_name("on_match")
+             .long("on-match")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .help("Run a shell command when a message matches e.g '^ANR in:adb shell screencap -p > anr.png'. \
+                    Capture groups are passed as the shell parameters $1 etc and in $ROGCAT_MATCH_1 etc. The record is passed as JSON on stdin and in $ROGCAT_* environment variables. Escape colons in the regex with '\\:'"))
         .arg(Arg::with_name("output")
              .short("o")
              .long("output")
              .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
              .conflicts_with("color")
-             .help("Write output to file"))
+             .help("Write output to file. Pass tcp://, udp://, tls:// or syslog:// urls to send records to a collector e.g \
+                    tcp://ADDR:PORT?format=csv, 'journald' to write to the systemd journal and '-' to write to the terminal. \
+                    Repeat to write to several outputs. Options like --format apply to the first file. Additional files are \
+                    written in the format of their extension"))
+        .arg(Arg::with_name("output_format")
+             .long("output-format")
+             .takes_value(true)
+             .value_name("TEMPLATE")
+             .conflicts_with("format")
+             .validator(|t| template::validate(&t).map_err(|e| e.to_string()))
+             .help("Format records with a template e.g '{time} [{level}] {tag:>20}: {message}'. Placeholders are time, \
+                    date, level, tag, pid, tid, message, raw, device, buffer, name and profile fields. {NAME:>W.P} \
+                    pads to width W on the left or right with < and truncates to P characters"))
+        .arg(Arg::with_name("overflow")
+             .long("overflow")
+             .takes_value(true)
+             .possible_values(&["block", "drop-oldest", "drop-newest"])
+             .help("Policy when the queue of --queue-size is full. Defaults to block that pauses reading"))
         .arg(Arg::with_name("overwrite")
              .long("overwrite")
              .requires("output")
              .help("Overwrite output file if present"))
+        .arg(Arg::with_name("preset")
+             .long("preset")
+             .takes_value(true)
+             .help("Select a preset of the profile"))
+        .arg(Arg::with_name("process_names")
+             .long("process-names")
+             .conflicts_with_all(&["input", "COMMAND"])
+             .help("Resolve pids to process names with \"adb shell ps\". Names are shown in the name column and exported as process_name"))
+        .arg(Arg::with_name("pid_of")
+             .long("pid-of")
+             .takes_value(true)
+             .conflicts_with_all(&["input", "COMMAND", "all_devices"])
+             .help("Show only records of the processes of a package e.g \"com.example.app\". Restarts of the package are followed"))
         .arg(Arg::with_name("profiles_path")
              .short("P")
              .long("profiles-path")
@@ -135,6 +370,18 @@ pub fn cli() -> App<'static, 'static> {
              .long("profile")
              .takes_value(true)
              .help("Select profile"))
+        .arg(Arg::with_name("queue_size")
+             .long("queue-size")
+             .takes_value(true)
+             .help("Read records on a own task into a queue of this size. Defaults to 10000 if --overflow is passed"))
+        .arg(Arg::with_name("reconnect")
+             .long("reconnect")
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .conflicts_with_all(&["dump", "input", "tail"])
+             .help("Reattach when the device disconnects or adb dies. Attempts are delayed with exponential backoff \
+                    up to the given interval e.g --reconnect=1m. Defaults to 30s"))
         .arg(Arg::with_name("records_per_file")
              .short("n")
              .long("records-per-file")
@@ -147,10 +394,62 @@ pub fn cli() -> App<'static, 'static> {
              .takes_value(true)
              .multiple(true)
              .help("Regex filter on tag, pid, thread and message."))
+        .arg(Arg::with_name("report")
+             .long("report")
+             .takes_value(true)
+             .help("Write a JUnit XML report with a testcase per --fail-on pattern and --timeout. Matching records are \
+                    attached to the failures. Logging continues after --fail-on matches and fails on exit"))
         .arg(Arg::with_name("restart")
              .long("restart")
              .conflicts_with_all(&["dump", "input", "tail"])
              .help("Restart command on exit"))
+        .arg(Arg::with_name("rotate_interval")
+             .long("rotate-interval")
+             .takes_value(true)
+             .requires("output")
+             .help("Start a new file after a interval e.g 30m or 1h. Use s, m, h, d suffixes or plain seconds"))
+        .arg(Arg::with_name("retrace")
+             .long("retrace")
+             .takes_value(true)
+             .value_name("MAPPING")
+             .help("Deobfuscate the class and method names of stack traces with a R8 or ProGuard mapping.txt"))
+        .arg(Arg::with_name("sample")
+             .long("sample")
+             .takes_value(true)
+             .help("Show only every nth record e.g 1/10"))
+        .arg(Arg::with_name("redact")
+             .long("redact")
+             .help("Mask emails, bearer tokens, MAC addresses, IMEIs, OAuth and API keys and the patterns of the profile key redact with •••"))
+        .arg(Arg::with_name("relative_timestamps")
+             .long("relative-timestamps")
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .possible_values(&["first", "marker"])
+             .help("Show timestamps as offset in seconds from the first record (default) or the last marker e.g +012.345. Csv and json output contain the offset as relative"))
+        .arg(Arg::with_name("rewrite")
+             .long("rewrite")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .help("Rewrite messages with a rule like 's/regex/replacement/' before filtering. Flags g (all matches) and i (ignore case) are supported. Groups are referenced with $1 or \\1"))
+        .arg(Arg::with_name("script")
+             .long("script")
+             .takes_value(true)
+             .help("Rhai script with a function process(record) that can drop, modify or add records"))
+        .arg(Arg::with_name("session_metadata")
+             .long("session-metadata")
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .possible_values(&["header", "sidecar"])
+             .requires("output")
+             .help("Write device, rogcat version, start time and filters of the capture to a sidecar .meta.json (default) or as header of each output file"))
+        .arg(Arg::with_name("serial")
+             .long("serial")
+             .takes_value(true)
+             .conflicts_with_all(&["all_devices", "input", "COMMAND"])
+             .help("Serial of the device to capture. A device is picked interactively if multiple devices are connected"))
         .arg(Arg::with_name("skip")
              .short("s")
              .long("skip")
@@ -163,6 +462,31 @@ pub fn cli() -> App<'static, 'static> {
      //         .long("show-time-diff")
      //         .conflicts_with("output")
      //         .help( "Show the time difference between the occurence of equal tags in terminal output"))
+        .arg(Arg::with_name("snooze")
+             .long("snooze")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("TAG=DURATION")
+             .help("Drop records of tags matching a regex for a while e.g --snooze Chatty=30s. The number of dropped \
+                    records is printed when the snooze expires"))
+        .arg(Arg::with_name("stats_line")
+             .long("stats-line")
+             .conflicts_with("output")
+             .help("Show a footer with the records per second, the error rate and a sparkline of the last minute below the terminal output"))
+        .arg(Arg::with_name("strict_parse")
+             .long("strict-parse")
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .value_name("FILE")
+             .help("Write lines that cannot be parsed to a file instead of passing them on as untagged records e.g \
+                    --strict-parse=rejects.log. Defaults to unparsed.log. The number of lines is reported at exit"))
+        .arg(Arg::with_name("suppress")
+             .long("suppress")
+             .takes_value(true)
+             .multiple(true)
+             .help("Drop records with a tag or message matching this regex. Prefix with tag:, msg:, pid: or tid: to match a single field"))
         .arg(Arg::with_name("tag")
              .short("t")
              .long("tag")
@@ -174,18 +498,135 @@ pub fn cli() -> App<'static, 'static> {
              .takes_value(true)
              .multiple(true)
              .help("Same as -t/--tag but case insensitive"))
+        .arg(Arg::with_name("tag_level")
+             .long("tag-level")
+             .takes_value(true)
+             .multiple(true)
+             .help("Minimum level for tags matching a RE2 pattern in the form TAG:LEVEL e.g \"MyApp:V\". Overrules -l/--level for matching tags"))
+        .arg(Arg::with_name("sort_window")
+             .long("sort-window")
+             .takes_value(true)
+             .help("Reorder records of merged sources e.g buffers, devices or files by timestamp. Records are held back for this window e.g 2s or 500ms"))
+        .arg(Arg::with_name("split_by")
+             .long("split-by")
+             .takes_value(true)
+             .requires("output")
+             .possible_values(&["tag", "pid", "process"])
+             .conflicts_with_all(&["compress", "filename_format", "keep", "max_file_size", "records_per_file", "rotate_interval"])
+             .help("Write the records of each tag, pid or process to a own file in the directory passed with '-o'"))
+        .arg(Arg::with_name("symbols")
+             .long("symbols")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("DIR")
+             .help("Directory with unstripped libraries. Frames of native crash dumps are symbolized with addr2line"))
         .arg(Arg::with_name("tail")
              .long("tail")
              .takes_value(true)
-             .conflicts_with_all(&["input", "COMMAND", "restart"])
-             .help("Dump only the most recent <COUNT> lines (implies --dump)"))
+             .conflicts_with_all(&["restart"])
+             .help("Dump only the most recent <COUNT> lines (implies --dump). Input files are read to the end before the last <COUNT> records are shown"))
+        .arg(Arg::with_name("theme")
+             .long("theme")
+             .takes_value(true)
+             .conflicts_with("output")
+             .help("Theme file (toml) with colors for levels, tags and message patterns"))
+        .arg(Arg::with_name("time_zone")
+             .long("time-zone")
+             .takes_value(true)
+             .possible_values(&["local", "utc"])
+             .help("Convert timestamps of the terminal output to the local time or UTC. Timestamps without zone are taken as UTC"))
+        .arg(Arg::with_name("timeout")
+             .long("timeout")
+             .takes_value(true)
+             .help("Stop with a non zero exit code if the log did not end within this duration e.g 10m. Use with --exit-on"))
+        .arg(Arg::with_name("timestamp_format")
+             .long("timestamp-format")
+             .takes_value(true)
+             .help("Format of timestamps in the terminal output in strftime syntax e.g '%Y-%m-%d %H:%M:%S.%f %z'. %f are milliseconds"))
+        .arg(Arg::with_name("transform")
+             .long("transform")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .possible_values(transform::TRANSFORMS)
+             .help("Rewrite records with a built-in transformer. demangle extracts the level and source location of Flutter, React Native and Chromium messages. \
+                    gc parses garbage collections and low memory kills into fields. gc-compact also shortens their messages"))
+        .arg(Arg::with_name("transport")
+             .long("transport")
+             .takes_value(true)
+             .conflicts_with_all(&["input", "COMMAND"])
+             .help("Run adb on a remote host e.g ssh://user@host:22 for devices attached to a different machine. \
+                    The ssh connection is reestablished when lost"))
+        .arg(Arg::with_name("tui")
+             .long("tui")
+             .conflicts_with_all(&["output", "format", "color"])
+             .help("Interactive terminal ui with scrollback, search (/), pause (p) and filter (f)"))
+        .arg(Arg::with_name("vitals")
+             .long("vitals")
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .value_name("INTERVAL")
+             .conflicts_with_all(&["input", "COMMAND", "all_devices"])
+             .help("Sample battery level, temperature and cpu frequencies of the device every 5s or the given interval \
+                    e.g --vitals=10s and show them as dimmed records"))
+        .arg(Arg::with_name("vitals_csv")
+             .long("vitals-csv")
+             .takes_value(true)
+             .value_name("FILE")
+             .requires("vitals")
+             .help("Write the samples of --vitals to this csv file instead of the log"))
+        .arg(Arg::with_name("wait_for_boot")
+             .long("wait-for-boot")
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .possible_values(&["clear"])
+             .conflicts_with_all(&["input", "COMMAND"])
+             .help("Wait for the device and sys.boot_completed before reading and after each restart. \
+                    Pass --wait-for-boot=clear to clear the buffers once booted"))
+        .arg(Arg::with_name("with_cpu")
+             .long("with-cpu")
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .value_name("INTERVAL")
+             .conflicts_with_all(&["input", "COMMAND", "all_devices"])
+             .help("Sample the cpu usage of the processes with top every 5s or the given interval e.g --with-cpu=2s. \
+                    Records carry the latest usage of their process as cpu in the json and csv output"))
+        .arg(Arg::with_name("wrap")
+             .long("wrap")
+             .takes_value(true)
+             .possible_values(&["chars", "word", "none"])
+             .help("Wrap long messages into chunks of the terminal width or on word boundaries with indented continuation lines. Defaults to chars"))
         .arg(Arg::with_name("COMMAND")
              .help( "Optional command to run and capture stdout and stdderr from. Pass \"-\" to d capture stdin'. If omitted, rogcat will run \"adb logcat -b all\" and restarts this commmand if 'adb' terminates",))
+        .subcommand(SubCommand::with_name("bootloop")
+                .about("Capture the device across reboots. Each boot cycle is written to a new numbered file with the boot reason in the header")
+                .arg(Arg::with_name("cycles")
+                        .short("c")
+                        .long("cycles")
+                        .takes_value(true)
+                        .help("Stop after this number of boot cycles"))
+                .arg(Arg::with_name("directory")
+                        .short("d")
+                        .long("directory")
+                        .takes_value(true)
+                        .help("Directory of the log files. Defaults to the current directory")))
         .subcommand(SubCommand::with_name("bugreport")
                 .about("Capture bugreport. This is only works for Android versions < 7.")
                 .arg(Arg::with_name("zip").short("z").long("zip").help("Zip report"))
                 .arg(Arg::with_name("overwrite").long("overwrite").help("Overwrite report file if present"))
-                .arg(Arg::with_name("file").help("Output file name - defaults to <now>-bugreport")))
+                .arg(Arg::with_name("extract")
+                     .short("e")
+                     .long("extract")
+                     .takes_value(true)
+                     .possible_values(&["logcat", "events", "radio", "kernel", "anr"])
+                     .conflicts_with_all(&["zip", "overwrite"])
+                     .requires("file")
+                     .help("Read a section of a existing bugreport (txt or zip) and process it like any other input"))
+                .arg(Arg::with_name("file").help("Output file name - defaults to <now>-bugreport. With --extract the bugreport to read")))
         .subcommand(SubCommand::with_name("completions")
                 .about("Generates completion scripts")
                 .arg(Arg::with_name("shell")
@@ -199,9 +640,122 @@ pub fn cli() -> App<'static, 'static> {
                          .long("buffer")
                          .multiple(true)
                          .takes_value(true)
+                         .use_delimiter(true)
                          .help("Select specific log buffers to clear. Defaults to main, events, kernel and crash")))
         .subcommand(SubCommand::with_name("devices")
                 .about("List available devices"))
+        .subcommand(SubCommand::with_name("diff")
+                .about("Compare two captures by level, tag and message ignoring timestamps, pids and tids")
+                .arg(Arg::with_name("ignore_numbers")
+                        .long("ignore-numbers")
+                        .help("Ignore numbers in messages e.g addresses or durations"))
+                .arg(Arg::with_name("a")
+                        .required(true)
+                        .help("First capture"))
+                .arg(Arg::with_name("b")
+                        .required(true)
+                        .help("Second capture")))
+        .subcommand(SubCommand::with_name("replay")
+                .about("Replay a captured log paced by the original timestamps")
+                .arg(Arg::with_name("speed")
+                        .long("speed")
+                        .takes_value(true)
+                        .conflicts_with("no_delay")
+                        .help("Replay speed factor e.g 2x or 0.5x. Defaults to 1x"))
+                .arg(Arg::with_name("no_delay")
+                        .long("no-delay")
+                        .help("Emit records without delay"))
+                .arg(Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .value_name("TIME")
+                        .validator(|s| index::since(&s).map(drop).map_err(|e| e.to_string()))
+                        .help("Skip records before [YYYY-]MM-DD HH:MM:SS[.mmm]. Seeks directly into captures written with --index"))
+                .arg(Arg::with_name("input")
+                        .required(true)
+                        .multiple(true)
+                        .help("Files to replay")))
+        .subcommand(SubCommand::with_name("dmesg")
+                .about("Capture kernel messages with \"adb shell dmesg\"")
+                .arg(Arg::with_name("dump")
+                        .short("d")
+                        .long("dump")
+                        .help("Dump the kernel log and then exit (don't block)"))
+                .arg(Arg::with_name("local")
+                        .long("local")
+                        .conflicts_with("dump")
+                        .help("Read the kernel messages of this host from /dev/kmsg")))
+        .subcommand(SubCommand::with_name("props")
+                .about("Dump the device properties reported by \"adb shell getprop\" as records")
+                .arg(Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .requires("watch")
+                        .help("Delay between polls of the properties e.g 500ms. Defaults to 1s"))
+                .arg(Arg::with_name("watch")
+                        .short("w")
+                        .long("watch")
+                        .help("Capture the device log and inject records of properties that change")))
+        .subcommand(SubCommand::with_name("run")
+                .about("Run a command e.g a instrumentation test and capture the device log while it runs. \
+                        The log file is named after the command, the start time and pass or fail by the exit code")
+                .arg(Arg::with_name("directory")
+                        .short("d")
+                        .long("directory")
+                        .takes_value(true)
+                        .help("Directory of the log file. Defaults to the current directory"))
+                .arg(Arg::with_name("command")
+                        .required(true)
+                        .multiple(true)
+                        .last(true)
+                        .help("Command and arguments passed after --")))
+        .subcommand(SubCommand::with_name("stats")
+                .about("Summarize records per level, tag, process, rate and the most frequent messages")
+                .arg(Arg::with_name("json")
+                        .long("json")
+                        .help("Print the summary as JSON"))
+                .arg(Arg::with_name("top")
+                        .long("top")
+                        .takes_value(true)
+                        .help("Number of tags, processes and messages listed. Defaults to 10"))
+                .arg(Arg::with_name("input")
+                        .multiple(true)
+                        .help("Files to summarize. Captures from adb logcat if omitted")))
+        .subcommand(SubCommand::with_name("analyze")
+                .about("Analyze records")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("latency")
+                        .about("Distribution of the time between pairs of records. Records are paired by the first capture group of both regexes if present")
+                        .arg(Arg::with_name("between")
+                                .long("between")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(2)
+                                .required(true)
+                                .value_names(&["START", "END"])
+                                .help("Tag and message regex of the start and end records e.g 'Net:send (\\d+)' 'Net:recv (\\d+)'"))
+                        .arg(Arg::with_name("json")
+                                .long("json")
+                                .help("Print the analysis as JSON"))
+                        .arg(Arg::with_name("input")
+                                .multiple(true)
+                                .help("Files to analyze. Captures from adb logcat if omitted"))))
+        .subcommand(SubCommand::with_name("serve")
+                .about("Serve the records to subscribers. Filters apply before records are sent")
+                .arg(Arg::with_name("grpc")
+                        .long("grpc")
+                        .takes_value(true)
+                        .required_unless("http")
+                        .value_name("ADDR")
+                        .help("Address of the gRPC endpoint e.g 0.0.0.0:9000. See proto/service.proto"))
+                .arg(Arg::with_name("http")
+                        .long("http")
+                        .takes_value(true)
+                        .value_name("ADDR")
+                        .help("Address or port of the web live view e.g 8080"))
+                .arg(Arg::with_name("input")
+                        .multiple(true)
+                        .help("Files to serve. Captures from adb logcat if omitted")))
         .subcommand(SubCommand::with_name("log")
                 .about("Add log message(s) log buffer")
                 .arg(Arg::with_name("tag")
@@ -209,11 +763,30 @@ pub fn cli() -> App<'static, 'static> {
                         .long("tag")
                         .takes_value(true)
                         .help("Log tag"))
+                .arg(Arg::with_name("marker")
+                        .short("m")
+                        .long("marker")
+                        .conflicts_with("tag")
+                        .help("Log as marker that rogcat renders as a horizontal line"))
                 .arg(Arg::with_name("level")
                         .short("l")
                         .long("level")
                         .takes_value(true)
                         .possible_values(&[ "trace", "debug", "info", "warn", "error", "fatal", "assert", "T", "D", "I", "W", "E", "F", "A" ],)
                         .help("Log on level"))
-                .arg_from_usage("[MESSAGE] 'Log message. Pass \"-\" to read from stdin'."))
+                .arg(Arg::with_name("rate")
+                        .short("r")
+                        .long("rate")
+                        .takes_value(true)
+                        .value_name("LINES")
+                        .help("Log at most this number of lines per second when reading from stdin"))
+                .arg_from_usage("[MESSAGE] 'Log message. Pass \"-\" to log each line read from stdin'."))
+        .subcommand(SubCommand::with_name("log-level")
+                .about("Set the minimum log level of a tag on the device with \"adb shell setprop log.tag.TAG LEVEL\". \
+                        Without arguments the log_levels of the selected profile are set")
+                .arg(Arg::with_name("TAG")
+                        .requires("LEVEL")
+                        .help("Tag to set the level of"))
+                .arg(Arg::with_name("LEVEL")
+                        .help("verbose, debug, info, warn, error, assert, suppress or default to clear the level")))
 }
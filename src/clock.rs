@@ -0,0 +1,237 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Clock skew of devices. The offset of a device clock is the difference
+//! between the host arrival time and the device timestamp of records.
+//! Transport delays only add to the difference, so the minimum within a
+//! window is the estimate. A warning is printed when the estimate exceeds
+//! `--clock-skew` and `--correct-clock` shifts the timestamps by the
+//! estimated offset. Until the first window is complete the estimate is the
+//! minimum so far and the corrections of the first records of a device may
+//! be off by the transport delay. Records read from files or replayed did
+//! not arrive live and cannot be corrected.
+
+use crate::{hosttime, utils};
+use clap::ArgMatches;
+use failure::{err_msg, Error};
+use rogcat::record::Record;
+use std::collections::HashMap;
+use time::{Timespec, Tm};
+
+/// Duration of the estimation windows in milliseconds
+const WINDOW: i64 = 10_000;
+
+/// Default warning threshold of `--correct-clock`
+const DEFAULT_THRESHOLD: i64 = 1000;
+
+const HALF_YEAR: i64 = 183 * 24 * 60 * 60 * 1000;
+
+/// Estimation of a single device
+#[derive(Debug, Default)]
+struct Skew {
+    /// Start and minimum offset of the current window
+    window: Option<(i64, i64)>,
+    /// Last estimate and the host time it was taken
+    estimate: Option<(i64, i64)>,
+    /// Drift in milliseconds per minute
+    drift: Option<f64>,
+    /// Estimate of the last warning
+    warned: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct Clock {
+    threshold: i64,
+    correct: bool,
+    /// Estimations by device serial
+    devices: HashMap<Option<String>, Skew>,
+}
+
+/// Check if the records are read from files of a subcommand like replay
+fn files(args: &ArgMatches) -> bool {
+    let subcommand = match args.subcommand() {
+        ("analyze", Some(analyze)) => analyze.subcommand().1,
+        (_, subcommand) => subcommand,
+    };
+    subcommand.map(|s| s.is_present("input")).unwrap_or(false)
+}
+
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Result<Option<Clock>, Error> {
+    if args.is_present("correct_clock") && files(args) {
+        return Err(err_msg(
+            "--correct-clock cannot be used with files that are not captured live",
+        ));
+    }
+    let threshold = match args.value_of("clock_skew") {
+        Some(threshold) => utils::interval(threshold)?.as_millis() as i64,
+        None if args.is_present("correct_clock") => DEFAULT_THRESHOLD,
+        None => return Ok(None),
+    };
+    Ok(Some(Clock {
+        threshold,
+        correct: args.is_present("correct_clock"),
+        devices: HashMap::new(),
+    }))
+}
+
+/// Milliseconds of the wall clock fields of tm
+fn millis(tm: &Tm) -> i64 {
+    let mut tm = *tm;
+    tm.tm_utcoff = 0;
+    let ts = tm.to_timespec();
+    ts.sec * 1000 + i64::from(ts.nsec) / 1_000_000
+}
+
+/// Offset of the device time in milliseconds. Timestamps without year are
+/// placed in the year closest to the host time
+fn offset(host: &Tm, device: &Tm) -> (i64, Tm) {
+    let mut device = *device;
+    device.tm_year = host.tm_year;
+    let offset = millis(host) - millis(&device);
+    if offset > HALF_YEAR {
+        device.tm_year += 1;
+    } else if offset < -HALF_YEAR {
+        device.tm_year -= 1;
+    } else {
+        return (offset, device);
+    }
+    (millis(host) - millis(&device), device)
+}
+
+impl Skew {
+    /// Account the offset of a record received at host time. Returns the
+    /// current estimate
+    fn update(&mut self, host: i64, offset: i64) -> i64 {
+        let (start, min) = match self.window {
+            Some((start, min)) => (start, min.min(offset)),
+            None => (host, offset),
+        };
+        if host - start < WINDOW {
+            self.window = Some((start, min));
+            return self.estimate.map(|(e, _)| e).unwrap_or(min);
+        }
+        if let Some((previous, at)) = self.estimate {
+            self.drift = Some((min - previous) as f64 * 60_000.0 / (host - at) as f64);
+        }
+        self.estimate = Some((min, host));
+        self.window = None;
+        min
+    }
+
+    /// Warning if the estimate exceeds threshold and changed since the last one
+    fn warning(&mut self, threshold: i64) -> Option<String> {
+        let (estimate, _) = self.estimate?;
+        let changed = self
+            .warned
+            .map(|w| (w - estimate).abs() > threshold)
+            .unwrap_or(true);
+        if estimate.abs() <= threshold || !changed {
+            return None;
+        }
+        self.warned = Some(estimate);
+        let direction = if estimate > 0 { "behind" } else { "ahead of" };
+        let mut warning = format!(
+            "clock is {:.3}s {} the host",
+            estimate.abs() as f64 / 1000.0,
+            direction
+        );
+        if let Some(drift) = self.drift {
+            warning.push_str(&format!(" and drifts {:+.1}ms/min", drift));
+        }
+        Some(warning)
+    }
+}
+
+impl Clock {
+    pub fn process(&mut self, record: &mut Record) {
+        self.process_at(record, time::now())
+    }
+
+    fn process_at(&mut self, record: &mut Record, now: Tm) {
+        let (offset, device) = match record.timestamp {
+            Some(ref ts) => offset(&now, ts),
+            None => return,
+        };
+        let skew = self.devices.entry(record.device.clone()).or_default();
+        let estimate = skew.update(millis(&now), offset);
+        if let Some(warning) = skew.warning(self.threshold) {
+            match record.device {
+                Some(ref device) => eprintln!("Device {} {}", device, warning),
+                None => eprintln!("Device {}", warning),
+            }
+        }
+        if self.correct {
+            let corrected = millis(&device) + estimate;
+            let ts = Timespec::new(
+                corrected.div_euclid(1000),
+                (corrected.rem_euclid(1000) * 1_000_000) as i32,
+            );
+            hosttime::retime(record, time::at_utc(ts));
+        }
+    }
+}
+
+#[test]
+fn skew() {
+    let tm = |s: &str| time::strptime(s, "%Y-%m-%d %H:%M:%S.%f").unwrap();
+    let device = |s: &str| time::strptime(s, "%m-%d %H:%M:%S.%f").unwrap();
+
+    // Records without year are placed next to the host time
+    let (o, d) = offset(
+        &tm("2024-01-01 00:00:01.000"),
+        &device("12-31 23:59:59.000"),
+    );
+    assert_eq!((o, d.tm_year), (2000, 123));
+    let (o, _) = offset(
+        &tm("2024-12-31 23:59:59.000"),
+        &device("01-01 00:00:01.000"),
+    );
+    assert_eq!(o, -2000);
+
+    let mut skew = Skew::default();
+    // The minimum of a window is the estimate
+    assert_eq!(skew.update(0, 5000), 5000);
+    assert_eq!(skew.update(1000, 4000), 4000);
+    assert_eq!(skew.update(WINDOW, 4500), 4000);
+    assert_eq!(skew.estimate, Some((4000, WINDOW)));
+    assert_eq!(skew.update(WINDOW + 1000, 3000), 4000);
+    assert!(skew.warning(5000).is_none());
+    assert_eq!(
+        skew.warning(1000).unwrap(),
+        "clock is 4.000s behind the host"
+    );
+    assert!(skew.warning(1000).is_none());
+    assert_eq!(skew.update(4 * WINDOW, -2000), -2000);
+    assert_eq!(
+        skew.warning(1000).unwrap(),
+        "clock is 2.000s ahead of the host and drifts -12000.0ms/min"
+    );
+
+    let mut clock = Clock {
+        threshold: 1000,
+        correct: true,
+        devices: HashMap::new(),
+    };
+    let mut record = rogcat::parser::Parser::default()
+        .parse_line("01-01 00:00:01.000  123  456 I Tag: A")
+        .unwrap();
+    clock.process_at(&mut record, tm("2024-01-01 00:00:03.500"));
+    assert_eq!(record.raw, "01-01 00:00:03.500  123  456 I Tag: A");
+}
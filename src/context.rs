@@ -0,0 +1,140 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Print records around matches like `grep -C`. Records before a match are
+//! kept in a ring buffer and records after a match pass as context. Context
+//! records are flagged and dimmed by the terminal.
+
+use crate::RecordStream;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{try_ready, Async, Poll, Stream};
+use rogcat::record::Record;
+use std::{collections::VecDeque, str::FromStr};
+
+struct Context<F> {
+    records: RecordStream,
+    matches: F,
+    before: usize,
+    after: usize,
+    /// Recent records that did not match
+    recent: VecDeque<Record>,
+    /// Records left to pass after the last match
+    left: usize,
+    pending: VecDeque<Record>,
+}
+
+/// Filter records with matches and add the records around matches if
+/// `--context`, `--before-context` or `--after-context` is passed
+pub fn from_args<'a, F>(
+    args: &ArgMatches<'a>,
+    records: RecordStream,
+    matches: F,
+) -> Result<RecordStream, Error>
+where
+    F: FnMut(&Record) -> bool + Send + 'static,
+{
+    let count = |name: &str| {
+        args.value_of(name)
+            .map(|v| usize::from_str(v).map_err(|e| format_err!("Invalid context {}: {}", v, e)))
+            .transpose()
+    };
+    let context = count("context")?;
+    let before = count("before_context")?.or(context);
+    let after = count("after_context")?.or(context);
+    if before.is_none() && after.is_none() {
+        return Ok(Box::new(records.filter(matches)));
+    }
+    Ok(Box::new(Context {
+        records,
+        matches,
+        before: before.unwrap_or(0),
+        after: after.unwrap_or(0),
+        recent: VecDeque::new(),
+        left: 0,
+        pending: VecDeque::new(),
+    }))
+}
+
+impl<F: FnMut(&Record) -> bool> Context<F> {
+    fn process(&mut self, mut record: Record) {
+        if (self.matches)(&record) {
+            self.pending.extend(self.recent.drain(..).map(|mut r| {
+                r.context = true;
+                r
+            }));
+            self.pending.push_back(record);
+            self.left = self.after;
+        } else if self.left > 0 {
+            self.left -= 1;
+            record.context = true;
+            self.pending.push_back(record);
+        } else if self.before > 0 {
+            if self.recent.len() == self.before {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(record);
+        }
+    }
+}
+
+impl<F: FnMut(&Record) -> bool> Stream for Context<F> {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return Ok(Async::Ready(Some(record)));
+            }
+            match try_ready!(self.records.poll()) {
+                Some(record) => self.process(record),
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+#[test]
+fn context() {
+    use futures::{stream::iter_ok, Future};
+
+    let records = (0..10)
+        .map(|i| Record {
+            message: i.to_string(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    let context = Context {
+        records: Box::new(iter_ok(records)),
+        matches: |r: &Record| r.message == "3" || r.message == "5",
+        before: 2,
+        after: 1,
+        recent: VecDeque::new(),
+        left: 0,
+        pending: VecDeque::new(),
+    };
+    let output = context
+        .map(|r| format!("{}{}", r.message, if r.context { "-" } else { "" }))
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(output, vec!["1-", "2-", "3", "4-", "5", "6-"]);
+}
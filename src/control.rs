@@ -0,0 +1,404 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Commands entered on the terminal while `rogcat` runs. Lines starting with
+//! a colon are commands, other lines are markers.
+//!
+//! ```text
+//! :hl REGEX                   add a highlight
+//! :filter -tag REGEX          add a tag filter. The prefix '!' inverts the match
+//! :filter -message REGEX      add a message filter
+//! :filter -regex REGEX        add a filter on tag, pid, thread and message
+//! :filter -level LEVEL        set a minimum level
+//! :filter EXPRESSION          add a filter expression e.g tag == Foo && level >= W
+//! :preset [NAME]              select a preset of the profile or none
+//! :snooze TAG=DURATION        drop records of a tag for a while e.g Chatty=30s
+//! :clear                      remove the highlights and filters added with commands
+//! ```
+//!
+//! The keys 1 to 9 typed at the start of a line toggle the filters and
+//! highlights passed as arguments or defined in the profile. Tab cycles
+//! through the presets of the profile.
+
+use crate::{
+    expression::Expression,
+    marker,
+    profiles::{Profile, Watch},
+    snooze::Snoozes,
+};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use regex::Regex;
+use rogcat::record::{Level, Record};
+use std::{
+    io::{stderr, stdin, BufRead, Read, Write},
+    mem::take,
+    thread,
+};
+
+/// Arguments of filters and highlights that can be toggled and their labels
+const TOGGLES: &[(&str, &str)] = &[
+    ("tag", "tag"),
+    ("tag-ignore-case", "Tag"),
+    ("message", "msg"),
+    ("message-ignore-case", "Msg"),
+    ("regex_filter", "regex"),
+    ("filter", "filter"),
+    ("highlight", "hl"),
+];
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Highlight(String),
+    Tag(String),
+    Message(String),
+    Regex(String),
+    Filter(String),
+    Preset(String),
+    Snooze(String),
+    Clear,
+}
+
+/// Parse and validate a command line without the leading colon
+fn parse(line: &str) -> Result<Command, Error> {
+    let line = line.trim();
+    let (command, argument) = match line.find(char::is_whitespace) {
+        Some(n) => (&line[..n], line[n..].trim()),
+        None => (line, ""),
+    };
+    let regex = |r: &str| -> Result<String, Error> {
+        Regex::new(r.trim_start_matches('!'))
+            .map(|_| r.to_owned())
+            .map_err(|e| format_err!("Invalid regex string: {}: {}", r, e))
+    };
+
+    match command {
+        "hl" | "highlight" if !argument.is_empty() => Ok(Command::Highlight(regex(argument)?)),
+        "filter" if !argument.is_empty() => {
+            let (option, value) = match argument.find(char::is_whitespace) {
+                Some(n) if argument.starts_with('-') => (&argument[..n], argument[n..].trim()),
+                _ => ("", argument),
+            };
+            match option.trim_start_matches('-') {
+                "t" | "tag" => Ok(Command::Tag(regex(value)?)),
+                "m" | "message" => Ok(Command::Message(regex(value)?)),
+                "r" | "regex" => Ok(Command::Regex(regex(value)?)),
+                "l" | "level" => match Level::from(value) {
+                    Level::None => Err(format_err!("Invalid level {}", value)),
+                    _ => Ok(Command::Filter(format!("level >= {}", value))),
+                },
+                "" => {
+                    value.parse::<Expression>()?;
+                    Ok(Command::Filter(value.to_owned()))
+                }
+                _ => Err(format_err!("Unknown filter option {}", option)),
+            }
+        }
+        "preset" => Ok(Command::Preset(argument.to_owned())),
+        "snooze" if !argument.is_empty() => Ok(Command::Snooze(argument.to_owned())),
+        "clear" => Ok(Command::Clear),
+        _ => Err(format_err!("Invalid command :{}", line)),
+    }
+}
+
+/// Filters and highlights of args and profile that can be toggled as
+/// `KIND:VALUE`. Limited to the number keys
+fn toggles<'a>(args: &ArgMatches<'a>, profile: &Profile) -> Vec<String> {
+    let mut toggles = vec![];
+    for (kind, _) in TOGGLES {
+        let from_profile = match *kind {
+            "tag" => &profile.tag,
+            "tag-ignore-case" => &profile.tag_ignore_case,
+            "message" => &profile.message,
+            "message-ignore-case" => &profile.message_ignore_case,
+            "regex_filter" => &profile.regex,
+            "filter" => &profile.filter,
+            _ => &profile.highlight,
+        };
+        let values = args
+            .values_of(kind)
+            .map(Iterator::collect::<Vec<_>>)
+            .unwrap_or_default()
+            .into_iter()
+            .chain(from_profile.iter().map(String::as_str));
+        for value in values {
+            let toggle = format!("{}:{}", kind, value);
+            if !toggles.contains(&toggle) {
+                toggles.push(toggle);
+            }
+        }
+    }
+    toggles.truncate(9);
+    toggles
+}
+
+/// One line overview of the toggles and their state
+fn legend(toggles: &[String], disabled: &[String]) -> String {
+    toggles
+        .iter()
+        .enumerate()
+        .map(|(n, toggle)| {
+            let (kind, value) = toggle.split_at(toggle.find(':').unwrap_or(0));
+            let label = TOGGLES
+                .iter()
+                .find(|(k, _)| *k == kind)
+                .map(|(_, l)| *l)
+                .unwrap_or(kind);
+            let state = if disabled.contains(toggle) { ' ' } else { 'x' };
+            format!("{} [{}] {} {}", n + 1, state, label, &value[1..])
+        })
+        .collect::<Vec<_>>()
+        .join("  ")
+}
+
+/// Apply a command or send a marker. Returns false if markers are no longer
+/// received
+fn process(
+    line: &str,
+    presets: &[String],
+    snoozes: &Snoozes,
+    watch: &Watch,
+    tx: &UnboundedSender<Record>,
+) -> bool {
+    if let Some(command) = line.trim_start().strip_prefix(':') {
+        match parse(command) {
+            Ok(Command::Preset(ref p)) if !p.is_empty() && !presets.contains(p) => {
+                eprintln!("Unknown preset {}", p)
+            }
+            Ok(Command::Snooze(s)) => {
+                if let Err(e) = snoozes.add(&s) {
+                    eprintln!("{}", e);
+                }
+            }
+            Ok(command) => watch.update(|profile| match command {
+                Command::Highlight(r) => profile.highlight.push(r),
+                Command::Tag(r) => profile.tag.push(r),
+                Command::Message(r) => profile.message.push(r),
+                Command::Regex(r) => profile.regex.push(r),
+                Command::Filter(e) => profile.filter.push(e),
+                Command::Preset(p) => profile.preset = Some(p),
+                Command::Snooze(_) => (),
+                // Toggles and the preset are kept
+                Command::Clear => {
+                    *profile = Profile {
+                        disabled: take(&mut profile.disabled),
+                        preset: take(&mut profile.preset),
+                        ..Default::default()
+                    }
+                }
+            }),
+            Err(e) => eprintln!("{}", e),
+        }
+        true
+    } else {
+        tx.unbounded_send(marker::record(line.trim())).is_ok()
+    }
+}
+
+/// Switch the terminal to read single keys without echo. The previous mode
+/// is restored at exit
+#[cfg(unix)]
+fn read_keys() -> bool {
+    use lazy_static::lazy_static;
+    use std::sync::Mutex;
+
+    lazy_static! {
+        static ref ORIGINAL: Mutex<Option<libc::termios>> = Mutex::new(None);
+    }
+
+    extern "C" fn restore() {
+        if let Some(termios) = ORIGINAL.lock().ok().and_then(|o| *o) {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) };
+        }
+    }
+
+    unsafe {
+        let mut termios = std::mem::zeroed::<libc::termios>();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+            return false;
+        }
+        *ORIGINAL.lock().expect("Failed to lock termios") = Some(termios);
+        libc::atexit(restore);
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) == 0
+    }
+}
+
+#[cfg(not(unix))]
+fn read_keys() -> bool {
+    false
+}
+
+/// Read keys and echo them. Number keys at the start of a line toggle,
+/// tab selects the next preset and other lines are processed on return
+fn keys(
+    toggles: &[String],
+    presets: &[String],
+    mut preset: Option<usize>,
+    snoozes: &Snoozes,
+    watch: &Watch,
+    tx: &UnboundedSender<Record>,
+) {
+    let mut disabled: Vec<String> = vec![];
+    let mut line: Vec<u8> = vec![];
+    let mut echo = stderr();
+    eprintln!("{}", legend(toggles, &disabled));
+
+    for byte in stdin().lock().bytes() {
+        let byte = match byte {
+            Ok(byte) => byte,
+            Err(_) => break,
+        };
+        match byte {
+            b'\n' | b'\r' => {
+                echo.write_all(b"\n").ok();
+                if !process(&String::from_utf8_lossy(&line), presets, snoozes, watch, tx) {
+                    break;
+                }
+                line.clear();
+            }
+            // Backspace removes the last character
+            0x7f | 0x08 => {
+                while let Some(b) = line.pop() {
+                    if b & 0xC0 != 0x80 {
+                        echo.write_all(b"\x08 \x08").ok();
+                        break;
+                    }
+                }
+            }
+            b'1'..=b'9' if line.is_empty() && usize::from(byte - b'0') <= toggles.len() => {
+                let toggle = &toggles[usize::from(byte - b'1')];
+                match disabled.iter().position(|d| d == toggle) {
+                    Some(n) => {
+                        disabled.remove(n);
+                    }
+                    None => disabled.push(toggle.clone()),
+                }
+                watch.update(|profile| profile.disabled = disabled.clone());
+                eprintln!("{}", legend(toggles, &disabled));
+            }
+            b'\t' if line.is_empty() && !presets.is_empty() => {
+                preset = match preset {
+                    Some(n) if n + 1 < presets.len() => Some(n + 1),
+                    Some(_) => None,
+                    None => Some(0),
+                };
+                let name = preset.map(|n| presets[n].clone()).unwrap_or_default();
+                eprintln!("preset {}", if name.is_empty() { "none" } else { &name });
+                watch.update(|profile| profile.preset = Some(name));
+            }
+            byte => {
+                line.push(byte);
+                echo.write_all(&[byte]).ok();
+            }
+        }
+        echo.flush().ok();
+    }
+}
+
+/// Read stdin and apply commands to watch. Markers are sent to the returned
+/// receiver.
+pub fn spawn<'a>(
+    args: &ArgMatches<'a>,
+    profile: &Profile,
+    watch: Watch,
+    snoozes: Snoozes,
+) -> UnboundedReceiver<Record> {
+    let (tx, rx) = unbounded();
+    let toggles = toggles(args, profile);
+    let presets = profile.presets.keys().cloned().collect::<Vec<_>>();
+    let preset = profile
+        .preset
+        .as_ref()
+        .and_then(|p| presets.iter().position(|n| n == p));
+    thread::spawn(move || {
+        if !(toggles.is_empty() && presets.is_empty()) && read_keys() {
+            keys(&toggles, &presets, preset, &snoozes, &watch, &tx);
+            return;
+        }
+        let stdin = stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if !process(&line, &presets, &snoozes, &watch, &tx) {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+#[test]
+fn parse_commands() {
+    assert_eq!(parse("hl foo").unwrap(), Command::Highlight("foo".into()));
+    assert_eq!(
+        parse("filter -tag Spam").unwrap(),
+        Command::Tag("Spam".into())
+    );
+    assert_eq!(
+        parse("filter --tag !Spam").unwrap(),
+        Command::Tag("!Spam".into())
+    );
+    assert_eq!(
+        parse("filter -m a b").unwrap(),
+        Command::Message("a b".into())
+    );
+    assert_eq!(
+        parse("filter -level W").unwrap(),
+        Command::Filter("level >= W".into())
+    );
+    assert_eq!(
+        parse("filter tag == Foo").unwrap(),
+        Command::Filter("tag == Foo".into())
+    );
+    assert_eq!(
+        parse("preset network").unwrap(),
+        Command::Preset("network".into())
+    );
+    assert_eq!(parse("preset").unwrap(), Command::Preset("".into()));
+    assert_eq!(parse(" clear ").unwrap(), Command::Clear);
+    assert!(parse("hl").is_err());
+    assert!(parse("hl (").is_err());
+    assert!(parse("filter -level X").is_err());
+    assert!(parse("filter -foo bar").is_err());
+    assert!(parse("filter tag =").is_err());
+    assert!(parse("nope").is_err());
+}
+
+#[test]
+fn toggle_legend() {
+    let toggles = vec!["tag:Foo".to_owned(), "highlight:a:b".to_owned()];
+    assert_eq!(legend(&toggles, &[]), "1 [x] tag Foo  2 [x] hl a:b");
+    let disabled = vec!["highlight:a:b".to_owned()];
+    assert_eq!(legend(&toggles, &disabled), "1 [x] tag Foo  2 [ ] hl a:b");
+
+    let profile = Profile {
+        disabled,
+        ..Default::default()
+    };
+    assert!(profile.is_disabled("highlight", "a:b"));
+    assert!(!profile.is_disabled("highlight", "a"));
+    assert!(!profile.is_disabled("tag", "Foo"));
+}
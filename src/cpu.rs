@@ -0,0 +1,141 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Cpu usage of processes sampled with `adb shell top`. Records carry the
+//! most recent usage of their process with `--with-cpu`.
+
+use crate::utils::{device_cmd, interval};
+use clap::ArgMatches;
+use failure::Error;
+use rogcat::record::Record;
+use std::{
+    collections::HashMap,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Delay between samples if `--with-cpu` is passed without interval
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// Cpu usage in percent by pid. The samples are replaced by a background
+/// thread running `adb shell top`.
+pub struct CpuUsage {
+    usage: Arc<Mutex<HashMap<String, f64>>>,
+}
+
+/// Parse the output of top. Toybox top has a `%CPU` column that is merged
+/// with the state in the header e.g `S[%CPU]`. The top of toolbox prints
+/// `CPU%` with values like `12%`.
+fn parse_top(output: &str) -> HashMap<String, f64> {
+    let mut lines = output.lines().skip_while(|l| !l.contains("PID"));
+    let header = match lines.next() {
+        Some(header) => header.split_whitespace().collect::<Vec<_>>(),
+        None => return HashMap::new(),
+    };
+    let pid = header.iter().position(|c| *c == "PID");
+    let cpu = header
+        .iter()
+        .position(|c| c.contains("%CPU") || c.contains("CPU%"))
+        .map(|n| {
+            if header[n].starts_with("S[") {
+                n + 1
+            } else {
+                n
+            }
+        });
+    let (pid, cpu) = match (pid, cpu) {
+        (Some(pid), Some(cpu)) => (pid, cpu),
+        _ => return HashMap::new(),
+    };
+    lines
+        .filter_map(|line| {
+            let columns = line.split_whitespace().collect::<Vec<_>>();
+            let usage = columns.get(cpu)?.trim_end_matches('%').parse().ok()?;
+            Some((columns.get(pid)?.to_string(), usage))
+        })
+        .collect()
+}
+
+/// Sample the cpu usage of all processes. Empty if the device is not reachable
+fn sample(adb: &[String]) -> HashMap<String, f64> {
+    Command::new(&adb[0])
+        .args(&adb[1..])
+        .args(["shell", "top", "-b", "-n", "1"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map(|output| parse_top(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or_default()
+}
+
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Result<Option<CpuUsage>, Error> {
+    if !args.is_present("with_cpu") {
+        return Ok(None);
+    }
+    let delay = match args.value_of("with_cpu") {
+        Some(i) => interval(i)?,
+        None => INTERVAL,
+    };
+    let adb = device_cmd(args)?;
+    let usage = Arc::new(Mutex::new(sample(&adb)));
+    let usage_bg = usage.clone();
+    thread::spawn(move || loop {
+        thread::sleep(delay);
+        let sample = sample(&adb);
+        // Keep the last sample while the device is not reachable
+        if !sample.is_empty() {
+            *usage_bg.lock().expect("Failed to lock cpu usage") = sample;
+        }
+    });
+    Ok(Some(CpuUsage { usage }))
+}
+
+impl CpuUsage {
+    /// Set the cpu usage of record if its pid was sampled
+    pub fn process(&self, record: &mut Record) {
+        if record.process.is_empty() || record.device.is_some() {
+            return;
+        }
+        let usage = self.usage.lock().expect("Failed to lock cpu usage");
+        record.cpu = usage.get(&record.process).cloned();
+    }
+}
+
+#[test]
+fn parse_top_output() {
+    let toybox = "Tasks: 700 total,   1 running, 699 sleeping\n\
+                  Mem:  5.5G total,  5.2G used\n\
+                  \n  \
+                  PID USER         PR  NI VIRT  RES  SHR S[%CPU] %MEM     TIME+ ARGS\n \
+                  1234 u0_a42       10 -10 4.5G 200M 100M S 12.3   2.7   1:23.45 com.example.app\n  \
+                  567 system       18  -2 4.8G 300M 200M S  0.0   5.1  10:01.00 system_server\n";
+    let usage = parse_top(toybox);
+    assert_eq!(usage.len(), 2);
+    assert_eq!(usage["1234"], 12.3);
+    assert_eq!(usage["567"], 0.0);
+
+    let toolbox = "User 5%, System 3%\n\n  \
+                   PID PR CPU% S  #THR     VSS     RSS PCY UID      Name\n \
+                   1234  0  12% S    30 900000K  80000K  fg u0_a42   com.example.app\n";
+    assert_eq!(parse_top(toolbox)["1234"], 12.0);
+    assert!(parse_top("/system/bin/sh: top: not found").is_empty());
+}
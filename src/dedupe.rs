@@ -0,0 +1,163 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Collapse runs of consecutive records with the same tag and message
+//! into the first record followed by a summary annotated with the number
+//! of repeats.
+
+use crate::RecordStream;
+use clap::ArgMatches;
+use failure::Error;
+use futures::{try_ready, Async, Poll, Stream};
+use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::record::Record;
+use std::borrow::Cow;
+
+lazy_static! {
+    /// Decimal and hex numbers that are ignored by the fuzzy comparison
+    static ref NUMBER: Regex = Regex::new(r"0[xX][[:xdigit:]]+|\d+").unwrap();
+}
+
+/// Replace the numbers in message with a placeholder
+pub fn fuzzy(message: &str) -> Cow<'_, str> {
+    NUMBER.replace_all(message, "#")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    /// Tag and message must be equal
+    Exact,
+    /// Numbers in the message are ignored
+    Fuzzy,
+}
+
+impl Mode {
+    fn key(self, record: &Record) -> (String, String) {
+        let message = match self {
+            Mode::Exact => record.message.clone(),
+            Mode::Fuzzy => fuzzy(&record.message).into_owned(),
+        };
+        (record.tag.clone(), message)
+    }
+}
+
+struct Dedupe {
+    records: RecordStream,
+    mode: Mode,
+    /// Key and most recent record of the current run
+    last: Option<((String, String), Record)>,
+    repeats: usize,
+    /// Record that ended a run and is emitted after the summary
+    pending: Option<Record>,
+    done: bool,
+}
+
+/// Wrap records into a deduplicating stream if requested
+pub fn from_args<'a>(args: &ArgMatches<'a>, records: RecordStream) -> RecordStream {
+    let mode = match args.value_of("dedupe") {
+        Some("fuzzy") => Mode::Fuzzy,
+        Some(_) => Mode::Exact,
+        None if args.is_present("dedupe") => Mode::Exact,
+        None => return records,
+    };
+    Box::new(Dedupe {
+        records,
+        mode,
+        last: None,
+        repeats: 0,
+        pending: None,
+        done: false,
+    })
+}
+
+impl Dedupe {
+    /// Summary of the current run if it contains repeats
+    fn summary(&mut self) -> Option<Record> {
+        if self.repeats == 0 {
+            return None;
+        }
+        let repeats = self.repeats;
+        self.repeats = 0;
+        self.last.as_ref().map(|(_, record)| {
+            let mut summary = record.clone();
+            summary.message = format!("{} ×{} repeats", record.message, repeats);
+            summary.raw = format!("{} ×{} repeats", record.raw, repeats);
+            summary
+        })
+    }
+}
+
+impl Stream for Dedupe {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        loop {
+            if let Some(record) = self.pending.take() {
+                return Ok(Async::Ready(Some(record)));
+            }
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            match try_ready!(self.records.poll()) {
+                Some(record) => {
+                    let key = self.mode.key(&record);
+                    if self.last.as_ref().map(|(k, _)| k == &key).unwrap_or(false) {
+                        self.repeats += 1;
+                        self.last = Some((key, record));
+                        continue;
+                    }
+
+                    let summary = self.summary();
+                    self.last = Some((key, record.clone()));
+                    return Ok(Async::Ready(match summary {
+                        Some(summary) => {
+                            self.pending = Some(record);
+                            Some(summary)
+                        }
+                        None => Some(record),
+                    }));
+                }
+                None => {
+                    self.done = true;
+                    if let Some(summary) = self.summary() {
+                        return Ok(Async::Ready(Some(summary)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn fuzzy_key() {
+    let record = |message: &str| Record {
+        tag: "Tag".into(),
+        message: message.into(),
+        ..Default::default()
+    };
+    let a = record("battery 42% at 0x7f3a");
+    let b = record("battery 7% at 0xbeef");
+    assert_eq!(Mode::Fuzzy.key(&a), Mode::Fuzzy.key(&b));
+    assert_ne!(Mode::Exact.key(&a), Mode::Exact.key(&b));
+    assert_ne!(Mode::Fuzzy.key(&a), Mode::Fuzzy.key(&record("battery low")));
+}
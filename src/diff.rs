@@ -0,0 +1,254 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Compare two captures. Records are aligned by level, tag and message.
+//! Timestamps, pids and tids are ignored.
+
+use crate::dedupe::fuzzy;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use rogcat::{parser::Parser, record::Record};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    process::exit,
+};
+
+/// Records of a capture reduced to the compared content
+struct Capture {
+    name: String,
+    keys: Vec<String>,
+}
+
+impl Capture {
+    fn read(filename: &str, numbers: bool) -> Result<Capture, Error> {
+        let file =
+            File::open(filename).map_err(|e| format_err!("Failed to open {}: {}", filename, e))?;
+        let mut parser = Parser::default();
+        let mut keys = vec![];
+        let mut push = |record: Record| {
+            let message = if numbers {
+                record.message.into()
+            } else {
+                fuzzy(&record.message)
+            };
+            keys.push(format!("{} {}: {}", record.level, record.tag, message));
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| format_err!("Failed to read {}: {}", filename, e))?;
+            if let Some(record) = parser.parse_line(&line) {
+                push(record);
+            }
+        }
+        if let Some(record) = parser.flush() {
+            push(record);
+        }
+        Ok(Capture {
+            name: filename.to_owned(),
+            keys,
+        })
+    }
+
+    fn counts(&self) -> HashMap<&str, usize> {
+        let mut counts = HashMap::new();
+        for key in &self.keys {
+            *counts.entry(key.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Index of the first occurrence of each key
+    fn first(&self) -> HashMap<&str, usize> {
+        let mut first = HashMap::new();
+        for (n, key) in self.keys.iter().enumerate() {
+            first.entry(key.as_str()).or_insert(n);
+        }
+        first
+    }
+}
+
+/// Differences of two captures
+#[derive(Debug, Default, PartialEq)]
+struct Diff {
+    /// Keys with surplus occurrences in a
+    only_a: Vec<(String, usize)>,
+    /// Keys with surplus occurrences in b
+    only_b: Vec<(String, usize)>,
+    /// Keys present in both whose first occurrences are in a different order
+    /// with the record numbers in a and b
+    moved: Vec<(String, usize, usize)>,
+}
+
+impl Diff {
+    fn is_empty(&self) -> bool {
+        self.only_a.is_empty() && self.only_b.is_empty() && self.moved.is_empty()
+    }
+}
+
+/// Keys of a that occur more often than in b in the order of a
+fn surplus(a: &Capture, b: &Capture) -> Vec<(String, usize)> {
+    let counts_a = a.counts();
+    let counts_b = b.counts();
+    let mut seen = HashMap::new();
+    a.keys
+        .iter()
+        .filter_map(|key| {
+            if seen.insert(key.as_str(), ()).is_some() {
+                return None;
+            }
+            let n = counts_a[key.as_str()];
+            let m = counts_b.get(key.as_str()).cloned().unwrap_or(0);
+            if n > m {
+                Some((key.clone(), n - m))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Indices of a longest increasing subsequence of values
+fn longest_increasing(values: &[usize]) -> Vec<usize> {
+    // Index of the smallest tail of all subsequences of length i + 1
+    let mut tails: Vec<usize> = vec![];
+    let mut previous = vec![None; values.len()];
+    for (i, value) in values.iter().enumerate() {
+        let n = tails.partition_point(|t| values[*t] < *value);
+        if n > 0 {
+            previous[i] = Some(tails[n - 1]);
+        }
+        if n == tails.len() {
+            tails.push(i);
+        } else {
+            tails[n] = i;
+        }
+    }
+    let mut result = vec![];
+    let mut next = tails.last().cloned();
+    while let Some(i) = next {
+        result.push(i);
+        next = previous[i];
+    }
+    result.reverse();
+    result
+}
+
+fn diff(a: &Capture, b: &Capture) -> Diff {
+    let first_b = b.first();
+    // Common keys in the order of a with their position in b
+    let mut common = a
+        .first()
+        .into_iter()
+        .filter_map(|(key, n)| first_b.get(key).map(|m| (key, n, *m)))
+        .collect::<Vec<_>>();
+    common.sort_by_key(|(_, n, _)| *n);
+
+    // Keys outside of the longest common order moved
+    let positions = common.iter().map(|(_, _, m)| *m).collect::<Vec<_>>();
+    let mut in_order = vec![false; common.len()];
+    for i in longest_increasing(&positions) {
+        in_order[i] = true;
+    }
+    let moved = common
+        .iter()
+        .zip(in_order)
+        .filter(|(_, in_order)| !in_order)
+        .map(|((key, n, m), _)| ((*key).to_owned(), n + 1, m + 1))
+        .collect();
+
+    Diff {
+        only_a: surplus(a, b),
+        only_b: surplus(b, a),
+        moved,
+    }
+}
+
+fn print(a: &Capture, b: &Capture, diff: &Diff) {
+    let count = |n: usize| {
+        if n > 1 {
+            format!(" (×{})", n)
+        } else {
+            String::new()
+        }
+    };
+    if !diff.only_a.is_empty() {
+        println!("Only in {} ({}):", a.name, diff.only_a.len());
+        for (key, n) in &diff.only_a {
+            println!("- {}{}", key, count(*n));
+        }
+    }
+    if !diff.only_b.is_empty() {
+        println!("Only in {} ({}):", b.name, diff.only_b.len());
+        for (key, n) in &diff.only_b {
+            println!("+ {}{}", key, count(*n));
+        }
+    }
+    if !diff.moved.is_empty() {
+        println!("Different order ({}):", diff.moved.len());
+        for (key, n, m) in &diff.moved {
+            println!("~ {} (#{} → #{})", key, n, m);
+        }
+    }
+}
+
+/// Compare the captures and exit with 0 if they match, 1 if they differ
+/// and 2 on errors like diff does
+pub fn run(args: &ArgMatches) {
+    let numbers = !args.is_present("ignore_numbers");
+    let captures = args
+        .value_of("a")
+        .into_iter()
+        .chain(args.value_of("b"))
+        .map(|f| Capture::read(f, numbers))
+        .collect::<Result<Vec<_>, Error>>();
+    match captures {
+        Ok(captures) => {
+            let (a, b) = (&captures[0], &captures[1]);
+            let diff = diff(a, b);
+            print(a, b, &diff);
+            exit(if diff.is_empty() { 0 } else { 1 });
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(2);
+        }
+    }
+}
+
+#[test]
+fn diff_captures() {
+    let capture = |keys: &[&str]| Capture {
+        name: String::new(),
+        keys: keys.iter().map(|k| (*k).to_owned()).collect(),
+    };
+    let a = capture(&["start", "a", "b", "c", "d", "d", "only a"]);
+    let b = capture(&["start", "c", "a", "b", "d", "only b", "only b"]);
+    assert_eq!(
+        diff(&a, &b),
+        Diff {
+            only_a: vec![("d".into(), 1), ("only a".into(), 1)],
+            only_b: vec![("only b".into(), 2)],
+            moved: vec![("c".into(), 4, 2)],
+        }
+    );
+    assert!(diff(&a, &a).is_empty());
+    assert_eq!(longest_increasing(&[3, 1, 2, 0, 4]), vec![1, 2, 4]);
+}
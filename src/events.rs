@@ -0,0 +1,210 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Binary event payloads and the event tag database found on devices in
+//! `/system/etc/event-log-tags`. The database maps tag numbers to names
+//! and describes the fields of the payload.
+
+use bytes::{Buf, IntoBuf};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{collections::HashMap, fmt};
+
+/// Location of the tag database on the device
+pub const EVENT_LOG_TAGS: &str = "/system/etc/event-log-tags";
+
+lazy_static! {
+    /// Field description like `(name|1|5)`. The unit is optional
+    static ref FIELD: Regex = Regex::new(r"\(([^|()]*)\|(\d+)(?:\|([0-9a-z]))?\)").unwrap();
+}
+
+/// Payload of a binary event
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Long(i64),
+    String(String),
+    List(Vec<Value>),
+    Float(f32),
+}
+
+/// Textual representation of logcat. Lists are enclosed in brackets and
+/// their elements separated by commas.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Long(v) => write!(f, "{}", v),
+            Value::String(v) => write!(f, "{}", v),
+            Value::List(elements) => {
+                let elements = elements.iter().map(Value::to_string).collect::<Vec<_>>();
+                write!(f, "[{}]", elements.join(","))
+            }
+            Value::Float(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl Value {
+    /// Decode a typed binary event payload and advance data
+    pub fn decode(data: &mut &[u8]) -> Option<Value> {
+        fn take<'a>(data: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+            if data.len() < n {
+                None
+            } else {
+                let (head, tail) = data.split_at(n);
+                *data = tail;
+                Some(head)
+            }
+        }
+
+        let value = match take(data, 1)?[0] {
+            0 => Value::Int(take(data, 4)?.into_buf().get_i32_le()),
+            1 => Value::Long(take(data, 8)?.into_buf().get_i64_le()),
+            2 => {
+                let len = take(data, 4)?.into_buf().get_u32_le() as usize;
+                Value::String(String::from_utf8_lossy(take(data, len)?).into_owned())
+            }
+            3 => {
+                let count = take(data, 1)?[0];
+                Value::List(
+                    (0..count)
+                        .map(|_| Value::decode(data))
+                        .collect::<Option<Vec<Value>>>()?,
+                )
+            }
+            4 => Value::Float(take(data, 4)?.into_buf().get_f32_le()),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    /// Suffix for units like milliseconds or percent
+    unit: &'static str,
+}
+
+#[derive(Debug)]
+struct EventTag {
+    name: String,
+    fields: Vec<Field>,
+}
+
+/// Event tag names and field descriptions by tag number
+#[derive(Debug, Default)]
+pub struct EventTags {
+    tags: HashMap<u32, EventTag>,
+}
+
+impl EventTags {
+    /// Parse the content of a `event-log-tags` file. Invalid lines are skipped.
+    pub fn parse(content: &str) -> EventTags {
+        let tags = content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|line| {
+                let mut split = line.splitn(3, char::is_whitespace);
+                let number = split.next()?.parse::<u32>().ok()?;
+                let name = split.next()?.to_owned();
+                let fields = FIELD
+                    .captures_iter(split.next().unwrap_or_default())
+                    .map(|c| Field {
+                        name: c[1].trim().to_owned(),
+                        unit: match c.get(3).map(|u| u.as_str()) {
+                            Some("2") => "B",
+                            Some("3") => "ms",
+                            Some("6") => "%",
+                            Some("s") => "s",
+                            _ => "",
+                        },
+                    })
+                    .collect();
+                Some((number, EventTag { name, fields }))
+            })
+            .collect();
+        EventTags { tags }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Resolve the tag name and describe value with the field names e.g
+    /// `[level=100,temperature=250]`. None if the tag is unknown
+    pub fn describe(&self, tag: u32, value: &Value) -> Option<(String, String)> {
+        let tag = self.tags.get(&tag)?;
+        let field = |field: Option<&Field>, value: &Value| match field {
+            Some(f) => format!("{}={}{}", f.name, value, f.unit),
+            None => value.to_string(),
+        };
+        let message = match value {
+            Value::List(elements) if elements.len() == tag.fields.len() => {
+                let elements = elements
+                    .iter()
+                    .zip(&tag.fields)
+                    .map(|(v, f)| field(Some(f), v))
+                    .collect::<Vec<_>>();
+                format!("[{}]", elements.join(","))
+            }
+            Value::List(_) => value.to_string(),
+            value => field(tag.fields.first(), value),
+        };
+        Some((tag.name.clone(), message))
+    }
+}
+
+#[test]
+fn parse_event_tags() {
+    let tags = EventTags::parse(
+        "# comment\n\
+         2722 battery_level (level|1|6),(voltage|1|1),(temperature|1|1)\n\
+         30001 am_finish_activity (User|1|5),(Component Name|3)\n\
+         42 answer (to life the universe etc|3)\n\
+         314 pi\n\
+         invalid line\n",
+    );
+    assert_eq!(tags.tags.len(), 4);
+    let battery = Value::List(vec![Value::Int(90), Value::Int(4000), Value::Int(250)]);
+    assert_eq!(
+        tags.describe(2722, &battery),
+        Some((
+            "battery_level".into(),
+            "[level=90%,voltage=4000,temperature=250]".into()
+        ))
+    );
+    let activity = Value::List(vec![Value::Int(0), Value::String("a/.B".into())]);
+    assert_eq!(
+        tags.describe(30001, &activity).unwrap().1,
+        "[User=0,Component Name=a/.B]"
+    );
+    assert_eq!(
+        tags.describe(42, &Value::String("42".into())).unwrap().1,
+        "to life the universe etc=42"
+    );
+    assert_eq!(
+        tags.describe(314, &Value::Long(3)),
+        Some(("pi".into(), "3".into()))
+    );
+    assert_eq!(tags.describe(1, &Value::Long(3)), None);
+}
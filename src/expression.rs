@@ -0,0 +1,378 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Filter expressions like
+//! `(tag == "ActivityManager" && level >= W) || message ~ "ANR"`
+//!
+//! Grammar:
+//!
+//! ```text
+//! expression := and ("||" and)*
+//! and        := unary ("&&" unary)*
+//! unary      := "!" unary | "(" expression ")" | comparison
+//! comparison := field operator value
+//! field      := tag | message | level | process | thread | device | buffer | raw
+//! operator   := "==" | "!=" | "~" | "!~" | "<" | "<=" | ">" | ">="
+//! value      := quoted string | word
+//! ```
+
+use failure::{format_err, Error};
+use regex::Regex;
+use rogcat::record::{Level, Record};
+use std::{iter::Peekable, str::Chars, str::FromStr};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Buffer,
+    Device,
+    Level,
+    Message,
+    Name,
+    Process,
+    Raw,
+    Tag,
+    Thread,
+}
+
+impl FromStr for Field {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buffer" => Ok(Field::Buffer),
+            "device" => Ok(Field::Device),
+            "level" => Ok(Field::Level),
+            "message" | "msg" => Ok(Field::Message),
+            "name" | "process_name" => Ok(Field::Name),
+            "process" | "pid" => Ok(Field::Process),
+            "raw" => Ok(Field::Raw),
+            "tag" => Ok(Field::Tag),
+            "thread" | "tid" => Ok(Field::Thread),
+            _ => Err(format_err!("Unknown field {}", s)),
+        }
+    }
+}
+
+impl Field {
+    fn value(self, record: &Record) -> &str {
+        match self {
+            Field::Buffer => record.buffer.as_deref().unwrap_or(""),
+            Field::Device => record.device.as_deref().unwrap_or(""),
+            Field::Level => "",
+            Field::Message => &record.message,
+            Field::Name => record.process_name.as_deref().unwrap_or(""),
+            Field::Process => &record.process,
+            Field::Raw => &record.raw,
+            Field::Tag => &record.tag,
+            Field::Thread => &record.thread,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    And,
+    Close,
+    Eq,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+    Match,
+    Ne,
+    Not,
+    NotMatch,
+    Open,
+    Or,
+    Word(String),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, Error> {
+    fn next_is(chars: &mut Peekable<Chars>, c: char) -> bool {
+        if chars.peek() == Some(&c) {
+            chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '~' => Token::Match,
+            '&' if next_is(&mut chars, '&') => Token::And,
+            '|' if next_is(&mut chars, '|') => Token::Or,
+            '=' if next_is(&mut chars, '=') => Token::Eq,
+            '!' if next_is(&mut chars, '=') => Token::Ne,
+            '!' if next_is(&mut chars, '~') => Token::NotMatch,
+            '!' => Token::Not,
+            '<' if next_is(&mut chars, '=') => Token::Le,
+            '<' => Token::Lt,
+            '>' if next_is(&mut chars, '=') => Token::Ge,
+            '>' => Token::Gt,
+            '"' | '\'' => {
+                let quote = c;
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == quote || c == '\\' => word.push(c),
+                            // Keep escapes that are meaningful in regexes
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(format_err!("Unterminated string in {}", s)),
+                        },
+                        Some(c) if c == quote => break,
+                        Some(c) => word.push(c),
+                        None => return Err(format_err!("Unterminated string in {}", s)),
+                    }
+                }
+                Token::Word(word)
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' {
+                        word.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Word(word)
+            }
+            c => return Err(format_err!("Unexpected character '{}' in {}", c, s)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// A parsed filter expression
+#[derive(Debug)]
+pub struct Expression(Node);
+
+#[derive(Debug)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Equal(Field, String),
+    Match(Field, Regex),
+    Level(Token, Level),
+}
+
+impl FromStr for Expression {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+        let node = parser
+            .expression()
+            .map_err(|e| format_err!("Invalid filter expression \"{}\": {}", s, e))?;
+        if parser.position != tokens.len() {
+            return Err(format_err!(
+                "Invalid filter expression \"{}\": Unexpected {:?}",
+                s,
+                tokens[parser.position]
+            ));
+        }
+        Ok(Expression(node))
+    }
+}
+
+impl Expression {
+    /// Evaluate expression for record
+    pub fn matches(&self, record: &Record) -> bool {
+        self.0.matches(record)
+    }
+}
+
+impl Node {
+    fn matches(&self, record: &Record) -> bool {
+        match self {
+            Node::And(a, b) => a.matches(record) && b.matches(record),
+            Node::Or(a, b) => a.matches(record) || b.matches(record),
+            Node::Not(e) => !e.matches(record),
+            Node::Equal(field, value) => field.value(record) == value,
+            Node::Match(field, regex) => regex.is_match(field.value(record)),
+            Node::Level(op, level) => match op {
+                Token::Eq => record.level == *level,
+                Token::Ne => record.level != *level,
+                Token::Lt => record.level < *level,
+                Token::Le => record.level <= *level,
+                Token::Gt => record.level > *level,
+                Token::Ge => record.level >= *level,
+                _ => unreachable!("Invalid level operator"),
+            },
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<&Token, Error> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| format_err!("Unexpected end of expression"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expression(&mut self) -> Result<Node, Error> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            left = Node::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Node, Error> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            left = Node::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, Error> {
+        match self.next()?.clone() {
+            Token::Not => Ok(Node::Not(Box::new(self.unary()?))),
+            Token::Open => {
+                let expression = self.expression()?;
+                match self.next()? {
+                    Token::Close => Ok(expression),
+                    t => Err(format_err!("Expected ) but found {:?}", t)),
+                }
+            }
+            Token::Word(field) => self.comparison(field.parse()?),
+            t => Err(format_err!("Expected field but found {:?}", t)),
+        }
+    }
+
+    fn comparison(&mut self, field: Field) -> Result<Node, Error> {
+        let op = self.next()?.clone();
+        let value = match self.next()? {
+            Token::Word(value) => value.clone(),
+            t => return Err(format_err!("Expected value but found {:?}", t)),
+        };
+
+        if field == Field::Level {
+            let level = Level::from(value.as_str());
+            if level == Level::None {
+                return Err(format_err!("Invalid level {}", value));
+            }
+            return match op {
+                Token::Eq | Token::Ne | Token::Lt | Token::Le | Token::Gt | Token::Ge => {
+                    Ok(Node::Level(op, level))
+                }
+                t => Err(format_err!("Invalid level operator {:?}", t)),
+            };
+        }
+
+        let regex =
+            || Regex::new(&value).map_err(|e| format_err!("Invalid regex {}: {}", value, e));
+        match op {
+            Token::Eq => Ok(Node::Equal(field, value.clone())),
+            Token::Ne => Ok(Node::Not(Box::new(Node::Equal(field, value.clone())))),
+            Token::Match => Ok(Node::Match(field, regex()?)),
+            Token::NotMatch => Ok(Node::Not(Box::new(Node::Match(field, regex()?)))),
+            t => Err(format_err!("Invalid operator {:?} for {:?}", t, field)),
+        }
+    }
+}
+
+#[cfg(test)]
+fn record(tag: &str, level: Level, message: &str) -> Record {
+    Record {
+        tag: tag.to_owned(),
+        level,
+        message: message.to_owned(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn parse_errors() {
+    assert!("".parse::<Expression>().is_err());
+    assert!("tag".parse::<Expression>().is_err());
+    assert!("tag ==".parse::<Expression>().is_err());
+    assert!("foo == bar".parse::<Expression>().is_err());
+    assert!("(tag == a".parse::<Expression>().is_err());
+    assert!("tag == a)".parse::<Expression>().is_err());
+    assert!("tag == \"a".parse::<Expression>().is_err());
+    assert!("level ~ W".parse::<Expression>().is_err());
+    assert!("level >= X".parse::<Expression>().is_err());
+    assert!("tag > a".parse::<Expression>().is_err());
+    assert!("tag ~ \"(\"".parse::<Expression>().is_err());
+}
+
+#[test]
+fn evaluate() {
+    let e: Expression = "(tag == \"ActivityManager\" && level >= W) || message ~ \"ANR\""
+        .parse()
+        .unwrap();
+    assert!(e.matches(&record("ActivityManager", Level::Warn, "")));
+    assert!(e.matches(&record("ActivityManager", Level::Error, "")));
+    assert!(!e.matches(&record("ActivityManager", Level::Info, "")));
+    assert!(e.matches(&record("Other", Level::Info, "ANR in foo")));
+    assert!(!e.matches(&record("Other", Level::Info, "foo")));
+
+    let e: Expression = "!(tag == a || tag == b) && msg !~ '^\\d+$'"
+        .parse()
+        .unwrap();
+    assert!(e.matches(&record("c", Level::Info, "foo")));
+    assert!(!e.matches(&record("a", Level::Info, "foo")));
+    assert!(!e.matches(&record("c", Level::Info, "123")));
+
+    let e: Expression = "level < I && tag != x".parse().unwrap();
+    assert!(e.matches(&record("a", Level::Debug, "")));
+    assert!(!e.matches(&record("x", Level::Debug, "")));
+    assert!(!e.matches(&record("a", Level::Info, "")));
+}
+
+#[test]
+fn precedence() {
+    // && binds stronger than ||
+    let e: Expression = "tag == a || tag == b && level == E".parse().unwrap();
+    assert!(e.matches(&record("a", Level::Info, "")));
+    assert!(!e.matches(&record("b", Level::Info, "")));
+    assert!(e.matches(&record("b", Level::Error, "")));
+}
@@ -18,43 +18,163 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::LogSink;
+use crate::{
+    html::Html,
+    index, jobs, marker,
+    profiles::Output,
+    session::{self, Metadata, Mode},
+    trace::Trace,
+    utils, LogSink,
+};
 use clap::ArgMatches;
 use failure::{err_msg, format_err, Error};
+use flate2::{write::GzEncoder, Compression};
 use futures::{Async, AsyncSink, Poll, Sink, StartSend};
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use lazy_static::lazy_static;
+use rayon::prelude::*;
 use regex::Regex;
 use rogcat::record::{Format, Record};
 use std::{
-    fs::{DirBuilder, File},
+    cmp::max,
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{remove_file, DirBuilder, File, OpenOptions},
     io::Write,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 use time::{now, strftime};
 
+/// Extension of gzip compressed files
+const GZ: &str = "gz";
+
+lazy_static! {
+    /// Files and split directories created by the writers
+    static ref CREATED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+}
+
+/// Remember a created file for `created`
+fn created_file(filename: &Path) {
+    CREATED
+        .lock()
+        .expect("Failed to lock created files")
+        .push(filename.to_path_buf());
+}
+
+/// Files and split directories created by the writers that weren't removed
+/// by `--keep`
+pub fn created() -> Vec<PathBuf> {
+    CREATED
+        .lock()
+        .expect("Failed to lock created files")
+        .iter()
+        .filter(|f| f.exists())
+        .cloned()
+        .collect()
+}
+
 /// Filename format
 #[derive(Clone)]
 enum FilenameFormat {
-    Date(bool, usize),
-    Enumerate(bool, usize),
+    Date(bool),
+    Enumerate(bool),
     Single(bool),
+    /// Expand strftime specifiers and `{index}` in the filename
+    Template(bool),
+}
+
+/// Limits that start a new output file when reached
+#[derive(Clone, Default)]
+struct Rotation {
+    records: Option<usize>,
+    bytes: Option<usize>,
+    interval: Option<Duration>,
+    /// Number of files to retain
+    keep: Option<usize>,
+}
+
+impl Rotation {
+    fn new(
+        records: Option<&str>,
+        bytes: Option<&str>,
+        interval: Option<&str>,
+        keep: Option<&str>,
+    ) -> Result<Rotation, Error> {
+        Ok(Rotation {
+            records: records.and_then(count),
+            bytes: bytes
+                .map(|s| count(s).ok_or_else(|| format_err!("Invalid max file size {}", s)))
+                .transpose()?,
+            interval: interval.map(utils::interval).transpose()?,
+            keep: keep
+                .map(|k| usize::from_str(k).map_err(|e| format_err!("Invalid keep {}: {}", k, e)))
+                .transpose()?,
+        })
+    }
+
+    fn rotates(&self) -> bool {
+        self.records.is_some() || self.bytes.is_some() || self.interval.is_some()
+    }
+}
+
+/// Maximum number of files kept open by a split writer
+const MAX_OPEN: usize = 64;
+
+/// Record property that selects the output file of a split writer
+#[derive(Clone, Copy)]
+enum SplitBy {
+    Tag,
+    Pid,
+    /// Process name or pid if the name isn't resolved
+    Process,
+}
+
+/// Writes the records of each tag or process to a own file in a
+/// directory. The least recently used files are closed if more than
+/// `MAX_OPEN` files are written and reopened for appending when needed.
+struct SplitWriter {
+    directory: PathBuf,
+    split_by: SplitBy,
+    format: Format,
+    overwrite: bool,
+    /// Open files by key with the tick of the last write
+    open: HashMap<String, (Textfile, usize)>,
+    /// Keys of the files created
+    created: HashSet<String>,
+    tick: usize,
+    progress: ProgressBar,
 }
 
+/// Output file. Gzip compressed if the filename ends with .gz
+type OutputFile = Box<dyn Write + Send>;
+
 /// Textfile with format
 struct Textfile {
-    file: File,
+    file: OutputFile,
     format: Format,
 }
 
 struct FileWriter<T> {
     current_filename: PathBuf,
+    file_bytes: usize,
+    file_created: Instant,
     file_size: usize,
     filename: PathBuf,
     filename_format: FilenameFormat,
+    files: VecDeque<PathBuf>,
     index: usize,
     format: Format,
+    /// Records waiting to be encoded on the worker threads of `--jobs`
+    batch: Option<Vec<Record>>,
+    /// Records between entries of the seekable index
+    index_interval: Option<usize>,
+    indexer: Option<index::Writer>,
+    metadata: Option<(Mode, Metadata)>,
+    next_index: usize,
     progress: ProgressBar,
+    rotation: Rotation,
     writer: Option<Box<T>>,
 }
 
@@ -62,95 +182,379 @@ trait Writer {
     fn with_file_format(filename: &Path, format: &Format) -> Result<Self, Error>
     where
         Self: Sized;
-    fn write(&mut self, record: &Record, index: usize) -> Result<(), Error>;
+    /// Write record and return the number of bytes written
+    fn write(&mut self, record: &Record, index: usize) -> Result<usize, Error>;
+    /// Encode a record without writing it. Writers that support encoding on
+    /// the worker threads of `--jobs` return the data for `write_encoded`
+    fn encode(_format: &Format, _record: &Record) -> Option<Result<Vec<u8>, Error>>
+    where
+        Self: Sized,
+    {
+        None
+    }
+    /// Write a record returned by `encode`
+    fn write_encoded(&mut self, _data: &[u8]) -> Result<usize, Error> {
+        unreachable!("Writer doesn't support encoded records")
+    }
+    /// Write comment lines at the top of the file
+    fn header(&mut self, _lines: &[String]) -> Result<(), Error> {
+        Ok(())
+    }
     fn flush(&mut self) -> Result<(), Error> {
         Ok(())
     }
 }
 
-/// Crate a new log sink for given arguments
-pub fn try_from<'a>(args: &ArgMatches<'a>) -> Result<LogSink, Error> {
-    let format = args
-        .value_of("format")
-        .and_then(|f| Format::from_str(f).ok())
-        .unwrap_or(Format::Raw);
+/// Named pipes like `\\.\pipe\rogcat` on Windows or fifos on unix. Pipes are
+/// opened for writing and are never rotated, compressed or overwritten.
+fn is_pipe(filename: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if filename
+            .metadata()
+            .map(|m| m.file_type().is_fifo())
+            .unwrap_or(false)
+        {
+            return true;
+        }
+    }
+    filename
+        .to_str()
+        .map(|f| {
+            f.replace('/', "\\")
+                .to_lowercase()
+                .starts_with(r"\\.\pipe\")
+        })
+        .unwrap_or(false)
+}
+
+/// Create a output file. Files with the extension gz are compressed on the fly
+fn create(filename: &Path) -> Result<OutputFile, Error> {
+    if is_pipe(filename) {
+        let pipe = OpenOptions::new()
+            .write(true)
+            .open(filename)
+            .map_err(|e| format_err!("Failed to open pipe {}: {}", filename.display(), e))?;
+        return Ok(Box::new(pipe));
+    }
+    let file = File::create(filename)
+        .map_err(|e| format_err!("Failed to create output file {}: {}", filename.display(), e))?;
+    if filename.extension().map(|e| e == GZ).unwrap_or(false) {
+        Ok(Box::new(GzEncoder::new(file, Compression::default())))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Parse a number with optional k, M or G suffix
+pub fn count(s: &str) -> Option<usize> {
+    Regex::new(r"^(\d+)([kMG])$")
+        .unwrap()
+        .captures(s)
+        .and_then(|caps| {
+            caps.get(1)
+                .map(|m| m.as_str())
+                .and_then(|size| usize::from_str(size).ok())
+                .map(|size| (size, caps.get(2).map(|m| m.as_str())))
+        })
+        .and_then(|(size, suffix)| match suffix {
+            Some("k") => Some(1_000 * size),
+            Some("M") => Some(1_000_000 * size),
+            Some("G") => Some(1_000_000_000 * size),
+            _ => None,
+        })
+        .or_else(|| usize::from_str(s).ok())
+}
+
+/// Format of a file by its extension e.g `json` for `log.json` or `log.json.gz`
+fn extension_format(filename: &Path) -> Option<Format> {
+    let filename = if filename.extension().map(|e| e == GZ).unwrap_or(false) {
+        Path::new(filename.file_stem()?)
+    } else {
+        filename
+    };
+    filename
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(|e| Format::from_str(e).ok())
+        .filter(|f| *f != Format::Human)
+}
+
+/// Crate a new log sink for output with the options given in arguments.
+/// Progress is drawn on the terminal if requested.
+pub fn try_from<'a>(args: &ArgMatches<'a>, output: &str, progress: bool) -> Result<LogSink, Error> {
+    let format = match args.value_of("output_format") {
+        Some(template) => Format::Template(template.to_owned()),
+        None => args
+            .value_of("format")
+            .and_then(|f| Format::from_str(f).ok())
+            .unwrap_or(Format::Raw),
+    };
+
+    if args.is_present("split_by") {
+        return Ok(Box::new(SplitWriter::from_args(args, output, format, progress)?) as LogSink);
+    }
 
     Ok(match format {
-        Format::Csv | Format::Json | Format::Raw => {
-            Box::new(FileWriter::<Textfile>::from_args(args, format)?) as LogSink
-        }
-        Format::Html => Box::new(FileWriter::<html::Html>::from_args(args, format)?) as LogSink,
+        Format::Csv
+        | Format::Json
+        | Format::Msgpack
+        | Format::Protobuf
+        | Format::Raw
+        | Format::Template(_) => Box::new(FileWriter::<Textfile>::from_args(
+            args, output, format, progress,
+        )?) as LogSink,
+        Format::Html => Box::new(FileWriter::<Html<OutputFile>>::from_args(
+            args, output, format, progress,
+        )?) as LogSink,
+        Format::Trace => Box::new(FileWriter::<Trace<OutputFile>>::from_args(
+            args, output, format, progress,
+        )?) as LogSink,
         Format::Human => panic!("Unsupported format human in output file"),
     })
 }
 
+/// Create a log sink for a file output without options. The format is the
+/// format of the file extension or raw
+pub fn from_path(output: &str) -> Result<LogSink, Error> {
+    from_output(&Output {
+        file: output.to_owned(),
+        format: extension_format(Path::new(output)).map(|f| f.to_string()),
+        ..Default::default()
+    })
+}
+
+/// Create a log sink for an output of a profile. Filenames with strftime
+/// specifiers or `{index}` are expanded as template
+pub fn from_output(output: &Output) -> Result<LogSink, Error> {
+    let format = match output.format {
+        Some(ref f) => Format::from_str(f)
+            .map_err(|_| format_err!("Invalid format {} of output {}", f, output.file))?,
+        None => Format::Raw,
+    };
+    let rotation = Rotation {
+        keep: output.keep,
+        ..Rotation::new(
+            output.records_per_file.as_deref(),
+            output.max_file_size.as_deref(),
+            output.rotate_interval.as_deref(),
+            None,
+        )?
+    };
+    let overwrite = output.overwrite.unwrap_or(false);
+    let filename_format = if output.file.contains('%') || output.file.contains("{index}") {
+        FilenameFormat::Template(overwrite)
+    } else if rotation.rotates() {
+        FilenameFormat::Enumerate(overwrite)
+    } else {
+        FilenameFormat::Single(overwrite)
+    };
+    let filename = PathBuf::from(&output.file);
+
+    Ok(match format {
+        Format::Csv
+        | Format::Json
+        | Format::Msgpack
+        | Format::Protobuf
+        | Format::Raw
+        | Format::Template(_) => Box::new(FileWriter::<Textfile>::new(
+            filename,
+            format,
+            rotation,
+            filename_format,
+            false,
+        )) as LogSink,
+        Format::Html => Box::new(FileWriter::<Html<OutputFile>>::new(
+            filename,
+            format,
+            rotation,
+            filename_format,
+            false,
+        )) as LogSink,
+        Format::Trace => Box::new(FileWriter::<Trace<OutputFile>>::new(
+            filename,
+            format,
+            rotation,
+            filename_format,
+            false,
+        )) as LogSink,
+        Format::Human => {
+            return Err(format_err!(
+                "Unsupported format human of output {}",
+                output.file
+            ))
+        }
+    })
+}
+
 impl Writer for Textfile {
     fn with_file_format(filename: &Path, format: &Format) -> Result<Textfile, Error> {
-        let file = File::create(filename).map_err(|e| {
-            format_err!("Failed to create output file {}: {}", filename.display(), e)
-        })?;
         Ok(Textfile {
-            file,
+            file: create(filename)?,
             format: format.clone(),
         })
     }
 
-    fn write(&mut self, record: &Record, _index: usize) -> Result<(), Error> {
-        self.file
-            .write(self.format.fmt_record(record)?.as_bytes())
-            .map_err(|e| format_err!("Failed to write: {}", e))?;
+    fn write(&mut self, record: &Record, _index: usize) -> Result<usize, Error> {
+        let data = Textfile::encode(&self.format, record).expect("Textfile encoding")?;
+        self.write_encoded(&data)
+    }
+
+    fn encode(format: &Format, record: &Record) -> Option<Result<Vec<u8>, Error>> {
+        Some(match format {
+            Format::Raw if marker::is_marker(record) => {
+                Ok(format!("{}\n", marker::line(record, 80)).into_bytes())
+            }
+            _ => format.encode_record(record),
+        })
+    }
+
+    fn write_encoded(&mut self, data: &[u8]) -> Result<usize, Error> {
         self.file
-            .write(b"\n")
+            .write_all(data)
             .map_err(|e| format_err!("Failed to write: {}", e))?;
+        Ok(data.len())
+    }
+
+    fn header(&mut self, lines: &[String]) -> Result<(), Error> {
+        for line in lines {
+            writeln!(self.file, "{}", line).map_err(|e| format_err!("Failed to write: {}", e))?;
+        }
         Ok(())
     }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.file
+            .flush()
+            .map_err(|e| format_err!("Failed to flush: {}", e))
+    }
 }
 
 impl<'a, T: Writer> FileWriter<T> {
-    pub fn from_args(args: &ArgMatches<'a>, format: Format) -> Result<Self, Error> {
-        let filename = args
-            .value_of("output")
-            .map(PathBuf::from)
-            .ok_or_else(|| err_msg("Invalid output filename!"))?;
-
-        let records_per_file = args.value_of("records_per_file").and_then(|l| {
-            Regex::new(r"^(\d+)([kMG])$")
-                .unwrap()
-                .captures(l)
-                .and_then(|caps| {
-                    caps.get(1)
-                        .map(|m| m.as_str())
-                        .and_then(|size| usize::from_str(size).ok())
-                        .map(|size| (size, caps.get(2).map(|m| m.as_str())))
-                })
-                .and_then(|(size, suffix)| match suffix {
-                    Some("k") => Some(1_000 * size),
-                    Some("M") => Some(1_000_000 * size),
-                    Some("G") => Some(1_000_000_000 * size),
-                    _ => None,
-                })
-                .or_else(|| usize::from_str(l).ok())
-        });
+    pub fn from_args(
+        args: &ArgMatches<'a>,
+        output: &str,
+        format: Format,
+        progress: bool,
+    ) -> Result<Self, Error> {
+        let mut filename = PathBuf::from(output);
 
-        let overwrite = args.is_present("overwrite");
+        if is_pipe(&filename) {
+            let unsupported = [
+                "compress",
+                "filename_format",
+                "index",
+                "keep",
+                "max_file_size",
+                "records_per_file",
+                "rotate_interval",
+            ];
+            if let Some(arg) = unsupported.iter().find(|a| args.is_present(a)) {
+                return Err(format_err!(
+                    "Option {} is not supported for pipe {}",
+                    arg.replace('_', "-"),
+                    filename.display()
+                ));
+            }
+            if let Some((Mode::Sidecar, _)) = session::from_args(args) {
+                return Err(format_err!(
+                    "Sidecar metadata is not supported for pipe {}",
+                    filename.display()
+                ));
+            }
+            return Ok(FileWriter {
+                metadata: session::from_args(args),
+                ..FileWriter::new(
+                    filename,
+                    format,
+                    Rotation::default(),
+                    FilenameFormat::Single(true),
+                    progress,
+                )
+            });
+        }
+
+        // Append the gz extension if compression is requested and not
+        // already part of the filename
+        if args.value_of("compress") == Some("gzip")
+            && filename.extension().map(|e| e != GZ).unwrap_or(true)
+        {
+            let mut name = filename.clone().into_os_string();
+            name.push(".");
+            name.push(GZ);
+            filename = PathBuf::from(name);
+        }
 
-        let records = records_per_file.unwrap_or(std::usize::MAX);
+        let rotation = Rotation::new(
+            args.value_of("records_per_file"),
+            args.value_of("max_file_size"),
+            args.value_of("rotate_interval"),
+            args.value_of("keep"),
+        )?;
+        let overwrite = args.is_present("overwrite");
         let filename_format = match args.value_of("filename_format") {
-            Some("enumerate") => FilenameFormat::Enumerate(overwrite, records),
-            Some("date") => FilenameFormat::Date(overwrite, records),
-            // If records per file is set, default to enumerated even if
+            Some("enumerate") => FilenameFormat::Enumerate(overwrite),
+            Some("date") => FilenameFormat::Date(overwrite),
+            Some("template") => FilenameFormat::Template(overwrite),
+            // If the output is rotated, default to enumerated even if
             // no file format argument is supplied.
             Some(_) | None => {
-                if let Some(n) = records_per_file {
-                    FilenameFormat::Enumerate(overwrite, n)
+                if rotation.rotates() {
+                    FilenameFormat::Enumerate(overwrite)
                 } else {
                     FilenameFormat::Single(overwrite)
                 }
             }
         };
 
+        let metadata = session::from_args(args);
+        if let Some((Mode::Header, _)) = metadata {
+            if format != Format::Raw {
+                return Err(format_err!(
+                    "Session metadata headers are only supported with the raw format"
+                ));
+            }
+        }
+
+        let index_interval = if args.is_present("index") {
+            if filename.extension().map(|e| e == GZ).unwrap_or(false) {
+                return Err(format_err!("Indexes of compressed files are not supported"));
+            }
+            if let Format::Html | Format::Trace = format {
+                return Err(format_err!("Indexes of {} files are not supported", format));
+            }
+            let interval = args.value_of("index").map(|n| {
+                count(n)
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format_err!("Invalid index interval {}", n))
+            });
+            Some(interval.unwrap_or(Ok(index::INTERVAL))?)
+        } else {
+            None
+        };
+
+        Ok(FileWriter {
+            batch: if args.is_present("jobs") {
+                Some(Vec::with_capacity(jobs::BATCH))
+            } else {
+                None
+            },
+            index_interval,
+            metadata,
+            ..FileWriter::new(filename, format, rotation, filename_format, progress)
+        })
+    }
+
+    fn new(
+        filename: PathBuf,
+        format: Format,
+        rotation: Rotation,
+        filename_format: FilenameFormat,
+        progress: bool,
+    ) -> Self {
         let progress = {
-            let (pb, chars, template) = if let Some(n) = records_per_file {
+            let (pb, chars, template) = if let Some(n) = rotation.records {
                 (
                     ProgressBar::new(n as u64),
                     "•• ",
@@ -168,22 +572,34 @@ impl<'a, T: Writer> FileWriter<T> {
                     .template(template)
                     .progress_chars(chars),
             );
+            if !progress {
+                pb.set_draw_target(ProgressDrawTarget::hidden());
+            }
             pb
         };
 
-        Ok(FileWriter {
+        FileWriter {
             current_filename: filename.clone(),
+            file_bytes: 0,
+            file_created: Instant::now(),
             file_size: 0,
             filename,
             filename_format,
+            files: VecDeque::new(),
             index: 0,
             format,
+            batch: None,
+            index_interval: None,
+            indexer: None,
+            metadata: None,
+            next_index: 0,
             progress,
+            rotation,
             writer: None,
-        })
+        }
     }
 
-    fn next_file(&self) -> Result<PathBuf, Error> {
+    fn next_file(&mut self) -> Result<PathBuf, Error> {
         match self.filename_format {
             FilenameFormat::Single(overwrite) => {
                 if self.filename.exists() && !overwrite {
@@ -195,7 +611,7 @@ impl<'a, T: Writer> FileWriter<T> {
                     Ok(self.filename.clone())
                 }
             }
-            FilenameFormat::Enumerate(_overwrite, _) => {
+            FilenameFormat::Enumerate(_overwrite) => {
                 if self.filename.as_path().is_dir() {
                     return Err(format_err!(
                         "Output file {} is a directory",
@@ -210,32 +626,88 @@ impl<'a, T: Writer> FileWriter<T> {
                     })?
                 }
 
+                // Enumerate compressed files as name-000.ext.gz instead of name.ext-000.gz
+                let (filename, gz) = if self.filename.extension().map(|e| e == GZ).unwrap_or(false)
+                {
+                    (self.filename.with_extension(""), true)
+                } else {
+                    (self.filename.clone(), false)
+                };
+
                 let next = |index| -> Result<PathBuf, Error> {
-                    let mut name = self.filename.clone();
-                    name = PathBuf::from(format!(
+                    let mut name = PathBuf::from(format!(
                         "{}-{:03}",
-                        name.file_stem()
+                        filename
+                            .file_stem()
                             .ok_or_else(|| err_msg("Invalid path"))?
                             .to_str()
                             .ok_or_else(|| err_msg("Invalid path"))?,
                         index
                     ));
-                    if let Some(extension) = self.filename.extension() {
+                    if let Some(extension) = filename.extension() {
                         name.set_extension(extension);
                     }
+                    if gz {
+                        let mut n = name.into_os_string();
+                        n.push(".");
+                        n.push(GZ);
+                        name = PathBuf::from(n);
+                    }
                     Ok(dir.join(name))
                 };
 
-                for index in 0.. {
+                // Continue after the last file. Files before might be
+                // removed due to the retention
+                for index in self.next_index.. {
                     let n = next(index)?;
                     if !n.exists() {
+                        self.next_index = index + 1;
                         return Ok(n);
                     }
                 }
 
                 unreachable!("Could not find a file - this is proably a bug here...")
             }
-            FilenameFormat::Date(overwrite, _) => {
+            FilenameFormat::Template(overwrite) => {
+                let dir = self.filename.parent().unwrap_or_else(|| Path::new(""));
+                if !dir.is_dir() {
+                    DirBuilder::new().recursive(true).create(dir).map_err(|e| {
+                        format_err!(
+                            "Failed to create outfile parent directory {}: {}",
+                            dir.display(),
+                            e
+                        )
+                    })?;
+                }
+
+                let template = self
+                    .filename
+                    .file_name()
+                    .ok_or_else(|| err_msg("Invalid path"))?
+                    .to_str()
+                    .ok_or_else(|| err_msg("Invalid path"))?;
+                let enumerated = template.contains("{index}");
+                let name = strftime(template, &now())
+                    .map_err(|e| format_err!("Invalid filename template {}: {}", template, e))?;
+
+                for index in self.next_index.. {
+                    let candidate = dir.join(name.replace("{index}", &format!("{:03}", index)));
+                    if candidate.exists() && !overwrite {
+                        if enumerated {
+                            continue;
+                        }
+                        return Err(format_err!(
+                            "{} exists. Use overwrite flag to force!",
+                            candidate.display()
+                        ));
+                    }
+                    self.next_index = index + 1;
+                    return Ok(candidate);
+                }
+
+                unreachable!("Could not find a file - this is proably a bug here...")
+            }
+            FilenameFormat::Date(overwrite) => {
                 // If the overwrite flag is set the files are
                 // enumerated from the first one to get nice
                 // aligned filenames.
@@ -276,35 +748,118 @@ impl<'a, T: Writer> FileWriter<T> {
         }
     }
 
-    fn write(&mut self, record: &Record) -> Result<(), Error> {
-        match self.writer {
-            Some(ref mut writer) => {
-                writer.write(record, self.index)?;
-                self.index += 1;
-            }
+    /// Encode the batched records on the worker threads and write them
+    fn write_batch(&mut self) -> Result<(), Error> {
+        let batch = match self.batch {
+            Some(ref mut batch) if !batch.is_empty() => std::mem::take(batch),
+            _ => return Ok(()),
+        };
+        let format = &self.format;
+        let encoded = batch
+            .par_iter()
+            .map(|record| T::encode(format, record))
+            .collect::<Vec<_>>();
+        for (record, data) in batch.iter().zip(encoded) {
+            self.write(record, data.transpose()?)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Record, encoded: Option<Vec<u8>>) -> Result<(), Error> {
+        let bytes = match self.writer {
+            Some(ref mut writer) => match encoded {
+                Some(data) => writer.write_encoded(&data)?,
+                None => writer.write(record, self.index)?,
+            },
             None => {
                 self.current_filename = self.next_file()?;
                 let mut writer = T::with_file_format(&self.current_filename, &self.format)?;
+                created_file(&self.current_filename);
+                let mut offset = 0;
+                match self.metadata {
+                    Some((Mode::Header, ref metadata)) => {
+                        let header = metadata.header();
+                        offset = header.iter().map(|l| l.len() as u64 + 1).sum();
+                        writer.header(&header)?
+                    }
+                    Some((Mode::Sidecar, ref metadata)) => {
+                        metadata.write_sidecar(&self.current_filename)?
+                    }
+                    None => (),
+                }
+                if let Some(header) = self.format.header() {
+                    offset += header.len() as u64 + 1;
+                    writer.header(&[header.to_owned()])?
+                }
+                if let Some(interval) = self.index_interval {
+                    self.indexer = Some(index::Writer::create(
+                        &self.current_filename,
+                        interval,
+                        offset,
+                    )?);
+                }
                 let message = format!("Writing {}", self.current_filename.display());
                 self.progress.set_message(&message);
-                writer.write(record, self.index)?;
-                self.index += 1;
+                self.file_created = Instant::now();
+                self.retain();
+                let bytes = match encoded {
+                    Some(data) => writer.write_encoded(&data)?,
+                    None => writer.write(record, self.index)?,
+                };
                 self.writer = Some(Box::new(writer));
+                bytes
             }
+        };
+        if let Some(ref mut indexer) = self.indexer {
+            indexer.add(record, bytes)?;
         }
+        self.index += 1;
 
         self.file_size += 1;
+        self.file_bytes += bytes;
         self.progress.set_position(self.file_size as u64);
 
-        match self.filename_format {
-            FilenameFormat::Enumerate(_, n) | FilenameFormat::Date(_, n) => {
-                if self.file_size >= n {
-                    self.flush()
-                } else {
-                    Ok(())
+        if let FilenameFormat::Single(_) = self.filename_format {
+            return Ok(());
+        }
+
+        let rotation = &self.rotation;
+        if rotation
+            .records
+            .map(|n| self.file_size >= n)
+            .unwrap_or(false)
+            || rotation
+                .bytes
+                .map(|n| self.file_bytes >= n)
+                .unwrap_or(false)
+            || rotation
+                .interval
+                .map(|i| self.file_created.elapsed() >= i)
+                .unwrap_or(false)
+        {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Remove the oldest files written if there are more than configured to keep
+    fn retain(&mut self) {
+        self.files.push_back(self.current_filename.clone());
+        if let Some(keep) = self.rotation.keep {
+            while self.files.len() > max(keep, 1) {
+                if let Some(file) = self.files.pop_front() {
+                    if let Err(e) = remove_file(&file) {
+                        eprintln!("Failed to remove {}: {}", file.display(), e);
+                    }
+                    if let Some((Mode::Sidecar, _)) = self.metadata {
+                        remove_file(session::sidecar(&file)).ok();
+                    }
+                    if self.index_interval.is_some() {
+                        remove_file(index::sidecar(&file)).ok();
+                    }
                 }
             }
-            _ => Ok(()),
         }
     }
 
@@ -312,11 +867,15 @@ impl<'a, T: Writer> FileWriter<T> {
         if let Some(ref mut writer) = self.writer {
             writer.flush()?;
         }
+        if let Some(mut indexer) = self.indexer.take() {
+            indexer.flush()?;
+        }
         self.progress
             .set_style(ProgressStyle::default_bar().template("{msg:.dim.bold}"));
         self.progress
             .finish_with_message(&format!("Dumped {} records", self.index));
         self.file_size = 0;
+        self.file_bytes = 0;
         self.writer.take();
         Ok(())
     }
@@ -327,193 +886,226 @@ impl<T: Writer> Sink for FileWriter<T> {
     type SinkError = Error;
 
     fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
-        self.write(&record).map(|_| AsyncSink::Ready)
+        match self.batch {
+            Some(ref mut batch) => {
+                batch.push(record);
+                if batch.len() >= jobs::BATCH {
+                    self.write_batch()?;
+                }
+            }
+            None => self.write(&record, None)?,
+        }
+        Ok(AsyncSink::Ready)
     }
 
     fn poll_complete(&mut self) -> Poll<(), Error> {
-        Ok(Async::Ready(()))
+        self.write_batch().map(Async::Ready)
+    }
+
+    fn close(&mut self) -> Poll<(), Error> {
+        self.write_batch()?;
+        self.flush().map(Async::Ready)
     }
 }
 
-mod html {
-    use super::Writer;
-    use crc::{crc32, Hasher32};
-    use failure::{format_err, Error};
-    use handlebars::{
-        to_json, Context, Handlebars, Helper, HelperResult, JsonRender, Output, RenderContext,
-        RenderError,
-    };
-    use rogcat::record::{Format, Record};
-    use serde::Serialize;
-    use serde_json::value::{Map, Value as Json};
-    use std::{
-        fs::File,
-        path::{Path, PathBuf},
-        str,
-    };
+impl SplitWriter {
+    fn from_args<'a>(
+        args: &ArgMatches<'a>,
+        output: &str,
+        format: Format,
+        progress: bool,
+    ) -> Result<SplitWriter, Error> {
+        let directory = PathBuf::from(output);
+        let split_by = match args.value_of("split_by") {
+            Some("pid") => SplitBy::Pid,
+            Some("process") => SplitBy::Process,
+            _ => SplitBy::Tag,
+        };
+        if format == Format::Html || format == Format::Human || format == Format::Trace {
+            return Err(format_err!(
+                "Unsupported format {} for split output",
+                format
+            ));
+        }
+        DirBuilder::new()
+            .recursive(true)
+            .create(&directory)
+            .map_err(|e| format_err!("Failed to create {}: {}", directory.display(), e))?;
+        created_file(&directory);
+
+        let progress = {
+            let pb = ProgressBar::new(u64::MAX);
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template(
+                        "{spinner:.yellow} Writing {msg:.dim.bold} {pos:>7.dim} {elapsed_precise:.dim}",
+                    )
+                    .progress_chars(" • "),
+            );
+            if !progress {
+                pb.set_draw_target(ProgressDrawTarget::hidden());
+            }
+            pb
+        };
 
-    #[derive(Serialize)]
-    struct HtmlRecord {
-        index: usize,
-        record: Record,
+        Ok(SplitWriter {
+            directory,
+            split_by,
+            format,
+            overwrite: args.is_present("overwrite"),
+            open: HashMap::new(),
+            created: HashSet::new(),
+            tick: 0,
+            progress,
+        })
     }
 
-    /// Simple static html file
-    pub struct Html {
-        filename: PathBuf,
-        records: Vec<HtmlRecord>,
-    }
-
-    impl Html {
-        // TODO: ensure readability
-        fn hash_color(value: &str) -> String {
-            let mut digest = crc32::Digest::new(crc32::IEEE);
-            digest.write(value.as_bytes());
-            let h = digest.sum32();
-            let r = h & 0xFF;
-            let g = (h & 0xFF00) >> 8;
-            let b = (h & 0xFF_0000) >> 16;
-            format!("#{:02x}{:02x}{:02x}", r, g, b)
+    /// Filename of key in the output directory
+    fn filename(&self, key: &str) -> PathBuf {
+        let mut name = key
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+                _ => '_',
+            })
+            .collect::<String>()
+            .trim_start_matches('.')
+            .to_owned();
+        if name.is_empty() {
+            name.push('_');
         }
-        fn color_helper(
-            h: &Helper,
-            _: &Handlebars,
-            _: &Context,
-            _: &mut RenderContext,
-            out: &mut dyn Output,
-        ) -> HelperResult {
-            let param = h
-                .param(0)
-                .ok_or_else(|| RenderError::new("Param 0 is required for format helper."))?;
-            let value = param.value().render();
-            let rendered = if value.is_empty() || value == "0" {
-                format!("<span style=\"color:grey\">{}</span>", value)
-            } else {
-                format!(
-                    "<span style=\"color:{}\">{}</span>",
-                    Self::hash_color(&value),
-                    value
-                )
-            };
-            out.write(&rendered)?;
-            Ok(())
+        let extension = match self.format {
+            Format::Csv => "csv",
+            Format::Json => "json",
+            Format::Msgpack => "msgpack",
+            Format::Protobuf => "pb",
+            _ => "log",
+        };
+        self.directory.join(format!("{}.{}", name, extension))
+    }
+
+    /// Open the file of key. Files created before are appended
+    fn open(&mut self, key: &str) -> Result<Textfile, Error> {
+        let filename = self.filename(key);
+        let file = if self.created.contains(key) {
+            OpenOptions::new().append(true).open(&filename)
+        } else if filename.exists() && !self.overwrite {
+            return Err(format_err!(
+                "{} exists. Use overwrite flag to force!",
+                filename.display()
+            ));
+        } else {
+            File::create(&filename)
         }
+        .map_err(|e| format_err!("Failed to open {}: {}", filename.display(), e))?;
+        self.created.insert(key.to_owned());
 
-        fn flush(&mut self) -> Result<(), Error> {
-            let mut hb = Handlebars::new();
-            let mut data: Map<String, Json> = Map::new();
-            data.insert("records".to_owned(), to_json(&self.records));
-            let mut output_file = File::create(&self.filename)?;
-            hb.register_helper("color", Box::new(Self::color_helper));
-            hb.register_template_string("t1", HTML_TEMPLATE)?;
-            hb.render_to_write("t1", &data, &mut output_file)
-                .map_err(|e| format_err!("Rednering error: {}", e))
-                .map(|_| ())
+        // Close the least recently used file
+        if self.open.len() >= MAX_OPEN {
+            if let Some(lru) = self
+                .open
+                .iter()
+                .min_by_key(|(_, (_, tick))| *tick)
+                .map(|(key, _)| key.clone())
+            {
+                self.open.remove(&lru);
+            }
         }
+
+        Ok(Textfile {
+            file: Box::new(file),
+            format: self.format.clone(),
+        })
     }
 
-    impl Writer for Html {
-        fn with_file_format(filename: &Path, _: &Format) -> Result<Html, Error> {
-            Ok(Html {
-                filename: filename.to_owned(),
-                records: Vec::new(),
-            })
+    fn write(&mut self, record: &Record) -> Result<(), Error> {
+        let key = match self.split_by {
+            SplitBy::Tag => record.tag.clone(),
+            SplitBy::Pid => record.process.clone(),
+            SplitBy::Process => record
+                .process_name
+                .clone()
+                .unwrap_or_else(|| record.process.clone()),
+        };
+        if !self.open.contains_key(&key) {
+            let file = self.open(&key)?;
+            self.open.insert(key.clone(), (file, 0));
         }
 
-        fn write(&mut self, record: &Record, index: usize) -> Result<(), Error> {
-            self.records.push(HtmlRecord {
-                index,
-                record: record.clone(),
-            });
-            Ok(())
-        }
+        self.tick += 1;
+        let tick = self.tick;
+        let (file, last) = self.open.get_mut(&key).expect("Missing split file");
+        *last = tick;
+        file.write(record, 0)?;
+
+        self.progress.set_message(&format!(
+            "{} ({} files)",
+            self.directory.display(),
+            self.created.len()
+        ));
+        self.progress.set_position(tick as u64);
+        Ok(())
     }
+}
 
-    impl Drop for Html {
-        fn drop(&mut self) {
-            self.flush().ok();
-        }
+impl Sink for SplitWriter {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        self.write(&record).map(|_| AsyncSink::Ready)
     }
 
-    const HTML_TEMPLATE: &str = r#"
-<!doctype HTML>
-<title>Rogcat</title>
-<link href='http://fonts.googleapis.com/css?family=Source+Code+Pro' rel='stylesheet' type='text/css'>
-<style>
-body {background: black; color: #BBBBBB; font-family: 'Source Code Pro', Monaco, monospace; font-size: 12px}
-.green, .I {color: #A8FF60}
-.white {color: #EEEEEE}
-.red, .E, .A, .F {color: #FF6C60}
-.yellow, .W {color: #FFFFB6}
-.black {color: #4F4F4F}
-.blue {color: #96CBFE}
-.cyan {color: #C6C5FE}
-.magenta {color: #FF73FD}
-tr.hover { background: #260041 }
-table {
-    border-spacing: 0;
-    width: 100%;
-}
-td {
-    vertical-align: top;
-    padding-bottom: 0;
-    padding-left: 2ex;
-    padding-right: 2ex;
-    white-space: nowrap;
-}
-tr:hover {
-    color: yellow;
-}
-td.level-D {
-    color: white;
-    background: #555;
-}
-td.level-I {
-    color: black;
-    background: #A8FF60;
-}
-td.level-W {
-    color: black;
-    background: #FFFFB6;
-}
-td.level-E {
-    color: black;
-    background: #FF6C60;
-}
-td.level-A {
-    color: black;
-    background: #FF6C60;
-}
-td.level-F {
-    color: black;
-    background: #FF6C60;
-}
-table tr td:first-child + td + td {
-    text-align: right
-}
-table tr td:first-child + td + td + td {
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), Error> {
+        self.open.clear();
+        self.progress
+            .set_style(ProgressStyle::default_bar().template("{msg:.dim.bold}"));
+        self.progress.finish_with_message(&format!(
+            "Dumped {} records to {} files",
+            self.tick,
+            self.created.len()
+        ));
+        Ok(Async::Ready(()))
+    }
 }
-table tr td:first-child + td + td + td + td {
-    text-align: right
+
+impl Writer for Html<OutputFile> {
+    fn with_file_format(filename: &Path, _: &Format) -> Result<Html<OutputFile>, Error> {
+        Html::new(create(filename)?)
+    }
+
+    fn write(&mut self, record: &Record, index: usize) -> Result<usize, Error> {
+        Html::write(self, record, index)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.finish()
+    }
 }
-table tr td:first-child + td + td + td + td + td {
+
+impl Writer for Trace<OutputFile> {
+    fn with_file_format(filename: &Path, _: &Format) -> Result<Trace<OutputFile>, Error> {
+        Trace::new(create(filename)?)
+    }
+
+    fn write(&mut self, record: &Record, index: usize) -> Result<usize, Error> {
+        Trace::write(self, record, index)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.finish()
+    }
 }
-</style>
-
-<table>
-
-{{#each records as |t| ~}}
-    <tr>
-    <td>{{t.index}}</td>
-    <td>{{t.record.timestamp}}</td>
-    <td><a>{{color t.record.tag}}</a></td>
-    <td>{{color t.record.process}}</td>
-    <td>{{color t.record.thread}}</td>
-    <td class="level-{{t.record.level}}">{{t.record.level}}</td>
-    <td>{{t.record.message}}</td>
-    </tr>
-{{/each~}}
-
-</table>
-"#;
+
+#[test]
+fn pipes() {
+    assert!(is_pipe(Path::new(r"\\.\pipe\rogcat")));
+    assert!(is_pipe(Path::new(r"\\.\PIPE\rogcat")));
+    assert!(is_pipe(Path::new("//./pipe/rogcat")));
+    assert!(!is_pipe(Path::new("pipe.txt")));
+    assert!(!is_pipe(Path::new(r"C:\pipe\rogcat")));
 }
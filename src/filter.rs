@@ -18,7 +18,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::profiles::Profile;
+use crate::{expression::Expression, pidof::PidOf, profiles::Profile};
 use clap::ArgMatches;
 use failure::{format_err, Error};
 use regex::Regex;
@@ -28,39 +28,177 @@ use rogcat::record::{Level, Record};
 #[derive(Debug)]
 pub struct Filter {
     level: Level,
+    tag_level: Vec<(Regex, Level)>,
     tag: FilterGroup,
     tag_ignore_case: FilterGroup,
     message: FilterGroup,
     message_ignore_case: FilterGroup,
     regex: FilterGroup,
+    expressions: Vec<Expression>,
+    suppress: Vec<Suppress>,
+    pid_of: Option<PidOf>,
+}
+
+/// Field a suppress entry is matched against
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    /// Tag or message
+    Any,
+    Tag,
+    Message,
+    Process,
+    Thread,
+}
+
+/// Records matching a suppress entry are dropped before any other filter.
+/// Entries are a regex with an optional field prefix e.g `tag:^Spammy`
+#[derive(Debug)]
+struct Suppress {
+    field: Field,
+    regex: Regex,
+}
+
+impl Suppress {
+    fn parse(s: &str) -> Result<Suppress, Error> {
+        let (field, regex) = match s.find(':') {
+            Some(n) => match &s[..n] {
+                "tag" => (Field::Tag, &s[n + 1..]),
+                "message" | "msg" => (Field::Message, &s[n + 1..]),
+                "pid" => (Field::Process, &s[n + 1..]),
+                "tid" => (Field::Thread, &s[n + 1..]),
+                _ => (Field::Any, s),
+            },
+            None => (Field::Any, s),
+        };
+        let regex =
+            Regex::new(regex).map_err(|e| format_err!("Invalid regex string: {}: {}", regex, e))?;
+        Ok(Suppress { field, regex })
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        match self.field {
+            Field::Any => self.regex.is_match(&record.tag) || self.regex.is_match(&record.message),
+            Field::Tag => self.regex.is_match(&record.tag),
+            Field::Message => self.regex.is_match(&record.message),
+            Field::Process => self.regex.is_match(&record.process),
+            Field::Thread => self.regex.is_match(&record.thread),
+        }
+    }
 }
 
 pub fn from_args_profile<'a>(args: &ArgMatches<'a>, profile: &Profile) -> Result<Filter, Error> {
+    let pid_of = args.value_of("pid_of").map(PidOf::new).transpose()?;
+    build(args, profile, pid_of)
+}
+
+fn build<'a>(
+    args: &ArgMatches<'a>,
+    profile: &Profile,
+    pid_of: Option<PidOf>,
+) -> Result<Filter, Error> {
     let tag = profile.tag.iter().map(String::as_str);
     let tag_ignorecase = profile.tag_ignore_case.iter().map(String::as_str);
     let message = profile.message.iter().map(String::as_str);
     let message_ignorecase = profile.message_ignore_case.iter().map(String::as_str);
     let regex = profile.regex.iter().map(String::as_str);
+    let tag_level = args
+        .values_of("tag_level")
+        .map(Iterator::collect::<Vec<_>>)
+        .unwrap_or_default()
+        .into_iter()
+        .chain(profile.tag_level.iter().map(String::as_str))
+        .map(tag_level)
+        .collect::<Result<Vec<_>, Error>>()?;
+    let expressions = args
+        .values_of("filter")
+        .map(Iterator::collect::<Vec<_>>)
+        .unwrap_or_default()
+        .into_iter()
+        .chain(profile.filter.iter().map(String::as_str))
+        .filter(|f| !profile.is_disabled("filter", f))
+        .map(str::parse)
+        .collect::<Result<Vec<Expression>, Error>>()?;
+    let suppress = args
+        .values_of("suppress")
+        .map(Iterator::collect::<Vec<_>>)
+        .unwrap_or_default()
+        .into_iter()
+        .chain(profile.suppress.iter().map(String::as_str))
+        .map(Suppress::parse)
+        .collect::<Result<Vec<_>, Error>>()?;
     let filter = Filter {
         level: Level::from(args.value_of("level").unwrap_or("")),
-        tag: FilterGroup::from_args(args, "tag", tag, false)?,
-        tag_ignore_case: FilterGroup::from_args(args, "tag-ignore-case", tag_ignorecase, true)?,
-        message: FilterGroup::from_args(args, "message", message, false)?,
+        tag_level,
+        tag: FilterGroup::from_args(args, profile, "tag", tag, false)?,
+        tag_ignore_case: FilterGroup::from_args(
+            args,
+            profile,
+            "tag-ignore-case",
+            tag_ignorecase,
+            true,
+        )?,
+        message: FilterGroup::from_args(args, profile, "message", message, false)?,
         message_ignore_case: FilterGroup::from_args(
             args,
+            profile,
             "message-ignore-case",
             message_ignorecase,
             true,
         )?,
-        regex: FilterGroup::from_args(args, "regex_filter", regex, false)?,
+        regex: FilterGroup::from_args(args, profile, "regex_filter", regex, false)?,
+        expressions,
+        suppress,
+        pid_of,
     };
 
     Ok(filter)
 }
 
+/// Parse a TAG:LEVEL tuple. The tag is a regex and the level
+/// is anything understood by `Level::from`
+fn tag_level(s: &str) -> Result<(Regex, Level), Error> {
+    let mut split = s.rsplitn(2, ':');
+    let level = split.next().map(Level::from).unwrap_or_default();
+    let tag = split
+        .next()
+        .ok_or_else(|| format_err!("Invalid tag level {}. Use TAG:LEVEL", s))?;
+    if level == Level::None {
+        return Err(format_err!("Invalid level in tag level {}", s));
+    }
+    let tag = Regex::new(tag).map_err(|e| format_err!("Invalid regex string: {}: {}", tag, e))?;
+    Ok((tag, level))
+}
+
 impl Filter {
+    /// Replace the filters with the ones from args and an updated profile.
+    /// The pid resolution is kept.
+    pub fn reload<'a>(&mut self, args: &ArgMatches<'a>, profile: &Profile) -> Result<(), Error> {
+        let mut filter = build(args, profile, None)?;
+        filter.pid_of = self.pid_of.take();
+        *self = filter;
+        Ok(())
+    }
+
     pub fn filter(&self, record: &Record) -> bool {
-        if record.level < self.level {
+        if self.suppress.iter().any(|s| s.matches(record)) {
+            return false;
+        }
+
+        // Process start and death messages must be seen regardless of other filters
+        if let Some(ref pid_of) = self.pid_of {
+            if !pid_of.filter(record) {
+                return false;
+            }
+        }
+
+        // Tag specific levels take precedence over the global level
+        let level = self
+            .tag_level
+            .iter()
+            .find(|(tag, _)| tag.is_match(&record.tag))
+            .map(|(_, level)| level)
+            .unwrap_or(&self.level);
+        if record.level < *level {
             return false;
         }
 
@@ -72,6 +210,7 @@ impl Filter {
                 || self.regex.filter(&record.thread)
                 || self.regex.filter(&record.tag)
                 || self.regex.filter(&record.message))
+            && self.expressions.iter().all(|e| e.matches(record))
     }
 }
 
@@ -85,6 +224,7 @@ struct FilterGroup {
 impl FilterGroup {
     fn from_args<'a, T: Iterator<Item = &'a str>>(
         args: &'a ArgMatches<'a>,
+        profile: &Profile,
         flag: &str,
         merge: T,
         ignore_case: bool,
@@ -94,6 +234,7 @@ impl FilterGroup {
             .map(Iterator::collect)
             .unwrap_or_default();
         filters.extend(merge);
+        filters.retain(|f| !profile.is_disabled(flag, f));
 
         let mut positive = vec![];
         let mut negative = vec![];
@@ -146,3 +287,22 @@ impl FilterGroup {
         true
     }
 }
+
+#[test]
+fn suppress() {
+    let record = Record {
+        tag: "Spammy".into(),
+        message: "Chatty says hello".into(),
+        process: "123".into(),
+        ..Default::default()
+    };
+    assert!(Suppress::parse("^Chatty").unwrap().matches(&record));
+    assert!(Suppress::parse("tag:^Spam").unwrap().matches(&record));
+    assert!(!Suppress::parse("tag:^Chatty").unwrap().matches(&record));
+    assert!(Suppress::parse("msg:hello$").unwrap().matches(&record));
+    assert!(Suppress::parse("pid:^123$").unwrap().matches(&record));
+    assert!(!Suppress::parse("tid:.").unwrap().matches(&record));
+    // Unknown prefixes are part of the regex
+    assert!(!Suppress::parse("says:hello").unwrap().matches(&record));
+    assert!(Suppress::parse("tag:(").is_err());
+}
@@ -0,0 +1,265 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tail growing input files with `--follow` like `tail -F`. Every file is
+//! read on a own thread that waits for the file to appear and reopens it when
+//! truncated or rotated. Records of all files are merged by timestamp and
+//! tagged with the file name in the device column.
+
+use crate::{LogStream, StreamData};
+use failure::Error;
+use futures::{
+    sync::mpsc::{channel, Receiver, Sender},
+    Async, Poll, Sink, Stream,
+};
+use rogcat::{parser::Parser, record::Record};
+use std::{
+    fs::{metadata, File},
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// Interval of checks for new data at the end of a file
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+enum Event {
+    Record(Box<Record>),
+    /// The end of the file is reached. The source is up to date
+    Idle,
+}
+
+/// Identity of a file to detect rotation
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    metadata(path).ok().map(|m| m.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_: &Path) -> Option<u64> {
+    None
+}
+
+/// Read path forever and send the records of each line
+fn tail(path: &Path, name: &str, tx: Sender<Event>) {
+    let mut tx = tx.wait();
+    let mut parser = Parser::default();
+    let mut announced = false;
+    loop {
+        let mut file = match File::open(path) {
+            Ok(file) => BufReader::new(file),
+            Err(_) => {
+                // Files that do not exist yet are up to date
+                if !announced {
+                    announced = true;
+                    if tx.send(Event::Idle).is_err() {
+                        return;
+                    }
+                }
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+        };
+        let id = file_id(path);
+        let mut position = 0;
+        let mut line = vec![];
+        let mut idle = false;
+        loop {
+            match file.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => {
+                    if !idle {
+                        idle = true;
+                        if tx.send(Event::Idle).is_err() {
+                            return;
+                        }
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                    // Reopen truncated or replaced files
+                    let truncated = metadata(path).map(|m| m.len() < position).unwrap_or(true);
+                    if truncated || file_id(path) != id {
+                        break;
+                    }
+                }
+                Ok(n) => {
+                    position += n as u64;
+                    // Wait for the rest of a partially written line
+                    if !line.ends_with(b"\n") {
+                        continue;
+                    }
+                    while line.ends_with(b"\n") || line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                    if let Some(mut record) = parser.parse_line(&String::from_utf8_lossy(&line)) {
+                        record.device = Some(name.to_owned());
+                        idle = false;
+                        if tx.send(Event::Record(Box::new(record))).is_err() {
+                            return;
+                        }
+                    }
+                    line.clear();
+                }
+            }
+        }
+        announced = true;
+    }
+}
+
+struct Source {
+    rx: Receiver<Event>,
+    head: Option<Record>,
+    idle: bool,
+    done: bool,
+}
+
+/// Merge of the records of several sources by timestamp. A record is emitted
+/// when every source has a record pending or is up to date.
+struct Merge {
+    sources: Vec<Source>,
+}
+
+impl Merge {
+    fn new(receivers: Vec<Receiver<Event>>) -> Merge {
+        let sources = receivers
+            .into_iter()
+            .map(|rx| Source {
+                rx,
+                head: None,
+                idle: false,
+                done: false,
+            })
+            .collect();
+        Merge { sources }
+    }
+}
+
+impl Stream for Merge {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut waiting = false;
+        for source in &mut self.sources {
+            while source.head.is_none() && !source.done {
+                match source.rx.poll() {
+                    Ok(Async::Ready(Some(Event::Record(record)))) => {
+                        source.head = Some(*record);
+                        source.idle = false;
+                    }
+                    Ok(Async::Ready(Some(Event::Idle))) => source.idle = true,
+                    Ok(Async::Ready(None)) | Err(_) => source.done = true,
+                    Ok(Async::NotReady) => break,
+                }
+            }
+            waiting |= source.head.is_none() && !source.idle && !source.done;
+        }
+        if waiting {
+            return Ok(Async::NotReady);
+        }
+
+        // Records without timestamp are emitted first
+        let next = self
+            .sources
+            .iter_mut()
+            .filter(|s| s.head.is_some())
+            .min_by_key(|s| {
+                s.head
+                    .as_ref()
+                    .and_then(|r| r.timestamp.as_ref())
+                    .map(|t| t.to_timespec())
+            });
+        match next.and_then(|s| s.head.take()) {
+            Some(record) => Ok(Async::Ready(Some(StreamData::Record(Box::new(record))))),
+            None if self.sources.iter().all(|s| s.done) => Ok(Async::Ready(None)),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// Tail files and merge their records
+pub fn files(files: Vec<PathBuf>) -> LogStream {
+    let receivers = files
+        .into_iter()
+        .map(|path| {
+            let (tx, rx) = channel(1024);
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string());
+            thread::spawn(move || tail(&path, &name, tx));
+            rx
+        })
+        .collect();
+    Box::new(Merge::new(receivers))
+}
+
+#[test]
+fn merge() {
+    use futures::{future::lazy, Future};
+    use rogcat::record::Timestamp;
+
+    let record = |t: i64, m: &str| Record {
+        timestamp: Some(Timestamp::new(time::at_utc(time::Timespec::new(t, 0)))),
+        message: m.into(),
+        ..Default::default()
+    };
+    let (a, a_rx) = channel(10);
+    let (b, b_rx) = channel(10);
+    let mut merge = Merge::new(vec![a_rx, b_rx]);
+    let mut poll = || {
+        lazy(|| {
+            Ok::<_, ()>(match merge.poll().unwrap() {
+                Async::Ready(Some(StreamData::Record(r))) => Some(r.message),
+                _ => None,
+            })
+        })
+        .wait()
+        .unwrap()
+    };
+
+    let a = a
+        .send(Event::Record(Box::new(record(1, "A1"))))
+        .wait()
+        .unwrap();
+    let a = a
+        .send(Event::Record(Box::new(record(3, "A3"))))
+        .wait()
+        .unwrap();
+    let a = a.send(Event::Idle).wait().unwrap();
+    // Source b is not up to date yet
+    assert_eq!(poll(), None);
+    let b = b
+        .send(Event::Record(Box::new(record(2, "B2"))))
+        .wait()
+        .unwrap();
+    let b = b.send(Event::Idle).wait().unwrap();
+    assert_eq!(poll(), Some("A1".to_owned()));
+    assert_eq!(poll(), Some("B2".to_owned()));
+    // b is idle and does not hold back a
+    assert_eq!(poll(), Some("A3".to_owned()));
+    assert_eq!(poll(), None);
+    let _b = b
+        .send(Event::Record(Box::new(record(0, "B0"))))
+        .wait()
+        .unwrap();
+    assert_eq!(poll(), Some("B0".to_owned()));
+    drop(a);
+}
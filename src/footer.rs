@@ -0,0 +1,201 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Footer line with `--stats-line` below the scrolling terminal output. The
+//! footer shows the records per second, the error rate and a sparkline of the
+//! volume of the last minute. It is redrawn after each record and once a
+//! second.
+
+use failure::Error;
+use rogcat::record::{Level, Record};
+use std::{
+    collections::VecDeque,
+    io::{stdout, Write},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Seconds shown in the sparkline
+const WINDOW: usize = 60;
+const BARS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Move to the start of the line and clear it
+const CLEAR: &[u8] = b"\r\x1b[2K";
+
+/// Records and errors per second
+#[derive(Default)]
+struct Counters {
+    /// Counts of the last seconds. The last entry is the current second
+    seconds: VecDeque<(usize, usize)>,
+    /// Second of the last entry since start
+    current: u64,
+    /// Output ended and the footer is no longer drawn
+    finished: bool,
+}
+
+impl Counters {
+    /// Advance to second now
+    fn tick(&mut self, now: u64) {
+        while self.current < now || self.seconds.is_empty() {
+            self.seconds.push_back((0, 0));
+            if self.seconds.len() > WINDOW {
+                self.seconds.pop_front();
+            }
+            if self.current < now {
+                self.current += 1;
+            }
+        }
+    }
+
+    fn count(&mut self, now: u64, error: bool) {
+        self.tick(now);
+        let last = self.seconds.back_mut().expect("Missing counter");
+        last.0 += 1;
+        if error {
+            last.1 += 1;
+        }
+    }
+
+    /// Text of the footer with at most width columns
+    fn line(&mut self, now: u64, width: usize) -> String {
+        self.tick(now);
+        // The current second is incomplete
+        let rate = self.seconds.iter().rev().nth(1).map(|s| s.0).unwrap_or(0);
+        let (records, errors) = self
+            .seconds
+            .iter()
+            .fold((0, 0), |(r, e), s| (r + s.0, e + s.1));
+        let error_rate = if records == 0 {
+            0.0
+        } else {
+            errors as f64 * 100.0 / records as f64
+        };
+        let text = format!(" {}/s │ errors {:.1}% │ ", rate, error_rate);
+        let bars = width.saturating_sub(text.chars().count()).min(WINDOW);
+        let volume = self.seconds.iter().map(|s| s.0).collect::<Vec<_>>();
+        let volume = &volume[volume.len().saturating_sub(bars)..];
+        format!("{}{}", text, sparkline(volume))
+    }
+}
+
+/// Bars scaled to the maximum of values
+fn sparkline(values: &[usize]) -> String {
+    let max = values.iter().cloned().max().unwrap_or(0);
+    values
+        .iter()
+        .map(|v| match v {
+            0 => ' ',
+            v => BARS[(v * (BARS.len() - 1)).div_ceil(max)],
+        })
+        .collect()
+}
+
+/// Footer drawn on stdout
+#[derive(Clone)]
+pub struct Footer {
+    counters: Arc<Mutex<Counters>>,
+    start: Instant,
+    width: usize,
+}
+
+impl Footer {
+    /// Create a footer and redraw it once a second
+    pub fn spawn(width: usize) -> Footer {
+        let footer = Footer {
+            counters: Arc::new(Mutex::new(Counters::default())),
+            start: Instant::now(),
+            width,
+        };
+        let redraw = footer.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+            if redraw.draw(|| Ok(())).is_err() {
+                break;
+            }
+        });
+        footer
+    }
+
+    /// Count record and print it with f above the footer
+    pub fn print<F>(&self, record: &Record, f: F) -> Result<(), Error>
+    where
+        F: FnOnce() -> Result<(), Error>,
+    {
+        let now = self.start.elapsed().as_secs();
+        let error = record.level >= Level::Error;
+        self.counters
+            .lock()
+            .expect("Failed to lock footer")
+            .count(now, error);
+        self.draw(f)
+    }
+
+    /// Move below the footer
+    pub fn finish(&self) -> Result<(), Error> {
+        self.counters
+            .lock()
+            .expect("Failed to lock footer")
+            .finished = true;
+        let mut stdout = stdout();
+        stdout.write_all(b"\n")?;
+        stdout.flush().map_err(Into::into)
+    }
+
+    /// Clear the footer, run f and draw the footer again
+    fn draw<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce() -> Result<(), Error>,
+    {
+        let mut counters = self.counters.lock().expect("Failed to lock footer");
+        if counters.finished {
+            return f();
+        }
+        let mut stdout = stdout();
+        stdout.write_all(CLEAR)?;
+        stdout.flush()?;
+        f()?;
+        let line = counters.line(self.start.elapsed().as_secs(), self.width);
+        stdout.write_all(format!("\x1b[7m{}\x1b[0m", line).as_bytes())?;
+        stdout.flush().map_err(Into::into)
+    }
+}
+
+#[test]
+fn stats_line() {
+    assert_eq!(sparkline(&[0, 1, 4, 8]), " ▂▅█");
+    assert_eq!(sparkline(&[]), "");
+
+    let mut counters = Counters::default();
+    for _ in 0..3 {
+        counters.count(0, true);
+    }
+    for _ in 0..5 {
+        counters.count(1, false);
+    }
+    counters.count(2, false);
+    assert_eq!(counters.line(2, 80), " 5/s │ errors 33.3% │ ▆█▃");
+    // Narrow terminals get a shorter sparkline
+    assert_eq!(counters.line(2, 23), " 5/s │ errors 33.3% │ █");
+    // Counts older than a minute are dropped
+    assert_eq!(
+        counters.line(100, 100),
+        format!(" 0/s │ errors 0.0% │ {}", " ".repeat(60))
+    );
+}
@@ -0,0 +1,239 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Use rogcat as a CI log gate: stop with `--exit-on`, fail with `--fail-on`
+//! and fail if the log did not end within `--timeout`. With `--report` the
+//! outcome is written as JUnit XML with one testcase per `--fail-on` pattern
+//! and the matching records attached to the failures.
+
+use crate::{html::escape, utils, RecordStream};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{Async, Future, Poll, Stream};
+use regex::Regex;
+use rogcat::record::Record;
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use tokio::timer::Delay;
+
+/// Records kept per pattern in the report
+const MAX_REPORTED: usize = 100;
+
+#[derive(Default)]
+struct State {
+    fail_on: Vec<(Regex, usize, Vec<Record>)>,
+    timeout: Option<(Duration, bool)>,
+    report: Option<PathBuf>,
+    start: Option<Instant>,
+    finished: bool,
+}
+
+/// Shared result of the gate checked on exit
+#[derive(Clone, Default)]
+pub struct Outcome(Arc<Mutex<State>>);
+
+impl Outcome {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.0.lock().expect("Failed to lock gate")
+    }
+
+    pub fn failed(&self) -> bool {
+        let state = self.state();
+        state.fail_on.iter().any(|(_, n, _)| *n > 0) || state.timeout.map(|t| t.1).unwrap_or(false)
+    }
+
+    /// Write the report once
+    pub fn finish(&self) {
+        let mut state = self.state();
+        if state.finished {
+            return;
+        }
+        state.finished = true;
+        if let Some(ref file) = state.report {
+            if let Err(e) = fs::write(file, junit(&state)) {
+                eprintln!("Failed to write report {}: {}", file.display(), e);
+            }
+        }
+    }
+}
+
+/// Render the state as JUnit XML
+fn junit(state: &State) -> String {
+    let elapsed = state
+        .start
+        .map(|s| s.elapsed().as_secs_f64())
+        .unwrap_or_default();
+    let mut cases = state
+        .fail_on
+        .iter()
+        .map(|(regex, count, records)| {
+            let name = format!("fail-on {}", regex.as_str());
+            let failure = if *count > 0 {
+                let text = records
+                    .iter()
+                    .map(|r| r.raw.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                Some((format!("{} matching records", count), text))
+            } else {
+                None
+            };
+            (name, failure)
+        })
+        .collect::<Vec<_>>();
+    if let Some((duration, expired)) = state.timeout {
+        let failure = if expired {
+            Some((
+                format!("Timeout after {}s", duration.as_secs()),
+                String::new(),
+            ))
+        } else {
+            None
+        };
+        cases.push(("timeout".to_owned(), failure));
+    }
+
+    let failures = cases.iter().filter(|c| c.1.is_some()).count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites>\n  <testsuite name=\"rogcat\" tests=\"{}\" failures=\"{}\" errors=\"0\" time=\"{:.3}\">\n",
+        cases.len(),
+        failures,
+        elapsed
+    ));
+    for (name, failure) in cases {
+        xml.push_str(&format!(
+            "    <testcase classname=\"rogcat\" name=\"{}\"",
+            escape(&name)
+        ));
+        match failure {
+            Some((message, text)) => xml.push_str(&format!(
+                ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                escape(&message),
+                escape(&text)
+            )),
+            None => xml.push_str("/>\n"),
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+struct Gate {
+    records: RecordStream,
+    exit_on: Vec<Regex>,
+    /// Delay of `--timeout`
+    timeout: Option<Delay>,
+    /// Keep running after a `--fail-on` match to report all matches
+    collect: bool,
+    outcome: Outcome,
+    done: bool,
+}
+
+impl Stream for Gate {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+
+        if let Some(ref mut delay) = self.timeout {
+            if let Async::Ready(()) = delay
+                .poll()
+                .map_err(|e| format_err!("Timer error: {}", e))?
+            {
+                let mut state = self.outcome.state();
+                if let Some(ref mut timeout) = state.timeout {
+                    timeout.1 = true;
+                    return Err(format_err!("Timeout after {}s", timeout.0.as_secs()));
+                }
+            }
+        }
+
+        let record = match self.records.poll()? {
+            Async::Ready(Some(record)) => record,
+            r => return Ok(r),
+        };
+
+        let mut state = self.outcome.state();
+        for (regex, count, records) in &mut state.fail_on {
+            if regex.is_match(&record.message) {
+                eprintln!("Matched --fail-on {}: {}", regex.as_str(), record.message);
+                *count += 1;
+                if records.len() < MAX_REPORTED {
+                    records.push(record.clone());
+                }
+                self.done |= !self.collect;
+            }
+        }
+        self.done |= self.exit_on.iter().any(|r| r.is_match(&record.message));
+        Ok(Async::Ready(Some(record)))
+    }
+}
+
+fn regexes<'a>(args: &ArgMatches<'a>, name: &str) -> Result<Vec<Regex>, Error> {
+    args.values_of(name)
+        .map(|v| v.collect::<Vec<_>>())
+        .unwrap_or_default()
+        .iter()
+        .map(|r| Regex::new(r).map_err(|e| format_err!("Invalid regex string: {}: {}", r, e)))
+        .collect()
+}
+
+/// Wrap records into a gate if any of the CI options is present
+pub fn from_args<'a>(
+    args: &ArgMatches<'a>,
+    records: RecordStream,
+) -> Result<(RecordStream, Outcome), Error> {
+    let exit_on = regexes(args, "exit_on")?;
+    let fail_on = regexes(args, "fail_on")?;
+    let timeout = args.value_of("timeout").map(utils::interval).transpose()?;
+    let report = args.value_of("report").map(PathBuf::from);
+    let report_present = report.is_some();
+    let outcome = Outcome::default();
+
+    if exit_on.is_empty() && fail_on.is_empty() && timeout.is_none() && report.is_none() {
+        return Ok((records, outcome));
+    }
+
+    {
+        let mut state = outcome.state();
+        state.fail_on = fail_on.into_iter().map(|r| (r, 0, vec![])).collect();
+        state.timeout = timeout.map(|t| (t, false));
+        state.report = report;
+        state.start = Some(Instant::now());
+    }
+
+    let gate = Gate {
+        records,
+        exit_on,
+        timeout: timeout.map(|t| Delay::new(Instant::now() + t)),
+        collect: report_present,
+        outcome: outcome.clone(),
+        done: false,
+    };
+    Ok((Box::new(gate), outcome))
+}
@@ -0,0 +1,543 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! gRPC endpoint of `rogcat serve --grpc`. Clients subscribe to the records
+//! with `rogcat.Rogcat/Subscribe` as defined in `proto/service.proto` and
+//! an optional filter expression. Server reflection is supported for tools
+//! like `grpcurl`.
+
+use crate::{expression::Expression, LogSink};
+use bytes::{BufMut, Bytes, BytesMut};
+use clap::ArgMatches;
+use failure::{err_msg, format_err, Error};
+use futures::{sync::mpsc, try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use h2::{
+    server::{self, SendResponse},
+    RecvStream, SendStream,
+};
+use http::{HeaderMap, HeaderValue, Request, Response};
+use rogcat::{
+    protobuf::{self, fields, put_bytes, put_message, put_uint, Field},
+    record::Record,
+};
+use std::{
+    cmp::min,
+    net::{SocketAddr, TcpListener as StdTcpListener, ToSocketAddrs},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+};
+use tokio::{net::TcpListener, reactor::Handle};
+
+const SUBSCRIBE: &str = "/rogcat.Rogcat/Subscribe";
+const REFLECTION: &str = "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo";
+const SERVICE: &str = "rogcat.Rogcat";
+/// Records queued per subscriber before records are dropped
+const QUEUE: usize = 1024;
+
+/// gRPC status codes
+const OK: u32 = 0;
+const INVALID_ARGUMENT: u32 = 3;
+const NOT_FOUND: u32 = 5;
+const UNIMPLEMENTED: u32 = 12;
+
+type Subscribers = Arc<Mutex<Vec<(Option<Expression>, mpsc::Sender<Bytes>)>>>;
+
+/// Prefix a message with the compression flag and length
+fn frame(message: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(message.len() + 5);
+    buf.put_u8(0);
+    buf.put_u32_be(message.len() as u32);
+    buf.put_slice(message);
+    buf.freeze()
+}
+
+fn response() -> Response<()> {
+    Response::builder()
+        .status(200)
+        .header("content-type", "application/grpc")
+        .body(())
+        .expect("Failed to build response")
+}
+
+fn status(code: u32, message: &str) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(code));
+    if let Ok(message) = HeaderValue::from_str(message) {
+        if !message.is_empty() {
+            trailers.insert("grpc-message", message);
+        }
+    }
+    trailers
+}
+
+/// Messages of a request body
+struct Messages {
+    body: RecvStream,
+    buf: BytesMut,
+}
+
+impl Messages {
+    fn new(body: RecvStream) -> Messages {
+        Messages {
+            body,
+            buf: BytesMut::new(),
+        }
+    }
+}
+
+impl Stream for Messages {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        loop {
+            if self.buf.len() >= 5 {
+                if self.buf[0] != 0 {
+                    return Err(err_msg("Compressed messages are not supported"));
+                }
+                let len = self.buf[1..5]
+                    .iter()
+                    .fold(0usize, |len, b| len << 8 | *b as usize);
+                if self.buf.len() >= len + 5 {
+                    let mut message = self.buf.split_to(len + 5);
+                    message.advance(5);
+                    return Ok(Async::Ready(Some(message.freeze())));
+                }
+            }
+            match try_ready!(self.body.poll()) {
+                Some(data) => {
+                    self.body.release_capacity().release_capacity(data.len())?;
+                    self.buf.extend_from_slice(&data);
+                }
+                None if self.buf.is_empty() => return Ok(Async::Ready(None)),
+                None => return Err(err_msg("Truncated message")),
+            }
+        }
+    }
+}
+
+/// Stream of the records of a subscriber to the client
+struct Subscription {
+    /// Dropping the request before its end cancels the call
+    _request: Messages,
+    records: mpsc::Receiver<Bytes>,
+    send: SendStream<Bytes>,
+    pending: Option<Bytes>,
+}
+
+impl Future for Subscription {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        loop {
+            // The client cancelled the call
+            if let Async::Ready(_) = self.send.poll_reset()? {
+                return Ok(Async::Ready(()));
+            }
+
+            let mut pending = match self.pending.take() {
+                Some(pending) => pending,
+                None => match self.records.poll() {
+                    Ok(Async::Ready(Some(frame))) => {
+                        self.send.reserve_capacity(frame.len());
+                        frame
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    // The log ended
+                    Ok(Async::Ready(None)) | Err(_) => {
+                        self.send.send_trailers(status(OK, ""))?;
+                        return Ok(Async::Ready(()));
+                    }
+                },
+            };
+
+            // Send as much as the flow control permits
+            let capacity = match self.send.capacity() {
+                0 => match self.send.poll_capacity()? {
+                    Async::Ready(Some(capacity)) => capacity,
+                    Async::Ready(None) => return Ok(Async::Ready(())),
+                    Async::NotReady => {
+                        self.pending = Some(pending);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                capacity => capacity,
+            };
+            let chunk = pending.split_to(min(capacity, pending.len()));
+            self.send.send_data(chunk, false)?;
+            if !pending.is_empty() {
+                self.send.reserve_capacity(pending.len());
+                self.pending = Some(pending);
+            }
+        }
+    }
+}
+
+fn string(buf: &[u8]) -> String {
+    String::from_utf8_lossy(buf).into_owned()
+}
+
+/// Register a subscriber with the filter of the request
+fn subscribe(
+    request: &[u8],
+    messages: Messages,
+    mut respond: SendResponse<Bytes>,
+    subscribers: &Subscribers,
+) -> Result<Option<Subscription>, Error> {
+    let mut filter = String::new();
+    fields(request, |field, value| {
+        if let (1, Field::Bytes(b)) = (field, value) {
+            filter = string(b);
+        }
+        Ok(())
+    })?;
+    let filter = match filter.trim() {
+        "" => None,
+        filter => match Expression::from_str(filter) {
+            Ok(filter) => Some(filter),
+            Err(e) => {
+                // Trailers only response
+                let mut response = response();
+                response
+                    .headers_mut()
+                    .extend(status(INVALID_ARGUMENT, &e.to_string()));
+                respond.send_response(response, true)?;
+                return Ok(None);
+            }
+        },
+    };
+
+    let (tx, records) = mpsc::channel(QUEUE);
+    subscribers
+        .lock()
+        .expect("Failed to lock subscribers")
+        .push((filter, tx));
+    let send = respond.send_response(response(), false)?;
+    Ok(Some(Subscription {
+        _request: messages,
+        records,
+        send,
+        pending: None,
+    }))
+}
+
+fn field(name: &str, number: u64, kind: u64, type_name: Option<&str>) -> Vec<u8> {
+    const LABEL_OPTIONAL: u64 = 1;
+    let mut buf = vec![];
+    put_bytes(&mut buf, 1, name.as_bytes());
+    put_uint(&mut buf, 3, number);
+    put_uint(&mut buf, 4, LABEL_OPTIONAL);
+    put_uint(&mut buf, 5, kind);
+    if let Some(type_name) = type_name {
+        put_bytes(&mut buf, 6, type_name.as_bytes());
+    }
+    buf
+}
+
+fn message(name: &str, fields: &[Vec<u8>]) -> Vec<u8> {
+    let mut buf = vec![];
+    put_bytes(&mut buf, 1, name.as_bytes());
+    for field in fields {
+        put_message(&mut buf, 2, field);
+    }
+    buf
+}
+
+/// `FileDescriptorProto` of `proto/record.proto`
+fn record_descriptor() -> Vec<u8> {
+    const INT64: u64 = 3;
+    const INT32: u64 = 5;
+    const STRING: u64 = 9;
+    const MESSAGE: u64 = 11;
+    const ENUM: u64 = 14;
+
+    let mut buf = vec![];
+    put_bytes(&mut buf, 1, b"record.proto");
+    put_bytes(&mut buf, 2, b"rogcat");
+    let timestamp = message(
+        "Timestamp",
+        &[
+            field("seconds", 1, INT64, None),
+            field("nanos", 2, INT32, None),
+        ],
+    );
+    put_message(&mut buf, 4, &timestamp);
+    let record = message(
+        "Record",
+        &[
+            field("timestamp", 1, MESSAGE, Some(".rogcat.Timestamp")),
+            field("message", 2, STRING, None),
+            field("level", 3, ENUM, Some(".rogcat.Level")),
+            field("tag", 4, STRING, None),
+            field("process", 5, STRING, None),
+            field("thread", 6, STRING, None),
+            field("raw", 7, STRING, None),
+            field("device", 8, STRING, None),
+            field("buffer", 9, STRING, None),
+            field("process_name", 10, STRING, None),
+        ],
+    );
+    put_message(&mut buf, 4, &record);
+    let mut level = vec![];
+    put_bytes(&mut level, 1, b"Level");
+    let levels = [
+        "NONE", "TRACE", "VERBOSE", "DEBUG", "INFO", "WARN", "ERROR", "FATAL", "ASSERT",
+    ];
+    for (number, name) in levels.iter().enumerate() {
+        let mut value = vec![];
+        put_bytes(&mut value, 1, name.as_bytes());
+        put_uint(&mut value, 2, number as u64);
+        put_message(&mut level, 2, &value);
+    }
+    put_message(&mut buf, 5, &level);
+    put_bytes(&mut buf, 12, b"proto3");
+    buf
+}
+
+/// `FileDescriptorProto` of `proto/service.proto`
+fn service_descriptor() -> Vec<u8> {
+    const STRING: u64 = 9;
+
+    let mut buf = vec![];
+    put_bytes(&mut buf, 1, b"service.proto");
+    put_bytes(&mut buf, 2, b"rogcat");
+    put_bytes(&mut buf, 3, b"record.proto");
+    let request = message("SubscribeRequest", &[field("filter", 1, STRING, None)]);
+    put_message(&mut buf, 4, &request);
+    let mut method = vec![];
+    put_bytes(&mut method, 1, b"Subscribe");
+    put_bytes(&mut method, 2, b".rogcat.SubscribeRequest");
+    put_bytes(&mut method, 3, b".rogcat.Record");
+    put_uint(&mut method, 6, 1);
+    let mut service = vec![];
+    put_bytes(&mut service, 1, b"Rogcat");
+    put_message(&mut service, 2, &method);
+    put_message(&mut buf, 6, &service);
+    put_bytes(&mut buf, 12, b"proto3");
+    buf
+}
+
+/// Answer a `ServerReflectionRequest` with a `ServerReflectionResponse`
+fn reflect(request: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut host = String::new();
+    let mut query = None;
+    fields(request, |field, value| {
+        match (field, value) {
+            (1, Field::Bytes(b)) => host = string(b),
+            (n @ 3..=7, Field::Bytes(b)) => query = Some((n, string(b))),
+            _ => (),
+        }
+        Ok(())
+    })?;
+
+    let mut response = vec![];
+    put_bytes(&mut response, 1, host.as_bytes());
+    put_message(&mut response, 2, request);
+
+    let files = match query {
+        // list_services
+        Some((7, _)) => {
+            let mut service = vec![];
+            put_bytes(&mut service, 1, SERVICE.as_bytes());
+            let mut list = vec![];
+            put_message(&mut list, 1, &service);
+            put_message(&mut response, 6, &list);
+            return Ok(response);
+        }
+        // file_by_filename
+        Some((3, ref file)) if file == "record.proto" => vec![record_descriptor()],
+        Some((3, ref file)) if file == "service.proto" => {
+            vec![service_descriptor(), record_descriptor()]
+        }
+        // file_containing_symbol
+        Some((4, ref symbol))
+            if ["rogcat.Record", "rogcat.Timestamp", "rogcat.Level"].contains(&symbol.as_str()) =>
+        {
+            vec![record_descriptor()]
+        }
+        Some((4, ref symbol))
+            if symbol == SERVICE
+                || symbol.starts_with("rogcat.Rogcat.")
+                || symbol == "rogcat.SubscribeRequest" =>
+        {
+            vec![service_descriptor(), record_descriptor()]
+        }
+        Some((n, ref what)) => {
+            let (code, message) = match n {
+                3 | 4 => (NOT_FOUND, format!("{} not found", what)),
+                _ => (UNIMPLEMENTED, "Extensions are not supported".to_owned()),
+            };
+            let mut error = vec![];
+            put_uint(&mut error, 1, u64::from(code));
+            put_bytes(&mut error, 2, message.as_bytes());
+            put_message(&mut response, 7, &error);
+            return Ok(response);
+        }
+        None => return Err(err_msg("Invalid reflection request")),
+    };
+
+    let mut descriptors = vec![];
+    for file in files {
+        put_message(&mut descriptors, 1, &file);
+    }
+    put_message(&mut response, 4, &descriptors);
+    Ok(response)
+}
+
+/// Handle a call
+fn call(
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    subscribers: Subscribers,
+) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+    let path = request.uri().path().to_owned();
+    let messages = Messages::new(request.into_body());
+    match path.as_str() {
+        SUBSCRIBE => Box::new(
+            messages
+                .into_future()
+                .map_err(|(e, _)| e)
+                .and_then(move |(request, messages)| {
+                    let request = request.unwrap_or_default();
+                    subscribe(&request, messages, respond, &subscribers)
+                })
+                .and_then(|subscription| match subscription {
+                    Some(subscription) => {
+                        Box::new(subscription) as Box<dyn Future<Item = _, Error = _> + Send>
+                    }
+                    None => Box::new(futures::future::ok(())),
+                }),
+        ),
+        REFLECTION => {
+            let send = match respond.send_response(response(), false) {
+                Ok(send) => send,
+                Err(e) => return Box::new(futures::future::err(e.into())),
+            };
+            Box::new(
+                messages
+                    .fold(send, |mut send, request| {
+                        send.send_data(frame(&reflect(&request)?), false)?;
+                        Ok::<_, Error>(send)
+                    })
+                    .and_then(|mut send| send.send_trailers(status(OK, "")).map_err(Into::into)),
+            )
+        }
+        path => {
+            let mut response = response();
+            response
+                .headers_mut()
+                .extend(status(UNIMPLEMENTED, &format!("Unknown method {}", path)));
+            Box::new(futures::future::result(
+                respond
+                    .send_response(response, true)
+                    .map(drop)
+                    .map_err(Into::into),
+            ))
+        }
+    }
+}
+
+/// Accept connections and serve calls until the process exits
+fn server(listener: TcpListener, subscribers: Subscribers) -> impl Future<Item = (), Error = ()> {
+    listener
+        .incoming()
+        .map_err(|e| eprintln!("Failed to accept gRPC connection: {}", e))
+        .for_each(move |socket| {
+            let subscribers = subscribers.clone();
+            let connection = server::handshake(socket)
+                .and_then(move |connection| {
+                    connection.for_each(move |(request, respond)| {
+                        let call = call(request, respond, subscribers.clone())
+                            .map_err(|e| eprintln!("gRPC call failed: {}", e));
+                        tokio::spawn(call);
+                        Ok(())
+                    })
+                })
+                // Closed connections are not reported
+                .map_err(drop);
+            tokio::spawn(connection);
+            Ok(())
+        })
+}
+
+/// Sink sending records to the subscribers
+struct Broadcast {
+    subscribers: Subscribers,
+}
+
+impl Sink for Broadcast {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let mut subscribers = self.subscribers.lock().expect("Failed to lock subscribers");
+        let mut encoded = None;
+        // Slow subscribers miss records and disconnected ones are removed
+        subscribers.retain_mut(|(filter, tx)| {
+            if filter.as_ref().map(|f| f.matches(&record)).unwrap_or(true) {
+                let frame = encoded.get_or_insert_with(|| frame(&protobuf::encode(&record)));
+                match tx.try_send(frame.clone()) {
+                    Err(ref e) if e.is_disconnected() => return false,
+                    _ => (),
+                }
+            }
+            true
+        });
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        // End the subscriptions
+        self.subscribers
+            .lock()
+            .expect("Failed to lock subscribers")
+            .clear();
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Start the gRPC server if requested and return a sink for the records
+pub fn serve<'a>(args: &ArgMatches<'a>) -> Result<Option<LogSink>, Error> {
+    let addr = match args.value_of("grpc") {
+        Some(addr) => addr,
+        None => return Ok(None),
+    };
+    let addr: SocketAddr = addr
+        .to_socket_addrs()
+        .map_err(|e| format_err!("Invalid address {}: {}", addr, e))?
+        .next()
+        .ok_or_else(|| format_err!("Invalid address {}", addr))?;
+    let listener = StdTcpListener::bind(addr)
+        .map_err(|e| format_err!("Failed to listen on {}: {}", addr, e))?;
+    eprintln!("Serving gRPC on {}", listener.local_addr()?);
+    let listener = TcpListener::from_std(listener, &Handle::default())?;
+
+    let subscribers = Subscribers::default();
+    let server = server(listener, subscribers.clone());
+    thread::spawn(move || tokio::run(server));
+
+    Ok(Some(Box::new(Broadcast { subscribers })))
+}
@@ -0,0 +1,104 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Stamp records with the host time of their arrival with
+//! `--host-timestamps`. Device clocks are often wrong or reset at boot. The
+//! timestamp at the start of raw lines is replaced as well so that all
+//! output formats show the host time. With `--host-timestamps=keep` the
+//! device time is kept in the field `device_time`.
+
+use clap::ArgMatches;
+use rogcat::record::{Record, Timestamp};
+use time::Tm;
+
+/// Field of the device time
+pub const DEVICE_TIME: &str = "device_time";
+
+#[derive(Clone, Copy, Debug)]
+pub struct HostTime {
+    keep: bool,
+}
+
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Option<HostTime> {
+    if args.is_present("host_timestamps") {
+        Some(HostTime {
+            keep: args.value_of("host_timestamps") == Some("keep"),
+        })
+    } else {
+        None
+    }
+}
+
+/// Format of threadtime raw lines
+fn format(tm: &Tm) -> String {
+    let mut ts = time::strftime("%m-%d %H:%M:%S.%f", tm).unwrap_or_default();
+    ts.truncate(18);
+    ts
+}
+
+/// Set the timestamp of record and replace the one at the start of the raw
+/// line. Returns the previous timestamp formatted like in raw lines
+pub fn retime(record: &mut Record, tm: Tm) -> Option<String> {
+    let previous = record.timestamp.take().map(|ts| format(&ts));
+    if let Some(ref previous) = previous {
+        if record.raw.starts_with(previous) {
+            record.raw = format!("{}{}", format(&tm), &record.raw[previous.len()..]);
+        }
+    }
+    record.timestamp = Some(Timestamp::new(tm));
+    previous
+}
+
+impl HostTime {
+    pub fn stamp(self, record: &mut Record) {
+        self.stamp_at(record, time::now())
+    }
+
+    fn stamp_at(self, record: &mut Record, now: Tm) {
+        if let Some(device) = retime(record, now) {
+            if self.keep {
+                record.fields.insert(DEVICE_TIME.to_owned(), device);
+            }
+        }
+    }
+}
+
+#[test]
+fn stamp() {
+    let now = time::strptime("03-02 10:11:12.345", "%m-%d %H:%M:%S.%f").unwrap();
+    let parse = || {
+        rogcat::parser::Parser::default()
+            .parse_line("01-01 00:00:01.000  123  456 I Tag: A")
+            .unwrap()
+    };
+
+    let mut record = parse();
+    HostTime { keep: false }.stamp_at(&mut record, now);
+    assert_eq!(record.raw, "03-02 10:11:12.345  123  456 I Tag: A");
+    assert_eq!(
+        format(record.timestamp.as_ref().unwrap()),
+        "03-02 10:11:12.345"
+    );
+    assert!(record.fields.is_empty());
+
+    let mut record = parse();
+    HostTime { keep: true }.stamp_at(&mut record, now);
+    assert_eq!(record.fields[DEVICE_TIME], "01-01 00:00:01.000");
+}
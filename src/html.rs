@@ -0,0 +1,282 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::terminal::hashed_color;
+use failure::Error;
+use rogcat::record::{Level, Record};
+use std::io::Write;
+use termcolor::Color;
+
+/// Streaming html writer. The page header is written upon creation,
+/// one table row per record and the footer when the writer is dropped
+pub struct Html<W: Write> {
+    writer: W,
+    finished: bool,
+}
+
+impl<W: Write> Html<W> {
+    pub fn new(mut writer: W) -> Result<Html<W>, Error> {
+        writer.write_all(HEADER.as_bytes())?;
+        Ok(Html {
+            writer,
+            finished: false,
+        })
+    }
+
+    /// Write a table row and return the number of bytes written
+    pub fn write(&mut self, record: &Record, index: usize) -> Result<usize, Error> {
+        let row = row(record, index);
+        self.writer.write_all(row.as_bytes())?;
+        Ok(row.len())
+    }
+
+    /// Write the page footer and flush the underlying writer
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if !self.finished {
+            self.finished = true;
+            self.writer.write_all(FOOTER.as_bytes())?;
+        }
+        self.writer.flush().map_err(Into::into)
+    }
+}
+
+impl<W: Write> Drop for Html<W> {
+    fn drop(&mut self) {
+        self.finish().ok();
+    }
+}
+
+/// Escape the html special characters in s
+pub fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Convert a terminal color into a css color
+fn css_color(color: &Color) -> String {
+    const ANSI: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+
+    let (r, g, b) = match *color {
+        Color::Black => ANSI[0],
+        Color::Red => ANSI[9],
+        Color::Green => ANSI[10],
+        Color::Yellow => ANSI[11],
+        Color::Blue => ANSI[12],
+        Color::Magenta => ANSI[13],
+        Color::Cyan => ANSI[14],
+        Color::White => ANSI[15],
+        Color::Ansi256(c) if c < 16 => ANSI[c as usize],
+        Color::Ansi256(c) if c < 232 => {
+            // 6x6x6 color cube
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            let c = c - 16;
+            (level(c / 36), level((c / 6) % 6), level(c % 6))
+        }
+        Color::Ansi256(c) => {
+            // Grayscale ramp
+            let v = 8 + (c - 232) * 10;
+            (v, v, v)
+        }
+        Color::Rgb(r, g, b) => (r, g, b),
+        _ => ANSI[7],
+    };
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Colorize value with the same color used in the terminal
+fn colored(value: &str) -> String {
+    if value.is_empty() || value == "0" {
+        format!("<span style=\"color:grey\">{}</span>", escape(value))
+    } else {
+        format!(
+            "<span style=\"color:{}\">{}</span>",
+            css_color(&hashed_color(value)),
+            escape(value)
+        )
+    }
+}
+
+/// Message cell content. Multi line messages are collapsed
+/// to their first line and expand on click
+fn message(message: &str) -> String {
+    let mut lines = message.lines();
+    match (lines.next(), lines.next()) {
+        (Some(first), Some(_)) => {
+            let rest = message[first.len()..].trim_start_matches(&['\r', '\n'][..]);
+            format!(
+                "<details><summary>{}</summary><pre>{}</pre></details>",
+                escape(first),
+                escape(rest)
+            )
+        }
+        _ => escape(message),
+    }
+}
+
+/// Render a record as table row
+pub fn row(record: &Record, index: usize) -> String {
+    let timestamp = record
+        .timestamp
+        .as_ref()
+        .and_then(|ts| time::strftime("%m-%d %H:%M:%S.%f", ts).ok())
+        .map(|mut ts| {
+            ts.truncate(18);
+            ts
+        })
+        .unwrap_or_default();
+    let level = match record.level {
+        Level::None => String::new(),
+        ref level => level.to_string(),
+    };
+    format!(
+        "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"level level-{}\">{}</td><td class=\"message message-{}\">{}</td></tr>\n",
+        index,
+        timestamp,
+        colored(&record.tag),
+        colored(&record.process),
+        colored(&record.thread),
+        level,
+        level,
+        level,
+        message(&record.message)
+    )
+}
+
+const HEADER: &str = r#"<!doctype HTML>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Rogcat</title>
+<style>
+body {background: black; color: #BBBBBB; font-family: 'Source Code Pro', Monaco, monospace; font-size: 12px}
+table {
+    border-spacing: 0;
+    width: 100%;
+}
+td {
+    vertical-align: top;
+    padding-bottom: 0;
+    padding-left: 2ex;
+    padding-right: 2ex;
+    white-space: nowrap;
+}
+tr:hover {
+    background: #260041;
+}
+td.level {
+    text-align: center;
+}
+td.level-D, td.level-V, td.level-T {
+    color: white;
+    background: #555;
+}
+td.level-I {
+    color: black;
+    background: #A8FF60;
+}
+td.level-W {
+    color: black;
+    background: #FFFFB6;
+}
+td.level-E, td.level-F, td.level-A {
+    color: black;
+    background: #FF6C60;
+}
+td.message {
+    white-space: pre-wrap;
+}
+td.message-I {color: #A8FF60}
+td.message-W {color: #FFFFB6}
+td.message-E, td.message-F, td.message-A {color: #FF6C60}
+details summary {
+    cursor: pointer;
+}
+details pre {
+    margin: 0;
+    font-family: inherit;
+}
+table tr td:first-child + td + td {
+    text-align: right
+}
+table tr td:first-child + td + td + td + td {
+    text-align: right
+}
+</style>
+</head>
+<body>
+<table>
+"#;
+
+const FOOTER: &str = "</table>
+</body>
+</html>
+";
+
+#[test]
+fn escape_html() {
+    assert_eq!(
+        escape("<a href=\"x\">&</a>"),
+        "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+    );
+}
+
+#[test]
+fn css_colors() {
+    assert_eq!(css_color(&Color::Ansi256(16)), "#000000");
+    assert_eq!(css_color(&Color::Ansi256(196)), "#ff0000");
+    assert_eq!(css_color(&Color::Ansi256(244)), "#808080");
+    assert_eq!(css_color(&Color::Rgb(1, 2, 3)), "#010203");
+}
+
+#[test]
+fn collapse_multiline_message() {
+    assert_eq!(message("a"), "a");
+    assert_eq!(
+        message("a\nb\nc"),
+        "<details><summary>a</summary><pre>b\nc</pre></details>"
+    );
+}
@@ -0,0 +1,188 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Seekable index of captures written with `--index`. The index is a
+//! sidecar `<file>.idx` listing the byte offset and timestamp of every Nth
+//! record. `rogcat replay --since` seeks to the last entry before the
+//! requested time instead of scanning the capture from the start.
+
+use failure::{format_err, Error};
+use rogcat::record::Record;
+use std::{
+    fs::{read_to_string, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use time::{Timespec, Tm};
+
+/// First line of index files
+const HEADER: &str = "# rogcat index";
+
+/// Records between two entries if not specified
+pub const INTERVAL: usize = 1000;
+
+/// Filename of the index of `file`
+pub fn sidecar(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".idx");
+    PathBuf::from(name)
+}
+
+/// Milliseconds of a timestamp ignoring the utc offset
+fn millis(tm: &Tm) -> i64 {
+    let mut tm = *tm;
+    tm.tm_utcoff = 0;
+    let spec = tm.to_timespec();
+    spec.sec * 1000 + i64::from(spec.nsec) / 1_000_000
+}
+
+/// True if `tm` is before `since`. Timestamps without a year e.g from
+/// threadtime captures are compared ignoring the year of `since`
+pub fn before(tm: &Tm, since: &Tm) -> bool {
+    let mut since = *since;
+    if tm.tm_year == 0 {
+        since.tm_year = 0;
+    }
+    millis(tm) < millis(&since)
+}
+
+/// Parse a `--since` argument like "01-02 03:04:05" or
+/// "2024-01-02 03:04:05.678" into a timestamp
+pub fn since(s: &str) -> Result<Tm, Error> {
+    let (s, fraction) = match s.rfind('.') {
+        Some(n) => (&s[..n], &s[n + 1..]),
+        None => (s, "0"),
+    };
+    let tm = ["%Y-%m-%d %H:%M:%S", "%m-%d %H:%M:%S"]
+        .iter()
+        .find_map(|format| time::strptime(s, format).ok())
+        .ok_or_else(|| format_err!("Invalid time {}: Expecting [YYYY-]MM-DD HH:MM:SS[.mmm]", s))?;
+    let nsec = format!("{:0<9.9}", fraction)
+        .parse::<i32>()
+        .map_err(|e| format_err!("Invalid fraction {}: {}", fraction, e))?;
+    Ok(Tm {
+        tm_nsec: nsec,
+        ..tm
+    })
+}
+
+/// Writes an index entry every `interval` records with a timestamp
+pub struct Writer {
+    file: BufWriter<File>,
+    filename: PathBuf,
+    interval: usize,
+    records: usize,
+    /// Record number of the next entry
+    next: usize,
+    /// Offset of the next record
+    offset: u64,
+}
+
+impl Writer {
+    /// Create the index of `file`. Offset is the size of headers written
+    /// before the first record
+    pub fn create(file: &Path, interval: usize, offset: u64) -> Result<Writer, Error> {
+        let filename = sidecar(file);
+        let mut file = File::create(&filename)
+            .map(BufWriter::new)
+            .map_err(|e| format_err!("Failed to create index {}: {}", filename.display(), e))?;
+        writeln!(file, "{}", HEADER)?;
+        Ok(Writer {
+            file,
+            filename,
+            interval,
+            records: 0,
+            next: 0,
+            offset,
+        })
+    }
+
+    /// Account record that is written with `bytes`
+    pub fn add(&mut self, record: &Record, bytes: usize) -> Result<(), Error> {
+        if self.records >= self.next {
+            if let Some(ref timestamp) = record.timestamp {
+                writeln!(
+                    self.file,
+                    "{} {} {}",
+                    self.records,
+                    self.offset,
+                    millis(timestamp)
+                )
+                .map_err(|e| format_err!("Failed to write {}: {}", self.filename.display(), e))?;
+                self.next = self.records + self.interval;
+            }
+        }
+        self.records += 1;
+        self.offset += bytes as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.file
+            .flush()
+            .map_err(|e| format_err!("Failed to write {}: {}", self.filename.display(), e))
+    }
+}
+
+/// Offset of the last indexed record of `file` before `since`. None if
+/// there's no index or the first entry is not before `since`
+pub fn offset(file: &Path, since: &Tm) -> Option<u64> {
+    let index = read_to_string(sidecar(file)).ok()?;
+    let mut lines = index.lines();
+    if lines.next() != Some(HEADER) {
+        return None;
+    }
+    lines
+        .filter_map(|line| {
+            let mut fields = line.split(' ').skip(1);
+            let offset = fields.next()?.parse::<u64>().ok()?;
+            let millis = fields.next()?.parse::<i64>().ok()?;
+            let tm = time::at_utc(Timespec::new(
+                millis.div_euclid(1000),
+                (millis.rem_euclid(1000) * 1_000_000) as i32,
+            ));
+            Some((offset, tm))
+        })
+        .take_while(|(_, tm)| before(tm, since))
+        .last()
+        .map(|(offset, _)| offset)
+}
+
+#[test]
+fn index() {
+    let file = crate::tests::utils::tempdir().unwrap().join("capture.log");
+    let mut parser = rogcat::parser::Parser::default();
+    let mut writer = Writer::create(&file, 2, 10).unwrap();
+    for second in 0..5 {
+        let line = format!("01-02 03:04:0{}.500  1  1 I Foo: bar", second);
+        let record = parser.parse_line(&line).unwrap();
+        writer.add(&record, line.len() + 1).unwrap();
+    }
+    writer.flush().unwrap();
+
+    // Entries at record 0, 2 and 4 with 36 bytes per line
+    let at = |s| offset(&file, &since(s).unwrap());
+    assert_eq!(at("01-02 03:04:00"), None);
+    assert_eq!(at("01-02 03:04:00.600"), Some(10));
+    assert_eq!(at("01-02 03:04:03"), Some(82));
+    assert_eq!(at("2024-01-02 03:04:10"), Some(154));
+    assert!(since("yesterday").is_err());
+    assert_eq!(since("01-02 03:04:05.12").unwrap().tm_nsec, 120_000_000);
+}
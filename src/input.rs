@@ -0,0 +1,65 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Inputs addressed by an url like `serial:///dev/ttyUSB0` or `tcp://0.0.0.0:1234`.
+//! Each kind of input is a `LogSource` registered for one or more url schemes.
+
+use crate::{
+    record::Record,
+    registry::{self, Schemes},
+};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::Stream;
+
+/// Item of an input: a line that is parsed later or a decoded record
+#[derive(Debug, Clone)]
+pub enum StreamData {
+    Record(Box<Record>),
+    Line(String),
+    /// Line no parser recognized as record with the line as message
+    Unparsed(Box<Record>),
+}
+
+/// Stream of an input
+pub type LogStream = Box<dyn Stream<Item = StreamData, Error = Error> + Send>;
+
+/// A source of log lines or records
+pub trait LogSource: Schemes + Send + Sync {
+    /// Open the input addressed by url
+    fn open(&self, url: &str, args: &ArgMatches) -> Result<LogStream, Error>;
+}
+
+/// Sources by url scheme
+pub type Registry = registry::Registry<dyn LogSource>;
+
+impl Registry {
+    /// Open input with the source registered for its scheme
+    pub fn open(&self, input: &str, args: &ArgMatches) -> Result<LogStream, Error> {
+        self.get(input)
+            .ok_or_else(|| format_err!("No input source for {}", input))?
+            .open(input, args)
+    }
+}
+
+/// Add source to the registries built afterwards
+pub fn register(source: Box<dyn LogSource>) {
+    registry::register(source)
+}
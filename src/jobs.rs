@@ -0,0 +1,135 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Parallel processing with `--jobs`. Files are parsed, filtered and
+//! encoded for output files on a pool of worker threads. Records are
+//! processed in batches of the records available without waiting and are
+//! emitted in their original order.
+
+use crate::RecordStream;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{Async, Poll, Stream};
+use rayon::prelude::*;
+use rogcat::record::Record;
+use std::collections::VecDeque;
+
+/// Maximum number of records processed at once
+pub const BATCH: usize = 4096;
+
+/// Start the worker threads if `--jobs` is passed
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Result<Option<usize>, Error> {
+    let jobs = match args.value_of("jobs") {
+        Some(jobs) => jobs
+            .parse::<usize>()
+            .ok()
+            .filter(|jobs| *jobs > 0)
+            .ok_or_else(|| format_err!("Invalid jobs {}", jobs))?,
+        None => return Ok(None),
+    };
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build_global()
+        .map_err(|e| format_err!("Failed to start {} jobs: {}", jobs, e))?;
+    Ok(Some(jobs))
+}
+
+/// Filter records on the worker threads
+pub fn filter<F>(records: RecordStream, matches: F) -> RecordStream
+where
+    F: Fn(&Record) -> bool + Send + Sync + 'static,
+{
+    Box::new(Filter {
+        records,
+        matches,
+        ready: VecDeque::new(),
+        done: false,
+    })
+}
+
+struct Filter<F> {
+    records: RecordStream,
+    matches: F,
+    /// Records of the last batch that passed
+    ready: VecDeque<Record>,
+    done: bool,
+}
+
+impl<F: Fn(&Record) -> bool + Send + Sync> Stream for Filter<F> {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        loop {
+            if let Some(record) = self.ready.pop_front() {
+                return Ok(Async::Ready(Some(record)));
+            }
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            let mut batch = Vec::new();
+            while batch.len() < BATCH {
+                match self.records.poll()? {
+                    Async::Ready(Some(record)) => batch.push(record),
+                    Async::Ready(None) => {
+                        self.done = true;
+                        break;
+                    }
+                    Async::NotReady => break,
+                }
+            }
+            if batch.is_empty() && !self.done {
+                return Ok(Async::NotReady);
+            }
+
+            let matches = &self.matches;
+            let passed = batch.par_iter().map(matches).collect::<Vec<_>>();
+            self.ready.extend(
+                batch
+                    .into_iter()
+                    .zip(passed)
+                    .filter_map(|(record, passed)| if passed { Some(record) } else { None }),
+            );
+        }
+    }
+}
+
+#[test]
+fn filter_order() {
+    use futures::{stream::iter_ok, Future};
+
+    let records = (0..10_000)
+        .map(|i| Record {
+            message: i.to_string(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    let records = Box::new(iter_ok(records)) as RecordStream;
+    let filtered = filter(records, |r| r.message.ends_with('7'))
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(filtered.len(), 1000);
+    assert!(filtered
+        .iter()
+        .enumerate()
+        .all(|(i, r)| r.message == (i * 10 + 7).to_string()));
+}
@@ -0,0 +1,149 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::LogSink;
+use failure::{format_err, Error};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend};
+use rogcat::record::Record;
+use std::{io::ErrorKind, os::unix::net::UnixDatagram, path::Path};
+
+const SOCKET: &str = "/run/systemd/journal/socket";
+/// Entry exceeds the maximum datagram size
+const EMSGSIZE: i32 = 90;
+
+/// Append a field in the native journal protocol. Values with newlines
+/// are length prefixed
+fn field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// Journal entry of a record
+fn entry(record: &Record) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(record.message.len() + 128);
+    field(&mut entry, "MESSAGE", &record.message);
+    field(&mut entry, "PRIORITY", &record.level.severity().to_string());
+    field(&mut entry, "SYSLOG_IDENTIFIER", "rogcat");
+    field(&mut entry, "TAG", &record.tag);
+    field(&mut entry, "ANDROID_PID", &record.process);
+    field(&mut entry, "ANDROID_TID", &record.thread);
+    if let Some(ref device) = record.device {
+        field(&mut entry, "DEVICE", device);
+    }
+    if let Some(ref buffer) = record.buffer {
+        field(&mut entry, "ANDROID_BUFFER", buffer);
+    }
+    if let Some(ref name) = record.process_name {
+        field(&mut entry, "ANDROID_PROCESS", name);
+    }
+    if let Some(ref timestamp) = record.timestamp {
+        let timestamp = ::time::strftime("%m-%d %H:%M:%S.%f", timestamp).unwrap_or_default();
+        field(&mut entry, "ANDROID_TIMESTAMP", &timestamp);
+    }
+    entry
+}
+
+/// Sink writing records to the systemd journal
+struct Journal {
+    socket: UnixDatagram,
+    dropped: usize,
+}
+
+impl Journal {
+    fn connect(path: &Path) -> Result<Journal, Error> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path).map_err(|e| {
+            format_err!("Failed to connect to the journal {}: {}", path.display(), e)
+        })?;
+        Ok(Journal { socket, dropped: 0 })
+    }
+}
+
+impl Sink for Journal {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        match self.socket.send(&entry(&record)) {
+            Ok(_) => (),
+            // Entries exceeding the datagram size are not passed via memfd
+            Err(ref e) if e.raw_os_error() == Some(EMSGSIZE) => self.dropped += 1,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => self.dropped += 1,
+            Err(e) => return Err(format_err!("Failed to write to the journal: {}", e)),
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        if self.dropped > 0 {
+            eprintln!(
+                "Dropped {} records while writing to the journal",
+                self.dropped
+            );
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Create a sink writing to the systemd journal
+pub fn try_from() -> Result<LogSink, Error> {
+    Ok(Box::new(Journal::connect(Path::new(SOCKET))?))
+}
+
+#[test]
+fn journal_entry() {
+    use rogcat::record::Level;
+
+    let path = crate::tests::utils::tempdir().unwrap().join("socket");
+    let journal = UnixDatagram::bind(&path).unwrap();
+    let mut sink = Journal::connect(&path).unwrap();
+
+    let record = Record {
+        message: "first\nsecond".to_owned(),
+        level: Level::Warn,
+        tag: "Tag".to_owned(),
+        process: "123".to_owned(),
+        thread: "456".to_owned(),
+        device: Some("A1".to_owned()),
+        ..Default::default()
+    };
+    sink.start_send(record).unwrap();
+
+    let mut buffer = [0u8; 1024];
+    let len = journal.recv(&mut buffer).unwrap();
+    let mut expected = b"MESSAGE\n".to_vec();
+    expected.extend_from_slice(&12u64.to_le_bytes());
+    expected.extend_from_slice(b"first\nsecond\n");
+    expected.extend_from_slice(
+        b"PRIORITY=4\nSYSLOG_IDENTIFIER=rogcat\nTAG=Tag\nANDROID_PID=123\nANDROID_TID=456\nDEVICE=A1\n",
+    );
+    assert_eq!(&buffer[..len], &expected[..]);
+}
@@ -18,5 +18,13 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod binary;
+pub mod events;
+pub mod input;
+pub mod msgpack;
+pub mod output;
 pub mod parser;
+pub mod protobuf;
 pub mod record;
+pub mod registry;
+pub mod template;
@@ -0,0 +1,121 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Log levels of tags on the device. `Log.isLoggable` and the native logger
+//! read the minimum level of a tag from the property `log.tag.TAG`.
+
+use crate::{profiles, utils::device_cmd};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use std::{
+    collections::BTreeMap,
+    process::{exit, Command, Stdio},
+};
+
+/// Value of the property for level. The default clears the property
+fn value(level: &str) -> Result<&'static str, Error> {
+    match level.to_lowercase().as_str() {
+        "v" | "verbose" | "t" | "trace" => Ok("VERBOSE"),
+        "d" | "debug" => Ok("DEBUG"),
+        "i" | "info" => Ok("INFO"),
+        "w" | "warn" => Ok("WARN"),
+        "e" | "error" => Ok("ERROR"),
+        "a" | "assert" | "f" | "fatal" => Ok("ASSERT"),
+        "s" | "suppress" => Ok("SUPPRESS"),
+        "default" => Ok("\"\""),
+        _ => Err(format_err!(
+            "Invalid level {}. Use verbose, debug, info, warn, error, assert, suppress or default",
+            level
+        )),
+    }
+}
+
+/// Set the level of tag on the device
+fn setprop(adb: &[String], tag: &str, level: &str) -> Result<(), Error> {
+    if tag.is_empty() || tag.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+        return Err(format_err!("Invalid tag {:?}", tag));
+    }
+    let value = value(level)?;
+    let output = Command::new(&adb[0])
+        .args(&adb[1..])
+        .args(["shell", "setprop", &format!("log.tag.{}", tag), value])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format_err!("Failed to run adb: {}", e))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() || !stderr.trim().is_empty() {
+        return Err(format_err!(
+            "Failed to set the level of {}: {}",
+            tag,
+            stderr.trim()
+        ));
+    }
+    Ok(())
+}
+
+fn set_all(adb: &[String], levels: &BTreeMap<String, String>) -> Result<(), Error> {
+    for (tag, level) in levels {
+        setprop(adb, tag, level)?;
+    }
+    Ok(())
+}
+
+/// Set the level of a tag or the log levels of the profile and exit
+pub fn run<'a>(args: &ArgMatches<'a>, sub: &ArgMatches<'a>) {
+    let result = device_cmd(args).and_then(|adb| match sub.value_of("TAG") {
+        Some(tag) => setprop(&adb, tag, sub.value_of("LEVEL").expect("Missing level")),
+        None => {
+            let profile = profiles::from_args(args)?.resolve();
+            if profile.log_levels.is_empty() {
+                return Err(format_err!("No log_levels in the selected profile"));
+            }
+            set_all(&adb, &profile.log_levels)
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(1);
+    }
+    exit(0);
+}
+
+/// Set the log levels of profile before a live capture. Failures are reported
+/// and the capture continues
+pub fn apply<'a>(args: &ArgMatches<'a>, profile: &profiles::Profile) {
+    let live = !args.is_present("input")
+        && !args.is_present("COMMAND")
+        && !args.is_present("all_devices")
+        && args.subcommand_name().is_none();
+    if !live || profile.log_levels.is_empty() {
+        return;
+    }
+    if let Err(e) = device_cmd(args).and_then(|adb| set_all(&adb, &profile.log_levels)) {
+        eprintln!("{}", e);
+    }
+}
+
+#[test]
+fn level_values() {
+    assert_eq!(value("D").unwrap(), "DEBUG");
+    assert_eq!(value("verbose").unwrap(), "VERBOSE");
+    assert_eq!(value("Fatal").unwrap(), "ASSERT");
+    assert_eq!(value("default").unwrap(), "\"\"");
+    assert!(value("loud").is_err());
+}
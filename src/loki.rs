@@ -0,0 +1,322 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::LogSink;
+use clap::ArgMatches;
+use failure::{err_msg, format_err, Error};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend};
+use native_tls::TlsConnector;
+use rogcat::record::{Level, Record};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// Records queued for pushing. Records are dropped if Loki cannot keep up
+const QUEUE: usize = 16_384;
+/// Maximum number of records per push
+const BATCH_SIZE: usize = 1024;
+/// Maximum time a record waits for a batch to fill
+const BATCH_WAIT: Duration = Duration::from_secs(1);
+/// Delay before the first retry of a failed push. Doubled on every retry
+const BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const RETRIES: usize = 8;
+/// Retries of a push after the sink is closed to not delay the exit
+const CLOSE_RETRIES: usize = 3;
+const PUSH_PATH: &str = "/loki/api/v1/push";
+
+/// Level label as understood by Grafana
+fn level(level: &Level) -> &'static str {
+    match level {
+        Level::None => "unknown",
+        Level::Trace | Level::Verbose => "trace",
+        Level::Debug => "debug",
+        Level::Info => "info",
+        Level::Warn => "warning",
+        Level::Error => "error",
+        Level::Fatal | Level::Assert => "critical",
+    }
+}
+
+/// Record received at a time in nanoseconds since the epoch
+type Entry = (String, Record);
+
+/// Push request body. Records are grouped into streams by their labels
+fn body(batch: &[Entry]) -> String {
+    let mut streams: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for (timestamp, record) in batch {
+        let labels = (
+            record.device.as_deref().unwrap_or_default(),
+            record.tag.as_str(),
+            level(&record.level),
+        );
+        let line = if record.raw.is_empty() {
+            &record.message
+        } else {
+            &record.raw
+        };
+        streams
+            .entry(labels)
+            .or_default()
+            .push(json!([timestamp, line]));
+    }
+    let streams = streams
+        .into_iter()
+        .map(|((device, tag, level), values)| {
+            let mut labels = json!({ "tag": tag, "level": level });
+            if !device.is_empty() {
+                labels["device"] = json!(device);
+            }
+            json!({ "stream": labels, "values": values })
+        })
+        .collect::<Vec<_>>();
+    json!({ "streams": streams }).to_string()
+}
+
+/// Endpoint of the push API
+struct Endpoint {
+    addr: SocketAddr,
+    host: String,
+    path: String,
+    tls: Option<TlsConnector>,
+}
+
+impl Endpoint {
+    fn new(url: &Url) -> Result<Endpoint, Error> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| format_err!("Missing host in {}", url))?
+            .to_owned();
+        let tls = match url.scheme() {
+            "http" => None,
+            "https" => Some(TlsConnector::new()?),
+            scheme => return Err(format_err!("Unsupported Loki scheme {}", scheme)),
+        };
+        let addr = url
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format_err!("Failed to parse addr {}", url))?;
+        // The push path is appended to urls without a path
+        let path = match url.path() {
+            "" | "/" => PUSH_PATH.to_owned(),
+            path => path.to_owned(),
+        };
+        Ok(Endpoint {
+            addr,
+            host,
+            path,
+            tls,
+        })
+    }
+
+    /// Post a body and fail on errors that are worth a retry
+    fn push(&self, body: &str) -> Result<(), Error> {
+        let stream = TcpStream::connect(self.addr)?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        let status = match self.tls {
+            Some(ref tls) => {
+                let stream = tls
+                    .connect(&self.host, stream)
+                    .map_err(|e| format_err!("TLS handshake failed: {}", e))?;
+                self.post(stream, body)?
+            }
+            None => self.post(stream, body)?,
+        };
+        match status {
+            200..=299 => Ok(()),
+            // Rejected records are dropped unless Loki is rate limiting
+            400..=499 if status != 429 => {
+                eprintln!("Loki rejected {} bytes with status {}", body.len(), status);
+                Ok(())
+            }
+            _ => Err(format_err!("Loki responded with status {}", status)),
+        }
+    }
+
+    /// Send a request and return the response status
+    fn post<S: Read + Write>(&self, mut stream: S, body: &str) -> Result<u16, Error> {
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        status
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| format_err!("Invalid Loki response {}", status.trim()))
+    }
+}
+
+/// Collect batches and push them with retries until the sink is closed
+fn pusher(endpoint: &Endpoint, rx: &Receiver<Entry>, closed: &AtomicBool) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut disconnected = false;
+    while !disconnected {
+        let deadline = Instant::now() + BATCH_WAIT;
+        while batch.len() < BATCH_SIZE {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok(entry) => batch.push(entry),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    disconnected = true;
+                    break;
+                }
+            }
+        }
+        if batch.is_empty() {
+            continue;
+        }
+
+        let body = body(&batch);
+        let mut backoff = BACKOFF;
+        for retry in 0.. {
+            match endpoint.push(&body) {
+                Ok(_) => break,
+                Err(e)
+                    if retry == RETRIES
+                        || (retry >= CLOSE_RETRIES && closed.load(Ordering::Relaxed)) =>
+                {
+                    eprintln!("Dropped {} records pushing to Loki: {}", batch.len(), e);
+                    break;
+                }
+                Err(_) => {
+                    sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+        batch.clear();
+    }
+}
+
+/// Push records to Grafana Loki from a thread that batches and retries
+struct Loki {
+    tx: Option<SyncSender<Entry>>,
+    thread: Option<JoinHandle<()>>,
+    closed: Arc<AtomicBool>,
+    dropped: usize,
+}
+
+impl Sink for Loki {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let now = time::get_time();
+        let timestamp = (i64::from(now.nsec) + now.sec * 1_000_000_000).to_string();
+        if let Some(ref tx) = self.tx {
+            match tx.try_send((timestamp, record)) {
+                Ok(_) => (),
+                Err(TrySendError::Full(_)) => self.dropped += 1,
+                Err(TrySendError::Disconnected(_)) => return Err(err_msg("Loki push stopped")),
+            }
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        // Push the queued records
+        self.closed.store(true, Ordering::Relaxed);
+        self.tx.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        if self.dropped > 0 {
+            eprintln!("Dropped {} records while pushing to Loki", self.dropped);
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Construct a Loki sink from args if requested
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Result<Option<LogSink>, Error> {
+    let url = match args.value_of("loki") {
+        Some(url) => Url::parse(url)?,
+        None => return Ok(None),
+    };
+    let endpoint = Endpoint::new(&url)?;
+    let (tx, rx) = sync_channel(QUEUE);
+    let closed = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let closed = closed.clone();
+        thread::spawn(move || pusher(&endpoint, &rx, &closed))
+    };
+    Ok(Some(Box::new(Loki {
+        tx: Some(tx),
+        thread: Some(thread),
+        closed,
+        dropped: 0,
+    })))
+}
+
+#[test]
+fn push_body() {
+    let record = |tag: &str, level: Level| Record {
+        tag: tag.to_owned(),
+        level,
+        raw: format!("{} raw", tag),
+        device: Some("A1".to_owned()),
+        ..Default::default()
+    };
+    let batch = vec![
+        ("1".to_owned(), record("A", Level::Info)),
+        ("2".to_owned(), record("B", Level::Warn)),
+        ("3".to_owned(), record("A", Level::Info)),
+    ];
+    let body: serde_json::Value = serde_json::from_str(&body(&batch)).unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "streams": [
+                {
+                    "stream": { "device": "A1", "tag": "A", "level": "info" },
+                    "values": [["1", "A raw"], ["3", "A raw"]]
+                },
+                {
+                    "stream": { "device": "A1", "tag": "B", "level": "warning" },
+                    "values": [["2", "B raw"]]
+                }
+            ]
+        })
+    );
+}
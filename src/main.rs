@@ -19,57 +19,150 @@
 // SOFTWARE.
 
 use failure::Error;
-use futures::{sync::oneshot, Future, Sink, Stream};
-use rogcat::{parser, record::Record};
-use std::{process::exit, str::FromStr};
+use futures::{sync::oneshot, Future, Stream};
+use rogcat::{
+    input::{LogStream, StreamData},
+    output::LogSink,
+    parser,
+    record::Record,
+};
+use std::{process::exit, str::FromStr, sync::Arc};
 use tokio::runtime::Runtime;
 use tokio_signal::ctrl_c;
-use url::Url;
 
+mod adbclient;
+mod adbserver;
+mod analyze;
+mod bootloop;
+mod budget;
+mod bugreport;
 mod cli;
+mod clock;
+mod context;
+mod control;
+mod cpu;
+mod dedupe;
+mod diff;
+mod expression;
 mod filewriter;
 mod filter;
+mod follow;
+mod footer;
+mod gate;
+mod grpc;
+mod hosttime;
+mod html;
+mod index;
+mod jobs;
+#[cfg(target_os = "linux")]
+mod journald;
+#[cfg(not(target_os = "linux"))]
+mod journald {
+    pub fn try_from() -> Result<crate::LogSink, failure::Error> {
+        Err(failure::err_msg(
+            "The systemd journal is only available on Linux",
+        ))
+    }
+}
+mod loglevel;
+mod loki;
 mod lossy_lines;
+mod marker;
+mod mmap;
+mod network;
+mod notify;
+mod pidof;
+mod procnames;
 mod profiles;
+mod props;
+mod queue;
 mod reader;
+mod relative;
+mod replay;
+mod retrace;
+mod run;
+mod sample;
+mod script;
+mod session;
+mod shutdown;
+mod sink;
+mod snooze;
+mod sort;
+mod source;
+mod stacktrace;
+mod stats;
+mod strict;
 mod subcommands;
+mod symbols;
 mod terminal;
 #[cfg(all(test, not(target_os = "windows")))]
 mod tests;
+mod theme;
+mod trace;
+mod transform;
+mod triggers;
+mod tui;
 mod utils;
+mod vitals;
+mod web;
 
 const DEFAULT_BUFFER: [&str; 4] = ["main", "events", "crash", "kernel"];
 
-#[derive(Debug, Clone)]
-pub enum StreamData {
-    Record(Record),
-    Line(String),
-}
-
-type LogStream = Box<dyn Stream<Item = StreamData, Error = Error> + Send>;
-type LogSink = Box<dyn Sink<SinkItem = Record, SinkError = Error> + Send>;
+type RecordStream = Box<dyn Stream<Item = Record, Error = Error> + Send>;
 
 fn run() -> Result<(), Error> {
     let args = cli::cli().get_matches();
     utils::config_init();
+    utils::adb_init(&args);
     subcommands::run(&args);
+    let jobs = jobs::from_args(&args)?;
+
+    let replay = match args.subcommand() {
+        ("replay", Some(replay)) => Some(replay),
+        _ => None,
+    };
+
+    let latency = args
+        .subcommand_matches("analyze")
+        .and_then(|analyze| analyze.subcommand_matches("latency"));
 
     let source = {
-        if args.is_present("input") {
+        if let Some(replay) = replay {
+            reader::files(replay)?
+        } else if let ("bugreport", Some(bugreport)) = args.subcommand() {
+            bugreport::extract_from_args(bugreport)?
+        } else if let ("dmesg", Some(dmesg)) = args.subcommand() {
+            reader::dmesg(&args, dmesg)?
+        } else if let ("props", Some(props)) = args.subcommand() {
+            props::from_args(&args, props)?
+        } else if let Some(latency) = latency {
+            if latency.is_present("input") {
+                reader::files(latency)?
+            } else {
+                reader::logcat(&args)?
+            }
+        } else if let ("stats", Some(stats)) = args.subcommand() {
+            if stats.is_present("input") {
+                reader::files(stats)?
+            } else {
+                reader::logcat(&args)?
+            }
+        } else if let ("serve", Some(serve)) = args.subcommand() {
+            if serve.is_present("input") {
+                reader::files(serve)?
+            } else {
+                reader::logcat(&args)?
+            }
+        } else if args.is_present("input") {
             reader::files(&args)?
         } else {
             match args.value_of("COMMAND") {
                 Some(c) => {
+                    let sources = source::command();
                     if c == "-" {
                         reader::stdin()
-                    } else if let Ok(url) = Url::parse(c) {
-                        match url.scheme() {
-                            #[cfg(target_os = "linux")]
-                            "can" => reader::can(&url.host_str().expect("Invalid can device"))?,
-                            "tcp" => reader::tcp(&url)?,
-                            "serial" => reader::serial(&args),
-                            _ => reader::process(&args)?,
-                        }
+                    } else if sources.handles(c) {
+                        sources.open(c, &args)?
                     } else {
                         reader::process(&args)?
                     }
@@ -80,48 +173,182 @@ fn run() -> Result<(), Error> {
     };
 
     let profile = profiles::from_args(&args)?;
-    let sink = if args.is_present("output") {
-        filewriter::try_from(&args)?
+    let watch = profiles::watch(&args, profile.clone())?;
+    let profile = profile.resolve();
+    loglevel::apply(&args, &profile);
+    let mut sinks = if let ("stats", Some(stats)) = args.subcommand() {
+        vec![stats::try_from(stats)?]
+    } else if let Some(latency) = latency {
+        vec![analyze::try_from(latency)?]
+    } else if let ("serve", Some(serve)) = args.subcommand() {
+        let sinks = grpc::serve(serve)?
+            .into_iter()
+            .chain(web::serve(serve)?)
+            .collect::<Vec<_>>();
+        assert!(!sinks.is_empty(), "Missing serve endpoint");
+        sinks
+    } else if args.is_present("output") {
+        sink::from_args(&args, || terminal::try_from(&args, &profile, &watch))?
+    } else if args.is_present("tui") {
+        vec![tui::try_from(&args, &profile)?]
     } else {
-        terminal::try_from(&args, &profile)?
+        vec![terminal::try_from(&args, &profile, &watch)?]
     };
 
+    // Write to the outputs of the profile in addition
+    for output in &profile.output {
+        sinks.push(filewriter::from_output(output)?);
+    }
+
+    // Send records to a remote collector in addition
+    sinks.extend(network::forward(&args)?);
+
+    // Push records to Loki in addition
+    sinks.extend(loki::from_args(&args)?);
+
+    let sink = sink::tee(sinks);
+
     // Stop process after n records if argument head is passed
-    let mut head = args
+    let head = args
         .value_of("head")
-        .map(|v| usize::from_str(v).expect("Invalid head arguement"));
+        .map(|v| u64::from_str(v).expect("Invalid head arguement"));
 
-    let filter = filter::from_args_profile(&args, &profile)?;
-    let mut parser = parser::Parser::default();
+    let mut filter = filter::from_args_profile(&args, &profile)?;
+    let filter_args = args.clone();
+    let filter_watch = watch.clone();
+    let mut filter_generation = 0;
+    let triggers = Arc::new(triggers::from_args(&args)?);
+    let triggers_wait = triggers.clone();
+    let notify = notify::from_args(&args)?;
+    let snoozes = snooze::from_args(&args)?;
+    let parser = parser::Parser::default();
+    let sidecar = strict::from_args(&args)?;
+    let unparsed = sidecar
+        .as_ref()
+        .map(strict::Sidecar::unparsed)
+        .unwrap_or_default();
+    let unparsed_exit = unparsed.clone();
 
     let mut runtime = Runtime::new()?;
 
-    let f = source
-        .map(move |a| match a {
-            StreamData::Line(l) => parser.parse(&l),
-            StreamData::Record(r) => r,
-        })
-        .filter(move |r| filter.filter(r))
-        .take_while(move |_| {
-            Ok(match head {
-                Some(0) => false,
-                Some(n) => {
-                    head = Some(n - 1);
-                    true
+    let records = reader::Records::new(source, parser)
+        .strict(sidecar)
+        .host_timestamps(hosttime::from_args(&args))
+        .clock(clock::from_args(&args)?);
+    // Records end on ctrl-c to flush the stages and outputs
+    let shutdown = shutdown::Shutdown::default();
+    let shutdown_exit = shutdown.clone();
+    let records = shutdown.wrap(Box::new(records));
+    let (records, dropped) = queue::from_args(&args, records, &runtime.executor())?;
+    let dropped_exit = dropped.clone();
+    let summary = shutdown::Summary::new(dropped.clone());
+    let summary_count = summary.clone();
+    let summary_exit = summary.clone();
+    let records = sort::from_args(&args, records)?;
+    let records = match replay {
+        Some(replay) => replay::pace(replay, replay::since(replay, records)?)?,
+        None => records,
+    };
+    let records = match procnames::from_args(&args)? {
+        Some(names) => Box::new(records.map(move |mut r| {
+            names.process(&mut r);
+            r
+        })) as RecordStream,
+        None => records,
+    };
+    let records = match cpu::from_args(&args)? {
+        Some(cpu) => Box::new(records.map(move |mut r| {
+            cpu.process(&mut r);
+            r
+        })) as RecordStream,
+        None => records,
+    };
+    let records = transform::from_args(&args, &profile, records)?;
+    let records = script::from_args(&args, records)?;
+    let records = stacktrace::from_args(&args, records);
+    let records = symbols::from_args(&args, records)?;
+    let records = retrace::from_args(&args, records)?;
+
+    // Markers and commands are entered on the terminal unless stdin is the input
+    let records = if atty::is(atty::Stream::Stdin)
+        && args.value_of("COMMAND") != Some("-")
+        && !args.is_present("tui")
+    {
+        let markers = control::spawn(&args, &profile, watch.clone(), snoozes.clone());
+        marker::inject(records, markers)
+    } else {
+        records
+    };
+    let records = vitals::from_args(&args, records)?;
+    let records = relative::from_args(&args, records);
+    let (records, outcome) = gate::from_args(&args, records)?;
+    let outcome_exit = outcome.clone();
+    let outcome_err = outcome.clone();
+
+    let records = records.inspect(move |r| {
+        triggers.process(r);
+        if let Some(ref notify) = notify {
+            notify.process(r);
+        }
+    });
+    let records = match jobs {
+        Some(_) => jobs::filter(Box::new(records), move |r| {
+            marker::is_marker(r) || vitals::is_vitals(r) || filter.filter(r)
+        }),
+        None => context::from_args(&args, Box::new(records), move |r| {
+            if let Some(profile) = filter_watch.changed(&mut filter_generation) {
+                if let Err(e) = filter.reload(&filter_args, &profile) {
+                    eprintln!("Failed to reload filters: {}", e);
                 }
-                None => true,
-            })
-        })
+            }
+            marker::is_marker(r) || vitals::is_vitals(r) || filter.filter(r)
+        })?,
+    };
+    let records = snooze::wrap(records, snoozes);
+    let records = dedupe::from_args(&args, records);
+    let records = sample::from_args(&args, records)?;
+
+    // End right after the last record to not wait for another one of a live source
+    let records = match head {
+        Some(n) => Box::new(records.take(n)) as RecordStream,
+        None => records,
+    };
+    let records = budget::from_args(&args, records)?;
+    let records = records.inspect(move |r| summary_count.count(r));
+
+    let f = records
         .forward(sink)
-        .map(|_| exit(0))
-        .map_err(|e| eprintln!("{}", e));
+        .map(move |_| {
+            triggers_wait.wait();
+            if shutdown_exit.interrupted() {
+                summary_exit.report();
+            } else {
+                dropped_exit.report();
+            }
+            unparsed_exit.report();
+            outcome_exit.finish();
+            exit(if outcome_exit.failed() { 1 } else { 0 })
+        })
+        .map_err(move |e| {
+            eprintln!("{}", e);
+            outcome_err.finish();
+            exit(1)
+        });
     let mut f = Some(oneshot::spawn(f, &runtime.executor()));
 
-    // Cancel stream processing on ctrl-c
-    runtime.block_on(ctrl_c().flatten_stream().take(1).for_each(move |()| {
-        f.take();
+    // End the records on the first ctrl-c and cancel stream processing on
+    // the second one
+    runtime.block_on(ctrl_c().flatten_stream().take(2).for_each(move |()| {
+        if shutdown.interrupted() {
+            f.take();
+        } else {
+            shutdown.interrupt();
+        }
         Ok(())
     }))?;
+    summary.report();
+    unparsed.report();
+    outcome.finish();
 
     Ok(())
 }
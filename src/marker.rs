@@ -0,0 +1,113 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::RecordStream;
+use failure::Error;
+use futures::{sync::mpsc::UnboundedReceiver, try_ready, Async, Poll, Stream};
+use rogcat::record::{Level, Record, Timestamp};
+use std::process;
+use unicode_width::UnicodeWidthStr;
+
+/// Tag of marker records. Markers logged with `rogcat log --marker` on the
+/// device use the same tag.
+pub const TAG: &str = "RogcatMarker";
+
+/// True if record is a marker
+pub fn is_marker(record: &Record) -> bool {
+    record.tag == TAG
+}
+
+/// Create a marker record with message
+pub fn record(message: &str) -> Record {
+    let now = time::now();
+    let process = process::id().to_string();
+    Record {
+        timestamp: Some(Timestamp::new(now)),
+        level: Level::Info,
+        tag: TAG.to_owned(),
+        raw: format!(
+            "{} {:>5} {:>5} I {}: {}",
+            time::strftime("%m-%d %H:%M:%S.%f", &now)
+                .map(|mut t| {
+                    t.truncate(18);
+                    t
+                })
+                .unwrap_or_default(),
+            process,
+            process,
+            TAG,
+            message
+        ),
+        process: process.clone(),
+        thread: process,
+        message: message.to_owned(),
+        ..Default::default()
+    }
+}
+
+/// Horizontal line of width columns containing the marker timestamp and text
+pub fn line(record: &Record, width: usize) -> String {
+    let timestamp = record
+        .timestamp
+        .as_ref()
+        .and_then(|t| time::strftime("%H:%M:%S.%f", t).ok())
+        .map(|mut t| {
+            t.truncate(12);
+            t
+        })
+        .unwrap_or_default();
+    let text = format!("━━━━ {} {} ", timestamp, record.message);
+    let len = text.width();
+    format!("{}{}", text, "━".repeat(width.saturating_sub(len)))
+}
+
+/// Merge markers into records. The returned stream ends with records.
+pub fn inject(records: RecordStream, markers: UnboundedReceiver<Record>) -> RecordStream {
+    Box::new(Inject { records, markers })
+}
+
+/// Merge markers into a stream that terminates with records
+struct Inject {
+    records: RecordStream,
+    markers: UnboundedReceiver<Record>,
+}
+
+impl Stream for Inject {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        if let Ok(Async::Ready(Some(marker))) = self.markers.poll() {
+            return Ok(Async::Ready(Some(marker)));
+        }
+        let record = try_ready!(self.records.poll());
+        Ok(Async::Ready(record))
+    }
+}
+
+#[test]
+fn marker_line() {
+    let mut r = record("step 1");
+    assert!(is_marker(&r));
+    r.timestamp = None;
+    let line = line(&r, 20);
+    assert_eq!(line.chars().count(), 20);
+    assert!(line.starts_with("━━━━  step 1 ━"));
+}
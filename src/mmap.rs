@@ -0,0 +1,450 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//! Memory mapped reading of capture files. The mapped file is split into
+//! chunks at line boundaries that are parsed in parallel. Parsed chunks are
+//! emitted in file order.
+//!
+//! The parser sticks to the format of the first record. Chunks are parsed
+//! alone until the format is known and in parallel with a parser of that
+//! format afterwards. The parser carries state across lines for multi line
+//! records (long and csv) and the logcat buffer dividers. If a chunk ends
+//! within a multi line record, the record is parsed again from its start
+//! until the parser agrees with the one of a following chunk on a record
+//! boundary. Lines no parser recognizes are passed as `Unparsed` to keep
+//! `--strict-parse` working.
+
+use crate::StreamData;
+use failure::Error;
+use futures::{Async, Poll, Stream};
+use memmap2::{Mmap, MmapOptions};
+use rayon::prelude::*;
+use rogcat::{parser::Parser, record::Record};
+use std::{collections::VecDeque, fs::File, ops::Range, path::Path};
+
+/// Approximate size of a chunk parsed by one thread
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Parsed chunk
+struct Chunk {
+    /// Items with the index of the line they were emitted on
+    data: Vec<(usize, StreamData)>,
+    /// Offsets of the lines in the map
+    lines: Vec<usize>,
+    /// The parser has no incomplete record after the line
+    idle: Vec<bool>,
+    /// Line that starts the record that is incomplete at the end of the chunk
+    tail: Option<usize>,
+    /// Buffer at the tail line
+    tail_buffer: Option<String>,
+    /// Buffer announced by the last divider of the chunk
+    buffer: Option<String>,
+    /// Format of the first record
+    format: Option<usize>,
+}
+
+/// Where to continue after an incomplete record was parsed again
+enum Resume {
+    /// Chunk and the first line of it that was parsed like sequentially
+    Chunk(usize, usize),
+    /// Offset in the map after the parsed chunks
+    Offset(usize),
+}
+
+/// Incomplete record of a chunk parsed until the parser is in sync again
+struct Stitch {
+    data: Vec<StreamData>,
+    buffer: Option<String>,
+    format: Option<usize>,
+    resume: Resume,
+}
+
+/// Lines from start to end with the offsets of their start and end. Line
+/// ends are stripped like `LossyLinesCodec`
+fn lines(
+    map: &[u8],
+    start: usize,
+    end: usize,
+) -> impl Iterator<Item = (usize, usize, String)> + '_ {
+    let chunk = &map[start..end];
+    let mut offset = start;
+    chunk
+        .strip_suffix(b"\n")
+        .unwrap_or(chunk)
+        .split(|b| *b == b'\n')
+        .map(move |line| {
+            let start = offset;
+            offset = (offset + line.len() + 1).min(end);
+            let stripped = line
+                .iter()
+                .rposition(|b| *b != b'\r')
+                .map(|n| n + 1)
+                .unwrap_or(0);
+            (
+                start,
+                offset,
+                String::from_utf8_lossy(&line[..stripped]).into_owned(),
+            )
+        })
+}
+
+/// Record returned by parser for line if any
+fn parse_line(parser: &mut Parser, line: &str) -> Option<StreamData> {
+    parser.parse_line(line).map(|record| wrap(parser, record))
+}
+
+/// Record or unparsed line depending on the last parse
+fn wrap(parser: &Parser, record: Record) -> StreamData {
+    if parser.is_fallback() {
+        StreamData::Unparsed(Box::new(record))
+    } else {
+        StreamData::Record(Box::new(record))
+    }
+}
+
+/// Parse the lines of range with a fresh parser of format
+fn parse(map: &[u8], range: Range<usize>, format: Option<usize>) -> Chunk {
+    let mut parser = Parser::default().with_format(format);
+    let mut chunk = Chunk {
+        data: Vec::new(),
+        lines: Vec::new(),
+        idle: Vec::new(),
+        tail: None,
+        tail_buffer: None,
+        buffer: None,
+        format: None,
+    };
+    for (n, (offset, _, line)) in lines(map, range.start, range.end).enumerate() {
+        let busy = parser.is_incomplete();
+        let data = parse_line(&mut parser, &line);
+        // A record starts if the parser wasn't collecting one or the
+        // previous one was completed by this line
+        if !parser.is_incomplete() {
+            chunk.tail = None;
+        } else if !busy || data.is_some() {
+            chunk.tail = Some(n);
+            chunk.tail_buffer = parser.buffer().map(ToOwned::to_owned);
+        }
+        if let Some(data) = data {
+            chunk.data.push((n, data));
+        }
+        chunk.lines.push(offset);
+        chunk.idle.push(!parser.is_incomplete());
+    }
+    chunk.buffer = parser.buffer().map(ToOwned::to_owned);
+    chunk.format = parser.format();
+    chunk
+}
+
+/// Parse from start of an incomplete record until the parser completed the
+/// record on a line where the parser of a following chunk was idle as well
+fn stitch(
+    map: &[u8],
+    start: usize,
+    format: Option<usize>,
+    chunks: &[Chunk],
+    ranges: &[Range<usize>],
+) -> Stitch {
+    let mut parser = Parser::default().with_format(format);
+    let mut data = Vec::new();
+    let mut resume = None;
+    for (offset, next, line) in lines(map, start, map.len()) {
+        data.extend(parse_line(&mut parser, &line));
+        if parser.is_incomplete() {
+            continue;
+        }
+        match ranges.iter().position(|r| r.contains(&offset)) {
+            Some(n) if next == ranges[n].end && n + 1 < chunks.len() => {
+                resume = Some(Resume::Chunk(n + 1, 0))
+            }
+            Some(n) if next == ranges[n].end => resume = Some(Resume::Offset(next)),
+            Some(n) => {
+                let chunk = &chunks[n];
+                if let Ok(line) = chunk.lines.binary_search(&offset) {
+                    if chunk.idle[line] && chunk.tail.map(|t| line < t).unwrap_or(true) {
+                        resume = Some(Resume::Chunk(n, line + 1));
+                    }
+                }
+            }
+            None => resume = Some(Resume::Offset(next)),
+        }
+        if resume.is_some() {
+            break;
+        }
+    }
+    if resume.is_none() {
+        data.extend(parser.flush().map(|record| wrap(&parser, record)));
+    }
+    Stitch {
+        data,
+        buffer: parser.buffer().map(ToOwned::to_owned),
+        format: parser.format(),
+        resume: resume.unwrap_or(Resume::Offset(map.len())),
+    }
+}
+
+/// Stream of lines and records of a memory mapped file
+pub struct MappedFile {
+    map: Mmap,
+    /// Offset of the next chunk
+    position: usize,
+    chunk_size: usize,
+    /// Format of the first record
+    format: Option<usize>,
+    /// Buffer of the last divider for records of following chunks
+    buffer: Option<String>,
+    pending: VecDeque<StreamData>,
+}
+
+impl MappedFile {
+    /// Map `file` from `offset`. Returns None for empty files or files that
+    /// can't be mapped e.g pipes. Those are read with the regular reader
+    pub fn open(file: &Path, offset: u64) -> Option<MappedFile> {
+        let f = File::open(file).ok()?;
+        let metadata = f.metadata().ok()?;
+        if !metadata.is_file() || metadata.len() <= offset {
+            return None;
+        }
+        // The capture is expected to be immutable while being read
+        let map = unsafe { MmapOptions::new().offset(offset).map(&f) }.ok()?;
+        Some(MappedFile {
+            map,
+            position: 0,
+            chunk_size: CHUNK_SIZE,
+            format: None,
+            buffer: None,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// End of the chunk starting at `start`
+    fn chunk_end(&self, start: usize) -> usize {
+        let end = (start + self.chunk_size).min(self.map.len());
+        self.map[end..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map(|n| end + n + 1)
+            .unwrap_or(self.map.len())
+    }
+
+    /// Queue data. Records without buffer get the one of the last divider
+    fn push(&mut self, mut data: StreamData) {
+        match data {
+            StreamData::Record(ref mut record) | StreamData::Unparsed(ref mut record)
+                if record.buffer.is_none() =>
+            {
+                record.buffer = self.buffer.clone()
+            }
+            _ => (),
+        }
+        self.pending.push_back(data);
+    }
+
+    /// Parse the next chunks on all threads
+    fn fill(&mut self) {
+        let count = if self.format.is_some() {
+            rayon::current_num_threads() * 2
+        } else {
+            1
+        };
+        let mut ranges = Vec::new();
+        while ranges.len() < count && self.position < self.map.len() {
+            let end = self.chunk_end(self.position);
+            ranges.push(self.position..end);
+            self.position = end;
+        }
+
+        let map = &self.map;
+        let format = self.format;
+        let mut chunks = ranges
+            .par_iter()
+            .map(|range| parse(map, range.clone(), format))
+            .collect::<Vec<_>>();
+
+        let mut n = 0;
+        let mut first = 0;
+        while n < chunks.len() {
+            let tail = chunks[n].tail;
+            for (line, data) in std::mem::take(&mut chunks[n].data) {
+                // Records from the tail on are parsed again
+                if line >= first && tail.map(|t| line <= t).unwrap_or(true) {
+                    self.push(data);
+                }
+            }
+            if self.format.is_none() {
+                self.format = chunks[n].format;
+            }
+            let tail = match tail {
+                Some(tail) => tail,
+                None => {
+                    if chunks[n].buffer.is_some() {
+                        self.buffer = chunks[n].buffer.clone();
+                    }
+                    n += 1;
+                    first = 0;
+                    continue;
+                }
+            };
+
+            if chunks[n].tail_buffer.is_some() {
+                self.buffer = chunks[n].tail_buffer.clone();
+            }
+            let start = chunks[n].lines[tail];
+            let stitch = stitch(&self.map, start, self.format, &chunks, &ranges);
+            for data in stitch.data {
+                self.push(data);
+            }
+            if stitch.buffer.is_some() {
+                self.buffer = stitch.buffer;
+            }
+            if self.format.is_none() {
+                self.format = stitch.format;
+            }
+            match stitch.resume {
+                Resume::Chunk(chunk, line) => {
+                    n = chunk;
+                    first = line;
+                }
+                Resume::Offset(offset) => {
+                    self.position = offset;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Stream for MappedFile {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<StreamData>, Error> {
+        loop {
+            if let Some(data) = self.pending.pop_front() {
+                return Ok(Async::Ready(Some(data)));
+            }
+            if self.position >= self.map.len() {
+                return Ok(Async::Ready(None));
+            }
+            self.fill();
+        }
+    }
+}
+
+#[cfg(test)]
+fn records(stream: crate::LogStream) -> Vec<Record> {
+    use crate::reader::Records;
+    use futures::Future;
+    Records::new(stream, Parser::default())
+        .collect()
+        .wait()
+        .unwrap()
+}
+
+#[cfg(test)]
+fn sequential(content: &str) -> Vec<Record> {
+    let lines = content
+        .lines()
+        .map(|l| StreamData::Line(l.trim_end_matches('\r').to_owned()))
+        .collect::<Vec<_>>();
+    records(Box::new(futures::stream::iter_ok(lines)))
+}
+
+#[test]
+fn mapped_file() {
+    use std::fs::write;
+
+    let dir = crate::tests::utils::tempdir().unwrap();
+
+    // Several chunks with dividers and a long format record at the end
+    let mut content = String::new();
+    for i in 0..60_000 {
+        match i {
+            10 => content.push_str("--------- beginning of main\n"),
+            40_000 => content.push_str("--------- switch to system\n"),
+            _ => (),
+        }
+        content.push_str(&format!(
+            "01-02 03:04:05.{:03}  1  1 I Foo: record {}\r\n",
+            i % 1000,
+            i
+        ));
+    }
+    content.push_str("garbage\n[ 01-02 03:04:05.678  1: 2 I/Bar ]\nlong\n\n");
+    let file = dir.join("capture.log");
+    write(&file, &content).unwrap();
+
+    let expected = sequential(&content);
+    let mapped = records(Box::new(MappedFile::open(&file, 0).unwrap()));
+    assert_eq!(mapped.len(), 60_003);
+    assert_eq!(mapped, expected);
+    assert_eq!(mapped[40_001].buffer, Some("system".to_owned()));
+
+    let empty = dir.join("empty.log");
+    write(&empty, "").unwrap();
+    assert!(MappedFile::open(&empty, 0).is_none());
+}
+
+#[test]
+fn mixed_file() {
+    use std::fs::write;
+
+    // Lines of other formats after the first record, long and csv records
+    // and dividers at all chunk boundaries
+    let mut content = String::from("garbage\nI/Runtime: mindroid before\n");
+    for i in 0..300 {
+        match i % 6 {
+            0 => content.push_str(&format!(
+                "01-02 03:04:05.{:03}  1  1 I Foo: record {}\n",
+                i, i
+            )),
+            1 => content.push_str(&format!("I/Runtime: mindroid {}\n", i)),
+            2 => content.push_str(&format!(
+                "[ 01-02 03:04:05.{:03}  1: 2 I/Bar ]\nlong {}\nlines\n\n",
+                i, i
+            )),
+            3 => content.push_str(&format!(
+                "01-02 10:11:12.{:09},\"csv {}\nover\nlines\",Info,Tag,1,2,raw\n",
+                i, i
+            )),
+            4 => content.push_str(&format!("--------- beginning of b{}\n", i)),
+            _ => content.push('\n'),
+        }
+    }
+    content.push_str("[ 01-02 03:04:05.678  1: 2 I/Bar ]\nunterminated");
+    let dir = crate::tests::utils::tempdir().unwrap();
+    let file = dir.join("mixed.log");
+    write(&file, &content).unwrap();
+
+    let expected = sequential(&content);
+    // Csv records are joined but not parsed after a threadtime record
+    assert!(expected.iter().any(|r| r
+        .message
+        .ends_with(",\"csv 3\nover\nlines\",Info,Tag,1,2,raw")));
+    for chunk_size in &[1, 20, 100, 1000, CHUNK_SIZE] {
+        let mut mapped = MappedFile::open(&file, 0).unwrap();
+        mapped.chunk_size = *chunk_size;
+        assert_eq!(
+            records(Box::new(mapped)),
+            expected,
+            "chunk size {}",
+            chunk_size
+        );
+    }
+}
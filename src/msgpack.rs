@@ -0,0 +1,396 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! MessagePack encoding of records. Records are maps with the keys of the
+//! `json` format and timestamps as MessagePack timestamp extension. Streams
+//! of records are length delimited like `protobuf`: each record is prefixed
+//! with its size as varint.
+
+use crate::{
+    protobuf::{put_varint, varint},
+    record::{Level, Record, Timestamp},
+};
+use bytes::BytesMut;
+use failure::{err_msg, format_err, Error};
+use std::{collections::BTreeMap, io};
+use time::{at_utc, Timespec};
+use tokio::codec::Decoder;
+
+/// Extension type of timestamps
+const EXT_TIMESTAMP: i8 = -1;
+/// Upper bound of a encoded record
+const MAX_RECORD: u64 = 16 * 1024 * 1024;
+
+fn put_len(buf: &mut Vec<u8>, len: usize, fix: u8, fix_max: usize, marker16: u8) {
+    if len <= fix_max {
+        buf.push(fix | len as u8);
+    } else if len <= 0xffff {
+        buf.push(marker16);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        buf.push(marker16 + 1);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+fn put_str(buf: &mut Vec<u8>, value: &str) {
+    match value.len() {
+        len if len < 32 => buf.push(0xa0 | len as u8),
+        len if len <= 0xff => buf.extend_from_slice(&[0xd9, len as u8]),
+        len => put_len(buf, len, 0, 0, 0xda),
+    }
+    buf.extend_from_slice(value.as_bytes());
+}
+
+fn put_opt_str(buf: &mut Vec<u8>, value: &Option<String>) {
+    match value {
+        Some(value) => put_str(buf, value),
+        None => buf.push(0xc0),
+    }
+}
+
+/// Timestamp 96 extension with nanoseconds and signed seconds
+fn put_timestamp(buf: &mut Vec<u8>, timestamp: &Option<Timestamp>) {
+    match timestamp {
+        Some(timestamp) => {
+            let spec = timestamp.to_timespec();
+            buf.extend_from_slice(&[0xc7, 12, EXT_TIMESTAMP as u8]);
+            buf.extend_from_slice(&(spec.nsec as u32).to_be_bytes());
+            buf.extend_from_slice(&spec.sec.to_be_bytes());
+        }
+        None => buf.push(0xc0),
+    }
+}
+
+/// Encode a record as map
+pub fn encode(record: &Record) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(record.raw.len() * 2 + 64);
+    let len = if record.cpu.is_some() { 13 } else { 12 };
+    put_len(&mut buf, len, 0x80, 15, 0xde);
+    put_str(&mut buf, "timestamp");
+    put_timestamp(&mut buf, &record.timestamp);
+    put_str(&mut buf, "message");
+    put_str(&mut buf, &record.message);
+    put_str(&mut buf, "level");
+    put_str(&mut buf, &record.level.to_string());
+    put_str(&mut buf, "tag");
+    put_str(&mut buf, &record.tag);
+    put_str(&mut buf, "process");
+    put_str(&mut buf, &record.process);
+    put_str(&mut buf, "thread");
+    put_str(&mut buf, &record.thread);
+    put_str(&mut buf, "raw");
+    put_str(&mut buf, &record.raw);
+    put_str(&mut buf, "device");
+    put_opt_str(&mut buf, &record.device);
+    put_str(&mut buf, "buffer");
+    put_opt_str(&mut buf, &record.buffer);
+    put_str(&mut buf, "process_name");
+    put_opt_str(&mut buf, &record.process_name);
+    put_str(&mut buf, "relative");
+    match record.relative {
+        Some(relative) => {
+            buf.push(0xcb);
+            buf.extend_from_slice(&relative.to_bits().to_be_bytes());
+        }
+        None => buf.push(0xc0),
+    }
+    if let Some(cpu) = record.cpu {
+        put_str(&mut buf, "cpu");
+        buf.push(0xcb);
+        buf.extend_from_slice(&cpu.to_bits().to_be_bytes());
+    }
+    put_str(&mut buf, "fields");
+    put_len(&mut buf, record.fields.len(), 0x80, 15, 0xde);
+    for (name, value) in &record.fields {
+        put_str(&mut buf, name);
+        put_str(&mut buf, value);
+    }
+    buf
+}
+
+/// Encode a record with its size as prefix
+pub fn encode_delimited(record: &Record) -> Vec<u8> {
+    let message = encode(record);
+    let mut buf = Vec::with_capacity(message.len() + 4);
+    put_varint(&mut buf, message.len() as u64);
+    buf.extend(message);
+    buf
+}
+
+/// Decoded value. Types that records do not use are skipped
+#[derive(Debug, PartialEq)]
+enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Timestamp(Timespec),
+    Other,
+}
+
+impl Value {
+    fn into_string(self) -> Option<String> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if buf.len() < len {
+        return Err(err_msg("Truncated MessagePack value"));
+    }
+    let (value, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(value)
+}
+
+/// Read a big endian unsigned integer of len bytes
+fn uint(buf: &mut &[u8], len: usize) -> Result<u64, Error> {
+    Ok(take(buf, len)?
+        .iter()
+        .fold(0, |value, b| value << 8 | u64::from(*b)))
+}
+
+fn string(buf: &mut &[u8], len: usize) -> Result<Value, Error> {
+    Ok(Value::Str(
+        String::from_utf8_lossy(take(buf, len)?).into_owned(),
+    ))
+}
+
+fn array(buf: &mut &[u8], len: usize) -> Result<Value, Error> {
+    (0..len)
+        .map(|_| value(buf))
+        .collect::<Result<_, _>>()
+        .map(Value::Array)
+}
+
+fn map(buf: &mut &[u8], len: usize) -> Result<Value, Error> {
+    (0..len)
+        .map(|_| Ok((value(buf)?, value(buf)?)))
+        .collect::<Result<_, Error>>()
+        .map(Value::Map)
+}
+
+fn ext(buf: &mut &[u8], len: usize) -> Result<Value, Error> {
+    let kind = take(buf, 1)?[0] as i8;
+    let mut data = take(buf, len)?;
+    if kind != EXT_TIMESTAMP {
+        return Ok(Value::Other);
+    }
+    let spec = match len {
+        4 => Timespec::new(uint(&mut data, 4)? as i64, 0),
+        8 => {
+            let value = uint(&mut data, 8)?;
+            Timespec::new((value & 0x3_ffff_ffff) as i64, (value >> 34) as i32)
+        }
+        12 => {
+            let nsec = uint(&mut data, 4)? as i32;
+            Timespec::new(uint(&mut data, 8)? as i64, nsec)
+        }
+        _ => return Err(format_err!("Invalid timestamp length {}", len)),
+    };
+    Ok(Value::Timestamp(spec))
+}
+
+fn value(buf: &mut &[u8]) -> Result<Value, Error> {
+    let marker = take(buf, 1)?[0];
+    match marker {
+        0x00..=0x7f => Ok(Value::Int(i64::from(marker))),
+        0x80..=0x8f => map(buf, usize::from(marker & 0x0f)),
+        0x90..=0x9f => array(buf, usize::from(marker & 0x0f)),
+        0xa0..=0xbf => string(buf, usize::from(marker & 0x1f)),
+        0xc0 => Ok(Value::Nil),
+        0xc2 => Ok(Value::Bool(false)),
+        0xc3 => Ok(Value::Bool(true)),
+        0xc4..=0xc6 => {
+            let len = uint(buf, 1 << (marker - 0xc4))? as usize;
+            take(buf, len).map(|_| Value::Other)
+        }
+        0xc7..=0xc9 => {
+            let len = uint(buf, 1 << (marker - 0xc7))? as usize;
+            ext(buf, len)
+        }
+        0xca => Ok(Value::Float(f64::from(
+            f32::from_bits(uint(buf, 4)? as u32),
+        ))),
+        0xcb => Ok(Value::Float(f64::from_bits(uint(buf, 8)?))),
+        0xcc..=0xcf => Ok(Value::Int(uint(buf, 1 << (marker - 0xcc))? as i64)),
+        0xd0..=0xd3 => {
+            let len = 1 << (marker - 0xd0);
+            let value = uint(buf, len)?;
+            // Sign extend
+            let shift = 64 - 8 * len;
+            Ok(Value::Int(((value << shift) as i64) >> shift))
+        }
+        0xd4..=0xd8 => ext(buf, 1 << (marker - 0xd4)),
+        0xd9..=0xdb => {
+            let len = uint(buf, 1 << (marker - 0xd9))? as usize;
+            string(buf, len)
+        }
+        0xdc | 0xdd => {
+            let len = uint(buf, 2 << (marker - 0xdc))? as usize;
+            array(buf, len)
+        }
+        0xde | 0xdf => {
+            let len = uint(buf, 2 << (marker - 0xde))? as usize;
+            map(buf, len)
+        }
+        0xe0..=0xff => Ok(Value::Int(i64::from(marker as i8))),
+        marker => Err(format_err!("Invalid MessagePack marker {:#x}", marker)),
+    }
+}
+
+/// Decode a record map. Unknown keys are skipped
+pub fn decode(mut buf: &[u8]) -> Result<Record, Error> {
+    let entries = match value(&mut buf)? {
+        Value::Map(entries) => entries,
+        _ => return Err(err_msg("MessagePack record is not a map")),
+    };
+    let mut record = Record::default();
+    for (key, value) in entries {
+        let key = match key {
+            Value::Str(key) => key,
+            _ => continue,
+        };
+        match (key.as_str(), value) {
+            ("timestamp", Value::Timestamp(spec)) => {
+                record.timestamp = Some(Timestamp::new(at_utc(spec)))
+            }
+            ("message", Value::Str(s)) => record.message = s,
+            ("level", Value::Str(s)) => record.level = Level::from(s.as_str()),
+            ("tag", Value::Str(s)) => record.tag = s,
+            ("process", Value::Str(s)) => record.process = s,
+            ("thread", Value::Str(s)) => record.thread = s,
+            ("raw", Value::Str(s)) => record.raw = s,
+            ("device", Value::Str(s)) => record.device = Some(s),
+            ("buffer", Value::Str(s)) => record.buffer = Some(s),
+            ("process_name", Value::Str(s)) => record.process_name = Some(s),
+            ("relative", Value::Float(f)) => record.relative = Some(f),
+            ("cpu", Value::Float(f)) => record.cpu = Some(f),
+            ("fields", Value::Map(fields)) => {
+                record.fields = fields
+                    .into_iter()
+                    .filter_map(|(k, v)| Some((k.into_string()?, v.into_string()?)))
+                    .collect::<BTreeMap<_, _>>();
+            }
+            _ => (),
+        }
+    }
+    Ok(record)
+}
+
+/// Codec for length delimited records
+#[derive(Clone, Debug, Default)]
+pub struct MsgpackCodec;
+
+impl Decoder for MsgpackCodec {
+    type Item = Record;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Record>, io::Error> {
+        let invalid = |e: Error| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+        let mut prefix = &buf[..];
+        let len = match varint(&mut prefix) {
+            Ok(len) if len > MAX_RECORD => {
+                return Err(invalid(format_err!("Invalid record length {}", len)))
+            }
+            Ok(len) => len as usize,
+            // Wait for the rest of a truncated prefix
+            Err(_) if buf.len() < 10 => return Ok(None),
+            Err(e) => return Err(invalid(e)),
+        };
+        let header = buf.len() - prefix.len();
+        if buf.len() < header + len {
+            return Ok(None);
+        }
+        let message = buf.split_to(header + len);
+        decode(&message[header..]).map(Some).map_err(invalid)
+    }
+}
+
+#[test]
+fn round_trip() {
+    let mut fields = BTreeMap::new();
+    fields.insert("request".to_owned(), "42".to_owned());
+    let record = Record {
+        timestamp: Some(Timestamp::new(at_utc(Timespec::new(1_500_000_000, 42)))),
+        message: "hello".into(),
+        level: Level::Warn,
+        tag: "Tag".into(),
+        process: "123".into(),
+        thread: "456".into(),
+        raw: "x".repeat(300),
+        device: Some("emulator-5554".into()),
+        buffer: None,
+        process_name: Some("com.example".into()),
+        relative: Some(1.5),
+        cpu: Some(12.5),
+        fields,
+        context: false,
+    };
+    let mut buf = BytesMut::from(encode_delimited(&record));
+    buf.extend_from_slice(&encode_delimited(&Record::default())[..3]);
+    let decoded = MsgpackCodec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(
+        decoded.timestamp.as_ref().unwrap().to_timespec(),
+        Timespec::new(1_500_000_000, 42)
+    );
+    // Timestamps are read back with the wall clock of the capture
+    let wall = |t: &Timestamp| time::strftime("%m-%d %H:%M:%S", t).unwrap();
+    assert_eq!(
+        wall(decoded.timestamp.as_ref().unwrap()),
+        wall(record.timestamp.as_ref().unwrap())
+    );
+    assert_eq!(
+        decoded,
+        Record {
+            timestamp: decoded.timestamp.clone(),
+            ..record
+        }
+    );
+    // The second record is incomplete
+    assert!(MsgpackCodec.decode(&mut buf).unwrap().is_none());
+    assert_eq!(buf.len(), 3);
+}
+
+#[test]
+fn values() {
+    let decode = |b: &[u8]| value(&mut &b[..]).unwrap();
+    assert_eq!(decode(&[0xff]), Value::Int(-1));
+    assert_eq!(decode(&[0xd1, 0xff, 0x00]), Value::Int(-256));
+    assert_eq!(decode(&[0xcd, 0x01, 0x00]), Value::Int(256));
+    assert_eq!(
+        decode(&[0x92, 0xc3, 0xc0]),
+        Value::Array(vec![Value::Bool(true), Value::Nil])
+    );
+    assert_eq!(
+        decode(&[0xd6, 0xff, 0, 0, 0, 5]),
+        Value::Timestamp(Timespec::new(5, 0))
+    );
+    assert_eq!(decode(&[0xc4, 1, 0]), Value::Other);
+    assert!(value(&mut &[0xc1][..]).is_err());
+    assert!(value(&mut &[0xa3, b'a'][..]).is_err());
+}
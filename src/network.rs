@@ -0,0 +1,431 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{lossy_lines::LossyLinesCodec, LogSink, LogStream, StreamData};
+use clap::ArgMatches;
+use failure::{err_msg, format_err, Error};
+use futures::{
+    future::lazy, sync::mpsc, try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream,
+};
+use native_tls::TlsConnector;
+use rogcat::record::{Format, Record};
+use std::{
+    collections::VecDeque,
+    io::Write,
+    net::{self, SocketAddr, ToSocketAddrs},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::Duration,
+};
+use tokio::{
+    codec::Decoder,
+    net::{TcpListener, UdpSocket},
+    reactor::Handle,
+};
+use url::Url;
+
+/// Maximum size of a received datagram
+const DATAGRAM_SIZE: usize = 65_507;
+
+fn socket_addr(url: &Url) -> Result<SocketAddr, Error> {
+    url.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format_err!("Failed to parse addr {}", url))
+}
+
+/// Listen on a tcp or udp url and provide a stream of the received lines
+pub fn listen(url: &str) -> Result<LogStream, Error> {
+    let url = Url::parse(url)?;
+    let addr = socket_addr(&url)?;
+    match url.scheme() {
+        "tcp" => tcp(&addr),
+        "udp" => udp(&addr),
+        scheme => Err(format_err!("Unsupported scheme {}", scheme)),
+    }
+}
+
+/// Accept tcp connections and merge the lines received on all connections
+fn tcp(addr: &SocketAddr) -> Result<LogStream, Error> {
+    let listener = net::TcpListener::bind(addr)
+        .and_then(|l| TcpListener::from_std(l, &Handle::default()))
+        .map_err(|e| format_err!("Failed to listen on {}: {}", addr, e))?;
+    let (tx, rx) = mpsc::channel(1024);
+
+    let server = listener
+        .incoming()
+        .map_err(|e| eprintln!("Failed to accept connection: {}", e))
+        .for_each(move |socket| {
+            let lines = Decoder::framed(LossyLinesCodec::new(), socket)
+                .map(StreamData::Line)
+                .map_err(|e| eprintln!("Connection error: {}", e));
+            tokio::spawn(lines.forward(tx.clone().sink_map_err(|_| ())).map(|_| ()));
+            Ok(())
+        });
+
+    let stream = lazy(move || {
+        tokio::spawn(server);
+        Ok(rx.map_err(|_| err_msg("Channel error")))
+    })
+    .flatten_stream();
+    Ok(Box::new(stream))
+}
+
+/// Lines of received datagrams
+struct UdpLines {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+    lines: VecDeque<String>,
+}
+
+impl Stream for UdpLines {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Ok(Async::Ready(Some(StreamData::Line(line))));
+            }
+            let (n, _) = try_ready!(self.socket.poll_recv_from(&mut self.buffer));
+            self.lines.extend(
+                String::from_utf8_lossy(&self.buffer[..n])
+                    .lines()
+                    .map(|l| l.trim_end_matches('\r').to_owned()),
+            );
+        }
+    }
+}
+
+/// Receive datagrams with one or more lines
+fn udp(addr: &SocketAddr) -> Result<LogStream, Error> {
+    let socket =
+        UdpSocket::bind(addr).map_err(|e| format_err!("Failed to listen on {}: {}", addr, e))?;
+    Ok(Box::new(UdpLines {
+        socket,
+        buffer: vec![0; DATAGRAM_SIZE],
+        lines: VecDeque::new(),
+    }))
+}
+
+/// Number of records buffered for forwarding. Records are dropped if the
+/// remote end cannot keep up
+const FORWARD_QUEUE: usize = 4096;
+
+/// Delay between connection attempts of a forward sink
+const RECONNECT: Duration = Duration::from_secs(1);
+
+/// Timeout of a write to a collector
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time granted to deliver the queued records when the sink is closed
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Encoding of forwarded records
+enum Encoding {
+    Format(Format),
+    /// RFC 5424 messages. Messages on streams are framed by octet counting
+    Syslog {
+        framed: bool,
+    },
+}
+
+impl Encoding {
+    fn encode(&self, record: &Record) -> Result<Vec<u8>, Error> {
+        match self {
+            Encoding::Format(format) => format.encode_record(record),
+            Encoding::Syslog { framed: false } => Ok(syslog(record).into_bytes()),
+            Encoding::Syslog { framed: true } => {
+                let message = syslog(record);
+                Ok(format!("{} {}", message.len(), message).into_bytes())
+            }
+        }
+    }
+}
+
+/// Syslog header field of printable ascii or the nil value
+fn header_field(value: &str, max: usize) -> String {
+    let value = value
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max)
+        .collect::<String>();
+    if value.is_empty() {
+        "-".to_owned()
+    } else {
+        value
+    }
+}
+
+/// RFC 5424 message of a record with facility user. The tag is the
+/// APP-NAME and the device the HOSTNAME. Timestamps without year are
+/// placed in the current one and carry an offset only if it is known
+fn syslog(record: &Record) -> String {
+    let timestamp = record
+        .timestamp
+        .as_ref()
+        .map(|t| {
+            let year = if t.tm_year == 0 {
+                time::now().tm_year
+            } else {
+                t.tm_year
+            };
+            let offset = t.tm_utcoff / 60;
+            // Logcat times are device local unless a zone was parsed
+            let zone = if offset == 0 {
+                String::new()
+            } else {
+                format!(
+                    "{}{:02}:{:02}",
+                    if offset < 0 { '-' } else { '+' },
+                    offset.abs() / 60,
+                    offset.abs() % 60
+                )
+            };
+            format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}{}",
+                year + 1900,
+                t.tm_mon + 1,
+                t.tm_mday,
+                t.tm_hour,
+                t.tm_min,
+                t.tm_sec,
+                t.tm_nsec / 1000,
+                zone
+            )
+        })
+        .unwrap_or_else(|| "-".to_owned());
+    format!(
+        "<{}>1 {} {} {} {} - - {}",
+        8 + record.level.severity(),
+        timestamp,
+        header_field(record.device.as_deref().unwrap_or_default(), 255),
+        header_field(&record.tag, 48),
+        header_field(&record.process, 128),
+        record.message
+    )
+}
+
+/// Send formatted records to a remote collector. The records are sent
+/// from a thread that (re)connects as needed.
+struct Forward {
+    encoding: Encoding,
+    tx: Option<SyncSender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+    /// Disconnected when the thread finished
+    done: Receiver<()>,
+    closed: Arc<AtomicBool>,
+    dropped: usize,
+}
+
+/// Construct a forward sink from args if requested
+pub fn forward<'a>(args: &ArgMatches<'a>) -> Result<Option<LogSink>, Error> {
+    let url = match args.value_of("forward") {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    let format = args
+        .value_of("forward_format")
+        .map(Format::from_str)
+        .unwrap_or(Ok(Format::Json))
+        .map_err(err_msg)?;
+    connect(url, format).map(Some)
+}
+
+/// Construct a sink that sends records encoded in format to a tcp, tls, udp
+/// or syslog url
+pub fn connect(url: &str, format: Format) -> Result<LogSink, Error> {
+    match format {
+        Format::Csv
+        | Format::Json
+        | Format::Msgpack
+        | Format::Protobuf
+        | Format::Raw
+        | Format::Template(_) => (),
+        Format::Html | Format::Human | Format::Trace => {
+            return Err(format_err!("Unsupported format {} for {}", format, url))
+        }
+    }
+    let url = Url::parse(url)?;
+    // Syslog is sent via udp, tcp or tls
+    let (transport, encoding, addr) = if url.scheme() == "syslog" {
+        let proto = url
+            .query_pairs()
+            .find(|(k, _)| k == "proto")
+            .map(|(_, v)| v.into_owned())
+            .unwrap_or_else(|| "udp".to_owned());
+        let port = if proto == "tls" { 6514 } else { 514 };
+        let addr = url
+            .with_default_port(|_| Ok(port))?
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format_err!("Failed to parse addr {}", url))?;
+        let framed = proto != "udp";
+        (proto, Encoding::Syslog { framed }, addr)
+    } else {
+        let encoding = Encoding::Format(format);
+        (url.scheme().to_owned(), encoding, socket_addr(&url)?)
+    };
+    let (tx, rx) = sync_channel(FORWARD_QUEUE);
+    let closed = Arc::new(AtomicBool::new(false));
+    let (done_tx, done) = channel();
+    let thread = match transport.as_str() {
+        "tcp" => {
+            let closed = closed.clone();
+            let connect = move || tcp_connect(&addr);
+            thread::spawn(move || {
+                let _done = done_tx;
+                forward_stream(&addr, connect, &rx, &closed)
+            })
+        }
+        "tls" => {
+            let closed = closed.clone();
+            let host = url
+                .host_str()
+                .ok_or_else(|| format_err!("Missing host in {}", url))?
+                .to_owned();
+            let connector = TlsConnector::new()?;
+            let connect = move || {
+                let stream = tcp_connect(&addr)?;
+                connector
+                    .connect(&host, stream)
+                    .map_err(|e| format_err!("TLS handshake failed: {}", e))
+            };
+            thread::spawn(move || {
+                let _done = done_tx;
+                forward_stream(&addr, connect, &rx, &closed)
+            })
+        }
+        "udp" => {
+            let socket = net::UdpSocket::bind(if addr.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            })?;
+            thread::spawn(move || {
+                let _done = done_tx;
+                for line in rx {
+                    socket.send_to(&line, addr).ok();
+                }
+            })
+        }
+        scheme => return Err(format_err!("Unsupported forward scheme {}", scheme)),
+    };
+
+    Ok(Box::new(Forward {
+        encoding,
+        tx: Some(tx),
+        thread: Some(thread),
+        done,
+        closed,
+        dropped: 0,
+    }))
+}
+
+/// Connect to addr. Writes fail instead of blocking if the collector stalls
+fn tcp_connect(addr: &SocketAddr) -> Result<net::TcpStream, Error> {
+    let stream = net::TcpStream::connect(addr)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    Ok(stream)
+}
+
+fn forward_stream<S, F>(addr: &SocketAddr, connect: F, rx: &Receiver<Vec<u8>>, closed: &AtomicBool)
+where
+    S: Write,
+    F: Fn() -> Result<S, Error>,
+{
+    let mut pending: Option<Vec<u8>> = None;
+    loop {
+        let mut stream = match connect() {
+            Ok(stream) => stream,
+            // Give up if there's no connection when the stream ended
+            Err(_) if closed.load(Ordering::Relaxed) => return,
+            Err(e) => {
+                eprintln!("Failed to connect to {}: {}", addr, e);
+                sleep(RECONNECT);
+                continue;
+            }
+        };
+        loop {
+            let line = match pending.take().map(Ok).unwrap_or_else(|| rx.recv()) {
+                Ok(line) => line,
+                Err(_) => return, // Sink closed
+            };
+            if let Err(e) = stream.write_all(&line) {
+                eprintln!("Failed to forward to {}: {}", addr, e);
+                // Don't reconnect to a collector that stalled on close
+                if closed.load(Ordering::Relaxed) {
+                    return;
+                }
+                pending = Some(line);
+                break;
+            }
+        }
+    }
+}
+
+impl Sink for Forward {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let line = self.encoding.encode(&record)?;
+        if let Some(ref tx) = self.tx {
+            match tx.try_send(line) {
+                Ok(_) => (),
+                Err(TrySendError::Full(_)) => self.dropped += 1,
+                Err(TrySendError::Disconnected(_)) => return Err(err_msg("Forwarding stopped")),
+            }
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        // Deliver the queued records
+        self.closed.store(true, Ordering::Relaxed);
+        self.tx.take();
+        if let Some(thread) = self.thread.take() {
+            match self.done.recv_timeout(CLOSE_TIMEOUT) {
+                Err(RecvTimeoutError::Timeout) => {
+                    eprintln!(
+                        "Gave up delivering the queued records after {:?}",
+                        CLOSE_TIMEOUT
+                    )
+                }
+                _ => {
+                    thread.join().ok();
+                }
+            }
+        }
+        if self.dropped > 0 {
+            eprintln!("Dropped {} records while forwarding", self.dropped);
+        }
+        Ok(Async::Ready(()))
+    }
+}
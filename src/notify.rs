@@ -0,0 +1,203 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Desktop notifications for records matching `--notify`. Notifications are
+//! shown with `notify-send` on Linux, `osascript` on macOS and PowerShell on
+//! Windows.
+
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use regex::Regex;
+use rogcat::record::Record;
+use std::{
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Number of notifications that can be shown at once
+const BURST: usize = 3;
+/// Time until another notification can be shown after the burst
+const REFILL: Duration = Duration::from_secs(5);
+/// Maximum length of the message in a notification
+const EXCERPT: usize = 200;
+
+/// Token bucket that limits the rate of notifications and counts the
+/// suppressed ones
+#[derive(Debug)]
+struct RateLimit {
+    tokens: usize,
+    last: Instant,
+    suppressed: usize,
+}
+
+impl RateLimit {
+    fn new(now: Instant) -> RateLimit {
+        RateLimit {
+            tokens: BURST,
+            last: now,
+            suppressed: 0,
+        }
+    }
+
+    /// Return the number of suppressed notifications since the last one
+    /// if a notification can be shown at now
+    fn allow(&mut self, now: Instant) -> Option<usize> {
+        let refill = (now.duration_since(self.last).as_millis() / REFILL.as_millis()) as usize;
+        if refill > 0 {
+            self.tokens = (self.tokens + refill).min(BURST);
+            self.last = now;
+        }
+
+        if self.tokens == 0 {
+            self.suppressed += 1;
+            None
+        } else {
+            self.tokens -= 1;
+            let suppressed = self.suppressed;
+            self.suppressed = 0;
+            Some(suppressed)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Notify {
+    patterns: Vec<Regex>,
+    limit: Mutex<RateLimit>,
+    failed: Arc<AtomicBool>,
+}
+
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Result<Option<Notify>, Error> {
+    let patterns = match args.values_of("notify") {
+        Some(values) => values
+            .map(|r| Regex::new(r).map_err(|e| format_err!("Invalid regex string: {}: {}", r, e)))
+            .collect::<Result<Vec<_>, Error>>()?,
+        None => return Ok(None),
+    };
+
+    Ok(Some(Notify {
+        patterns,
+        limit: Mutex::new(RateLimit::new(Instant::now())),
+        failed: Arc::new(AtomicBool::new(false)),
+    }))
+}
+
+impl Notify {
+    /// Show a notification if record matches
+    pub fn process(&self, record: &Record) {
+        if !self
+            .patterns
+            .iter()
+            .any(|p| p.is_match(&record.tag) || p.is_match(&record.message))
+        {
+            return;
+        }
+
+        let suppressed = match self
+            .limit
+            .lock()
+            .expect("Failed to lock rate limit")
+            .allow(Instant::now())
+        {
+            Some(suppressed) => suppressed,
+            None => return,
+        };
+
+        let title = format!("{} {}", record.level, record.tag);
+        let mut body = record.message.chars().take(EXCERPT).collect::<String>();
+        if suppressed > 0 {
+            body.push_str(&format!(" (+{} suppressed)", suppressed));
+        }
+
+        let mut command = command(&title, &body);
+        let failed = self.failed.clone();
+        thread::spawn(move || {
+            if let Err(e) = command.status() {
+                // Report a missing backend only once
+                if !failed.swap(true, Ordering::Relaxed) {
+                    eprintln!("Failed to show notification: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn command(title: &str, body: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.args(["-a", "rogcat", title, body]);
+    command
+}
+
+#[cfg(target_os = "macos")]
+fn command(title: &str, body: &str) -> Command {
+    let quote = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(format!(
+        "display notification \"{}\" with title \"rogcat\" subtitle \"{}\"",
+        quote(body),
+        quote(title)
+    ));
+    command
+}
+
+#[cfg(target_os = "windows")]
+fn command(title: &str, body: &str) -> Command {
+    let quote = |s: &str| s.replace('\'', "''");
+    let script = format!(
+        "Add-Type -AssemblyName System.Windows.Forms; \
+         $n = New-Object System.Windows.Forms.NotifyIcon; \
+         $n.Icon = [System.Drawing.SystemIcons]::Information; \
+         $n.Visible = $true; \
+         $n.ShowBalloonTip(5000, '{}', '{}', 'Info'); \
+         Start-Sleep -Seconds 5; $n.Dispose()",
+        quote(title),
+        quote(body)
+    );
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-Command", &script]);
+    command
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn command(title: &str, body: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.args([title, body]);
+    command
+}
+
+#[test]
+fn rate_limit() {
+    let start = Instant::now();
+    let mut limit = RateLimit::new(start);
+    for _ in 0..BURST {
+        assert_eq!(limit.allow(start), Some(0));
+    }
+    assert_eq!(limit.allow(start), None);
+    assert_eq!(limit.allow(start + Duration::from_secs(1)), None);
+    assert_eq!(limit.allow(start + REFILL), Some(2));
+    assert_eq!(limit.allow(start + REFILL), None);
+    assert_eq!(limit.allow(start + REFILL * 10), Some(1));
+}
@@ -0,0 +1,56 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Outputs addressed by an url like `tcp://localhost:1234?format=csv`. Each
+//! kind of output is a `RecordSink` registered for one or more url schemes.
+
+use crate::{
+    record::Record,
+    registry::{self, Schemes},
+};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::Sink;
+
+/// Destination of the records of an output
+pub type LogSink = Box<dyn Sink<SinkItem = Record, SinkError = Error> + Send>;
+
+/// A destination of records
+pub trait RecordSink: Schemes + Send + Sync {
+    /// Open the output addressed by url
+    fn open(&self, url: &str, args: &ArgMatches) -> Result<LogSink, Error>;
+}
+
+/// Sinks by url scheme
+pub type Registry = registry::Registry<dyn RecordSink>;
+
+impl Registry {
+    /// Open output with the sink registered for its scheme
+    pub fn open(&self, output: &str, args: &ArgMatches) -> Result<LogSink, Error> {
+        self.get(output)
+            .ok_or_else(|| format_err!("No output sink for {}", output))?
+            .open(output, args)
+    }
+}
+
+/// Add sink to the registries built afterwards
+pub fn register(sink: Box<dyn RecordSink>) {
+    registry::register(sink)
+}
@@ -22,16 +22,16 @@ use crate::record::{Level, Record, Timestamp};
 use csv::ReaderBuilder;
 use failure::Fail;
 use nom::{
-    alt, char, complete, do_parse, flat_map, hex_digit, many0, many1, map, named, opt, parse_to,
-    peek, rest, space, tag, take, take_until, take_until_either, types::CompleteStr,
+    alt, char, complete, digit, do_parse, flat_map, hex_digit, map, map_opt, named, opt, parse_to,
+    peek, rest, space, tag, take, take_until, take_until_either, take_while1, types::CompleteStr,
 };
-use serde_json::from_str;
+use serde_json::{from_str, Map, Value};
 use std::{
     convert::Into,
     io::{Cursor, Read},
 };
 
-use time::Tm;
+use time::{at_utc, Timespec, Tm};
 
 #[derive(Fail, Debug)]
 #[fail(display = "{}", _0)]
@@ -54,48 +54,85 @@ named!(
       )
 );
 
-// 2017-03-25 19:11:19.052
+/// Fraction of a second with millisecond, microsecond or nanosecond
+/// precision (logcat -v usec, -v nsec) as nanoseconds
+fn nanoseconds(fraction: CompleteStr) -> Option<i32> {
+    let digits = fraction.0;
+    if digits.len() > 9 {
+        return None;
+    }
+    let value = digits.parse::<i32>().ok()?;
+    Some(value * 10i32.pow(9 - digits.len() as u32))
+}
+
+// +0100 (logcat -v zone)
+named!(
+    utc_offset<CompleteStr, i32>,
+    do_parse!(
+        space
+            >> sign: map!(alt!(char!('-') | char!('+')), |c| if c == '-' { -1 } else { 1 })
+            >> utc_off_hours: flat_map!(take!(2), parse_to!(i32))
+            >> utc_off_minutes: flat_map!(take!(2), parse_to!(i32))
+            >> (sign * (utc_off_hours * 60 * 60 + utc_off_minutes * 60))
+    )
+);
+
+// 2017-03-25 19:11:19.052 or 1490469079.052 (logcat -v epoch)
 named!(
     timestamp<CompleteStr, Tm>,
+    alt!(
+        do_parse!(
+            year: opt!(do_parse!(
+                y: flat_map!(peek!(take!(4)), parse_to!(i32)) >> take!(4) >> char!('-') >> (y)
+            )) >> month: flat_map!(take!(2), parse_to!(i32))
+                >> char!('-')
+                >> day: flat_map!(take!(2), parse_to!(i32))
+                >> space
+                >> hour: flat_map!(take!(2), parse_to!(i32))
+                >> char!(':')
+                >> minute: flat_map!(take!(2), parse_to!(i32))
+                >> char!(':')
+                >> second: flat_map!(take!(2), parse_to!(i32))
+                >> char!('.')
+                >> nsec: map_opt!(digit, nanoseconds)
+                >> utcoff: opt!(complete!(utc_offset))
+                >> (Tm {
+                    tm_sec: second,
+                    tm_min: minute,
+                    tm_hour: hour,
+                    tm_mday: day,
+                    tm_mon: month - 1,
+                    // Years are counted from 1900
+                    tm_year: year.map(|y| y - 1900).unwrap_or(0),
+                    tm_wday: 0,
+                    tm_yday: 0,
+                    tm_isdst: 0,
+                    tm_utcoff: utcoff.unwrap_or(0),
+                    tm_nsec: nsec,
+                })
+        ) | do_parse!(
+            seconds: flat_map!(digit, parse_to!(i64))
+                >> char!('.')
+                >> nsec: map_opt!(digit, nanoseconds)
+                >> (at_utc(Timespec::new(seconds, nsec)))
+        )
+    )
+);
+
+fn is_uid_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Optional uid column of logcat -v uid e.g in bugreports
+named!(
+    uid<CompleteStr, CompleteStr>,
     do_parse!(
-        year: opt!(do_parse!(
-            y: flat_map!(peek!(take!(4)), parse_to!(i32)) >> take!(4) >> char!('-') >> (y)
-        )) >> month: flat_map!(take!(2), parse_to!(i32))
-            >> char!('-')
-            >> day: flat_map!(take!(2), parse_to!(i32))
+        uid: take_while1!(is_uid_char)
             >> space
-            >> hour: flat_map!(take!(2), parse_to!(i32))
-            >> char!(':')
-            >> minute: flat_map!(take!(2), parse_to!(i32))
-            >> char!(':')
-            >> second: flat_map!(take!(2), parse_to!(i32))
-            >> char!('.')
-            >> millisecond: flat_map!(take!(3), parse_to!(i32))
-            >> utcoff:
-                opt!(complete!(do_parse!(
-                    space
-                        >> sign: map!(alt!(char!('-') | char!('+')), |c| if c == '-' {
-                            -1
-                        } else {
-                            1
-                        })
-                        >> utc_off_hours: flat_map!(take!(2), parse_to!(i32))
-                        >> utc_off_minutes: flat_map!(take!(2), parse_to!(i32))
-                        >> (sign * (utc_off_hours * 60 * 60 + utc_off_minutes * 60))
-                )))
-            >> (Tm {
-                tm_sec: second,
-                tm_min: minute,
-                tm_hour: hour,
-                tm_mday: day,
-                tm_mon: month - 1,
-                tm_year: year.unwrap_or(0),
-                tm_wday: 0,
-                tm_yday: 0,
-                tm_isdst: 0,
-                tm_utcoff: utcoff.unwrap_or(0),
-                tm_nsec: millisecond * 1_000_000,
-            })
+            >> peek!(do_parse!(
+                hex_digit >> space >> hex_digit >> space >> level >> space >> ()
+            ))
+            >> (uid)
     )
 );
 
@@ -103,11 +140,12 @@ named!(
     printable<CompleteStr, Record>,
     do_parse!(
         timestamp: timestamp
-            >> many1!(space)
+            >> space
+            >> opt!(uid)
             >> process: hex_digit
-            >> many1!(space)
+            >> space
             >> thread: hex_digit
-            >> many1!(space)
+            >> space
             >> level: level
             >> space
             >> tag: take_until!(":")
@@ -125,6 +163,35 @@ named!(
     )
 );
 
+// [ 03-01 02:19:45.207  1000: 1001 I/ActivityManager ]
+named!(
+    long_header<CompleteStr, Record>,
+    do_parse!(
+        char!('[')
+            >> space
+            >> timestamp: timestamp
+            >> opt!(digit)
+            >> space
+            >> process: hex_digit
+            >> char!(':')
+            >> opt!(space)
+            >> thread: hex_digit
+            >> space
+            >> level: level
+            >> char!('/')
+            >> tag: take_until!(" ]")
+            >> tag!(" ]")
+            >> (Record {
+                timestamp: Some(Timestamp::new(timestamp)),
+                level,
+                tag: tag.trim().to_owned(),
+                process: process.trim().to_owned(),
+                thread: thread.trim().to_owned(),
+                ..Default::default()
+            })
+    )
+);
+
 named!(
     mindroid<CompleteStr, Record>,
     alt!(
@@ -153,10 +220,10 @@ named!(
         // Long format with timestamp
         do_parse!(
             timestamp: timestamp >>
-            many0!(space) >>
+            opt!(space) >>
             opt!(tag!("0x")) >>
             process: hex_digit >>
-            many1!(space) >>
+            space >>
             level: level >>
             space >>
             tag: take_until!(":") >>
@@ -199,10 +266,131 @@ named!(
     )
 );
 
+/// Parse the digits of b as number
+fn number(b: &[u8]) -> Option<i32> {
+    if b.is_empty() || b.len() > 9 {
+        return None;
+    }
+    b.iter().try_fold(0i32, |n, d| {
+        if d.is_ascii_digit() {
+            Some(n * 10 + i32::from(d - b'0'))
+        } else {
+            None
+        }
+    })
+}
+
+/// Split s after the leading characters matching f
+fn split_while(s: &str, f: impl Fn(u8) -> bool) -> (&str, &str) {
+    let n = s.bytes().position(|b| !f(b)).unwrap_or(s.len());
+    s.split_at(n)
+}
+
+fn is_space(b: u8) -> bool {
+    b == b' ' || b == b'\t'
+}
+
+/// Fields of a line of the default `threadtime` format with optional year.
+/// The text fields are slices of the line and parsing copies nothing. Lines
+/// with uid, zone or epoch timestamps are left to `printable`.
+#[derive(Debug, PartialEq)]
+pub struct Threadtime<'a> {
+    pub timestamp: Tm,
+    pub level: Level,
+    pub tag: &'a str,
+    pub process: &'a str,
+    pub thread: &'a str,
+    pub message: &'a str,
+}
+
+impl<'a> Threadtime<'a> {
+    pub fn parse(line: &'a str) -> Option<Threadtime<'a>> {
+        let b = line.as_bytes();
+        // Optional year of logcat -v year
+        let (year, offset) = if b.len() > 4 && b[4] == b'-' {
+            (Some(number(&b[..4])?), 5)
+        } else {
+            (None, 0)
+        };
+        // 03-01 02:19:45.207
+        let t = b.get(offset..offset + 15)?;
+        if t[2] != b'-' || t[5] != b' ' || t[8] != b':' || t[11] != b':' || t[14] != b'.' {
+            return None;
+        }
+        let (fraction, rest) = split_while(&line[offset + 15..], |b| b.is_ascii_digit());
+        let timestamp = Tm {
+            tm_sec: number(&t[12..14])?,
+            tm_min: number(&t[9..11])?,
+            tm_hour: number(&t[6..8])?,
+            tm_mday: number(&t[3..5])?,
+            tm_mon: number(&t[..2])? - 1,
+            tm_year: year.map(|y| y - 1900).unwrap_or(0),
+            tm_wday: 0,
+            tm_yday: 0,
+            tm_isdst: 0,
+            tm_utcoff: 0,
+            tm_nsec: nanoseconds(CompleteStr(fraction))?,
+        };
+
+        let (space, rest) = split_while(rest, is_space);
+        let (process, rest) = split_while(rest, |b| b.is_ascii_hexdigit());
+        if space.is_empty() || process.is_empty() {
+            return None;
+        }
+        let (space, rest) = split_while(rest, is_space);
+        let (thread, rest) = split_while(rest, |b| b.is_ascii_hexdigit());
+        if space.is_empty() || thread.is_empty() {
+            return None;
+        }
+        let (space, rest) = split_while(rest, is_space);
+        let level = match rest.bytes().next()? {
+            b'V' => Level::Verbose,
+            b'D' => Level::Debug,
+            b'I' => Level::Info,
+            b'W' => Level::Warn,
+            b'E' => Level::Error,
+            b'F' => Level::Fatal,
+            b'A' => Level::Assert,
+            _ => return None,
+        };
+        let (space2, rest) = split_while(&rest[1..], is_space);
+        if space.is_empty() || space2.is_empty() {
+            return None;
+        }
+        let colon = rest.find(':')?;
+
+        Some(Threadtime {
+            timestamp,
+            level,
+            tag: rest[..colon].trim(),
+            process,
+            thread,
+            message: rest[colon + 1..].trim(),
+        })
+    }
+
+    /// Record of the fields with line as raw
+    pub fn to_record(&self, line: &str) -> Record {
+        Record {
+            timestamp: Some(Timestamp::new(self.timestamp)),
+            level: self.level.clone(),
+            tag: self.tag.to_owned(),
+            process: self.process.to_owned(),
+            thread: self.thread.to_owned(),
+            message: self.message.to_owned(),
+            raw: line.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
 pub struct DefaultParser;
 
 impl FormatParser for DefaultParser {
     fn try_parse_str<'a>(&self, line: &'a str) -> Result<Record, ParserError> {
+        if let Some(threadtime) = Threadtime::parse(line) {
+            return Ok(threadtime.to_record(line));
+        }
         printable(CompleteStr(line))
             .map(|(_, mut v)| {
                 v.raw = line.into();
@@ -227,8 +415,41 @@ impl FormatParser for MindroidParser {
 
 pub struct CsvParser;
 
+/// Leading columns of the csv output of all versions. Header lines starting
+/// with these names are skipped
+const CSV_COLUMNS: &str = "timestamp,message,level,tag,process,thread,raw";
+
+impl CsvParser {
+    /// Check if line starts like a record in the csv output. The first field
+    /// is empty or a timestamp like `01-02 10:11:12.123000000`
+    fn is_record_start(line: &str) -> bool {
+        let timestamp = match line.find(',') {
+            Some(n) => &line.as_bytes()[..n],
+            None => return false,
+        };
+        timestamp.is_empty()
+            || timestamp.len() == 24
+                && timestamp.iter().enumerate().all(|(i, b)| match i {
+                    2 => *b == b'-',
+                    5 => *b == b' ',
+                    8 | 11 => *b == b':',
+                    14 => *b == b'.',
+                    _ => b.is_ascii_digit(),
+                })
+    }
+
+    /// Quoted fields are incomplete if the number of quotes is odd
+    fn is_complete(record: &str) -> bool {
+        record.matches('"').count() & 1 == 0
+    }
+}
+
 impl FormatParser for CsvParser {
     fn try_parse_str<'a>(&self, line: &'a str) -> Result<Record, ParserError> {
+        // Skip the setup of a csv reader for lines that cannot have all columns
+        if line.bytes().filter(|b| *b == b',').count() < CSV_COLUMNS.matches(',').count() {
+            return Err(ParserError("Failed to parse csv".to_string()));
+        }
         let reader = Cursor::new(line).chain(Cursor::new([b'\n']));
         let mut rdr = ReaderBuilder::new().has_headers(false).from_reader(reader);
         if let Some(result) = rdr.deserialize().next() {
@@ -241,9 +462,225 @@ impl FormatParser for CsvParser {
 
 pub struct JsonParser;
 
+impl JsonParser {
+    /// Get the first present field of object with one of the given names
+    fn field<'a>(object: &'a Map<String, Value>, names: &[&str]) -> Option<&'a Value> {
+        names
+            .iter()
+            .filter_map(|n| object.get(*n))
+            .find(|v| !v.is_null())
+    }
+
+    fn string(value: &Value) -> String {
+        match value {
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
+        }
+    }
+
+    fn level(value: &Value) -> Level {
+        match value {
+            // Android priorities
+            Value::Number(n) => match n.as_u64() {
+                Some(2) => Level::Verbose,
+                Some(3) => Level::Debug,
+                Some(4) => Level::Info,
+                Some(5) => Level::Warn,
+                Some(6) => Level::Error,
+                Some(7) => Level::Fatal,
+                _ => Level::None,
+            },
+            Value::String(s) => match s.to_lowercase().as_str() {
+                "warning" => Level::Warn,
+                "err" => Level::Error,
+                "critical" | "crit" => Level::Fatal,
+                "v" | "d" | "i" | "w" | "e" | "f" | "a" => Level::from(s.to_uppercase().as_str()),
+                l => match Level::from(l) {
+                    // Deserialized rogcat levels e.g "Warn"
+                    Level::None => from_str(&format!("\"{}\"", s)).unwrap_or_default(),
+                    l => l,
+                },
+            },
+            _ => Level::None,
+        }
+    }
+
+    fn timestamp(value: &Value) -> Option<Timestamp> {
+        match value {
+            Value::String(s) => {
+                let s = s.replacen('T', " ", 1);
+                timestamp(CompleteStr(&s))
+                    .ok()
+                    .map(|(_, t)| Timestamp::new(t))
+            }
+            // Seconds or milliseconds since epoch
+            Value::Number(n) => n.as_f64().map(|t| {
+                let t = if t > 100_000_000_000f64 {
+                    t / 1000f64
+                } else {
+                    t
+                };
+                let spec = Timespec::new(t.trunc() as i64, (t.fract() * 1e9) as i32);
+                Timestamp::new(at_utc(spec))
+            }),
+            _ => None,
+        }
+    }
+
+    /// Map structured logs of other tools by common field names
+    fn try_parse_value(line: &str) -> Result<Record, ParserError> {
+        let value: Value = from_str(line)
+            .map_err(|e| ParserError(format!("Failed to deserialize json: {}", e)))?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| ParserError("Json is not an object".into()))?;
+        let message = Self::field(object, &["message", "msg", "log", "text"])
+            .map(Self::string)
+            .ok_or_else(|| ParserError("Json without message".into()))?;
+        let string = |names: &[&str]| {
+            Self::field(object, names)
+                .map(Self::string)
+                .unwrap_or_default()
+        };
+
+        Ok(Record {
+            timestamp: Self::field(object, &["timestamp", "time", "ts", "@timestamp"])
+                .and_then(Self::timestamp),
+            message,
+            level: Self::field(object, &["level", "priority", "severity", "lvl"])
+                .map(Self::level)
+                .unwrap_or_default(),
+            tag: string(&["tag", "logger", "name", "component"]),
+            process: string(&["process", "pid"]),
+            thread: string(&["thread", "tid"]),
+            raw: Self::field(object, &["raw"])
+                .map(Self::string)
+                .unwrap_or_else(|| line.to_owned()),
+            device: Self::field(object, &["device", "serial"]).map(Self::string),
+            buffer: Self::field(object, &["buffer"]).map(Self::string),
+            process_name: Self::field(object, &["process_name", "comm"]).map(Self::string),
+            relative: Self::field(object, &["relative"]).and_then(Value::as_f64),
+            cpu: Self::field(object, &["cpu"]).and_then(Value::as_f64),
+            fields: Self::field(object, &["fields"])
+                .and_then(Value::as_object)
+                .map(|f| {
+                    f.iter()
+                        .map(|(k, v)| (k.clone(), Self::string(v)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            context: false,
+        })
+    }
+}
+
 impl FormatParser for JsonParser {
     fn try_parse_str<'a>(&self, line: &'a str) -> Result<Record, ParserError> {
-        from_str(line).map_err(|e| ParserError(format!("Failed to deserialize json: {}", e)))
+        if !line.trim_start().starts_with('{') {
+            return Err(ParserError("Json is not an object".into()));
+        }
+        // Records of schema 1 and 2 deserialize as the version is ignored and
+        // the fields added since are optional
+        from_str(line).or_else(|_| Self::try_parse_value(line))
+    }
+}
+
+/// Kernel messages as printed by `dmesg` (optionally with `-r`) or read
+/// from `/dev/kmsg`
+pub struct DmesgParser;
+
+impl DmesgParser {
+    /// Map syslog priority to level. The facility is ignored
+    fn level(priority: u32) -> Level {
+        match priority & 7 {
+            0 => Level::Assert,
+            1 | 2 => Level::Fatal,
+            3 => Level::Error,
+            4 => Level::Warn,
+            5 | 6 => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+
+    /// Time since boot as timestamp
+    fn uptime(seconds: i64, nanoseconds: i32) -> Timestamp {
+        Timestamp::new(at_utc(Timespec::new(seconds, nanoseconds)))
+    }
+
+    /// Parse "[    1.234567]"
+    fn brackets(s: &str) -> Option<(Timestamp, &str)> {
+        let s = s.strip_prefix('[')?;
+        let end = s.find(']')?;
+        let mut uptime = s[..end].trim().splitn(2, '.');
+        let seconds = uptime.next()?.parse::<i64>().ok()?;
+        let fraction = uptime.next().unwrap_or("0");
+        if fraction.len() > 9 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let nanoseconds = format!("{:0<9}", fraction).parse::<i32>().ok()?;
+        Some((
+            Self::uptime(seconds, nanoseconds),
+            s[end + 1..].trim_start(),
+        ))
+    }
+
+    /// Split a subsystem prefix like "usb 1-1: " from text
+    fn subsystem(text: &str) -> (String, String) {
+        match text.find(": ") {
+            Some(n) if n > 0 && n <= 32 && !text[..n].contains('[') => {
+                (text[..n].to_owned(), text[n + 2..].to_owned())
+            }
+            _ => (String::new(), text.to_owned()),
+        }
+    }
+
+    fn record(line: &str, timestamp: Timestamp, level: Level, text: &str) -> Record {
+        let (tag, message) = Self::subsystem(text);
+        Record {
+            timestamp: Some(timestamp),
+            message,
+            level,
+            tag,
+            raw: line.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+impl FormatParser for DmesgParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        let error = || ParserError(format!("Failed to parse dmesg line: {}", line));
+
+        // /dev/kmsg: "6,339,5140900,-;NET: Registered protocol family 10"
+        if let Some(n) = line.find(';') {
+            let mut fields = line[..n].split(',');
+            if let (Some(priority), Some(_), Some(usec), Some(_)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            {
+                if let (Ok(priority), Ok(usec)) = (priority.parse::<u32>(), usec.parse::<i64>()) {
+                    let timestamp =
+                        Self::uptime(usec / 1_000_000, (usec % 1_000_000) as i32 * 1000);
+                    return Ok(Self::record(
+                        line,
+                        timestamp,
+                        Self::level(priority),
+                        &line[n + 1..],
+                    ));
+                }
+            }
+        }
+
+        // dmesg: "<6>[    1.234567] usb 1-1: new high-speed USB device"
+        let (level, rest) = match line.strip_prefix('<') {
+            Some(rest) => {
+                let end = rest.find('>').ok_or_else(error)?;
+                let priority = rest[..end].parse::<u32>().map_err(|_| error())?;
+                (Self::level(priority), &rest[end + 1..])
+            }
+            None => (Level::None, line),
+        };
+        let (timestamp, text) = Self::brackets(rest).ok_or_else(error)?;
+        Ok(Self::record(line, timestamp, level, text))
     }
 }
 
@@ -344,6 +781,14 @@ impl FormatParser for BugReportParser {
 pub struct Parser {
     parsers: Vec<Box<dyn FormatParser>>,
     last: Option<usize>,
+    /// Record of the long format (logcat -v long) waiting for message lines
+    pending: Option<Record>,
+    /// Buffer announced by the last logcat divider line
+    buffer: Option<String>,
+    /// Csv record with a quoted field spanning multiple lines
+    csv: Option<String>,
+    /// The last record was not recognized by any parser
+    fallback: bool,
 }
 
 impl Default for Parser {
@@ -356,8 +801,13 @@ impl Default for Parser {
                 Box::new(JsonParser),
                 Box::new(GTestParser),
                 Box::new(BugReportParser),
+                Box::new(DmesgParser),
             ],
             last: None,
+            pending: None,
+            buffer: None,
+            csv: None,
+            fallback: false,
         }
     }
 }
@@ -367,10 +817,137 @@ impl Parser {
         Parser {
             parsers: Vec::new(),
             last: None,
+            pending: None,
+            buffer: None,
+            csv: None,
+            fallback: false,
+        }
+    }
+
+    /// Parse a line that might be part of a multi line record. Records of the
+    /// long format (logcat -v long) span a header, the message lines and a
+    /// empty line. Those are returned when complete.
+    /// The divider lines of logcat (`--------- beginning of main` and with
+    /// `-D` `--------- switch to main`) set the buffer of the following
+    /// records. Switch dividers are consumed.
+    /// Quoted fields of csv records may contain newlines. Those lines are
+    /// joined until the record is complete.
+    pub fn parse_line(&mut self, line: &str) -> Option<Record> {
+        self.fallback = false;
+        if let Some(mut csv) = self.csv.take() {
+            csv.push('\n');
+            csv.push_str(line);
+            if CsvParser::is_complete(&csv) {
+                return Some(self.parse_csv(&csv));
+            }
+            self.csv = Some(csv);
+            return None;
+        }
+
+        // Skip the CSV header and the session metadata header written by rogcat
+        if line.starts_with(CSV_COLUMNS) || line.starts_with("# rogcat ") {
+            return None;
+        }
+
+        if self.pending.is_none()
+            && !CsvParser::is_complete(line)
+            && CsvParser::is_record_start(line)
+        {
+            self.csv = Some(line.to_owned());
+            return None;
+        }
+
+        let mut divider = false;
+        if let Some(buffer) = line.trim_end().strip_prefix("--------- ") {
+            if let Some(b) = buffer.strip_prefix("switch to ") {
+                self.buffer = Some(b.to_owned());
+                return None;
+            } else if let Some(b) = buffer.strip_prefix("beginning of ") {
+                self.buffer = Some(b.to_owned());
+                divider = true;
+            }
+        }
+
+        if let Ok((_, mut header)) = long_header(CompleteStr(line.trim_end())) {
+            header.raw = line.to_owned();
+            header.buffer = self.buffer.clone();
+            return self.pending.replace(header);
+        }
+
+        match self.pending {
+            Some(_) if line.trim().is_empty() => self.flush(),
+            Some(ref mut pending) => {
+                if !pending.message.is_empty() {
+                    pending.message.push('\n');
+                }
+                pending.message.push_str(line);
+                pending.raw.push('\n');
+                pending.raw.push_str(line);
+                None
+            }
+            None => {
+                let mut record = self.parse(line);
+                // Dividers are not recognized by the parsers but are no
+                // unparsed lines either
+                self.fallback &= !divider;
+                if record.buffer.is_none() {
+                    record.buffer = self.buffer.clone();
+                }
+                Some(record)
+            }
+        }
+    }
+
+    /// Take a incomplete multi line record
+    pub fn flush(&mut self) -> Option<Record> {
+        self.fallback = false;
+        match self.csv.take() {
+            Some(csv) => Some(self.parse_csv(&csv)),
+            None => self.pending.take(),
         }
     }
 
+    /// Parse a joined csv record. Records without buffer column get the one
+    /// of the last divider like single line records
+    fn parse_csv(&mut self, csv: &str) -> Record {
+        let mut record = self.parse(csv);
+        if record.buffer.is_none() {
+            record.buffer = self.buffer.clone();
+        }
+        record
+    }
+
+    /// Index of the format parser that recognized the first record. The
+    /// following lines are parsed with this one only
+    pub fn format(&self) -> Option<usize> {
+        self.last
+    }
+
+    /// Parse with the format parser at index format as if a record of it
+    /// was parsed before
+    pub fn with_format(mut self, format: Option<usize>) -> Self {
+        self.last = format;
+        self
+    }
+
+    /// True while a multi line record is collected
+    pub fn is_incomplete(&self) -> bool {
+        self.pending.is_some() || self.csv.is_some()
+    }
+
+    /// Buffer announced by the last divider line
+    pub fn buffer(&self) -> Option<&str> {
+        self.buffer.as_deref()
+    }
+
+    /// True if the last returned record was not recognized by any parser
+    /// and carries the line as message
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+
     pub fn parse(&mut self, line: &str) -> Record {
+        self.fallback = false;
         if let Some(last) = self.last {
             let p = &self.parsers[last];
             if let Ok(r) = p.try_parse_str(&line) {
@@ -387,6 +964,7 @@ impl Parser {
 
         // Seems that we cannot parse this record
         // Treat the raw input as message
+        self.fallback = true;
         Record {
             raw: String::from(line),
             message: String::from(line),
@@ -418,7 +996,44 @@ fn parse_timestamp() {
     timestamp(CompleteStr(t)).unwrap();
 
     let t = "2017-03-25 19:11:19.052";
-    timestamp(CompleteStr(t)).unwrap();
+    let (_, tm) = timestamp(CompleteStr(t)).unwrap();
+    assert_eq!(tm.tm_year, 117);
+    assert_eq!(tm.tm_nsec, 52_000_000);
+
+    // logcat -v usec,zone
+    let t = "03-25 19:11:19.052123 +0100";
+    let (_, tm) = timestamp(CompleteStr(t)).unwrap();
+    assert_eq!(tm.tm_nsec, 52_123_000);
+    assert_eq!(tm.tm_utcoff, 3600);
+
+    // logcat -v epoch
+    let t = "1490469079.052";
+    let (_, tm) = timestamp(CompleteStr(t)).unwrap();
+    assert_eq!(tm.to_timespec(), Timespec::new(1_490_469_079, 52_000_000));
+
+    let t = "1490469079.052  1234  1235 I Tag: epoch";
+    let r = DefaultParser.try_parse_str(t).unwrap();
+    assert_eq!(r.tag, "Tag");
+    assert_eq!(r.message, "epoch");
+    assert_eq!(
+        r.timestamp.unwrap().to_timespec(),
+        Timespec::new(1_490_469_079, 52_000_000)
+    );
+}
+
+#[test]
+fn parse_uid() {
+    let t = "03-01 02:19:45.207 system  1000  1001 I ActivityManager: Start proc";
+    let r = DefaultParser.try_parse_str(t).unwrap();
+    assert_eq!(r.process, "1000");
+    assert_eq!(r.thread, "1001");
+    assert_eq!(r.tag, "ActivityManager");
+
+    let t = "03-01 02:19:45.207 10123  4242  4243 W Tag: numeric uid";
+    let r = DefaultParser.try_parse_str(t).unwrap();
+    assert_eq!(r.process, "4242");
+    assert_eq!(r.level, Level::Warn);
+    assert_eq!(r.message, "numeric uid");
 }
 
 #[test]
@@ -539,6 +1154,59 @@ fn test_parse_csv() {
     );
 }
 
+#[test]
+fn parse_csv_multiline() {
+    let mut p = Parser::default();
+    assert!(p.parse_line(crate::record::CSV_HEADER).is_none());
+    assert!(p
+        .parse_line("timestamp,message,level,tag,process,thread,raw")
+        .is_none());
+    assert!(p.parse_line("01-02 10:11:12.123000000,\"two\"\"").is_none());
+    let r = p.parse_line("lines\",Info,Tag,100,200,raw,,main,").unwrap();
+    assert_eq!(r.message, "two\"\nlines");
+    assert_eq!(r.tag, "Tag");
+    assert_eq!(r.buffer, Some("main".to_owned()));
+    let r = p
+        .parse_line("01-02 10:11:12.124000000,x,Warn,Tag,100,200,raw")
+        .unwrap();
+    assert_eq!(r.level, Level::Warn);
+    assert_eq!(r.buffer, None);
+
+    assert!(p.parse_line(",\"unterminated,Info").is_none());
+    assert_eq!(p.flush().unwrap().message, ",\"unterminated,Info");
+}
+
+#[test]
+fn csv_round_trip() {
+    use crate::record::{Format, CSV_HEADER};
+    let record = Record {
+        timestamp: Some(Timestamp::new(at_utc(Timespec::new(1_500_000_000, 42_000)))),
+        message: "a, \"quoted\"\nmessage".to_owned(),
+        level: Level::Warn,
+        tag: "Tag".to_owned(),
+        process: "123".to_owned(),
+        thread: "456".to_owned(),
+        raw: "raw".to_owned(),
+        device: Some("emulator-5554".to_owned()),
+        buffer: Some("main".to_owned()),
+        process_name: Some("com.example".to_owned()),
+        relative: Some(1.5),
+        cpu: Some(12.3),
+        ..Default::default()
+    };
+    let csv = Format::Csv.fmt_record(&record).unwrap();
+    let mut p = Parser::default();
+    assert!(p.parse_line(CSV_HEADER).is_none());
+    let mut lines = csv.lines();
+    assert!(p.parse_line(lines.next().unwrap()).is_none());
+    let parsed = p.parse_line(lines.next().unwrap()).unwrap();
+    assert_eq!(parsed.message, record.message);
+    assert_eq!(
+        Format::Csv.fmt_record(&parsed).unwrap(),
+        Format::Csv.fmt_record(&record).unwrap()
+    );
+}
+
 #[test]
 fn parse_property() {
     let t = "[ro.build.tags]: [release-keys]";
@@ -563,3 +1231,183 @@ fn test_parse_gtest() {
     //     "07-01 14:13:14.446   225   295 I ThermalEngine: Sensor:batt_therm:29000 mC"
     // );
 }
+
+#[test]
+fn parse_json() {
+    let p = JsonParser {};
+    let line = r#"{"timestamp":"01-02 03:04:05.678000000","message":"hello","level":"Warn","tag":"Tag","process":"123","thread":"456","raw":"raw","device":null}"#;
+    let r = p.try_parse_str(line).unwrap();
+    assert_eq!(r.message, "hello");
+    assert_eq!(r.level, Level::Warn);
+    assert_eq!(r.process, "123");
+    assert_eq!(r.raw, "raw");
+    assert_eq!(r.timestamp.unwrap().tm_nsec, 678_000_000);
+
+    let line = r#"{"schema":2,"timestamp":"01-02 03:04:05.678000000","message":"hello","level":"Warn","tag":"Tag","process":"123","thread":"456","raw":"raw","device":"A1","buffer":"main","process_name":null,"relative":1.5,"fields":{"user":"42"}}"#;
+    let r = p.try_parse_str(line).unwrap();
+    assert_eq!(r.message, "hello");
+    assert_eq!(r.raw, "raw");
+    assert_eq!(r.device, Some("A1".to_owned()));
+    assert_eq!(r.buffer, Some("main".to_owned()));
+    assert_eq!(r.relative, Some(1.5));
+    assert_eq!(r.fields["user"], "42");
+    assert_eq!(r.timestamp.unwrap().tm_nsec, 678_000_000);
+
+    let line = r#"{"time":"2019-05-01T10:11:12.130Z","msg":"hello","severity":"WARNING","logger":"L","pid":12}"#;
+    let r = p.try_parse_str(line).unwrap();
+    assert_eq!(r.message, "hello");
+    assert_eq!(r.level, Level::Warn);
+    assert_eq!(r.tag, "L");
+    assert_eq!(r.process, "12");
+    assert_eq!(r.raw, line);
+    let t = r.timestamp.unwrap();
+    assert_eq!(t.tm_hour, 10);
+    assert_eq!(t.tm_nsec, 130_000_000);
+
+    let line = r#"{"ts":1556705472.5,"log":"hello","priority":6}"#;
+    let r = p.try_parse_str(line).unwrap();
+    assert_eq!(r.level, Level::Error);
+    assert_eq!(r.timestamp.unwrap().tm_nsec, 500_000_000);
+
+    assert!(p.try_parse_str(r#"{"foo":"bar"}"#).is_err());
+    assert!(p.try_parse_str(r#"["hello"]"#).is_err());
+    assert!(p.try_parse_str("hello").is_err());
+}
+
+#[test]
+fn parse_dmesg() {
+    let p = DmesgParser {};
+    let r = p
+        .try_parse_str("<6>[    1.234567] usb 1-1: new high-speed USB device")
+        .unwrap();
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.tag, "usb 1-1");
+    assert_eq!(r.message, "new high-speed USB device");
+    let t = r.timestamp.unwrap();
+    assert_eq!(t.tm_sec, 1);
+    assert_eq!(t.tm_nsec, 234_567_000);
+
+    let r = p.try_parse_str("[12345.5] Booting Linux").unwrap();
+    assert_eq!(r.level, Level::None);
+    assert_eq!(r.tag, "");
+    assert_eq!(r.message, "Booting Linux");
+    let t = r.timestamp.unwrap();
+    assert_eq!((t.tm_hour, t.tm_min, t.tm_sec), (3, 25, 45));
+    assert_eq!(t.tm_nsec, 500_000_000);
+
+    let r = p
+        .try_parse_str("3,339,5140900,-;EXT4-fs (sda1): error")
+        .unwrap();
+    assert_eq!(r.level, Level::Error);
+    assert_eq!(r.tag, "EXT4-fs (sda1)");
+    assert_eq!(r.message, "error");
+    let t = r.timestamp.unwrap();
+    assert_eq!(t.tm_sec, 5);
+    assert_eq!(t.tm_nsec, 140_900_000);
+
+    assert!(p.try_parse_str("").is_err());
+    assert!(p.try_parse_str("<6>").is_err());
+    assert!(p.try_parse_str("[abc] foo").is_err());
+    assert!(p.try_parse_str("Booting Linux").is_err());
+}
+
+#[test]
+fn parse_long() {
+    let mut p = Parser::default();
+    assert!(p
+        .parse_line("[ 03-01 02:19:45.207  1000: 1001 I/ActivityManager ]")
+        .is_none());
+    assert!(p.parse_line("Start proc").is_none());
+    assert!(p.parse_line("for activity").is_none());
+    let r = p.parse_line("").unwrap();
+    assert_eq!(r.tag, "ActivityManager");
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.process, "1000");
+    assert_eq!(r.thread, "1001");
+    assert_eq!(r.message, "Start proc\nfor activity");
+    assert_eq!(
+        r.raw,
+        "[ 03-01 02:19:45.207  1000: 1001 I/ActivityManager ]\nStart proc\nfor activity"
+    );
+
+    // Microseconds and a record without trailing empty line
+    assert!(p
+        .parse_line("[ 2019-03-01 02:19:45.207123 123:456 W/Some Tag ]")
+        .is_none());
+    assert!(p.parse_line("hello").is_none());
+    let r = p.flush().unwrap();
+    assert_eq!(r.tag, "Some Tag");
+    assert_eq!(r.message, "hello");
+    assert!(p.flush().is_none());
+
+    // Other formats pass
+    let r = p.parse_line("03-01 02:19:45.207  1  2 I A: b").unwrap();
+    assert_eq!(r.message, "b");
+}
+
+#[test]
+fn parse_buffer() {
+    let mut p = Parser::default();
+    let r = p.parse_line("03-01 02:19:45.207  1  2 I A: b").unwrap();
+    assert_eq!(r.buffer, None);
+    let r = p.parse_line("--------- beginning of main").unwrap();
+    assert_eq!(r.buffer, Some("main".to_owned()));
+    let r = p.parse_line("03-01 02:19:45.207  1  2 I A: b").unwrap();
+    assert_eq!(r.buffer, Some("main".to_owned()));
+    assert!(p.parse_line("--------- switch to crash").is_none());
+    let r = p.parse_line("03-01 02:19:45.207  1  2 F A: b").unwrap();
+    assert_eq!(r.buffer, Some("crash".to_owned()));
+}
+
+#[test]
+fn parse_threadtime() {
+    let lines = [
+        "03-01 02:19:45.207  1000  1001 I ActivityManager: Start proc 3142:com.example/u0a96",
+        "2017-03-25 19:11:19.052  3758  3758 D A: B",
+        "03-01 02:19:45.207123  1  2 W Tag:",
+        "03-01 02:19:45.207123456  1  2 E  Tag  : message : with colon ",
+        "03-01 02:19:45.2  abc  def F Tag: x",
+        "03-01 02:19:45.207\t1\t2\tA\tTag: tabs",
+    ];
+    for line in &lines {
+        let mut record = printable(CompleteStr(line)).unwrap().1;
+        record.raw = line.to_string();
+        assert_eq!(Threadtime::parse(line).unwrap().to_record(line), record);
+    }
+    let threadtime = Threadtime::parse(lines[3]).unwrap();
+    assert_eq!(threadtime.tag, "Tag");
+    assert_eq!(threadtime.message, "message : with colon");
+
+    // Variants that are left to the nom parser
+    for line in &[
+        "03-01 02:19:45.207 +0100  1  2 I Tag: zone",
+        "03-01 02:19:45.207 u0_a1  1  2 I Tag: uid",
+        "1490469079.052  1  2 I Tag: epoch",
+    ] {
+        assert!(Threadtime::parse(line).is_none());
+        assert!(printable(CompleteStr(line)).is_ok());
+    }
+    // Invalid lines
+    assert!(Threadtime::parse("03-01 02:19:45.207  1  2 X Tag: level").is_none());
+    assert!(Threadtime::parse("03-01 02:19:45.207  1  2 I Tag without colon").is_none());
+    assert!(Threadtime::parse("03-01 02:19:45  1  2 I Tag: fraction").is_none());
+    assert!(Threadtime::parse("03-01").is_none());
+}
+
+#[test]
+fn parse_fallback() {
+    let mut parser = Parser::default();
+    parser
+        .parse_line("03-01 02:19:45.207  1234  1234 I Tag: hello")
+        .unwrap();
+    assert!(!parser.is_fallback());
+    let record = parser.parse_line("garbage").unwrap();
+    assert!(parser.is_fallback());
+    assert_eq!(record.message, "garbage");
+    parser
+        .parse_line("03-01 02:19:45.208  1234  1234 I Tag: again")
+        .unwrap();
+    assert!(!parser.is_fallback());
+    parser.parse_line("--------- beginning of main").unwrap();
+    assert!(!parser.is_fallback());
+}
@@ -0,0 +1,108 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::utils::pidof;
+use failure::{format_err, Error};
+use regex::Regex;
+use rogcat::record::Record;
+use std::{
+    sync::{
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Interval for retrying to resolve the pid of a package that isn't running
+const RETRY: Duration = Duration::from_secs(1);
+
+/// Tracks the pids of a package. The pids are resolved with `adb shell pidof`
+/// and re-resolved in the background whenever the `ActivityManager` reports
+/// a start or death of the package.
+#[derive(Debug)]
+pub struct PidOf {
+    package: String,
+    pids: Arc<Mutex<Vec<String>>>,
+    resolve: Sender<()>,
+    start: Regex,
+}
+
+impl PidOf {
+    pub fn new(package: &str) -> Result<PidOf, Error> {
+        let pids = Arc::new(Mutex::new(pidof(package)?));
+        let (resolve, rx) = channel::<()>();
+
+        let package_bg = package.to_owned();
+        let pids_bg = pids.clone();
+        thread::spawn(move || loop {
+            let unresolved = pids_bg.lock().expect("Failed to lock pids").is_empty();
+            let event = if unresolved {
+                rx.recv_timeout(RETRY)
+            } else {
+                rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            };
+            if let Err(RecvTimeoutError::Disconnected) = event {
+                break;
+            }
+            if let Ok(p) = pidof(&package_bg) {
+                *pids_bg.lock().expect("Failed to lock pids") = p;
+            }
+        });
+
+        let start = Regex::new(&format!(
+            r"^Start proc (\d+):{}[/ ]",
+            regex::escape(package)
+        ))
+        .map_err(|e| format_err!("Invalid package name {}: {}", package, e))?;
+
+        Ok(PidOf {
+            package: package.to_owned(),
+            pids,
+            resolve,
+            start,
+        })
+    }
+
+    /// Check if record is from one of the tracked processes. Process
+    /// starts and deaths reported in record trigger a update of the pids.
+    pub fn filter(&self, record: &Record) -> bool {
+        if record.tag == "ActivityManager" && record.message.contains(&self.package) {
+            if let Some(pid) = self
+                .start
+                .captures(&record.message)
+                .and_then(|c| c.get(1))
+                .map(|m| m.as_str().to_owned())
+            {
+                self.pids.lock().expect("Failed to lock pids").push(pid);
+            } else if record.message.starts_with("Start proc")
+                || record.message.contains("has died")
+                || record.message.starts_with("Killing")
+            {
+                self.resolve.send(()).ok();
+            }
+        }
+
+        self.pids
+            .lock()
+            .expect("Failed to lock pids")
+            .contains(&record.process)
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::utils::{adb_cmd, device_cmd, ps};
+use clap::ArgMatches;
+use failure::Error;
+use rogcat::record::Record;
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{sync_channel, RecvTimeoutError, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Interval for refreshing the process list
+const REFRESH: Duration = Duration::from_secs(10);
+/// Minimum time between two refreshes requested for unknown pids
+const MIN_REFRESH: Duration = Duration::from_secs(1);
+
+/// Resolves pids to process names. The process list is read with
+/// `adb shell ps` and refreshed periodically and whenever a unknown pid
+/// is seen.
+pub struct ProcessNames {
+    /// Names by pid. Pids that are looked up but not (yet) resolved are None
+    names: Arc<Mutex<HashMap<String, Option<String>>>>,
+    refresh: SyncSender<()>,
+}
+
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Result<Option<ProcessNames>, Error> {
+    // Splitting a capture by process needs the names
+    let split = args.value_of("split_by") == Some("process")
+        && !args.is_present("input")
+        && !args.is_present("COMMAND");
+    if !args.is_present("process_names") && !split {
+        return Ok(None);
+    }
+
+    // Records of multiple devices carry the device and are not resolved
+    let adb = if args.is_present("all_devices") {
+        adb_cmd(args)?
+    } else {
+        device_cmd(args)?
+    };
+    let resolve = move || {
+        ps(&adb)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(pid, name)| (pid, Some(name)))
+            .collect::<HashMap<_, _>>()
+    };
+    let names = Arc::new(Mutex::new(resolve()));
+    let (refresh, rx) = sync_channel(1);
+    let names_bg = names.clone();
+    thread::spawn(move || loop {
+        thread::sleep(MIN_REFRESH);
+        if let Err(RecvTimeoutError::Disconnected) = rx.recv_timeout(REFRESH - MIN_REFRESH) {
+            break;
+        }
+        let processes = resolve();
+        names_bg
+            .lock()
+            .expect("Failed to lock process names")
+            .extend(processes);
+    });
+
+    Ok(Some(ProcessNames { names, refresh }))
+}
+
+impl ProcessNames {
+    /// Set the process name of record if the pid is known
+    pub fn process(&self, record: &mut Record) {
+        // Records of other devices can't be resolved
+        if record.process_name.is_some() || record.process.is_empty() || record.device.is_some() {
+            return;
+        }
+        let mut names = self.names.lock().expect("Failed to lock process names");
+        match names.get(&record.process) {
+            Some(name) => record.process_name = name.clone(),
+            None => {
+                names.insert(record.process.clone(), None);
+                self.refresh.try_send(()).ok();
+            }
+        }
+    }
+}
@@ -23,8 +23,19 @@ use clap::{value_t, ArgMatches};
 use failure::{format_err, Error};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap, convert::Into, env::var, fs::File, io::Read, ops::AddAssign,
+    collections::{BTreeMap, HashMap},
+    convert::Into,
+    env::var,
+    fs::{metadata, File},
+    io::Read,
+    ops::AddAssign,
     path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
 };
 use toml::from_str;
 
@@ -34,18 +45,73 @@ const DEFAULT_PROFILE_NAME: &str = "default";
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct Profile {
     pub comment: Option<String>,
+    /// Filters and highlights toggled off at runtime as `KIND:VALUE` where
+    /// kind is the name of the argument e.g `tag:Foo`
+    pub disabled: Vec<String>,
     pub extends: Vec<String>,
+    pub fields: BTreeMap<String, String>,
+    pub filter: Vec<String>,
     pub highlight: Vec<String>,
+    /// Actions of highlight patterns e.g `fg:red,underline`
+    pub highlight_actions: BTreeMap<String, String>,
+    /// Log levels of tags set on the device with `setprop log.tag.TAG`
+    pub log_levels: BTreeMap<String, String>,
     pub message: Vec<String>,
     pub message_ignore_case: Vec<String>,
+    pub output: Vec<Output>,
+    /// Selected preset. An empty name deselects the preset of the profile
+    pub preset: Option<String>,
+    /// Named profiles added to this profile when selected
+    pub presets: BTreeMap<String, Profile>,
+    pub redact: Vec<String>,
     pub regex: Vec<String>,
+    pub rewrite: Vec<String>,
+    pub suppress: Vec<String>,
     pub tag: Vec<String>,
     pub tag_ignore_case: Vec<String>,
+    pub tag_level: Vec<String>,
+    pub terminal_columns: Option<String>,
+    pub terminal_hide_timestamp: Option<bool>,
+    pub terminal_level_glyphs: HashMap<String, String>,
+    pub terminal_level_padding: Option<usize>,
+    pub terminal_level_style: Option<String>,
+    pub terminal_no_dimm: Option<bool>,
+    pub terminal_show_date: Option<bool>,
+    pub terminal_tag_width: Option<usize>,
+    pub theme: Option<String>,
+    pub transform: Vec<String>,
+}
+
+/// Output file written in addition to the terminal or `--output`
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Output {
+    /// Filename with optional strftime specifiers and `{index}`
+    pub file: String,
+    pub format: Option<String>,
+    pub keep: Option<usize>,
+    pub max_file_size: Option<String>,
+    pub overwrite: Option<bool>,
+    pub records_per_file: Option<String>,
+    pub rotate_interval: Option<String>,
 }
 
 /// Create a new Profiles instance from a give configuration file
 /// and default if file is not present or readable
 pub fn from_args(args: &ArgMatches) -> Result<Profile, Error> {
+    let mut profile = load(args)?;
+    if let Some(preset) = args.value_of("preset") {
+        profile.preset = Some(preset.to_owned());
+    }
+    if let Some(ref preset) = profile.preset {
+        if !profile.presets.contains_key(preset) {
+            return Err(format_err!("Unknown preset {}", preset));
+        }
+    }
+    Ok(profile)
+}
+
+/// Read the profiles file and select a profile
+fn load(args: &ArgMatches) -> Result<Profile, Error> {
     let file = file(Some(args))?;
     if !file.exists() {
         Ok(Profile::default())
@@ -76,10 +142,94 @@ pub fn from_args(args: &ArgMatches) -> Result<Profile, Error> {
             expand(DEFAULT_PROFILE_NAME, &mut profile, &profiles)?;
         }
 
+        // Theme files are relative to the profiles file
+        if let (Some(theme), Some(dir)) = (&profile.theme, file.parent()) {
+            profile.theme = Some(dir.join(theme).display().to_string());
+        }
+
         Ok(profile)
     }
 }
 
+/// Profile that is reloaded whenever the profiles file is modified and
+/// extended by the commands entered at runtime
+#[derive(Clone, Debug)]
+pub struct Watch {
+    generation: Arc<AtomicUsize>,
+    state: Arc<Mutex<WatchState>>,
+}
+
+#[derive(Debug)]
+struct WatchState {
+    /// Profile read from the profiles file
+    file: Profile,
+    /// Filters and highlights added at runtime
+    runtime: Profile,
+}
+
+impl Watch {
+    /// Return the current profile if it changed since the last call
+    /// with generation and update generation
+    pub fn changed(&self, generation: &mut usize) -> Option<Profile> {
+        let current = self.generation.load(Ordering::Acquire);
+        if current == *generation {
+            None
+        } else {
+            *generation = current;
+            let state = self.state.lock().expect("Failed to lock profile");
+            let mut profile = state.file.clone();
+            profile += state.runtime.clone();
+            if let Some(ref preset) = state.runtime.preset {
+                profile.preset = Some(preset.clone()).filter(|p| !p.is_empty());
+            }
+            Some(profile.resolve())
+        }
+    }
+
+    /// Modify the runtime additions to the profile
+    pub fn update<F: FnOnce(&mut Profile)>(&self, f: F) {
+        f(&mut self.state.lock().expect("Failed to lock profile").runtime);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// Poll the profiles file for modifications and reload the selected profile.
+/// Failures are reported and the previous profile stays active.
+pub fn watch(args: &ArgMatches<'static>, profile: Profile) -> Result<Watch, Error> {
+    let file = file(Some(args))?;
+    let watch = Watch {
+        generation: Arc::new(AtomicUsize::new(0)),
+        state: Arc::new(Mutex::new(WatchState {
+            file: profile,
+            runtime: Profile::default(),
+        })),
+    };
+    let args = args.clone();
+    let w = watch.clone();
+    let modified = |file: &PathBuf| metadata(file).map(|m| (m.modified().ok(), m.len())).ok();
+
+    thread::spawn(move || {
+        let mut last = modified(&file);
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            let current = modified(&file);
+            if current == last {
+                continue;
+            }
+            last = current;
+            match from_args(&args) {
+                Ok(profile) => {
+                    w.state.lock().expect("Failed to lock profile").file = profile;
+                    w.generation.fetch_add(1, Ordering::AcqRel);
+                }
+                Err(e) => eprintln!("Failed to reload profiles: {}", e),
+            }
+        }
+    });
+
+    Ok(watch)
+}
+
 /// Expand a profile with file content
 fn expand(n: &str, p: &mut Profile, a: &HashMap<String, Profile>) -> Result<(), Error> {
     let mut recursion_limit = 100;
@@ -139,31 +289,114 @@ struct ConfigurationFile {
     profile: HashMap<String, ProfileFile>,
 }
 
+/// A single profile name or a list of names to extend
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum Extends {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl From<Extends> for Vec<String> {
+    fn from(e: Extends) -> Vec<String> {
+        match e {
+            Extends::Single(s) => vec![s],
+            Extends::Multiple(m) => m,
+        }
+    }
+}
+
 /// Struct with exact layout as used in config file
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 struct ProfileFile {
     comment: Option<String>,
-    extends: Option<Vec<String>>,
+    extends: Option<Extends>,
+    fields: Option<BTreeMap<String, String>>,
+    filter: Option<Vec<String>>,
     highlight: Option<Vec<String>>,
+    highlight_actions: Option<BTreeMap<String, String>>,
+    log_levels: Option<BTreeMap<String, String>>,
     message: Option<Vec<String>>,
     message_ignore_case: Option<Vec<String>>,
+    output: Option<Vec<Output>>,
+    preset: Option<String>,
+    presets: Option<BTreeMap<String, ProfileFile>>,
+    redact: Option<Vec<String>>,
     regex: Option<Vec<String>>,
+    rewrite: Option<Vec<String>>,
+    suppress: Option<Vec<String>>,
     tag: Option<Vec<String>>,
     tag_ignore_case: Option<Vec<String>>,
+    tag_level: Option<Vec<String>>,
+    terminal_columns: Option<String>,
+    terminal_hide_timestamp: Option<bool>,
+    terminal_level_glyphs: Option<HashMap<String, String>>,
+    terminal_level_padding: Option<usize>,
+    terminal_level_style: Option<String>,
+    terminal_no_dimm: Option<bool>,
+    terminal_show_date: Option<bool>,
+    terminal_tag_width: Option<usize>,
+    theme: Option<String>,
+    transform: Option<Vec<String>>,
 }
 
 impl From<ProfileFile> for Profile {
     fn from(f: ProfileFile) -> Profile {
         Profile {
             comment: f.comment,
-            extends: f.extends.unwrap_or_default(),
+            disabled: Vec::new(),
+            extends: f.extends.map(Into::into).unwrap_or_default(),
+            fields: f.fields.unwrap_or_default(),
+            filter: f.filter.unwrap_or_default(),
             highlight: f.highlight.unwrap_or_default(),
+            highlight_actions: f.highlight_actions.unwrap_or_default(),
+            log_levels: f.log_levels.unwrap_or_default(),
             message: f.message.unwrap_or_default(),
             message_ignore_case: f.message_ignore_case.unwrap_or_default(),
+            output: f.output.unwrap_or_default(),
+            preset: f.preset,
+            presets: f
+                .presets
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(k, v)| (k, v.into()))
+                .collect(),
+            redact: f.redact.unwrap_or_default(),
             regex: f.regex.unwrap_or_default(),
+            rewrite: f.rewrite.unwrap_or_default(),
+            suppress: f.suppress.unwrap_or_default(),
             tag: f.tag.unwrap_or_default(),
             tag_ignore_case: f.tag_ignore_case.unwrap_or_default(),
+            tag_level: f.tag_level.unwrap_or_default(),
+            terminal_columns: f.terminal_columns,
+            terminal_hide_timestamp: f.terminal_hide_timestamp,
+            terminal_level_glyphs: f.terminal_level_glyphs.unwrap_or_default(),
+            terminal_level_padding: f.terminal_level_padding,
+            terminal_level_style: f.terminal_level_style,
+            terminal_no_dimm: f.terminal_no_dimm,
+            terminal_show_date: f.terminal_show_date,
+            terminal_tag_width: f.terminal_tag_width,
+            theme: f.theme,
+            transform: f.transform.unwrap_or_default(),
+        }
+    }
+}
+
+impl Profile {
+    /// Check if a filter or highlight of kind is toggled off
+    pub fn is_disabled(&self, kind: &str, value: &str) -> bool {
+        self.disabled
+            .iter()
+            .any(|d| d.strip_prefix(kind).and_then(|d| d.strip_prefix(':')) == Some(value))
+    }
+
+    /// Profile with the filters and settings of the selected preset added
+    pub fn resolve(&self) -> Profile {
+        let mut profile = self.clone();
+        if let Some(preset) = self.preset.as_ref().and_then(|p| self.presets.get(p)) {
+            profile += preset.clone();
         }
+        profile
     }
 }
 
@@ -177,9 +410,60 @@ impl AddAssign for Profile {
             };
         }
 
+        vec_extend!(self.disabled, other.disabled);
         vec_extend!(self.extends, other.extends);
+        vec_extend!(self.filter, other.filter);
         vec_extend!(self.highlight, other.highlight);
         vec_extend!(self.message, other.message);
+        vec_extend!(self.message_ignore_case, other.message_ignore_case);
+        vec_extend!(self.output, other.output);
+        vec_extend!(self.redact, other.redact);
+        vec_extend!(self.regex, other.regex);
+        vec_extend!(self.suppress, other.suppress);
         vec_extend!(self.tag, other.tag);
+        vec_extend!(self.tag_ignore_case, other.tag_ignore_case);
+        vec_extend!(self.tag_level, other.tag_level);
+        vec_extend!(self.transform, other.transform);
+
+        // Rewrite rules are applied in order: rules of extended profiles first
+        let mut rewrite = other.rewrite;
+        rewrite.retain(|r| !self.rewrite.contains(r));
+        rewrite.append(&mut self.rewrite);
+        self.rewrite = rewrite;
+
+        // Settings of the extending profile take precedence
+        macro_rules! option_or {
+            ($x:expr, $y:expr) => {
+                if $x.is_none() {
+                    $x = $y;
+                }
+            };
+        }
+
+        option_or!(self.terminal_columns, other.terminal_columns);
+        option_or!(self.terminal_hide_timestamp, other.terminal_hide_timestamp);
+        option_or!(self.terminal_level_padding, other.terminal_level_padding);
+        option_or!(self.terminal_level_style, other.terminal_level_style);
+        option_or!(self.terminal_no_dimm, other.terminal_no_dimm);
+        option_or!(self.terminal_show_date, other.terminal_show_date);
+        option_or!(self.terminal_tag_width, other.terminal_tag_width);
+        option_or!(self.theme, other.theme);
+        option_or!(self.preset, other.preset);
+
+        for (name, regex) in other.fields {
+            self.fields.entry(name).or_insert(regex);
+        }
+        for (pattern, action) in other.highlight_actions {
+            self.highlight_actions.entry(pattern).or_insert(action);
+        }
+        for (tag, level) in other.log_levels {
+            self.log_levels.entry(tag).or_insert(level);
+        }
+        for (name, preset) in other.presets {
+            self.presets.entry(name).or_insert(preset);
+        }
+        for (level, glyph) in other.terminal_level_glyphs {
+            self.terminal_level_glyphs.entry(level).or_insert(glyph);
+        }
     }
 }
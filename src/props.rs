@@ -0,0 +1,181 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Device properties as records. `rogcat props` dumps the output of
+//! `getprop`. With `--watch` the properties are polled and changes are
+//! injected into the logcat stream.
+
+use crate::{
+    reader,
+    session::getprop,
+    utils::{device_cmd, interval},
+    LogStream, StreamData,
+};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{stream::iter_ok, Future, Stream};
+use rogcat::record::{Level, Record, Timestamp};
+use std::{
+    collections::BTreeMap,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+use tokio::timer::Interval;
+use tokio_process::CommandExt;
+
+/// Tag of property records
+pub const TAG: &str = "getprop";
+
+/// Delay between polls if not set with `--interval`
+const POLL: Duration = Duration::from_secs(1);
+
+/// Record of a property in the notation of getprop. The previous value is
+/// set for changed properties
+fn record(key: &str, value: &str, previous: Option<&str>) -> Record {
+    let now = time::now();
+    let message = match previous {
+        Some(previous) => format!("[{}]: [{}] (was [{}])", key, value, previous),
+        None => format!("[{}]: [{}]", key, value),
+    };
+    let mut fields = BTreeMap::new();
+    fields.insert("property".to_owned(), key.to_owned());
+    fields.insert("value".to_owned(), value.to_owned());
+    if let Some(previous) = previous {
+        fields.insert("previous".to_owned(), previous.to_owned());
+    }
+    Record {
+        timestamp: Some(Timestamp::new(now)),
+        level: Level::Info,
+        tag: TAG.to_owned(),
+        raw: format!(
+            "{} I {}: {}",
+            time::strftime("%m-%d %H:%M:%S.%f", &now)
+                .map(|mut t| {
+                    t.truncate(18);
+                    t
+                })
+                .unwrap_or_default(),
+            TAG,
+            message
+        ),
+        message,
+        fields,
+        ..Default::default()
+    }
+}
+
+/// Records of the properties that differ between old and new. Removed
+/// properties have a empty value
+fn changes(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<Record> {
+    let changed = new.iter().filter_map(|(key, value)| match old.get(key) {
+        Some(previous) if previous == value => None,
+        Some(previous) => Some(record(key, value, Some(previous))),
+        None => Some(record(key, value, None)),
+    });
+    let removed = old
+        .iter()
+        .filter(|(key, _)| !new.contains_key(*key))
+        .map(|(key, previous)| record(key, "", Some(previous)));
+    changed.chain(removed).collect()
+}
+
+/// Run getprop on the device
+fn read(adb: &[String]) -> impl Future<Item = BTreeMap<String, String>, Error = Error> {
+    Command::new(&adb[0])
+        .args(&adb[1..])
+        .args(["shell", "getprop"])
+        .stdin(Stdio::null())
+        .output_async()
+        .map_err(|e| format_err!("Failed to run adb: {}", e))
+        .and_then(|output| {
+            if output.status.success() {
+                Ok(getprop(&String::from_utf8_lossy(&output.stdout)))
+            } else {
+                Err(format_err!(
+                    "Failed to run getprop: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ))
+            }
+        })
+}
+
+/// Property records of `rogcat props`
+pub fn from_args<'a>(args: &ArgMatches<'a>, props: &ArgMatches<'a>) -> Result<LogStream, Error> {
+    let adb = device_cmd(args)?;
+    if !props.is_present("watch") {
+        let records = read(&adb)
+            .map(|props| {
+                let records = props
+                    .iter()
+                    .map(|(key, value)| StreamData::Record(Box::new(record(key, value, None))))
+                    .collect::<Vec<_>>();
+                iter_ok(records)
+            })
+            .flatten_stream();
+        return Ok(Box::new(records));
+    }
+
+    let poll = match props.value_of("interval") {
+        Some(i) => interval(i)?,
+        None => POLL,
+    };
+    // Polls fail while the device is offline. Changes are reported once it is back
+    let mut last: Option<BTreeMap<String, String>> = None;
+    let changes = Interval::new(Instant::now(), poll)
+        .map_err(|e| format_err!("Timer error: {}", e))
+        .and_then(move |_| read(&adb).then(Ok::<_, Error>))
+        .filter_map(Result::ok)
+        .map(move |props| {
+            let changes = match last {
+                Some(ref last) => changes(last, &props),
+                None => Vec::new(),
+            };
+            last = Some(props);
+            iter_ok(changes.into_iter().map(|r| StreamData::Record(Box::new(r))))
+        })
+        .flatten();
+    Ok(Box::new(reader::logcat(args)?.select(changes)))
+}
+
+#[test]
+fn property_changes() {
+    let props = |p: &[(&str, &str)]| {
+        p.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let old = props(&[("a", "1"), ("b", "2"), ("c", "3")]);
+    let new = props(&[("a", "1"), ("b", "4"), ("d", "5")]);
+    let changes = changes(&old, &new)
+        .into_iter()
+        .map(|r| r.message)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        changes,
+        vec!["[b]: [4] (was [2])", "[d]: [5]", "[c]: [] (was [3])"]
+    );
+
+    let record = record("ro.product.model", "Pixel 4", None);
+    assert_eq!(record.tag, TAG);
+    assert_eq!(record.fields["value"], "Pixel 4");
+    assert!(record
+        .raw
+        .ends_with("I getprop: [ro.product.model]: [Pixel 4]"));
+}
@@ -0,0 +1,256 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Protocol buffers encoding of records as defined in `proto/record.proto`.
+//! Streams of records are length delimited: each message is prefixed with its
+//! size as varint.
+
+use crate::record::{Level, Record, Timestamp};
+use failure::{err_msg, format_err, Error};
+use time::{at_utc, Timespec};
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_LEN: u64 = 2;
+
+pub fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_key(buf: &mut Vec<u8>, field: u64, wire: u64) {
+    put_varint(buf, field << 3 | wire);
+}
+
+/// Append a varint field. Default values are omitted as in proto3
+pub fn put_uint(buf: &mut Vec<u8>, field: u64, value: u64) {
+    if value != 0 {
+        put_key(buf, field, WIRE_VARINT);
+        put_varint(buf, value);
+    }
+}
+
+pub fn put_bytes(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    if !value.is_empty() {
+        put_key(buf, field, WIRE_LEN);
+        put_varint(buf, value.len() as u64);
+        buf.extend_from_slice(value);
+    }
+}
+
+/// Append a embedded message. Empty messages are written as well to
+/// select a member of a `oneof`
+pub fn put_message(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    put_key(buf, field, WIRE_LEN);
+    put_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+fn level_value(level: &Level) -> u64 {
+    match level {
+        Level::None => 0,
+        Level::Trace => 1,
+        Level::Verbose => 2,
+        Level::Debug => 3,
+        Level::Info => 4,
+        Level::Warn => 5,
+        Level::Error => 6,
+        Level::Fatal => 7,
+        Level::Assert => 8,
+    }
+}
+
+fn level(value: u64) -> Level {
+    match value {
+        1 => Level::Trace,
+        2 => Level::Verbose,
+        3 => Level::Debug,
+        4 => Level::Info,
+        5 => Level::Warn,
+        6 => Level::Error,
+        7 => Level::Fatal,
+        8 => Level::Assert,
+        _ => Level::None,
+    }
+}
+
+/// Encode a record as `rogcat.Record` message
+pub fn encode(record: &Record) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(record.raw.len() + record.message.len() + 64);
+    if let Some(ref timestamp) = record.timestamp {
+        let spec = timestamp.to_timespec();
+        let mut t = vec![];
+        put_uint(&mut t, 1, spec.sec as u64);
+        put_uint(&mut t, 2, spec.nsec as u64);
+        put_key(&mut buf, 1, WIRE_LEN);
+        put_varint(&mut buf, t.len() as u64);
+        buf.extend(t);
+    }
+    put_bytes(&mut buf, 2, record.message.as_bytes());
+    put_uint(&mut buf, 3, level_value(&record.level));
+    put_bytes(&mut buf, 4, record.tag.as_bytes());
+    put_bytes(&mut buf, 5, record.process.as_bytes());
+    put_bytes(&mut buf, 6, record.thread.as_bytes());
+    put_bytes(&mut buf, 7, record.raw.as_bytes());
+    if let Some(ref device) = record.device {
+        put_bytes(&mut buf, 8, device.as_bytes());
+    }
+    if let Some(ref buffer) = record.buffer {
+        put_bytes(&mut buf, 9, buffer.as_bytes());
+    }
+    if let Some(ref process_name) = record.process_name {
+        put_bytes(&mut buf, 10, process_name.as_bytes());
+    }
+    buf
+}
+
+/// Encode a record prefixed with its length
+pub fn encode_delimited(record: &Record) -> Vec<u8> {
+    let message = encode(record);
+    let mut buf = Vec::with_capacity(message.len() + 4);
+    put_varint(&mut buf, message.len() as u64);
+    buf.extend(message);
+    buf
+}
+
+pub fn varint(buf: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = buf
+            .split_first()
+            .ok_or_else(|| err_msg("Truncated varint"))?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(err_msg("Invalid varint"))
+}
+
+fn bytes<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8], Error> {
+    let len = varint(buf)? as usize;
+    if buf.len() < len {
+        return Err(err_msg("Truncated field"));
+    }
+    let (value, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(value)
+}
+
+/// Iterate the fields of a message as field number and value
+pub fn fields<F: FnMut(u64, Field) -> Result<(), Error>>(
+    mut buf: &[u8],
+    mut f: F,
+) -> Result<(), Error> {
+    while !buf.is_empty() {
+        let key = varint(&mut buf)?;
+        let value = match key & 0x7 {
+            WIRE_VARINT => Field::Varint(varint(&mut buf)?),
+            WIRE_LEN => Field::Bytes(bytes(&mut buf)?),
+            wire => return Err(format_err!("Unsupported wire type {}", wire)),
+        };
+        f(key >> 3, value)?;
+    }
+    Ok(())
+}
+
+pub enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Decode a `rogcat.Record` message
+pub fn decode(buf: &[u8]) -> Result<Record, Error> {
+    let mut record = Record::default();
+    let string = |b: &[u8]| String::from_utf8_lossy(b).into_owned();
+    fields(buf, |field, value| {
+        match (field, value) {
+            (1, Field::Bytes(b)) => {
+                let mut spec = Timespec::new(0, 0);
+                fields(b, |field, value| {
+                    match (field, value) {
+                        (1, Field::Varint(v)) => spec.sec = v as i64,
+                        (2, Field::Varint(v)) => spec.nsec = v as i32,
+                        _ => (),
+                    }
+                    Ok(())
+                })?;
+                record.timestamp = Some(Timestamp::new(at_utc(spec)));
+            }
+            (2, Field::Bytes(b)) => record.message = string(b),
+            (3, Field::Varint(v)) => record.level = level(v),
+            (4, Field::Bytes(b)) => record.tag = string(b),
+            (5, Field::Bytes(b)) => record.process = string(b),
+            (6, Field::Bytes(b)) => record.thread = string(b),
+            (7, Field::Bytes(b)) => record.raw = string(b),
+            (8, Field::Bytes(b)) => record.device = Some(string(b)),
+            (9, Field::Bytes(b)) => record.buffer = Some(string(b)),
+            (10, Field::Bytes(b)) => record.process_name = Some(string(b)),
+            // Unknown fields are skipped
+            _ => (),
+        }
+        Ok(())
+    })?;
+    Ok(record)
+}
+
+/// Decode a length delimited record and advance buf
+pub fn decode_delimited(buf: &mut &[u8]) -> Result<Record, Error> {
+    decode(bytes(buf)?)
+}
+
+#[test]
+fn round_trip() {
+    let record = Record {
+        timestamp: Some(Timestamp::new(at_utc(Timespec::new(1_500_000_000, 42)))),
+        message: "hello".into(),
+        level: Level::Warn,
+        tag: "Tag".into(),
+        process: "123".into(),
+        thread: "456".into(),
+        raw: "x".repeat(200),
+        device: Some("emulator-5554".into()),
+        buffer: None,
+        process_name: Some("com.example".into()),
+        relative: None,
+        cpu: None,
+        fields: Default::default(),
+        context: false,
+    };
+    let buf = encode_delimited(&record);
+    // Messages longer than 127 bytes need a two byte length prefix
+    assert_eq!(buf.len(), encode(&record).len() + 2);
+    let mut b = &buf[..];
+    let decoded = decode_delimited(&mut b).unwrap();
+    assert!(b.is_empty());
+    assert_eq!(
+        decoded.timestamp.unwrap().to_timespec(),
+        Timespec::new(1_500_000_000, 42)
+    );
+    assert_eq!(decoded.message, record.message);
+    assert_eq!(decoded.level, record.level);
+    assert_eq!(decoded.raw, record.raw);
+    assert_eq!(decoded.device, record.device);
+    assert_eq!(decoded.buffer, None);
+    assert_eq!(decoded.process_name, record.process_name);
+}
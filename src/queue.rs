@@ -0,0 +1,289 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Bounded queue between the reader and the processing of records. The
+//! reader runs as own task and keeps draining the source while filters and
+//! sinks are busy. The `--overflow` policy decides what happens when the
+//! queue is full: `block` stops reading until records are taken,
+//! `drop-oldest` and `drop-newest` discard records and count them.
+
+use crate::RecordStream;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{
+    task::{self, Task},
+    Async, Future, Poll, Stream,
+};
+use rogcat::record::Record;
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::runtime::TaskExecutor;
+
+/// Default capacity if only the policy is given
+const DEFAULT_SIZE: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Overflow {
+    Block,
+    DropOldest,
+    DropNewest,
+}
+
+impl FromStr for Overflow {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Overflow, Error> {
+        match s {
+            "block" => Ok(Overflow::Block),
+            "drop-oldest" => Ok(Overflow::DropOldest),
+            "drop-newest" => Ok(Overflow::DropNewest),
+            _ => Err(format_err!("Invalid overflow policy {}", s)),
+        }
+    }
+}
+
+/// Number of records dropped due to overflows
+#[derive(Clone, Debug, Default)]
+pub struct Dropped(Arc<AtomicUsize>);
+
+impl Dropped {
+    /// Number of dropped records
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Print the number of dropped records if any
+    pub fn report(&self) {
+        let dropped = self.count();
+        if dropped > 0 {
+            eprintln!("Dropped {} records due to queue overflows", dropped);
+        }
+    }
+}
+
+struct State {
+    records: VecDeque<Record>,
+    capacity: usize,
+    overflow: Overflow,
+    /// The reader ended or failed
+    done: bool,
+    error: Option<Error>,
+    /// The consumer is gone
+    closed: bool,
+    reader: Option<Task>,
+    consumer: Option<Task>,
+}
+
+type Shared = Arc<Mutex<State>>;
+
+/// Task that moves records from the source to the queue
+struct Reader {
+    records: RecordStream,
+    state: Shared,
+    dropped: Dropped,
+}
+
+impl Reader {
+    /// Queue record according to the overflow policy. With `block` the
+    /// queue never overflows because reading pauses
+    fn push(&self, state: &mut State, record: Record) {
+        let full = state.records.len() >= state.capacity;
+        match state.overflow {
+            Overflow::DropNewest if full => {
+                self.dropped.0.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            Overflow::DropOldest if full => {
+                self.dropped.0.fetch_add(1, Ordering::Relaxed);
+                state.records.pop_front();
+            }
+            _ => (),
+        }
+        state.records.push_back(record);
+    }
+}
+
+impl Future for Reader {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            {
+                let mut state = self.state.lock().expect("Failed to lock queue");
+                if state.closed {
+                    return Ok(Async::Ready(()));
+                }
+                if state.overflow == Overflow::Block && state.records.len() >= state.capacity {
+                    state.reader = Some(task::current());
+                    return Ok(Async::NotReady);
+                }
+            }
+
+            let item = self.records.poll();
+            let mut state = self.state.lock().expect("Failed to lock queue");
+            let done = match item {
+                Ok(Async::Ready(Some(record))) => {
+                    self.push(&mut state, record);
+                    false
+                }
+                Ok(Async::Ready(None)) => true,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    state.error = Some(e);
+                    true
+                }
+            };
+            state.done = done;
+            if let Some(consumer) = state.consumer.take() {
+                consumer.notify();
+            }
+            if done {
+                return Ok(Async::Ready(()));
+            }
+        }
+    }
+}
+
+/// Receiving end of the queue
+struct Queue {
+    state: Shared,
+}
+
+impl Stream for Queue {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        let mut state = self.state.lock().expect("Failed to lock queue");
+        if let Some(record) = state.records.pop_front() {
+            if let Some(reader) = state.reader.take() {
+                reader.notify();
+            }
+            Ok(Async::Ready(Some(record)))
+        } else if let Some(e) = state.error.take() {
+            Err(e)
+        } else if state.done {
+            Ok(Async::Ready(None))
+        } else {
+            state.consumer = Some(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+            if let Some(reader) = state.reader.take() {
+                reader.notify();
+            }
+        }
+    }
+}
+
+/// Read records on a own task into a bounded queue if `--queue-size` or
+/// `--overflow` is passed
+pub fn from_args<'a>(
+    args: &ArgMatches<'a>,
+    records: RecordStream,
+    executor: &TaskExecutor,
+) -> Result<(RecordStream, Dropped), Error> {
+    let dropped = Dropped::default();
+    if !args.is_present("queue_size") && !args.is_present("overflow") {
+        return Ok((records, dropped));
+    }
+
+    let capacity = match args.value_of("queue_size") {
+        Some(s) => match usize::from_str(s) {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format_err!("Invalid queue size {}", s)),
+        },
+        None => DEFAULT_SIZE,
+    };
+    let overflow = args
+        .value_of("overflow")
+        .map(Overflow::from_str)
+        .transpose()?
+        .unwrap_or(Overflow::Block);
+
+    let (reader, queue) = channel(records, capacity, overflow, dropped.clone());
+    executor.spawn(reader);
+    Ok((Box::new(queue) as RecordStream, dropped))
+}
+
+fn channel(
+    records: RecordStream,
+    capacity: usize,
+    overflow: Overflow,
+    dropped: Dropped,
+) -> (Reader, Queue) {
+    let state = Arc::new(Mutex::new(State {
+        records: VecDeque::new(),
+        capacity,
+        overflow,
+        done: false,
+        error: None,
+        closed: false,
+        reader: None,
+        consumer: None,
+    }));
+    let reader = Reader {
+        records,
+        state: state.clone(),
+        dropped,
+    };
+    (reader, Queue { state })
+}
+
+#[test]
+fn overflow() {
+    use futures::stream::iter_ok;
+
+    let records = |n: usize| {
+        Box::new(iter_ok((0..n).map(|i| Record {
+            message: i.to_string(),
+            ..Default::default()
+        }))) as RecordStream
+    };
+    let messages = |queue: Queue| queue.map(|r| r.message).collect().wait().unwrap().join(",");
+
+    let dropped = Dropped::default();
+    let (reader, queue) = channel(records(5), 2, Overflow::DropOldest, dropped.clone());
+    reader.wait().unwrap();
+    assert_eq!(messages(queue), "3,4");
+    assert_eq!(dropped.0.load(Ordering::Relaxed), 3);
+
+    let dropped = Dropped::default();
+    let (reader, queue) = channel(records(5), 2, Overflow::DropNewest, dropped.clone());
+    reader.wait().unwrap();
+    assert_eq!(messages(queue), "0,1");
+    assert_eq!(dropped.0.load(Ordering::Relaxed), 3);
+
+    assert_eq!(Overflow::from_str("block").unwrap(), Overflow::Block);
+    assert!(Overflow::from_str("drop").is_err());
+}
@@ -19,29 +19,63 @@
 // SOFTWARE.
 
 use crate::{
+    adbclient::{self, Target},
+    adbserver,
+    clock::Clock,
+    follow,
+    hosttime::HostTime,
+    index,
     lossy_lines::{lossy_lines, LossyLinesCodec},
-    utils::{adb, config_get},
+    mmap::MappedFile,
+    source,
+    strict::Sidecar,
+    utils::{adb_cmd, config_get, device_cmd, devices, interval, select_device},
     LogStream, StreamData, DEFAULT_BUFFER,
 };
 use clap::{value_t, ArgMatches};
 use failure::{err_msg, format_err, Error};
-use futures::{stream::iter_ok, Async, Future, Stream};
+use futures::{
+    future::{loop_fn, ok, Either, Loop},
+    stream::empty,
+    stream::iter_ok,
+    try_ready, Async, Future, Poll, Stream,
+};
+use lazy_static::lazy_static;
 #[cfg(target_os = "linux")]
-use rogcat::record::{Record, Timestamp};
+use rogcat::record::Timestamp;
+use rogcat::{
+    binary::LogcatBinaryCodec,
+    events::{EventTags, EVENT_LOG_TAGS},
+    msgpack::MsgpackCodec,
+    parser::Parser,
+    record::Record,
+};
 use std::{
     borrow::ToOwned,
+    cmp::min,
+    collections::HashMap,
     convert::Into,
-    io::BufReader,
-    net::ToSocketAddrs,
+    env,
+    fs::read_to_string,
+    io::{BufReader, SeekFrom, Write},
+    net::{SocketAddr, ToSocketAddrs},
     path::PathBuf,
-    process::{Command, Stdio},
+    process::{Command, Output, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+use time::Tm;
 use tokio::{
     codec::{Decoder, FramedRead},
     fs::File,
+    io::AsyncRead,
     net::TcpStream,
+    reactor::Handle,
+    timer::Delay,
 };
 use tokio_process::{Child, CommandExt};
+use tokio_serial::{DataBits, FlowControl, Parity, SerialPortSettings, StopBits};
 use url::Url;
 
 /// A spawned child process that implements LogStream
@@ -49,29 +83,304 @@ struct Process {
     cmd: Vec<String>,
     /// Respawn cmd upone termination
     respawn: bool,
+    /// Decode stdout as binary logger entries instead of lines
+    binary: bool,
+    /// Tag database for decoding binary events
+    event_tags: Option<Arc<EventTags>>,
+    /// Run cmd through the adb server protocol instead of spawning it
+    native: Option<Target>,
+    /// Wait before respawning e.g to not hammer a unreachable ssh host
+    backoff: Option<Backoff>,
+    /// Output was received since the last spawn
+    received: bool,
+    /// Wait for the device to boot before each spawn
+    boot: Option<Boot>,
+    booting: Option<Box<dyn Future<Item = (), Error = Error> + Send>>,
+    delay: Option<Delay>,
     child: Option<Child>,
     stream: Option<LogStream>,
 }
 
-/// Open a file and provide a stream of lines
+/// Delay between checks if the device completed booting
+const BOOT_POLL: Duration = Duration::from_secs(1);
+
+/// Delay between attempts to reopen a disconnected serial port
+const SERIAL_RETRY: Duration = Duration::from_secs(1);
+
+/// Delay between connection attempts of a remote adb
+const RECONNECT: Duration = Duration::from_secs(1);
+
+/// Upper limit of the delay between reconnects if not set with `--reconnect`
+const MAX_RECONNECT: Duration = Duration::from_secs(30);
+
+/// Delay between respawns. The delay doubles with each attempt that
+/// yields no output up to a maximum.
+#[derive(Clone, Copy, Debug)]
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    /// Delay before the next attempt
+    fn next(&mut self, received: bool) -> Duration {
+        if received {
+            self.current = self.initial;
+        }
+        let delay = self.current;
+        self.current = min(self.current * 2, self.max);
+        delay
+    }
+}
+
+/// Wait for a device and `sys.boot_completed` before logcat is spawned
+#[derive(Clone, Debug)]
+struct Boot {
+    adb: Vec<String>,
+    /// Arguments to clear the buffers once booted
+    clear: Option<Vec<String>>,
+}
+
+impl Boot {
+    fn from_args<'a>(args: &ArgMatches<'a>, adb: &[String]) -> Option<Boot> {
+        if !args.is_present("wait_for_boot") {
+            return None;
+        }
+        let clear = if args.value_of("wait_for_boot") == Some("clear") {
+            let mut clear = vec!["logcat".to_owned(), "-c".to_owned()];
+            for buffer in buffers(args) {
+                clear.push("-b".into());
+                clear.push(buffer);
+            }
+            Some(clear)
+        } else {
+            None
+        };
+        Some(Boot {
+            adb: adb.to_vec(),
+            clear,
+        })
+    }
+
+    fn wait(&self) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+        status("Waiting for device to boot");
+        let adb = self.adb.clone();
+        let clear = self.clear.clone();
+        let booted = loop_fn(adb.clone(), |adb| {
+            run(&adb, &["shell", "getprop", "sys.boot_completed"]).and_then(move |output| {
+                if String::from_utf8_lossy(&output.stdout).trim() == "1" {
+                    Either::A(ok(Loop::Break(adb)))
+                } else {
+                    let delay = Delay::new(Instant::now() + BOOT_POLL)
+                        .map(|_| Loop::Continue(adb))
+                        .map_err(|e| format_err!("Timer error: {}", e));
+                    Either::B(delay)
+                }
+            })
+        });
+        let wait = run(&adb, &["wait-for-device"])
+            .and_then(|_| booted)
+            .and_then(move |adb| match clear {
+                Some(clear) => {
+                    let clear = clear.iter().map(String::as_str).collect::<Vec<_>>();
+                    Either::A(run(&adb, &clear).map(|_| ()))
+                }
+                None => Either::B(ok(())),
+            });
+        Box::new(wait)
+    }
+}
+
+/// Run adb with args and collect the output
+fn run(adb: &[String], args: &[&str]) -> impl Future<Item = Output, Error = Error> {
+    let line = format!("{} {}", adb.join(" "), args.join(" "));
+    Command::new(&adb[0])
+        .args(&adb[1..])
+        .args(args)
+        .stdin(Stdio::null())
+        .output_async()
+        .map_err(move |e| format_err!("Failed to run {}: {}", line, e))
+}
+
+/// Print a dimmed status line on stderr
+fn status(message: &str) {
+    let choice = if atty::is(atty::Stream::Stderr) {
+        ColorChoice::Auto
+    } else {
+        ColorChoice::Never
+    };
+    let mut stderr = StandardStream::stderr(choice);
+    stderr
+        .set_color(ColorSpec::new().set_fg(Some(Color::Ansi256(243))))
+        .and_then(|_| writeln!(stderr, "{}", message))
+        .and_then(|_| stderr.reset())
+        .ok();
+}
+
+/// Lines read from a serial port that is reopened when it disappears
+struct Serial {
+    path: String,
+    settings: SerialPortSettings,
+    stream: Option<LogStream>,
+    retry: Option<Delay>,
+    /// Failures to reopen are reported once until the port is back
+    reported: bool,
+}
+
+/// Parses the lines of a stream into records
+pub struct Records {
+    stream: LogStream,
+    parser: Parser,
+    /// Receives the lines no parser recognized
+    sidecar: Option<Sidecar>,
+    host_time: Option<HostTime>,
+    clock: Option<Clock>,
+}
+
+impl Records {
+    pub fn new(stream: LogStream, parser: Parser) -> Records {
+        Records {
+            stream,
+            parser,
+            sidecar: None,
+            host_time: None,
+            clock: None,
+        }
+    }
+
+    /// Estimate the clock skew of the device and correct timestamps
+    pub fn clock(mut self, clock: Option<Clock>) -> Records {
+        self.clock = clock;
+        self
+    }
+
+    /// Replace the device timestamps with the arrival time
+    pub fn host_timestamps(mut self, host_time: Option<HostTime>) -> Records {
+        self.host_time = host_time;
+        self
+    }
+
+    /// Write unparsed lines to sidecar instead of passing them on
+    pub fn strict(mut self, sidecar: Option<Sidecar>) -> Records {
+        self.sidecar = sidecar;
+        self
+    }
+}
+
+impl Stream for Records {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut record = try_ready!(self.next_record());
+        if let Some(record) = record.as_mut() {
+            if let Some(ref mut clock) = self.clock {
+                clock.process(record);
+            }
+            if let Some(host_time) = self.host_time {
+                host_time.stamp(record);
+            }
+        }
+        Ok(Async::Ready(record))
+    }
+}
+
+impl Records {
+    fn next_record(&mut self) -> Poll<Option<Record>, Error> {
+        loop {
+            match try_ready!(self.stream.poll()) {
+                Some(StreamData::Line(line)) => {
+                    if let Some(record) = self.parser.parse_line(&line) {
+                        match self.sidecar {
+                            Some(ref mut sidecar) if self.parser.is_fallback() => {
+                                sidecar.write(&record.raw)?
+                            }
+                            _ => return Ok(Async::Ready(Some(record))),
+                        }
+                    }
+                }
+                Some(StreamData::Record(record)) => return Ok(Async::Ready(Some(*record))),
+                Some(StreamData::Unparsed(record)) => match self.sidecar {
+                    Some(ref mut sidecar) => sidecar.write(&record.raw)?,
+                    None => return Ok(Async::Ready(Some(*record))),
+                },
+                // Emit a incomplete multi line record at the end
+                None => return Ok(Async::Ready(self.parser.flush())),
+            }
+        }
+    }
+}
+
+/// Open files or listen on tcp or udp urls and provide a stream of lines
 pub fn files<'a>(args: &ArgMatches<'a>) -> Result<LogStream, Error> {
-    let files = args
+    let registry = source::registry();
+    let (urls, files): (Vec<&str>, Vec<&str>) = args
         .values_of("input")
         .ok_or_else(|| err_msg("Missing input argument"))?
+        .partition(|i| registry.handles(i));
+    let files = files
+        .into_iter()
         .map(PathBuf::from)
         .collect::<Vec<PathBuf>>();
 
-    let f = iter_ok::<_, Error>(files)
-        .map(|f| {
-            File::open(f.clone())
-                .map(|s| Decoder::framed(LossyLinesCodec::new(), s))
-                .flatten_stream()
-                .map(StreamData::Line)
-                .map_err(move |e| format_err!("Failed to open {}: {}", f.display(), e))
-        })
-        .flatten();
+    // Indexed files are read from the last indexed record before --since
+    let since = args.value_of("since").map(index::since).transpose()?;
 
-    Ok(Box::new(f))
+    let f = if args.is_present("follow") {
+        follow::files(files)
+    } else {
+        Box::new(
+            iter_ok::<_, Error>(files)
+                .map(move |f| file(f, since))
+                .flatten(),
+        ) as LogStream
+    };
+
+    // Inputs like network listeners or serial ports are received concurrently to the files
+    let mut stream = f;
+    for url in urls {
+        let input = registry.open(url, args)?;
+        stream = Box::new(stream.select(input));
+    }
+    Ok(stream)
+}
+
+/// Read file. Captures written with `--index` are read from the last indexed
+/// record before since
+pub fn file(f: PathBuf, since: Option<Tm>) -> LogStream {
+    // Binary captures of rogcat are decoded to records
+    let msgpack = f.extension().map(|e| e == "msgpack").unwrap_or(false);
+    let offset = since.and_then(|since| index::offset(&f, &since));
+    if !msgpack {
+        if let Some(mapped) = MappedFile::open(&f, offset.unwrap_or(0)) {
+            return Box::new(mapped);
+        }
+    }
+    let stream = File::open(f.clone())
+        .and_then(move |s| match offset {
+            Some(offset) => Either::A(s.seek(SeekFrom::Start(offset)).map(|s| s.0)),
+            None => Either::B(ok(s)),
+        })
+        .map(move |s| {
+            if msgpack {
+                Box::new(FramedRead::new(s, MsgpackCodec).map(|r| StreamData::Record(Box::new(r))))
+                    as Box<dyn Stream<Item = _, Error = _> + Send>
+            } else {
+                Box::new(Decoder::framed(LossyLinesCodec::new(), s).map(StreamData::Line))
+            }
+        })
+        .flatten_stream()
+        .map_err(move |e| format_err!("Failed to open {}: {}", f.display(), e));
+    Box::new(stream)
 }
 
 /// Open stdin and provide a stream of lines
@@ -82,9 +391,157 @@ pub fn stdin() -> LogStream {
     Box::new(s)
 }
 
-/// Open a serial port and provide a stream of lines
-pub fn serial<'a>(_args: &ArgMatches<'a>) -> LogStream {
-    unimplemented!()
+/// Parse a serial url into the device path and port settings. Settings are
+/// passed as query e.g `serial:///dev/ttyUSB0?baud=115200&parity=even` or
+/// in the short form `serial://COM0@115200,8N1`.
+fn serial_settings(url: &str) -> Result<(String, SerialPortSettings), Error> {
+    let invalid = |e: &str| format_err!("Invalid serial url {}: {}", url, e);
+    let url_body = url.trim_start_matches("serial://");
+    let mut settings = SerialPortSettings {
+        baud_rate: 115_200,
+        ..Default::default()
+    };
+
+    let data_bits = |b: &str| match b {
+        "5" => Ok(DataBits::Five),
+        "6" => Ok(DataBits::Six),
+        "7" => Ok(DataBits::Seven),
+        "8" => Ok(DataBits::Eight),
+        _ => Err(invalid("data bits must be 5, 6, 7 or 8")),
+    };
+    let stop_bits = |b: &str| match b {
+        "1" => Ok(StopBits::One),
+        "2" => Ok(StopBits::Two),
+        _ => Err(invalid("stop bits must be 1 or 2")),
+    };
+    let baud_rate = |b: &str| b.parse::<u32>().map_err(|_| invalid("invalid baud rate"));
+
+    let (path, query) = match url_body.find('?') {
+        Some(n) => (&url_body[..n], Some(&url_body[n + 1..])),
+        None => (url_body, None),
+    };
+
+    let path = match path.find('@') {
+        Some(n) => {
+            let mut spec = path[n + 1..].split(',');
+            settings.baud_rate = baud_rate(spec.next().unwrap_or_default())?;
+            if let Some(mode) = spec.next() {
+                let mode = mode.chars().map(|c| c.to_string()).collect::<Vec<_>>();
+                if mode.len() != 3 {
+                    return Err(invalid("mode must be like 8N1"));
+                }
+                settings.data_bits = data_bits(&mode[0])?;
+                settings.parity = match mode[1].to_uppercase().as_str() {
+                    "N" => Parity::None,
+                    "E" => Parity::Even,
+                    "O" => Parity::Odd,
+                    _ => return Err(invalid("parity must be N, E or O")),
+                };
+                settings.stop_bits = stop_bits(&mode[2])?;
+            }
+            &path[..n]
+        }
+        None => path,
+    };
+
+    for pair in query.iter().flat_map(|q| q.split('&')) {
+        let mut pair = pair.splitn(2, '=');
+        let key = pair.next().unwrap_or_default();
+        let value = pair.next().unwrap_or_default();
+        match key {
+            "baud" => settings.baud_rate = baud_rate(value)?,
+            "data_bits" => settings.data_bits = data_bits(value)?,
+            "stop_bits" => settings.stop_bits = stop_bits(value)?,
+            "parity" => {
+                settings.parity = match value {
+                    "none" => Parity::None,
+                    "even" => Parity::Even,
+                    "odd" => Parity::Odd,
+                    _ => return Err(invalid("parity must be none, even or odd")),
+                }
+            }
+            "flow_control" => {
+                settings.flow_control = match value {
+                    "none" => FlowControl::None,
+                    "software" => FlowControl::Software,
+                    "hardware" => FlowControl::Hardware,
+                    _ => return Err(invalid("flow control must be none, software or hardware")),
+                }
+            }
+            _ => return Err(invalid(&format!("unknown option {}", key))),
+        }
+    }
+
+    if path.is_empty() {
+        return Err(invalid("missing device"));
+    }
+    Ok((path.to_owned(), settings))
+}
+
+fn open_serial(path: &str, settings: &SerialPortSettings) -> Result<LogStream, Error> {
+    let port = tokio_serial::Serial::from_path(path, settings)
+        .map_err(|e| format_err!("Failed to open {}: {}", path, e))?;
+    Ok(Box::new(
+        FramedRead::new(port, LossyLinesCodec::new())
+            .map_err(Into::into)
+            .map(StreamData::Line),
+    ))
+}
+
+/// Open a serial port and provide a stream of lines. The port is reopened
+/// if it disappears e.g when a usb adapter is unplugged.
+pub fn serial(url: &str) -> Result<LogStream, Error> {
+    let (path, settings) = serial_settings(url)?;
+    let stream = open_serial(&path, &settings)?;
+    Ok(Box::new(Serial {
+        path,
+        settings,
+        stream: Some(stream),
+        retry: None,
+        reported: false,
+    }))
+}
+
+impl Stream for Serial {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(ref mut stream) = self.stream {
+                match stream.poll() {
+                    Ok(Async::Ready(None)) | Err(_) => {
+                        eprintln!("Serial port {} disconnected", self.path);
+                        self.stream = None;
+                        self.retry = Some(Delay::new(Instant::now() + SERIAL_RETRY));
+                    }
+                    poll => return poll,
+                }
+            }
+
+            if let Some(ref mut retry) = self.retry {
+                try_ready!(retry.poll().map_err(|e| format_err!("Timer error: {}", e)));
+                self.retry = None;
+            }
+
+            match open_serial(&self.path, &self.settings) {
+                Ok(stream) => {
+                    if self.reported {
+                        eprintln!("Serial port {} reconnected", self.path);
+                    }
+                    self.reported = false;
+                    self.stream = Some(stream);
+                }
+                Err(e) => {
+                    if !self.reported {
+                        eprintln!("{}. Retrying...", e);
+                        self.reported = true;
+                    }
+                    self.retry = Some(Delay::new(Instant::now() + SERIAL_RETRY));
+                }
+            }
+        }
+    }
 }
 
 #[cfg(target_os = "linux")]
@@ -100,7 +557,7 @@ pub fn can(dev: &str) -> Result<LogStream, Error> {
                 .map(|b| format!("{:02x}", b))
                 .collect::<Vec<String>>();
             let extended = if s.is_extended() { "E" } else { " " };
-            StreamData::Record(Record {
+            StreamData::Record(Box::new(Record {
                 timestamp: Some(Timestamp::new(now)),
                 message: format!("{} {} ", extended, data.join(" ")),
                 tag: format!("0x{:x}", s.id()),
@@ -117,7 +574,7 @@ pub fn can(dev: &str) -> Result<LogStream, Error> {
                 ),
                 process: process.clone(),
                 ..Default::default()
-            })
+            }))
         });
     Ok(Box::new(stream))
 }
@@ -139,9 +596,184 @@ pub fn tcp(addr: &Url) -> Result<LogStream, Error> {
 
 /// Start a process and stream it stdout
 pub fn logcat<'a>(args: &ArgMatches<'a>) -> Result<LogStream, Error> {
-    let mut cmd = vec![adb()?.display().to_string()];
+    if args.is_present("all_devices") {
+        return all_devices(args);
+    }
+
+    if let Some(addr) = adbclient::from_args(args)? {
+        return native(args, addr);
+    }
+
+    let cmd = device_cmd(args)?;
+    Ok(Box::new(device(args, cmd, backoff(args)?)))
+}
+
+/// Logcat of the device with serial or the device selected by adb if None
+pub fn logcat_device<'a>(args: &ArgMatches<'a>, serial: Option<&str>) -> Result<LogStream, Error> {
+    let mut cmd = adb_cmd(args)?;
+    if let Some(serial) = serial {
+        cmd.push("-s".into());
+        cmd.push(serial.to_owned());
+    }
+    Ok(Box::new(device(args, cmd, backoff(args)?)))
+}
+
+/// Logcat process of the device addressed by the adb command line cmd
+fn device<'a>(args: &ArgMatches<'a>, mut cmd: Vec<String>, backoff: Option<Backoff>) -> Process {
+    let boot = Boot::from_args(args, &cmd);
+    let event_tags = event_tags(args, || pull_event_tags(&cmd));
+    let respawn = logcat_args(args, &mut cmd);
+    let mut process = Process::with_cmd(cmd, respawn);
+    process.binary = args.is_present("binary");
+    process.event_tags = event_tags;
+    process.backoff = backoff;
+    process.boot = boot;
+    process
+}
+
+/// Start a logcat process for each connected device and merge the
+/// streams. Each record is tagged with the device serial.
+fn all_devices<'a>(args: &ArgMatches<'a>) -> Result<LogStream, Error> {
+    let adb = adb_cmd(args)?;
+    let devices = devices(&adb)?;
+    if devices.is_empty() {
+        return Err(err_msg("No devices found"));
+    }
+
+    let backoff = backoff(args)?;
+    let mut streams = devices.into_iter().map(|serial| {
+        let mut cmd = adb.clone();
+        cmd.push("-s".into());
+        cmd.push(serial.clone());
+        let process = device(args, cmd, backoff);
+        let stream = Records::new(Box::new(process), Parser::default()).map(move |mut record| {
+            record.device = Some(serial.clone());
+            StreamData::Record(Box::new(record))
+        });
+        Box::new(stream) as LogStream
+    });
+
+    let first = streams.next().expect("Empty device list");
+    Ok(streams.fold(first, |a, b| Box::new(a.select(b)) as LogStream))
+}
+
+/// Stream logcat through the adb server at addr without spawning adb. The
+/// connection is retried until the server and device are available.
+fn native<'a>(args: &ArgMatches<'a>, addr: SocketAddr) -> Result<LogStream, Error> {
+    let serial = match select_device(args, || adbclient::devices(&addr))? {
+        Some(serial) => Some(serial),
+        None => env::var("ANDROID_SERIAL").ok(),
+    };
+    let target = Target { addr, serial };
+    let event_tags = event_tags(args, || {
+        adbclient::output(&target, &format!("cat {}", EVENT_LOG_TAGS))
+            .map(|output| String::from_utf8_lossy(&output).into_owned())
+    });
+    let mut cmd = Vec::new();
+    let respawn = logcat_args(args, &mut cmd);
+    let mut process = Process::with_cmd(cmd, respawn);
+    process.binary = args.is_present("binary");
+    process.event_tags = event_tags;
+    process.native = Some(target);
+    process.backoff = backoff(args)?.or_else(|| Some(Backoff::new(RECONNECT, MAX_RECONNECT)));
+    Ok(Box::new(process))
+}
+
+/// Load the event tag database for binary captures. A local file passed
+/// with `--event-tags` takes precedence over the one pulled from the device.
+/// Events are shown with numeric tags if neither is available.
+fn event_tags<'a, F>(args: &ArgMatches<'a>, pull: F) -> Option<Arc<EventTags>>
+where
+    F: FnOnce() -> Result<String, Error>,
+{
+    if !args.is_present("binary") {
+        return None;
+    }
+
+    let content = match args.value_of("event_tags") {
+        Some(file) => {
+            read_to_string(file).map_err(|e| format_err!("Failed to read {}: {}", file, e))
+        }
+        None => pull(),
+    };
+
+    match content.map(|c| EventTags::parse(&c)) {
+        Ok(ref tags) if tags.is_empty() => None,
+        Ok(tags) => Some(Arc::new(tags)),
+        Err(e) => {
+            eprintln!("{}. Event tags are not resolved", e);
+            None
+        }
+    }
+}
+
+/// Read the event tag database of the device with adb
+fn pull_event_tags(adb: &[String]) -> Result<String, Error> {
+    let output = Command::new(&adb[0])
+        .args(&adb[1..])
+        .args(["shell", "cat", EVENT_LOG_TAGS])
+        .output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(format_err!(
+            "Failed to pull {}: {}",
+            EVENT_LOG_TAGS,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Delay respawns of remote commands until the connection is back. With
+/// `--reconnect` the delay grows up to the given maximum.
+fn backoff<'a>(args: &ArgMatches<'a>) -> Result<Option<Backoff>, Error> {
+    if args.is_present("reconnect") {
+        let max = match args.value_of("reconnect") {
+            Some(max) => interval(max)?,
+            None => MAX_RECONNECT,
+        };
+        Ok(Some(Backoff::new(min(RECONNECT, max), max)))
+    } else if args.is_present("transport") {
+        Ok(Some(Backoff::new(RECONNECT, RECONNECT)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// True if the device runs commands with `adb exec-out`. The output of `adb
+/// logcat` passes a pty on some platforms that mangles line endings. The
+/// result is probed once per adb command line i.e device.
+fn exec_out(adb: &[String]) -> bool {
+    lazy_static! {
+        static ref PROBED: Mutex<HashMap<Vec<String>, bool>> = Mutex::new(HashMap::new());
+    }
+    if let Some(exec_out) = PROBED.lock().expect("Failed to lock probes").get(adb) {
+        return *exec_out;
+    }
+    let exec_out = adbserver::output(
+        Command::new(&adb[0])
+            .args(&adb[1..])
+            .args(["exec-out", "echo", "rogcat"]),
+    )
+    .map(|output| output.status.success() && output.stdout == b"rogcat\n")
+    .unwrap_or(false);
+    PROBED
+        .lock()
+        .expect("Failed to lock probes")
+        .insert(adb.to_vec(), exec_out);
+    exec_out
+}
+
+/// Append the logcat arguments to cmd and return if the command
+/// shall be respawned. An empty cmd is run natively without adb
+fn logcat_args<'a>(args: &ArgMatches<'a>, cmd: &mut Vec<String>) -> bool {
+    if !cmd.is_empty() && exec_out(cmd) {
+        cmd.push("exec-out".into());
+    }
     cmd.push("logcat".into());
-    let mut respawn = args.is_present("restart") | config_get::<bool>("restart").unwrap_or(true);
+    let mut respawn = args.is_present("restart")
+        || args.is_present("reconnect")
+        || config_get::<bool>("restart").unwrap_or(true);
 
     if args.is_present("tail") {
         let count = value_t!(args, "tail", u32).unwrap_or_else(|e| e.exit());
@@ -155,27 +787,59 @@ pub fn logcat<'a>(args: &ArgMatches<'a>) -> Result<LogStream, Error> {
         respawn = false;
     }
 
-    for buffer in args
-        .values_of("buffer")
+    if args.is_present("binary") {
+        cmd.push("-B".into());
+    } else if args.is_present("dividers") {
+        // Dividers announce the buffer of the following lines. logcat of
+        // Android 6 and older fails on -D
+        cmd.push("-D".into());
+    }
+
+    for buffer in buffers(args) {
+        cmd.push("-b".into());
+        cmd.push(buffer);
+    }
+
+    respawn
+}
+
+/// Buffers selected with `--buffer`, the configuration or the default
+fn buffers<'a>(args: &ArgMatches<'a>) -> Vec<String> {
+    args.values_of("buffer")
         .map(|m| m.map(ToOwned::to_owned).collect::<Vec<String>>())
         .or_else(|| config_get("buffer"))
         .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_owned()).collect())
-    {
-        cmd.push("-b".into());
-        cmd.push(buffer);
+}
+
+/// Stream kernel messages from `adb shell dmesg` or the local `/dev/kmsg`
+pub fn dmesg<'a>(args: &ArgMatches<'a>, dmesg: &ArgMatches<'a>) -> Result<LogStream, Error> {
+    if dmesg.is_present("local") {
+        let kmsg = File::open("/dev/kmsg")
+            .map(|s| Decoder::framed(LossyLinesCodec::new(), s))
+            .flatten_stream()
+            .map(StreamData::Line)
+            .map_err(|e| format_err!("Failed to read /dev/kmsg: {}", e));
+        return Ok(Box::new(kmsg));
     }
 
-    Ok(Box::new(Process::with_cmd(cmd, respawn)))
+    let mut cmd = device_cmd(args)?;
+    cmd.extend(["shell", "dmesg", "-r"].iter().map(|s| s.to_string()));
+    if !dmesg.is_present("dump") {
+        cmd.push("-w".into());
+    }
+    Ok(Box::new(Process::with_cmd(cmd, false)))
 }
 
 /// Start a process and stream it stdout
 pub fn process<'a>(args: &ArgMatches<'a>) -> Result<LogStream, Error> {
-    let respawn = args.is_present("restart");
+    let respawn = args.is_present("restart") || args.is_present("reconnect");
     let cmd = value_t!(args, "COMMAND", String)?
         .split_whitespace()
         .map(ToOwned::to_owned)
         .collect();
-    Ok(Box::new(Process::with_cmd(cmd, respawn)))
+    let mut process = Process::with_cmd(cmd, respawn);
+    process.backoff = backoff(args)?;
+    Ok(Box::new(process))
 }
 
 impl Process {
@@ -183,25 +847,62 @@ impl Process {
         Process {
             cmd,
             respawn,
+            binary: false,
+            event_tags: None,
+            native: None,
+            backoff: None,
+            received: false,
+            boot: None,
+            booting: None,
+            delay: None,
             child: None,
             stream: None,
         }
     }
 
+    /// Spawn cmd once the device booted
+    fn start(&mut self) -> Result<Async<Option<StreamData>>, Error> {
+        match self.boot {
+            Some(ref boot) => {
+                self.stream = None;
+                self.booting = Some(boot.wait());
+                self.poll()
+            }
+            None => self.spawn(),
+        }
+    }
+
     fn spawn(&mut self) -> Result<Async<Option<StreamData>>, Error> {
+        self.received = false;
+        if let Some(ref target) = self.native {
+            let mut stream = match adbclient::exec(target, &self.cmd.join(" ")) {
+                Ok(stream) => {
+                    let stream = TcpStream::from_std(stream, &Handle::default())?;
+                    self.decode(stream)
+                }
+                // Retry after the backoff delay
+                Err(e) if self.respawn => {
+                    status(&e.to_string());
+                    Box::new(empty()) as LogStream
+                }
+                Err(e) => return Err(e),
+            };
+            let poll = stream.poll();
+            self.stream = Some(stream);
+            return poll;
+        }
+
         let mut child = Command::new(self.cmd[0].clone())
             .args(&self.cmd[1..])
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn_async()?;
 
-        let stdout = BufReader::new(child.stdout().take().unwrap());
+        let stdout = child.stdout().take().unwrap();
         let stderr = BufReader::new(child.stderr().take().unwrap());
         self.child = Some(child);
 
-        let stdout = lossy_lines(stdout)
-            .map_err(Into::into)
-            .map(StreamData::Line);
+        let stdout = self.decode(stdout);
         let stderr = lossy_lines(stderr)
             .map_err(Into::into)
             .map(StreamData::Line);
@@ -211,6 +912,27 @@ impl Process {
         self.stream = Some(Box::new(stream));
         poll
     }
+
+    /// Decode output as binary logger entries or lines
+    fn decode<R: AsyncRead + Send + 'static>(&self, output: R) -> LogStream {
+        if self.binary {
+            let codec = match self.event_tags {
+                Some(ref tags) => LogcatBinaryCodec::with_tags(tags.clone()),
+                None => LogcatBinaryCodec::new(),
+            };
+            Box::new(
+                FramedRead::new(output, codec)
+                    .map_err(Into::into)
+                    .map(|r| StreamData::Record(Box::new(r))),
+            )
+        } else {
+            Box::new(
+                lossy_lines(BufReader::new(output))
+                    .map_err(Into::into)
+                    .map(StreamData::Line),
+            )
+        }
+    }
 }
 
 impl Stream for Process {
@@ -218,13 +940,75 @@ impl Stream for Process {
     type Error = Error;
 
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        if let Some(ref mut delay) = self.delay {
+            try_ready!(delay.poll().map_err(|e| format_err!("Timer error: {}", e)));
+            self.delay = None;
+            return self.start();
+        }
+
+        if let Some(ref mut booting) = self.booting {
+            try_ready!(booting.poll());
+            self.booting = None;
+            return self.spawn();
+        }
+
         if let Some(ref mut inner) = self.stream {
             match inner.poll() {
-                Ok(Async::Ready(None)) if self.respawn => self.spawn(),
+                Ok(Async::Ready(None)) if self.respawn => match self.backoff {
+                    Some(ref mut backoff) => {
+                        let delay = backoff.next(self.received);
+                        status(&format!(
+                            "{} exited. Reconnecting in {}s",
+                            self.cmd.join(" "),
+                            delay.as_secs()
+                        ));
+                        self.delay = Some(Delay::new(Instant::now() + delay));
+                        self.poll()
+                    }
+                    None => self.start(),
+                },
+                Ok(Async::Ready(Some(item))) => {
+                    self.received = true;
+                    Ok(Async::Ready(Some(item)))
+                }
                 poll => poll,
             }
         } else {
-            self.spawn()
+            self.start()
         }
     }
 }
+
+#[test]
+fn reconnect_backoff() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+    let delays = (0..5)
+        .map(|_| backoff.next(false).as_secs())
+        .collect::<Vec<_>>();
+    assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+    assert_eq!(backoff.next(true), Duration::from_secs(1));
+}
+
+#[test]
+fn serial_url() {
+    let (path, settings) = serial_settings("serial:///dev/ttyUSB0?baud=9600&parity=even").unwrap();
+    assert_eq!(path, "/dev/ttyUSB0");
+    assert_eq!(settings.baud_rate, 9600);
+    assert_eq!(settings.parity, Parity::Even);
+    assert_eq!(settings.data_bits, DataBits::Eight);
+
+    let (path, settings) = serial_settings("serial://COM0@57600,7O2").unwrap();
+    assert_eq!(path, "COM0");
+    assert_eq!(settings.baud_rate, 57600);
+    assert_eq!(settings.data_bits, DataBits::Seven);
+    assert_eq!(settings.parity, Parity::Odd);
+    assert_eq!(settings.stop_bits, StopBits::Two);
+
+    let (_, settings) = serial_settings("serial:///dev/ttyS0").unwrap();
+    assert_eq!(settings.baud_rate, 115_200);
+
+    assert!(serial_settings("serial://").is_err());
+    assert!(serial_settings("serial:///dev/ttyS0?baud=fast").is_err());
+    assert!(serial_settings("serial:///dev/ttyS0?speed=1").is_err());
+    assert!(serial_settings("serial://COM0@9600,8X1").is_err());
+}
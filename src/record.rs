@@ -18,14 +18,16 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::{msgpack, protobuf, template};
 use csv::WriterBuilder;
-use failure::{format_err, Error};
+use failure::{err_msg, format_err, Error};
 use serde::{
     de::{Deserializer, Visitor},
-    ser::Serializer,
+    ser::{SerializeStruct, Serializer},
     Deserialize, Serialize,
 };
 use std::{
+    collections::BTreeMap,
     fmt::{Display, Formatter},
     ops::Deref,
     str::FromStr,
@@ -40,7 +42,12 @@ pub enum Format {
     Html,
     Human,
     Json,
+    Msgpack,
+    Protobuf,
     Raw,
+    /// User defined format of `--output-format`
+    Template(String),
+    Trace,
 }
 
 impl Format {
@@ -48,17 +55,43 @@ impl Format {
         match self {
             Format::Csv => {
                 let mut wtr = WriterBuilder::new().has_headers(false).from_writer(vec![]);
-                wtr.serialize(record)?;
+                wtr.serialize(Csv(record))?;
                 wtr.flush()?;
                 Ok(String::from_utf8(wtr.into_inner().unwrap())?
                     .trim_end_matches('\n')
                     .to_owned())
             }
-            Format::Html => unimplemented!(),
-            Format::Human => unimplemented!(),
-            Format::Json => serde_json::to_string(record)
+            Format::Html | Format::Human | Format::Trace => {
+                Err(format_err!("{} is not a line format", self))
+            }
+            Format::Json => serde_json::to_string(&Json(record))
                 .map_err(|e| format_err!("Json serialization error: {}", e)),
+            Format::Msgpack => Err(err_msg("MessagePack is a binary format")),
+            Format::Protobuf => Err(err_msg("Protobuf is a binary format")),
             Format::Raw => Ok(record.raw.clone()),
+            Format::Template(ref template) => template::render(template, record),
+        }
+    }
+
+    /// Line written before the first record of a file or stream
+    pub fn header(&self) -> Option<&'static str> {
+        match self {
+            Format::Csv => Some(CSV_HEADER),
+            _ => None,
+        }
+    }
+
+    /// Encode record for output. MessagePack and Protobuf messages are length delimited and
+    /// all other formats are terminated by a newline.
+    pub fn encode_record(&self, record: &Record) -> Result<Vec<u8>, Error> {
+        match self {
+            Format::Msgpack => Ok(msgpack::encode_delimited(record)),
+            Format::Protobuf => Ok(protobuf::encode_delimited(record)),
+            _ => {
+                let mut line = self.fmt_record(record)?.into_bytes();
+                line.push(b'\n');
+                Ok(line)
+            }
         }
     }
 }
@@ -71,7 +104,10 @@ impl FromStr for Format {
             "html" => Ok(Format::Html),
             "human" => Ok(Format::Human),
             "json" => Ok(Format::Json),
+            "msgpack" => Ok(Format::Msgpack),
+            "protobuf" => Ok(Format::Protobuf),
             "raw" => Ok(Format::Raw),
+            "trace" => Ok(Format::Trace),
             _ => Err("Format parsing error"),
         }
     }
@@ -87,7 +123,11 @@ impl Display for Format {
                 Format::Html => "html",
                 Format::Human => "human",
                 Format::Json => "json",
+                Format::Msgpack => "msgpack",
+                Format::Protobuf => "protobuf",
                 Format::Raw => "raw",
+                Format::Template(_) => "template",
+                Format::Trace => "trace",
             }
         )
     }
@@ -156,6 +196,17 @@ impl Level {
     pub fn values() -> &'static [&'static str] {
         LEVEL_VALUES
     }
+
+    /// Syslog severity of the level
+    pub fn severity(&self) -> u8 {
+        match self {
+            Level::Assert | Level::Fatal => 2,
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info | Level::None => 6,
+            Level::Debug | Level::Verbose | Level::Trace => 7,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -233,4 +284,115 @@ pub struct Record {
     pub process: String,
     pub thread: String,
     pub raw: String,
+    /// Serial of the device the record was captured from
+    #[serde(default)]
+    pub device: Option<String>,
+    /// Logd buffer e.g main or events the record was read from
+    #[serde(default)]
+    pub buffer: Option<String>,
+    /// Name of the process resolved from the pid
+    #[serde(default)]
+    pub process_name: Option<String>,
+    /// Seconds since the first record or the last marker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative: Option<f64>,
+    /// Cpu usage in percent of the process sampled with `--with-cpu`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<f64>,
+    /// Values of the fields defined in the profile
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    /// Record is shown as context of a match
+    #[serde(skip)]
+    pub context: bool,
+}
+
+/// Version of the json format. Schema 1 is a plain record without the
+/// version and omits empty relative timestamps and fields
+pub const SCHEMA: u64 = 2;
+
+/// Self describing json representation of a record with all fields
+struct Json<'a>(&'a Record);
+
+impl<'a> Serialize for Json<'a> {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let record = self.0;
+        let mut s = serializer.serialize_struct("Record", 14)?;
+        s.serialize_field("schema", &SCHEMA)?;
+        s.serialize_field("timestamp", &record.timestamp)?;
+        s.serialize_field("message", &record.message)?;
+        s.serialize_field("level", &record.level)?;
+        s.serialize_field("tag", &record.tag)?;
+        s.serialize_field("process", &record.process)?;
+        s.serialize_field("thread", &record.thread)?;
+        s.serialize_field("raw", &record.raw)?;
+        s.serialize_field("device", &record.device)?;
+        s.serialize_field("buffer", &record.buffer)?;
+        s.serialize_field("process_name", &record.process_name)?;
+        s.serialize_field("relative", &record.relative)?;
+        s.serialize_field("cpu", &record.cpu)?;
+        s.serialize_field("fields", &record.fields)?;
+        s.end()
+    }
+}
+
+/// Column names of the csv output. Later versions may append columns and
+/// consumers should map the columns by the names of this header.
+pub const CSV_HEADER: &str =
+    "timestamp,message,level,tag,process,thread,raw,device,buffer,process_name,relative,cpu";
+
+/// Csv representation of a record. Every row has the same columns. Maps
+/// cannot be written as csv and fields are left out.
+struct Csv<'a>(&'a Record);
+
+impl<'a> Serialize for Csv<'a> {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let record = self.0;
+        let mut s = serializer.serialize_struct("Record", 12)?;
+        s.serialize_field("timestamp", &record.timestamp)?;
+        s.serialize_field("message", &record.message)?;
+        s.serialize_field("level", &record.level)?;
+        s.serialize_field("tag", &record.tag)?;
+        s.serialize_field("process", &record.process)?;
+        s.serialize_field("thread", &record.thread)?;
+        s.serialize_field("raw", &record.raw)?;
+        s.serialize_field("device", &record.device)?;
+        s.serialize_field("buffer", &record.buffer)?;
+        s.serialize_field("process_name", &record.process_name)?;
+        s.serialize_field("relative", &record.relative)?;
+        s.serialize_field("cpu", &record.cpu)?;
+        s.end()
+    }
+}
+
+#[test]
+fn csv_columns() {
+    let columns = |record: &Record| Format::Csv.fmt_record(record).unwrap().matches(',').count();
+    let record = Record::default();
+    let relative = Record {
+        relative: Some(1.5),
+        ..Default::default()
+    };
+    let cpu = Record {
+        cpu: Some(12.3),
+        ..Default::default()
+    };
+    assert_eq!(columns(&record), columns(&relative));
+    assert_eq!(columns(&record), columns(&cpu));
+    assert_eq!(columns(&record), CSV_HEADER.matches(',').count());
+}
+
+#[test]
+fn line_formats() {
+    let record = Record::default();
+    for format in &[Format::Html, Format::Human, Format::Trace] {
+        assert!(format.fmt_record(&record).is_err());
+    }
+    assert!(Format::Raw.fmt_record(&record).is_ok());
 }
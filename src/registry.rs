@@ -0,0 +1,161 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Implementations of a kind like inputs or outputs by the url schemes they
+//! handle. Implementations passed to `register` are added to every registry
+//! of their kind built with `Registry::with_registered` and take precedence
+//! over the ones registered before.
+
+use lazy_static::lazy_static;
+use std::{
+    any::Any,
+    sync::{Arc, Mutex},
+};
+
+/// Url schemes handled by an implementation
+pub trait Schemes {
+    fn schemes(&self) -> &[&str];
+}
+
+lazy_static! {
+    /// Implementations passed to `register` as `Arc<T>` of any kind
+    static ref REGISTERED: Mutex<Vec<Box<dyn Any + Send>>> = Mutex::new(Vec::new());
+}
+
+/// Add item to the registries of its kind built afterwards
+pub fn register<T: ?Sized + Schemes + Send + Sync + 'static>(item: Box<T>) {
+    REGISTERED
+        .lock()
+        .expect("Failed to lock registry")
+        .push(Box::new(Arc::<T>::from(item)));
+}
+
+/// Implementations of kind T by url scheme
+pub struct Registry<T: ?Sized> {
+    items: Vec<Arc<T>>,
+}
+
+impl<T: ?Sized> Clone for Registry<T> {
+    fn clone(&self) -> Registry<T> {
+        Registry {
+            items: self.items.clone(),
+        }
+    }
+}
+
+impl<T: ?Sized> Default for Registry<T> {
+    fn default() -> Registry<T> {
+        Registry { items: Vec::new() }
+    }
+}
+
+impl<T: ?Sized + Schemes + Send + Sync + 'static> Registry<T> {
+    /// Registry without any implementation
+    pub fn empty() -> Registry<T> {
+        Registry::default()
+    }
+
+    /// Add item. Items registered later take precedence for a scheme.
+    pub fn register(&mut self, item: Box<T>) {
+        self.items.push(Arc::from(item));
+    }
+
+    /// Add the items of kind T passed to `register`
+    pub fn with_registered(mut self) -> Registry<T> {
+        let registered = REGISTERED.lock().expect("Failed to lock registry");
+        self.items.extend(
+            registered
+                .iter()
+                .filter_map(|i| i.downcast_ref::<Arc<T>>())
+                .cloned(),
+        );
+        self
+    }
+
+    /// Find the item handling scheme
+    fn find(&self, scheme: &str) -> Option<&T> {
+        self.items
+            .iter()
+            .rev()
+            .find(|i| i.schemes().iter().any(|s| s.eq_ignore_ascii_case(scheme)))
+            .map(AsRef::as_ref)
+    }
+
+    /// Item handling the scheme of url
+    pub fn get(&self, url: &str) -> Option<&T> {
+        scheme(url).and_then(|s| self.find(s))
+    }
+
+    /// Check if url has a registered scheme
+    pub fn handles(&self, url: &str) -> bool {
+        self.get(url).is_some()
+    }
+}
+
+/// Scheme of input if it looks like an url. Single letters are treated as
+/// drive letters of Windows paths and not as scheme.
+pub fn scheme(input: &str) -> Option<&str> {
+    let scheme = &input[..input.find(':')?];
+    let valid = scheme.len() > 1
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+    if valid {
+        Some(scheme)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn registry() {
+    struct Dummy(&'static [&'static str]);
+    impl Schemes for Dummy {
+        fn schemes(&self) -> &[&str] {
+            self.0
+        }
+    }
+
+    assert_eq!(scheme("serial:///dev/ttyUSB0"), Some("serial"));
+    assert_eq!(scheme("C:\\log.txt"), None);
+    assert_eq!(scheme("log.txt"), None);
+    assert_eq!(scheme("1tcp://foo"), None);
+
+    let mut registry = Registry::empty();
+    registry.register(Box::new(Dummy(&["tcp"])));
+    assert!(registry.handles("TCP://0.0.0.0:1234"));
+    assert!(!registry.handles("dummy:"));
+    assert!(!registry.handles("C:\\log.txt"));
+    assert!(!registry.handles("/tmp/log.txt"));
+
+    registry.register(Box::new(Dummy(&["tcp", "dummy"])));
+    assert!(registry.handles("dummy:"));
+    assert_eq!(registry.get("tcp:").unwrap().schemes(), &["tcp", "dummy"]);
+    assert!(!Registry::<Dummy>::empty().handles("tcp://0.0.0.0:1234"));
+
+    // Registered items are added to the registries of their kind only
+    register(Box::new(Dummy(&["hooked"])));
+    assert!(!registry.handles("hooked:"));
+    assert!(registry.with_registered().handles("hooked:"));
+    assert!(!Registry::<dyn Schemes + Send + Sync>::empty()
+        .with_registered()
+        .handles("hooked:"));
+}
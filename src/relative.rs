@@ -0,0 +1,122 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Timestamps as offsets with `--relative-timestamps`. Offsets are counted
+//! from the first record or restart at each marker.
+
+use crate::{marker, RecordStream};
+use clap::ArgMatches;
+use futures::Stream;
+use rogcat::record::Record;
+use time::Timespec;
+
+/// Origin of offsets
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Origin {
+    First,
+    Marker,
+}
+
+struct Relative {
+    origin: Origin,
+    start: Option<Timespec>,
+}
+
+impl Relative {
+    fn process(&mut self, record: &mut Record) {
+        if marker::is_marker(record) {
+            if self.origin == Origin::Marker {
+                // The marker is taken on the host clock. Count from the next
+                // record of the device instead
+                self.start = None;
+                record.relative = Some(0.0);
+            }
+            return;
+        }
+        if let Some(ref timestamp) = record.timestamp {
+            let ts = timestamp.to_timespec();
+            let start = *self.start.get_or_insert(ts);
+            record.relative = (ts - start)
+                .num_microseconds()
+                .map(|us| us as f64 / 1_000_000.0);
+        }
+    }
+}
+
+/// Format an offset in seconds e.g `+012.345`
+pub fn format(offset: f64) -> String {
+    format!("{:+08.3}", offset)
+}
+
+/// Set the offsets of records if requested
+pub fn from_args<'a>(args: &ArgMatches<'a>, records: RecordStream) -> RecordStream {
+    if !args.is_present("relative_timestamps") {
+        return records;
+    }
+    let origin = match args.value_of("relative_timestamps") {
+        Some("marker") => Origin::Marker,
+        _ => Origin::First,
+    };
+    let mut relative = Relative {
+        origin,
+        start: None,
+    };
+    Box::new(records.map(move |mut r| {
+        relative.process(&mut r);
+        r
+    }))
+}
+
+#[test]
+fn offsets() {
+    use rogcat::record::Timestamp;
+
+    let record = |s: &str| Record {
+        timestamp: time::strptime(s, "%m-%d %H:%M:%S.%f")
+            .ok()
+            .map(Timestamp::new),
+        ..Default::default()
+    };
+    let mut relative = Relative {
+        origin: Origin::Marker,
+        start: None,
+    };
+    let mut offsets = vec![];
+    for mut r in [
+        record("01-01 10:00:00.500"),
+        record("01-01 10:00:12.845"),
+        marker::record("test"),
+        record("01-01 10:01:00.000"),
+        record("01-01 10:01:01.250"),
+    ] {
+        relative.process(&mut r);
+        offsets.push(r.relative.map(format));
+    }
+    assert_eq!(
+        offsets,
+        vec![
+            Some("+000.000".to_owned()),
+            Some("+012.345".to_owned()),
+            Some("+000.000".to_owned()),
+            Some("+000.000".to_owned()),
+            Some("+001.250".to_owned()),
+        ]
+    );
+}
@@ -0,0 +1,105 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{index, RecordStream};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{future::Either, Future, Stream};
+use rogcat::record::Timestamp;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+/// Parse a replay speed factor like "2", "2x" or "0.5x"
+pub fn speed(s: &str) -> Result<f64, Error> {
+    let speed = s
+        .trim_end_matches(&['x', 'X'][..])
+        .parse::<f64>()
+        .map_err(|e| format_err!("Invalid speed {}: {}", s, e))?;
+    if speed.is_finite() && speed > 0f64 {
+        Ok(speed)
+    } else {
+        Err(format_err!(
+            "Invalid speed {}: Must be greater than zero",
+            s
+        ))
+    }
+}
+
+/// Skip records before `--since`. Records without a timestamp are passed
+pub fn since<'a>(args: &ArgMatches<'a>, records: RecordStream) -> Result<RecordStream, Error> {
+    match args.value_of("since").map(index::since).transpose()? {
+        Some(since) => Ok(Box::new(records.filter(move |record| {
+            record
+                .timestamp
+                .as_ref()
+                .map(|timestamp| !index::before(timestamp, &since))
+                .unwrap_or(true)
+        }))),
+        None => Ok(records),
+    }
+}
+
+/// Delay records of stream according to the distance of their timestamps
+/// to the timestamp of the first record. Records without a timestamp are
+/// passed without delay.
+pub fn pace<'a>(args: &ArgMatches<'a>, records: RecordStream) -> Result<RecordStream, Error> {
+    if args.is_present("no_delay") {
+        return Ok(records);
+    }
+    let speed = args.value_of("speed").map(speed).unwrap_or(Ok(1f64))?;
+
+    let mut start: Option<(Timestamp, Instant)> = None;
+    let records = records.and_then(move |record| {
+        let deadline = match (&start, &record.timestamp) {
+            (Some((first, instant)), Some(timestamp)) => {
+                let offset = (timestamp.tm - first.tm)
+                    .to_std()
+                    .unwrap_or_else(|_| Duration::from_secs(0));
+                Some(*instant + offset.div_f64(speed))
+            }
+            (None, Some(timestamp)) => {
+                start = Some((timestamp.clone(), Instant::now()));
+                None
+            }
+            _ => None,
+        };
+
+        match deadline {
+            Some(deadline) if deadline > Instant::now() => Either::A(
+                Delay::new(deadline)
+                    .map(|_| record)
+                    .map_err(|e| format_err!("Timer error: {}", e)),
+            ),
+            _ => Either::B(futures::future::ok(record)),
+        }
+    });
+    Ok(Box::new(records))
+}
+
+#[test]
+fn parse_speed() {
+    assert_eq!(speed("2").unwrap(), 2f64);
+    assert_eq!(speed("2x").unwrap(), 2f64);
+    assert_eq!(speed("0.5x").unwrap(), 0.5f64);
+    assert!(speed("0").is_err());
+    assert!(speed("-1x").is_err());
+    assert!(speed("x").is_err());
+    assert!(speed("fast").is_err());
+}
@@ -0,0 +1,301 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Deobfuscate the class and method names of Java stack traces with the
+//! R8 or ProGuard mapping file passed with `--retrace`.
+
+use crate::{transform, RecordStream};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::Stream;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use rogcat::record::Record;
+use std::{collections::HashMap, fs::read_to_string, str::FromStr};
+
+lazy_static! {
+    /// Method of a class mapping e.g `1:3:void bar(int):42:44 -> a`
+    static ref METHOD: Regex = Regex::new(
+        r"^\s+(?:(\d+):(\d+):)?\S+ ([^\s(]+)\([^)]*\)((?::\d+){0,2}) -> (\S+)$"
+    )
+    .unwrap();
+    /// Source file of the preceding class e.g `# {"id":"sourceFile","fileName":"Foo.kt"}`
+    static ref SOURCE_FILE: Regex =
+        Regex::new(r#"^\s*# \{.*"id":"sourceFile".*"fileName":"([^"]+)""#).unwrap();
+    /// Stack frame e.g `at a.b.a(SourceFile:12)`
+    static ref FRAME: Regex =
+        Regex::new(r"^(\s*at )([\w$]+(?:\.[\w$]+)+)\.([\w$<>]+)\([^:)]*(?::(\d+))?\)").unwrap();
+    /// Exception class at the start of a line e.g `Caused by: a.b: message`
+    static ref EXCEPTION: Regex =
+        Regex::new(r"^(\s*(?:Caused by: |Suppressed: )?)([\w$]+(?:\.[\w$]+)+)(:|$)").unwrap();
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Method {
+    /// Original name. Qualified if inlined from a other class
+    name: String,
+    /// Obfuscated line range
+    range: Option<(u32, u32)>,
+    /// Original line range
+    original: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Class {
+    name: String,
+    source: Option<String>,
+    /// Methods by obfuscated name
+    methods: HashMap<String, Vec<Method>>,
+}
+
+/// Classes of a mapping file by obfuscated name
+#[derive(Debug, Default)]
+pub struct Mapping {
+    classes: HashMap<String, Class>,
+}
+
+impl FromStr for Mapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Mapping, Error> {
+        let mut classes = HashMap::new();
+        let mut class: Option<(String, Class)> = None;
+        for (n, line) in s.lines().enumerate() {
+            if line.starts_with(char::is_whitespace) {
+                let class = match class {
+                    Some((_, ref mut class)) => class,
+                    None => continue,
+                };
+                if let Some(c) = METHOD.captures(line) {
+                    let number = |n: usize| c.get(n).and_then(|m| m.as_str().parse::<u32>().ok());
+                    let lines = c[4]
+                        .split(':')
+                        .filter_map(|l| l.parse::<u32>().ok())
+                        .collect::<Vec<_>>();
+                    let method = Method {
+                        name: c[3].to_owned(),
+                        range: number(1).and_then(|s| number(2).map(|e| (s, e))),
+                        original: match lines.as_slice() {
+                            [start] => Some((*start, *start)),
+                            [start, end] => Some((*start, *end)),
+                            _ => None,
+                        },
+                    };
+                    class
+                        .methods
+                        .entry(c[5].to_owned())
+                        .or_insert_with(Vec::new)
+                        .push(method);
+                } else if let Some(c) = SOURCE_FILE.captures(line) {
+                    class.source = Some(c[1].to_owned());
+                }
+            } else if line.starts_with('#') || line.trim().is_empty() {
+                if let (Some(c), Some((_, class))) = (SOURCE_FILE.captures(line), class.as_mut()) {
+                    class.source = Some(c[1].to_owned());
+                }
+            } else {
+                let (original, obfuscated) = line
+                    .trim_end_matches(':')
+                    .split_once(" -> ")
+                    .ok_or_else(|| format_err!("Invalid mapping in line {}: {}", n + 1, line))?;
+                let next = Class {
+                    name: original.trim().to_owned(),
+                    ..Default::default()
+                };
+                if let Some((name, class)) = class.replace((obfuscated.trim().to_owned(), next)) {
+                    classes.insert(name, class);
+                }
+            }
+        }
+        if let Some((name, class)) = class {
+            classes.insert(name, class);
+        }
+        Ok(Mapping { classes })
+    }
+}
+
+/// Source file of a class named after the outer class
+fn source_file(class: &str) -> String {
+    let simple = class.rsplit('.').next().unwrap_or(class);
+    format!("{}.java", simple.split('$').next().unwrap_or(simple))
+}
+
+impl Mapping {
+    fn class(&self, obfuscated: &str) -> Option<&str> {
+        self.classes.get(obfuscated).map(|c| c.name.as_str())
+    }
+
+    /// Original frames of a obfuscated frame. Inlined methods yield
+    /// multiple frames
+    fn frames(&self, c: &Captures) -> Option<Vec<String>> {
+        let class = self.classes.get(&c[2])?;
+        let line = c.get(4).and_then(|l| l.as_str().parse::<u32>().ok());
+        let candidates = class
+            .methods
+            .get(&c[3])
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let ranged = candidates
+            .iter()
+            .filter(|m| match (m.range, line) {
+                (Some((start, end)), Some(line)) => start <= line && line <= end,
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+        let methods = if ranged.is_empty() {
+            candidates.iter().take(1).collect()
+        } else {
+            ranged
+        };
+
+        if methods.is_empty() {
+            let source = class
+                .source
+                .clone()
+                .unwrap_or_else(|| source_file(&class.name));
+            let location = line.map(|l| format!("{}:{}", source, l)).unwrap_or(source);
+            return Some(vec![format!(
+                "{}{}.{}({})",
+                &c[1], class.name, &c[3], location
+            )]);
+        }
+
+        let frames = methods
+            .iter()
+            .map(|m| {
+                // Methods inlined from other classes are qualified
+                let (class_name, method) = match m.name.rfind('.') {
+                    Some(n) => (&m.name[..n], &m.name[n + 1..]),
+                    None => (class.name.as_str(), m.name.as_str()),
+                };
+                let source = match class.source {
+                    Some(ref source) if class_name == class.name => source.clone(),
+                    _ => source_file(class_name),
+                };
+                let line = match (line, m.range, m.original) {
+                    (Some(l), Some((start, end)), Some((o_start, o_end)))
+                        if end - start == o_end - o_start =>
+                    {
+                        Some(o_start + l - start)
+                    }
+                    (Some(_), _, Some((o_start, _))) => Some(o_start),
+                    (l, _, _) => l,
+                };
+                let location = match line {
+                    Some(l) => format!("{}:{}", source, l),
+                    None => source,
+                };
+                format!("{}{}.{}({})", &c[1], class_name, method, location)
+            })
+            .collect();
+        Some(frames)
+    }
+
+    /// Retrace a line of a stack trace
+    fn line(&self, line: &str) -> Option<String> {
+        if let Some(c) = FRAME.captures(line) {
+            self.frames(&c).map(|f| f.join("\n"))
+        } else if let Some(c) = EXCEPTION.captures(line) {
+            let class = self.class(&c[2])?;
+            Some(format!("{}{}{}", &c[1], class, &line[c.get(2)?.end()..]))
+        } else {
+            None
+        }
+    }
+
+    fn retrace(&self, record: &mut Record) {
+        let mut changed = false;
+        let message = record
+            .message
+            .split('\n')
+            .map(|line| match self.line(line) {
+                Some(retraced) => {
+                    changed = true;
+                    retraced
+                }
+                None => line.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        if changed {
+            // Keep the raw line in sync for the raw output format
+            transform::set_message(record, message);
+        }
+    }
+}
+
+/// Retrace records if `--retrace` is passed
+pub fn from_args<'a>(args: &ArgMatches<'a>, records: RecordStream) -> Result<RecordStream, Error> {
+    let file = match args.value_of("retrace") {
+        Some(file) => file,
+        None => return Ok(records),
+    };
+    let mapping = read_to_string(file)
+        .map_err(|e| format_err!("Failed to read {}: {}", file, e))?
+        .parse::<Mapping>()
+        .map_err(|e| format_err!("Failed to parse {}: {}", file, e))?;
+    Ok(Box::new(records.map(move |mut r| {
+        mapping.retrace(&mut r);
+        r
+    })))
+}
+
+#[test]
+fn retrace() {
+    let mapping = r#"# compiler: R8
+com.example.Foo -> a.b:
+# {"id":"sourceFile","fileName":"Foo.kt"}
+    int count -> a
+    1:3:void bar(int):42:44 -> a
+    4:4:void com.example.Util.check():7:7 -> a
+    4:4:void bar(int):45 -> a
+    void baz() -> b
+com.example.Foo$Inner -> a.c:
+com.example.BoomException -> a.d:
+"#
+    .parse::<Mapping>()
+    .unwrap();
+
+    let retrace = |line: &str| mapping.line(line);
+    assert_eq!(
+        retrace("\tat a.b.a(SourceFile:2)").unwrap(),
+        "\tat com.example.Foo.bar(Foo.kt:43)"
+    );
+    assert_eq!(
+        retrace("at a.b.a(SourceFile:4)").unwrap(),
+        "at com.example.Util.check(Util.java:7)\nat com.example.Foo.bar(Foo.kt:45)"
+    );
+    assert_eq!(
+        retrace("at a.b.b(Unknown Source)").unwrap(),
+        "at com.example.Foo.baz(Foo.kt)"
+    );
+    assert_eq!(
+        retrace("at a.c.run(SourceFile:1)").unwrap(),
+        "at com.example.Foo$Inner.run(Foo.java:1)"
+    );
+    assert_eq!(
+        retrace("Caused by: a.d: boom").unwrap(),
+        "Caused by: com.example.BoomException: boom"
+    );
+    assert_eq!(retrace("a.d").unwrap(), "com.example.BoomException");
+    assert!(retrace("at java.lang.Thread.run(Thread.java:764)").is_none());
+    assert!(retrace("x.y: not mapped").is_none());
+    assert!("garbage".parse::<Mapping>().is_err());
+}
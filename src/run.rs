@@ -0,0 +1,139 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Run a command e.g a instrumentation test and capture the device log while
+//! it runs. The log is written to a file named after the command, the start
+//! time and the outcome of the command.
+
+use crate::utils::device_cmd;
+use clap::{values_t, ArgMatches};
+use failure::{err_msg, format_err, Error};
+use std::{
+    fs::{rename, DirBuilder, File},
+    path::{Path, PathBuf},
+    process::{exit, Command, Stdio},
+    thread,
+    time::Duration,
+};
+use time::{now, strftime};
+
+/// Time given to logcat to deliver the last lines after the command exited
+const GRACE: Duration = Duration::from_millis(500);
+
+/// Length limit of the command part of filenames
+const MAX_NAME: usize = 64;
+
+/// Filename for a capture of command started at date
+fn filename(command: &[String], date: &str, success: bool) -> String {
+    let name = command
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| match i {
+            // Only the file name of the program
+            0 => Path::new(arg)
+                .file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or_else(|| arg.clone()),
+            _ => arg.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .take(MAX_NAME)
+        .collect::<String>();
+    let outcome = if success { "pass" } else { "fail" };
+    format!("{}-{}-{}.log", name.trim_matches('_'), date, outcome)
+}
+
+/// Capture the log while the command runs and exit with its exit code
+pub fn run<'a>(args: &ArgMatches<'a>, run: &ArgMatches<'a>) {
+    match capture(args, run) {
+        Ok(code) => exit(code),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1)
+        }
+    }
+}
+
+fn capture<'a>(args: &ArgMatches<'a>, run: &ArgMatches<'a>) -> Result<i32, Error> {
+    let command = values_t!(run.values_of("command"), String)?;
+    let dir = PathBuf::from(run.value_of("directory").unwrap_or("."));
+    if !dir.is_dir() {
+        DirBuilder::new().recursive(true).create(&dir)?;
+    }
+    let date = strftime("%Y-%m-%d_%H-%M-%S", &now())?;
+    let partial = dir.join(format!(".{}", filename(&command, &date, true)));
+
+    // Start at the most recent line to skip what is already in the buffers
+    let mut logcat = device_cmd(args)?;
+    logcat.extend(
+        ["logcat", "-v", "threadtime", "-T", "1"]
+            .iter()
+            .map(|s| s.to_string()),
+    );
+    let mut logcat = Command::new(&logcat[0])
+        .args(&logcat[1..])
+        .stdout(File::create(&partial)?)
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format_err!("Failed to run adb: {}", e))?;
+
+    let status = Command::new(&command[0])
+        .args(&command[1..])
+        .status()
+        .map_err(|e| format_err!("Failed to run {}: {}", command[0], e));
+
+    thread::sleep(GRACE);
+    logcat.kill().ok();
+    logcat.wait().ok();
+
+    let status = status?;
+    let file = dir.join(filename(&command, &date, status.success()));
+    rename(&partial, &file)?;
+    eprintln!("Log written to {}", file.display());
+    status
+        .code()
+        .ok_or_else(|| err_msg("Command terminated by signal"))
+}
+
+#[test]
+fn run_filename() {
+    let command = [
+        "/usr/bin/adb",
+        "shell",
+        "am instrument -w com.example/.Runner",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect::<Vec<_>>();
+    assert_eq!(
+        filename(&command, "2019-03-01_02-19-45", true),
+        "adb_shell_am_instrument_-w_com.example_.Runner-2019-03-01_02-19-45-pass.log"
+    );
+    assert_eq!(
+        filename(&command[..1], "2019-03-01_02-19-45", false),
+        "adb-2019-03-01_02-19-45-fail.log"
+    );
+}
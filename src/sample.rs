@@ -0,0 +1,94 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Skim large captures: keep every nth record with `--sample` and only the
+//! last records of a input with `--tail`.
+
+use crate::{marker, RecordStream};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{stream::iter_ok, Future, Stream};
+use std::collections::VecDeque;
+
+/// Parse a sampling rate like `1/10` or `10` into the interval
+fn interval(rate: &str) -> Result<usize, Error> {
+    let n = match rate.find('/') {
+        Some(p) if rate[..p].trim() == "1" => &rate[p + 1..],
+        Some(_) => return Err(format_err!("Invalid sample rate {}. Use 1/N", rate)),
+        None => rate,
+    };
+    match n.trim().parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format_err!("Invalid sample rate {}. Use 1/N", rate)),
+    }
+}
+
+/// Apply sampling and the tail of file input if requested
+pub fn from_args<'a>(args: &ArgMatches<'a>, records: RecordStream) -> Result<RecordStream, Error> {
+    let records = match args.value_of("sample") {
+        Some(rate) => {
+            let interval = interval(rate)?;
+            let mut count = 0usize;
+            // Markers are never dropped
+            Box::new(records.filter(move |r| {
+                if marker::is_marker(r) {
+                    return true;
+                }
+                let keep = count == 0;
+                count = (count + 1) % interval;
+                keep
+            })) as RecordStream
+        }
+        None => records,
+    };
+
+    // The tail of adb captures is selected by logcat
+    if args.is_present("input") || args.is_present("COMMAND") {
+        if let Some(tail) = args.value_of("tail") {
+            let tail = tail
+                .parse::<usize>()
+                .map_err(|e| format_err!("Invalid tail {}: {}", tail, e))?;
+            let records = records
+                .fold(VecDeque::with_capacity(tail), move |mut last, r| {
+                    if tail > 0 && last.len() == tail {
+                        last.pop_front();
+                    }
+                    if tail > 0 {
+                        last.push_back(r);
+                    }
+                    Ok::<_, Error>(last)
+                })
+                .map(iter_ok)
+                .flatten_stream();
+            return Ok(Box::new(records));
+        }
+    }
+
+    Ok(records)
+}
+
+#[test]
+fn sample_rate() {
+    assert_eq!(interval("1/10").unwrap(), 10);
+    assert_eq!(interval("5").unwrap(), 5);
+    assert!(interval("2/10").is_err());
+    assert!(interval("1/0").is_err());
+    assert!(interval("x").is_err());
+}
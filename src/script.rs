@@ -0,0 +1,211 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! User defined record processing with [Rhai](https://rhai.rs) scripts passed
+//! with `--script`. The script defines a function `process(record)` that is
+//! called for every record. The record is passed as map with the fields
+//! `message`, `level`, `tag`, `process`, `thread`, `raw`, `device`, `buffer`
+//! and `process_name`. The return value decides what is emitted:
+//!
+//! * `true` or nothing: the record unchanged
+//! * `false`: nothing. The record is dropped
+//! * a map: the record with the fields of the map applied
+//! * an array of maps: one record per map e.g to emit derived records
+//!
+//! Top level statements are evaluated once before the first record.
+
+use crate::RecordStream;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{stream::iter_ok, Stream};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use rogcat::record::{Level, Record};
+use std::{fs::read_to_string, path::Path};
+
+const FUNCTION: &str = "process";
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+}
+
+impl Script {
+    /// Check for the process function and evaluate the top level statements
+    fn new(engine: Engine, ast: AST) -> Result<Script, Error> {
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == FUNCTION && f.params.len() == 1)
+        {
+            return Err(format_err!("Script does not define {}(record)", FUNCTION));
+        }
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| format_err!("Script error: {}", e))?;
+        Ok(Script { engine, ast, scope })
+    }
+
+    pub fn compile(script: &str) -> Result<Script, Error> {
+        let engine = Engine::new();
+        let ast = engine
+            .compile(script)
+            .map_err(|e| format_err!("Script error: {}", e))?;
+        Script::new(engine, ast)
+    }
+
+    pub fn load(file: &Path) -> Result<Script, Error> {
+        let script = read_to_string(file)
+            .map_err(|e| format_err!("Failed to read script {}: {}", file.display(), e))?;
+        Script::compile(&script)
+    }
+
+    /// Run the script on record and return the records to emit
+    pub fn process(&mut self, record: Record) -> Result<Vec<Record>, Error> {
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self
+            .engine
+            .call_fn_with_options::<Dynamic>(
+                options,
+                &mut self.scope,
+                &self.ast,
+                FUNCTION,
+                (to_map(&record),),
+            )
+            .map_err(|e| format_err!("Script error: {}", e))?;
+
+        if result.is_unit() {
+            Ok(vec![record])
+        } else if let Ok(keep) = result.as_bool() {
+            Ok(if keep { vec![record] } else { vec![] })
+        } else if result.is_map() {
+            Ok(vec![apply(record, result.cast::<Map>())?])
+        } else if result.is_array() {
+            result
+                .cast::<Array>()
+                .into_iter()
+                .map(|m| match m.try_cast::<Map>() {
+                    Some(m) => apply(record.clone(), m),
+                    None => Err(format_err!("Script error: {} must return maps", FUNCTION)),
+                })
+                .collect()
+        } else {
+            Err(format_err!(
+                "Script error: invalid return value {} of {}",
+                result.type_name(),
+                FUNCTION
+            ))
+        }
+    }
+}
+
+fn to_map(record: &Record) -> Map {
+    let optional = |v: &Option<String>| v.clone().map(Dynamic::from).unwrap_or(Dynamic::UNIT);
+    let mut map = Map::new();
+    map.insert("message".into(), record.message.clone().into());
+    map.insert("level".into(), record.level.to_string().into());
+    map.insert("tag".into(), record.tag.clone().into());
+    map.insert("process".into(), record.process.clone().into());
+    map.insert("thread".into(), record.thread.clone().into());
+    map.insert("raw".into(), record.raw.clone().into());
+    map.insert("device".into(), optional(&record.device));
+    map.insert("buffer".into(), optional(&record.buffer));
+    map.insert("process_name".into(), optional(&record.process_name));
+    map
+}
+
+/// Update the fields of record with the values of map. The timestamp is kept
+fn apply(mut record: Record, map: Map) -> Result<Record, Error> {
+    for (key, value) in map {
+        let value = if value.is_unit() {
+            None
+        } else {
+            Some(value.to_string())
+        };
+        match (key.as_str(), value) {
+            ("message", Some(v)) => record.message = v,
+            ("level", Some(v)) => record.level = Level::from(v.as_str()),
+            ("tag", Some(v)) => record.tag = v,
+            ("process", Some(v)) => record.process = v,
+            ("thread", Some(v)) => record.thread = v,
+            ("raw", Some(v)) => record.raw = v,
+            ("device", v) => record.device = v,
+            ("buffer", v) => record.buffer = v,
+            ("process_name", v) => record.process_name = v,
+            (key, _) => return Err(format_err!("Script error: invalid record field {}", key)),
+        }
+    }
+    Ok(record)
+}
+
+/// Pass records through the script given with `--script`
+pub fn from_args<'a>(args: &ArgMatches<'a>, records: RecordStream) -> Result<RecordStream, Error> {
+    let mut script = match args.value_of("script") {
+        Some(file) => Script::load(Path::new(file))?,
+        None => return Ok(records),
+    };
+    Ok(Box::new(
+        records
+            .and_then(move |r| script.process(r).map(iter_ok))
+            .flatten(),
+    ))
+}
+
+#[test]
+fn script_process() {
+    let record = Record {
+        message: "Foo 42".into(),
+        tag: "Foo".into(),
+        ..Default::default()
+    };
+
+    let mut script = Script::compile("fn process(r) { r.tag != \"Drop\" }").unwrap();
+    assert_eq!(
+        script.process(record.clone()).unwrap(),
+        vec![record.clone()]
+    );
+    let drop = Record {
+        tag: "Drop".into(),
+        ..Default::default()
+    };
+    assert!(script.process(drop).unwrap().is_empty());
+
+    let mut script = Script::compile(
+        r#"
+        fn process(r) {
+            let n = r.message.split(" ")[1];
+            r.level = "W";
+            [r, #{ tag: "Derived", message: `value=${n}` }]
+        }"#,
+    )
+    .unwrap();
+    let records = script.process(record.clone()).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].level, Level::Warn);
+    assert_eq!(records[0].message, "Foo 42");
+    assert_eq!(records[1].tag, "Derived");
+    assert_eq!(records[1].message, "value=42");
+
+    assert!(Script::compile("fn foo(r) { true }").is_err());
+    assert!(Script::compile("fn process(r) { 1 }")
+        .unwrap()
+        .process(record)
+        .is_err());
+}
@@ -0,0 +1,199 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Metadata of a capture written with `--session-metadata` next to or at the
+//! top of output files: device, rogcat version, start time and filters.
+
+use crate::utils;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Prefix of header lines. Lines with this prefix are skipped when reading
+pub const HEADER_PREFIX: &str = "# rogcat ";
+
+/// Arguments listed as filters
+const FILTERS: &[&str] = &[
+    "filter",
+    "level",
+    "message",
+    "message-ignore-case",
+    "pid_of",
+    "preset",
+    "profile",
+    "regex_filter",
+    "suppress",
+    "tag",
+    "tag-ignore-case",
+    "tag_level",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Comment lines at the top of each file
+    Header,
+    /// `.meta.json` file next to each file
+    Sidecar,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Metadata {
+    version: String,
+    start: String,
+    command: Vec<String>,
+    serial: Option<String>,
+    model: Option<String>,
+    android_version: Option<String>,
+    build: Option<String>,
+    filters: BTreeMap<String, Vec<String>>,
+}
+
+/// Parse the output of getprop e.g "[ro.product.model]: [Pixel 4]"
+pub fn getprop(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim().strip_prefix('[')?.strip_suffix(']')?;
+            let n = line.find("]: [")?;
+            Some((line[..n].to_owned(), line[n + 4..].to_owned()))
+        })
+        .collect()
+}
+
+impl Metadata {
+    fn new<'a>(args: &ArgMatches<'a>) -> Metadata {
+        let filters = FILTERS
+            .iter()
+            .filter_map(|f| {
+                args.values_of(f)
+                    .map(|v| (f.replace('_', "-"), v.map(ToOwned::to_owned).collect()))
+            })
+            .collect();
+        let mut metadata = Metadata {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            start: time::strftime("%Y-%m-%dT%H:%M:%S%z", &time::now()).unwrap_or_default(),
+            command: env::args().collect(),
+            filters,
+            ..Default::default()
+        };
+
+        // Only live captures of a device have device properties
+        let live = !args.is_present("input")
+            && !args.is_present("COMMAND")
+            && args.subcommand_name().is_none();
+        if live {
+            let props = utils::device_cmd(args)
+                .and_then(|adb| {
+                    Command::new(&adb[0])
+                        .args(&adb[1..])
+                        .args(["shell", "getprop"])
+                        .output()
+                        .map_err(Into::into)
+                })
+                .map(|output| getprop(&String::from_utf8_lossy(&output.stdout)))
+                .unwrap_or_default();
+            let prop = |key: &str| props.get(key).filter(|v| !v.is_empty()).cloned();
+            metadata.serial = prop("ro.serialno");
+            metadata.model = prop("ro.product.model");
+            metadata.android_version = prop("ro.build.version.release");
+            metadata.build = prop("ro.build.fingerprint");
+        }
+        metadata
+    }
+
+    /// Comment lines for the top of a file
+    pub fn header(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("{}version: {}", HEADER_PREFIX, self.version),
+            format!("{}start: {}", HEADER_PREFIX, self.start),
+            format!("{}command: {}", HEADER_PREFIX, self.command.join(" ")),
+        ];
+        let device = [
+            ("serial", &self.serial),
+            ("model", &self.model),
+            ("android-version", &self.android_version),
+            ("build", &self.build),
+        ];
+        for (key, value) in device.iter() {
+            if let Some(value) = value {
+                lines.push(format!("{}{}: {}", HEADER_PREFIX, key, value));
+            }
+        }
+        for (key, values) in &self.filters {
+            lines.push(format!("{}{}: {}", HEADER_PREFIX, key, values.join(" ")));
+        }
+        lines
+    }
+
+    /// Write the metadata to `<file>.meta.json`
+    pub fn write_sidecar(&self, file: &Path) -> Result<(), Error> {
+        let name = sidecar(file);
+        let json = serde_json::to_string_pretty(self)?;
+        write(&name, json).map_err(|e| format_err!("Failed to write {}: {}", name.display(), e))
+    }
+}
+
+/// Filename of the sidecar of `file`
+pub fn sidecar(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".meta.json");
+    PathBuf::from(name)
+}
+
+/// Metadata if `--session-metadata` is passed
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Option<(Mode, Metadata)> {
+    if !args.is_present("session_metadata") {
+        return None;
+    }
+    let mode = match args.value_of("session_metadata") {
+        Some("header") => Mode::Header,
+        _ => Mode::Sidecar,
+    };
+    Some((mode, Metadata::new(args)))
+}
+
+#[test]
+fn parse_getprop() {
+    let props =
+        getprop("[ro.product.model]: [Pixel 4]\n[ro.build.version.release]: [10]\ngarbage\n");
+    assert_eq!(props.len(), 2);
+    assert_eq!(props["ro.product.model"], "Pixel 4");
+
+    let metadata = Metadata {
+        version: "1.0".into(),
+        model: props.get("ro.product.model").cloned(),
+        filters: vec![("tag".to_owned(), vec!["A".to_owned(), "B".to_owned()])]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let header = metadata.header();
+    assert!(header.contains(&"# rogcat version: 1.0".to_owned()));
+    assert!(header.contains(&"# rogcat model: Pixel 4".to_owned()));
+    assert!(header.contains(&"# rogcat tag: A B".to_owned()));
+    assert!(header.iter().all(|l| l.starts_with(HEADER_PREFIX)));
+}
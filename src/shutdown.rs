@@ -0,0 +1,175 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Graceful shutdown on ctrl-c. The first ctrl-c ends the records of the
+//! input. The records buffered in the stages are processed and the outputs
+//! are flushed and closed before a summary of the session is printed. A
+//! second ctrl-c cancels right away.
+
+use crate::{filewriter, queue::Dropped, RecordStream};
+use failure::Error;
+use futures::{task::AtomicTask, Async, Poll, Stream};
+use rogcat::record::{Level, Record};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+/// Levels in the order of the summary
+const LEVELS: [Level; 9] = [
+    Level::Assert,
+    Level::Fatal,
+    Level::Error,
+    Level::Warn,
+    Level::Info,
+    Level::Debug,
+    Level::Verbose,
+    Level::Trace,
+    Level::None,
+];
+
+/// Shared flag set by ctrl-c
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    interrupted: Arc<AtomicBool>,
+    task: Arc<AtomicTask>,
+}
+
+impl Shutdown {
+    /// End records on the next poll after ctrl-c
+    pub fn wrap(&self, records: RecordStream) -> RecordStream {
+        Box::new(Interruptible {
+            records,
+            shutdown: self.clone(),
+        })
+    }
+
+    /// Request the records to end
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+        self.task.notify();
+    }
+
+    pub fn interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
+    }
+}
+
+struct Interruptible {
+    records: RecordStream,
+    shutdown: Shutdown,
+}
+
+impl Stream for Interruptible {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        self.shutdown.task.register();
+        if self.shutdown.interrupted() {
+            return Ok(Async::Ready(None));
+        }
+        self.records.poll()
+    }
+}
+
+/// Duration, records by level and dropped records of a session
+#[derive(Clone)]
+pub struct Summary {
+    start: Instant,
+    levels: Arc<Mutex<[usize; 9]>>,
+    dropped: Dropped,
+}
+
+impl Summary {
+    pub fn new(dropped: Dropped) -> Summary {
+        Summary {
+            start: Instant::now(),
+            levels: Arc::new(Mutex::new([0; 9])),
+            dropped,
+        }
+    }
+
+    /// Count a record passed to the outputs
+    pub fn count(&self, record: &Record) {
+        if let Some(n) = LEVELS.iter().position(|l| *l == record.level) {
+            self.levels.lock().expect("Failed to lock summary")[n] += 1;
+        }
+    }
+
+    /// Text of the summary e.g
+    /// `Session of 12s: 42 records (E 2, W 10, I 30), 0 dropped`
+    fn text(&self) -> String {
+        let levels = self.levels.lock().expect("Failed to lock summary");
+        let records = levels.iter().sum::<usize>();
+        let by_level = LEVELS
+            .iter()
+            .zip(levels.iter())
+            .filter(|(_, n)| **n > 0)
+            .map(|(l, n)| format!("{} {}", l, n))
+            .collect::<Vec<_>>();
+        let by_level = if by_level.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", by_level.join(", "))
+        };
+        format!(
+            "Session of {}s: {} records{}, {} dropped",
+            self.start.elapsed().as_secs(),
+            records,
+            by_level,
+            self.dropped.count()
+        )
+    }
+
+    /// Print the summary and the files written
+    pub fn report(&self) {
+        eprintln!("{}", self.text());
+        for file in filewriter::created() {
+            eprintln!("Wrote {}", file.display());
+        }
+    }
+}
+
+#[test]
+fn summary() {
+    use futures::stream::iter_ok;
+
+    let summary = Summary::new(Dropped::default());
+    for level in &[Level::Info, Level::Error, Level::Info] {
+        summary.count(&Record {
+            level: level.clone(),
+            ..Default::default()
+        });
+    }
+    assert!(summary
+        .text()
+        .ends_with(": 3 records (E 1, I 2), 0 dropped"));
+
+    let shutdown = Shutdown::default();
+    let records = shutdown.wrap(Box::new(iter_ok(vec![Record::default(); 3])));
+    let mut records = futures::executor::spawn(records);
+    assert!(records.wait_stream().is_some());
+    shutdown.interrupt();
+    assert!(records.wait_stream().is_none());
+}
@@ -0,0 +1,296 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Outputs addressed by a path or an url like `tcp://localhost:1234?format=csv`.
+//! Outputs other than files are a `RecordSink` registered for one or more url
+//! schemes in `registry`. Records are broadcasted to all outputs by a `Tee`.
+
+use crate::{filewriter, journald, network};
+use clap::ArgMatches;
+use failure::{err_msg, format_err, Error};
+use futures::{try_ready, Async, AsyncSink, Poll, Sink, StartSend};
+use rogcat::{
+    output::{LogSink, RecordSink, Registry},
+    record::{Format, Record},
+    registry::Schemes,
+};
+use std::str::FromStr;
+use url::Url;
+
+/// Output that writes to the terminal
+pub const TERMINAL: &str = "-";
+
+/// Builtin sinks and the sinks passed to `rogcat::output::register`
+pub fn registry() -> Registry {
+    let mut registry = Registry::empty();
+    registry.register(Box::new(Files));
+    registry.register(Box::new(Forward));
+    registry.register(Box::new(Journald));
+    registry.with_registered()
+}
+
+/// Open the outputs passed with `-o`. The terminal is written to for `-`.
+/// Options like `--format` or `--records-per-file` apply to the first file
+/// output. Additional files are written in the format of their extension.
+pub fn from_args<'a, F>(args: &ArgMatches<'a>, terminal: F) -> Result<Vec<LogSink>, Error>
+where
+    F: FnOnce() -> Result<LogSink, Error>,
+{
+    let outputs = args
+        .values_of("output")
+        .ok_or_else(|| err_msg("Missing output argument"))?
+        .collect::<Vec<_>>();
+    // Progress bars would interleave with the records on the terminal
+    let progress = !outputs.contains(&TERMINAL);
+    let registry = registry();
+    let mut terminal = Some(terminal);
+    let mut options = true;
+    let mut sinks = Vec::with_capacity(outputs.len());
+    for output in outputs {
+        if output == TERMINAL {
+            if let Some(terminal) = terminal.take() {
+                sinks.push(terminal()?);
+            }
+        } else if output == "journald" {
+            sinks.push(registry.open("journald:", args)?);
+        } else if registry.handles(output) {
+            sinks.push(registry.open(output, args)?);
+        } else if options {
+            options = false;
+            sinks.push(filewriter::try_from(args, output, progress)?);
+        } else {
+            sinks.push(filewriter::from_path(output)?);
+        }
+    }
+    Ok(sinks)
+}
+
+/// Broadcast records to sinks. A single sink is returned as is.
+pub fn tee(mut sinks: Vec<LogSink>) -> LogSink {
+    if sinks.len() == 1 {
+        sinks.remove(0)
+    } else {
+        Box::new(Tee {
+            sinks: sinks.into_iter().map(|s| (s, None)).collect(),
+        })
+    }
+}
+
+/// Sends each record to all sinks. A record not accepted by a sink is kept
+/// until the sink is ready while the other sinks proceed. The next record is
+/// taken once all sinks accepted the previous one.
+struct Tee {
+    sinks: Vec<(LogSink, Option<Record>)>,
+}
+
+impl Tee {
+    /// Retry the records not accepted by their sink
+    fn push(&mut self) -> Poll<(), Error> {
+        let mut ready = true;
+        for (sink, pending) in &mut self.sinks {
+            if let Some(record) = pending.take() {
+                if let AsyncSink::NotReady(record) = sink.start_send(record)? {
+                    *pending = Some(record);
+                    ready = false;
+                }
+            }
+        }
+        Ok(if ready {
+            Async::Ready(())
+        } else {
+            Async::NotReady
+        })
+    }
+}
+
+impl Sink for Tee {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        if self.push()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(record));
+        }
+        for (sink, pending) in &mut self.sinks {
+            if let AsyncSink::NotReady(record) = sink.start_send(record.clone())? {
+                *pending = Some(record);
+            }
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        try_ready!(self.push());
+        let mut ready = true;
+        for (sink, _) in &mut self.sinks {
+            ready &= sink.poll_complete()?.is_ready();
+        }
+        Ok(if ready {
+            Async::Ready(())
+        } else {
+            Async::NotReady
+        })
+    }
+
+    fn close(&mut self) -> Poll<(), Error> {
+        try_ready!(self.push());
+        // Closed sinks are dropped to not close them again on the next poll
+        let mut result = Ok(());
+        self.sinks.retain_mut(|(sink, _)| match sink.close() {
+            Ok(Async::Ready(())) => false,
+            Ok(Async::NotReady) => true,
+            Err(e) => {
+                result = Err(e);
+                false
+            }
+        });
+        result?;
+        Ok(if self.sinks.is_empty() {
+            Async::Ready(())
+        } else {
+            Async::NotReady
+        })
+    }
+}
+
+/// Local file e.g `file:///tmp/log.json`
+struct Files;
+
+impl Schemes for Files {
+    fn schemes(&self) -> &[&str] {
+        &["file"]
+    }
+}
+
+impl RecordSink for Files {
+    fn open(&self, url: &str, _: &ArgMatches) -> Result<LogSink, Error> {
+        let path = Url::parse(url)?
+            .to_file_path()
+            .map_err(|_| format_err!("Invalid file url {}", url))?;
+        let path = path
+            .to_str()
+            .ok_or_else(|| format_err!("Invalid file url {}", url))?;
+        filewriter::from_path(path)
+    }
+}
+
+/// Send records to a remote collector e.g `udp://10.0.0.1:5000?format=csv`.
+/// Records are sent as json unless a format is given in the query.
+struct Forward;
+
+impl Schemes for Forward {
+    fn schemes(&self) -> &[&str] {
+        &["syslog", "tcp", "tls", "udp"]
+    }
+}
+
+impl RecordSink for Forward {
+    fn open(&self, url: &str, _: &ArgMatches) -> Result<LogSink, Error> {
+        let format = Url::parse(url)?
+            .query_pairs()
+            .find(|(k, _)| k == "format")
+            .map(|(_, v)| Format::from_str(&v).map_err(|_| format_err!("Invalid format {}", v)))
+            .transpose()?;
+        network::connect(url, format.unwrap_or(Format::Json))
+    }
+}
+
+/// Systemd journal e.g `journald:`
+struct Journald;
+
+impl Schemes for Journald {
+    fn schemes(&self) -> &[&str] {
+        &["journald"]
+    }
+}
+
+impl RecordSink for Journald {
+    fn open(&self, _: &str, _: &ArgMatches) -> Result<LogSink, Error> {
+        journald::try_from()
+    }
+}
+
+#[test]
+fn broadcast() {
+    use futures::{stream::iter_ok, Future, Stream};
+    use std::sync::{Arc, Mutex};
+
+    /// Collects messages. A slow sink rejects every other record on the first try
+    struct Collect {
+        records: Arc<Mutex<Vec<String>>>,
+        slow: bool,
+        busy: bool,
+        closed: usize,
+    }
+
+    impl Collect {
+        fn new(slow: bool) -> (Collect, Arc<Mutex<Vec<String>>>) {
+            let records = Arc::new(Mutex::new(Vec::new()));
+            let sink = Collect {
+                records: records.clone(),
+                slow,
+                busy: false,
+                closed: 0,
+            };
+            (sink, records)
+        }
+    }
+
+    impl Sink for Collect {
+        type SinkItem = Record;
+        type SinkError = Error;
+
+        fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+            self.busy = self.slow && !self.busy;
+            if self.busy {
+                return Ok(AsyncSink::NotReady(record));
+            }
+            self.records.lock().unwrap().push(record.message);
+            Ok(AsyncSink::Ready)
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn close(&mut self) -> Poll<(), Error> {
+            self.closed += 1;
+            assert_eq!(self.closed, 1);
+            Ok(Async::Ready(()))
+        }
+    }
+
+    let (slow, slow_records) = Collect::new(true);
+    let (fast, fast_records) = Collect::new(false);
+    let sink = tee(vec![Box::new(slow), Box::new(fast)]);
+    let records = (0..5).map(|n| Record {
+        message: n.to_string(),
+        ..Default::default()
+    });
+    iter_ok::<_, Error>(records)
+        .forward(sink)
+        .map(drop)
+        .wait()
+        .unwrap();
+
+    let expected = vec!["0", "1", "2", "3", "4"];
+    assert_eq!(*slow_records.lock().unwrap(), expected);
+    assert_eq!(*fast_records.lock().unwrap(), expected);
+}
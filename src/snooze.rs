@@ -0,0 +1,236 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Snoozed tags. Records with a snoozed tag are dropped until the snooze
+//! expires. A marker with the number of dropped records is emitted at the
+//! expiry e.g `suppressed 1,204 records from Chatty`.
+
+use crate::{marker, utils, RecordStream};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{Async, Future, Poll, Stream};
+use regex::Regex;
+use rogcat::record::Record;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::timer::Delay;
+
+struct Entry {
+    tag: String,
+    regex: Regex,
+    until: Instant,
+    count: usize,
+}
+
+/// Snoozed tags shared with the terminal commands
+#[derive(Clone, Default)]
+pub struct Snoozes(Arc<Mutex<Vec<Entry>>>);
+
+impl Snoozes {
+    /// Snooze the tag of a `TAG=DURATION` argument. Snoozing a tag again
+    /// extends the snooze
+    pub fn add(&self, snooze: &str) -> Result<(), Error> {
+        let (tag, duration) = parse(snooze)?;
+        let regex =
+            Regex::new(&tag).map_err(|e| format_err!("Invalid regex string: {}: {}", tag, e))?;
+        let until = Instant::now() + duration;
+        let mut entries = self.0.lock().expect("Failed to lock snoozes");
+        match entries.iter_mut().find(|e| e.tag == tag) {
+            Some(entry) => entry.until = until,
+            None => entries.push(Entry {
+                tag,
+                regex,
+                until,
+                count: 0,
+            }),
+        }
+        Ok(())
+    }
+
+    /// Count and drop records of snoozed tags. Markers are never snoozed
+    fn snoozed(&self, record: &Record) -> bool {
+        if marker::is_marker(record) {
+            return false;
+        }
+        let mut entries = self.0.lock().expect("Failed to lock snoozes");
+        match entries.iter_mut().find(|e| e.regex.is_match(&record.tag)) {
+            Some(entry) => {
+                entry.count += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove expired or all snoozes and return their summaries
+    fn expire(&self, all: bool) -> Vec<Record> {
+        let now = Instant::now();
+        let mut entries = self.0.lock().expect("Failed to lock snoozes");
+        let mut summaries = vec![];
+        entries.retain(|e| {
+            if all || e.until <= now {
+                summaries.push(marker::record(&summary(e.count, &e.tag)));
+                false
+            } else {
+                true
+            }
+        });
+        summaries
+    }
+
+    /// Expiry of the next snooze
+    fn next(&self) -> Option<Instant> {
+        let entries = self.0.lock().expect("Failed to lock snoozes");
+        entries.iter().map(|e| e.until).min()
+    }
+}
+
+/// Split a `TAG=DURATION` argument
+fn parse(snooze: &str) -> Result<(String, Duration), Error> {
+    match snooze.rfind('=') {
+        Some(n) if n > 0 => Ok((snooze[..n].to_owned(), utils::interval(&snooze[n + 1..])?)),
+        _ => Err(format_err!("Invalid snooze {}. Use TAG=DURATION", snooze)),
+    }
+}
+
+/// Summary of a expired snooze with thousands separated count
+fn summary(count: usize, tag: &str) -> String {
+    let mut grouped = String::new();
+    let mut rest = count;
+    while rest >= 1000 {
+        grouped = format!(",{:03}{}", rest % 1000, grouped);
+        rest /= 1000;
+    }
+    let grouped = format!("{}{}", rest, grouped);
+    let records = if count == 1 { "record" } else { "records" };
+    format!("suppressed {} {} from {}", grouped, records, tag)
+}
+
+/// Snoozes of the arguments. More tags are snoozed with terminal commands
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Result<Snoozes, Error> {
+    let snoozes = Snoozes::default();
+    for snooze in args.values_of("snooze").into_iter().flatten() {
+        snoozes.add(snooze)?;
+    }
+    Ok(snoozes)
+}
+
+/// Drop the records of snoozed tags
+pub fn wrap(records: RecordStream, snoozes: Snoozes) -> RecordStream {
+    Box::new(Snooze {
+        records,
+        snoozes,
+        delay: None,
+        pending: VecDeque::new(),
+        done: false,
+    })
+}
+
+struct Snooze {
+    records: RecordStream,
+    snoozes: Snoozes,
+    /// Timer of the next expiry
+    delay: Option<(Instant, Delay)>,
+    /// Summaries not yet emitted
+    pending: VecDeque<Record>,
+    done: bool,
+}
+
+impl Stream for Snooze {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        loop {
+            self.pending.extend(self.snoozes.expire(self.done));
+            if let Some(summary) = self.pending.pop_front() {
+                return Ok(Async::Ready(Some(summary)));
+            }
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            // Wake up at the next expiry even without records
+            match self.snoozes.next() {
+                Some(until) if self.delay.as_ref().map(|(u, _)| *u) != Some(until) => {
+                    self.delay = Some((until, Delay::new(until)));
+                }
+                Some(_) => (),
+                None => self.delay = None,
+            }
+            if let Some((_, ref mut delay)) = self.delay {
+                let expired = delay
+                    .poll()
+                    .map_err(|e| format_err!("Timer error: {}", e))?
+                    .is_ready();
+                if expired {
+                    self.delay = None;
+                    continue;
+                }
+            }
+
+            match self.records.poll()? {
+                Async::Ready(Some(record)) => {
+                    if !self.snoozes.snoozed(&record) {
+                        return Ok(Async::Ready(Some(record)));
+                    }
+                }
+                Async::Ready(None) => self.done = true,
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+#[test]
+fn snooze() {
+    assert_eq!(
+        summary(1204, "Chatty"),
+        "suppressed 1,204 records from Chatty"
+    );
+    assert_eq!(summary(1, "A"), "suppressed 1 record from A");
+    assert_eq!(
+        summary(1_000_005, "A"),
+        "suppressed 1,000,005 records from A"
+    );
+    assert!(parse("Chatty").is_err());
+    assert!(parse("Chatty=soon").is_err());
+    assert_eq!(
+        parse("a=b=5m").unwrap(),
+        ("a=b".to_owned(), Duration::from_secs(300))
+    );
+
+    let snoozes = Snoozes::default();
+    snoozes.add("^Chatty$=1h").unwrap();
+    let record = |tag: &str| Record {
+        tag: tag.into(),
+        ..Default::default()
+    };
+    assert!(snoozes.snoozed(&record("Chatty")));
+    assert!(!snoozes.snoozed(&record("Chatty2")));
+    assert!(!snoozes.snoozed(&marker::record("Chatty")));
+    assert!(snoozes.expire(false).is_empty());
+    let summaries = snoozes.expire(true);
+    assert_eq!(summaries[0].message, "suppressed 1 record from ^Chatty$");
+    assert!(snoozes.next().is_none());
+}
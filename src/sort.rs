@@ -0,0 +1,199 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Reorder records of merged sources by timestamp with `--sort-window`.
+//! Records are held back until a record that is newer by the window arrived
+//! or they waited for the window. Records without timestamp stay behind their
+//! predecessor.
+
+use crate::{utils, RecordStream};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{try_ready, Async, Future, Poll, Stream};
+use rogcat::record::Record;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    time::{Duration, Instant},
+};
+use time::Timespec;
+use tokio::timer::Delay;
+
+struct Entry {
+    timestamp: Timespec,
+    /// Arrival order for a stable sort of equal timestamps
+    sequence: u64,
+    arrival: Instant,
+    record: Record,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> Ordering {
+        (self.timestamp, self.sequence).cmp(&(other.timestamp, other.sequence))
+    }
+}
+
+struct Sort {
+    records: RecordStream,
+    window: Duration,
+    pending: BinaryHeap<Reverse<Entry>>,
+    /// Newest timestamp received
+    newest: Option<Timespec>,
+    /// Timestamp of the last record with timestamp
+    last: Timespec,
+    sequence: u64,
+    flush: Option<Delay>,
+    done: bool,
+}
+
+impl Sort {
+    fn new(records: RecordStream, window: Duration) -> Sort {
+        Sort {
+            records,
+            window,
+            pending: BinaryHeap::new(),
+            newest: None,
+            last: Timespec::new(0, 0),
+            sequence: 0,
+            flush: None,
+            done: false,
+        }
+    }
+
+    fn push(&mut self, record: Record) {
+        if let Some(ref timestamp) = record.timestamp {
+            self.last = timestamp.to_timespec();
+            self.newest = self.newest.max(Some(self.last));
+        }
+        self.sequence += 1;
+        self.pending.push(Reverse(Entry {
+            timestamp: self.last,
+            sequence: self.sequence,
+            arrival: Instant::now(),
+            record,
+        }));
+    }
+
+    /// True if the oldest record cannot be preceded by a future record
+    fn expired(&self, entry: &Entry) -> bool {
+        let window =
+            time::Duration::from_std(self.window).unwrap_or_else(|_| time::Duration::max_value());
+        self.done
+            || self
+                .newest
+                .map(|n| n - entry.timestamp >= window)
+                .unwrap_or(false)
+            || entry.arrival.elapsed() >= self.window
+    }
+}
+
+impl Stream for Sort {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        loop {
+            if let Some(Reverse(oldest)) = self.pending.peek() {
+                if self.expired(oldest) {
+                    self.flush = None;
+                    return Ok(Async::Ready(self.pending.pop().map(|Reverse(e)| e.record)));
+                }
+            } else if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            match self.records.poll()? {
+                Async::Ready(Some(record)) => self.push(record),
+                Async::Ready(None) => self.done = true,
+                Async::NotReady => {
+                    let deadline = match self.pending.peek() {
+                        Some(Reverse(oldest)) => oldest.arrival + self.window,
+                        None => return Ok(Async::NotReady),
+                    };
+                    let flush = self.flush.get_or_insert_with(|| Delay::new(deadline));
+                    flush.reset(deadline);
+                    try_ready!(flush.poll().map_err(|e| format_err!("Timer error: {}", e)));
+                }
+            }
+        }
+    }
+}
+
+/// Wrap records into a sorting stream if requested
+pub fn from_args<'a>(args: &ArgMatches<'a>, records: RecordStream) -> Result<RecordStream, Error> {
+    match args.value_of("sort_window") {
+        Some(window) => Ok(Box::new(Sort::new(records, utils::interval(window)?))),
+        None => Ok(records),
+    }
+}
+
+#[test]
+fn sort() {
+    use futures::stream::iter_ok;
+    use rogcat::record::Timestamp;
+
+    assert_eq!(
+        utils::interval("500ms").unwrap(),
+        Duration::from_millis(500)
+    );
+
+    let record = |millis: i64, message: &str| Record {
+        timestamp: Some(Timestamp::new(time::at_utc(Timespec::new(
+            millis / 1000,
+            (millis % 1000 * 1_000_000) as i32,
+        )))),
+        message: message.into(),
+        ..Default::default()
+    };
+    let input = vec![
+        record(1000, "a"),
+        record(3000, "c"),
+        record(2000, "b"),
+        Record {
+            message: "b2".into(),
+            ..Default::default()
+        },
+        record(2000, "b3"),
+        // Too late: the window of one second is exceeded by c
+        record(1500, "late"),
+        record(5000, "d"),
+    ];
+    let records = Box::new(iter_ok::<_, Error>(input)) as RecordStream;
+    let sorted = Sort::new(records, Duration::from_secs(1))
+        .map(|r| r.message)
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(sorted, vec!["a", "b", "b2", "b3", "late", "c", "d"]);
+}
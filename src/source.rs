@@ -0,0 +1,195 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Builtin inputs addressed by an url like `serial:///dev/ttyUSB0` or
+//! `tcp://0.0.0.0:1234`. New inputs implement `LogSource` and are added in
+//! `registry` without touching the input selection in `main` or `reader`.
+
+use crate::{network, reader};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use rogcat::{
+    input::{LogSource, LogStream, Registry},
+    registry::Schemes,
+};
+use url::Url;
+
+/// Builtin sources
+fn builtin() -> Registry {
+    let mut registry = Registry::empty();
+    registry.register(Box::new(Files));
+    registry.register(Box::new(Stdin));
+    registry.register(Box::new(Serial));
+    registry.register(Box::new(Listen));
+    registry.register(Box::new(Adb));
+    #[cfg(target_os = "linux")]
+    registry.register(Box::new(Can));
+    registry
+}
+
+/// Builtin sources and the sources passed to `rogcat::input::register`
+pub fn registry() -> Registry {
+    builtin().with_registered()
+}
+
+/// Sources for a `COMMAND`. A tcp url is connected to instead of listened on.
+pub fn command() -> Registry {
+    let mut registry = builtin();
+    registry.register(Box::new(Connect));
+    registry.with_registered()
+}
+
+/// Local file e.g `file:///tmp/log.txt`
+struct Files;
+
+impl Schemes for Files {
+    fn schemes(&self) -> &[&str] {
+        &["file"]
+    }
+}
+
+impl LogSource for Files {
+    fn open(&self, url: &str, args: &ArgMatches) -> Result<LogStream, Error> {
+        let path = Url::parse(url)?
+            .to_file_path()
+            .map_err(|_| format_err!("Invalid file url {}", url))?;
+        let since = args
+            .value_of("since")
+            .map(crate::index::since)
+            .transpose()?;
+        Ok(reader::file(path, since))
+    }
+}
+
+/// Standard input e.g `stdin:`
+struct Stdin;
+
+impl Schemes for Stdin {
+    fn schemes(&self) -> &[&str] {
+        &["stdin"]
+    }
+}
+
+impl LogSource for Stdin {
+    fn open(&self, _: &str, _: &ArgMatches) -> Result<LogStream, Error> {
+        Ok(reader::stdin())
+    }
+}
+
+/// Serial port e.g `serial:///dev/ttyUSB0?baud=115200`
+struct Serial;
+
+impl Schemes for Serial {
+    fn schemes(&self) -> &[&str] {
+        &["serial"]
+    }
+}
+
+impl LogSource for Serial {
+    fn open(&self, url: &str, _: &ArgMatches) -> Result<LogStream, Error> {
+        reader::serial(url)
+    }
+}
+
+/// Listen for tcp connections or udp datagrams e.g `udp://0.0.0.0:5000`
+struct Listen;
+
+impl Schemes for Listen {
+    fn schemes(&self) -> &[&str] {
+        &["tcp", "udp"]
+    }
+}
+
+impl LogSource for Listen {
+    fn open(&self, url: &str, _: &ArgMatches) -> Result<LogStream, Error> {
+        network::listen(url)
+    }
+}
+
+/// Connect to a tcp server e.g `connect://localhost:1234`
+struct Connect;
+
+impl Schemes for Connect {
+    fn schemes(&self) -> &[&str] {
+        &["connect", "tcp"]
+    }
+}
+
+impl LogSource for Connect {
+    fn open(&self, url: &str, _: &ArgMatches) -> Result<LogStream, Error> {
+        reader::tcp(&Url::parse(url)?)
+    }
+}
+
+/// Logcat of a device e.g `adb://emulator-5554` or `adb:` for the default device
+struct Adb;
+
+impl Schemes for Adb {
+    fn schemes(&self) -> &[&str] {
+        &["adb"]
+    }
+}
+
+impl LogSource for Adb {
+    fn open(&self, url: &str, args: &ArgMatches) -> Result<LogStream, Error> {
+        let serial = url[4..].trim_start_matches("//").trim_end_matches('/');
+        let serial = if serial.is_empty() {
+            None
+        } else {
+            Some(serial)
+        };
+        reader::logcat_device(args, serial)
+    }
+}
+
+/// CAN bus e.g `can://can0`
+#[cfg(target_os = "linux")]
+struct Can;
+
+#[cfg(target_os = "linux")]
+impl Schemes for Can {
+    fn schemes(&self) -> &[&str] {
+        &["can"]
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl LogSource for Can {
+    fn open(&self, url: &str, _: &ArgMatches) -> Result<LogStream, Error> {
+        let url = Url::parse(url)?;
+        let dev = url
+            .host_str()
+            .ok_or_else(|| format_err!("Invalid can url {}", url))?;
+        reader::can(dev)
+    }
+}
+
+#[test]
+fn builtin_sources() {
+    let registry = registry();
+    assert!(registry.handles("serial://COM0@115200,8N1"));
+    assert!(registry.handles("TCP://0.0.0.0:1234"));
+    assert!(registry.handles("adb:"));
+    assert!(registry.handles("file:///tmp/log.txt"));
+    assert!(!registry.handles("connect://localhost:1234"));
+    assert!(!registry.handles("C:\\log.txt"));
+    assert!(!registry.handles("/tmp/log.txt"));
+    assert!(command().handles("connect://localhost:1234"));
+}
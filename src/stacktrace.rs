@@ -0,0 +1,181 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Join the records of a stack trace into one multi-line record with
+//! `--join-stacktraces`. Java and Kotlin log a exception as one record per
+//! line with the same tag, pid and tid. Native crash dumps of the tag
+//! `DEBUG` are joined from the `*** ***` line to the end of the backtrace.
+
+use crate::RecordStream;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{try_ready, Async, Future, Poll, Stream};
+use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::record::Record;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+lazy_static! {
+    /// Lines of a stack trace that continue the previous record
+    static ref CONTINUATION: Regex = Regex::new(concat!(
+        r"^(\s*at [\w$.<>\-]+\(",
+        r"|\s*Caused by: ",
+        r"|\s*Suppressed: ",
+        r"|\s*\.\.\. \d+ more$",
+        r"|Process: .+, PID: \d+$",
+        r"|[\w$]+(\.[\w$]+)+(Exception|Error)(: |$))"
+    ))
+    .unwrap();
+}
+
+/// Tag of native crash dumps
+const TOMBSTONE_TAG: &str = "DEBUG";
+
+/// First line of a native crash dump
+const TOMBSTONE_START: &str = "*** ***";
+
+/// Time a incomplete trace is held back when no further records arrive
+const FLUSH: Duration = Duration::from_millis(100);
+
+/// True if record continues the trace in trace
+fn continues(trace: &Record, record: &Record) -> bool {
+    trace.tag == record.tag
+        && trace.process == record.process
+        && trace.thread == record.thread
+        && trace.level == record.level
+        && if record.tag == TOMBSTONE_TAG {
+            !record.message.starts_with(TOMBSTONE_START)
+        } else {
+            CONTINUATION.is_match(&record.message)
+        }
+}
+
+struct Join {
+    records: RecordStream,
+    /// Record that is extended with the lines of a trace
+    trace: Option<Record>,
+    flush: Option<Delay>,
+    done: bool,
+}
+
+/// Wrap records into a joining stream if requested
+pub fn from_args<'a>(args: &ArgMatches<'a>, records: RecordStream) -> RecordStream {
+    if args.is_present("join_stacktraces") {
+        Box::new(Join {
+            records,
+            trace: None,
+            flush: None,
+            done: false,
+        })
+    } else {
+        records
+    }
+}
+
+impl Stream for Join {
+    type Item = Record;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, Error> {
+        loop {
+            if self.done {
+                return Ok(Async::Ready(self.trace.take()));
+            }
+
+            match self.records.poll()? {
+                Async::Ready(Some(record)) => {
+                    self.flush = None;
+                    match self.trace {
+                        Some(ref mut trace) if continues(trace, &record) => {
+                            trace.message.push('\n');
+                            trace.message.push_str(&record.message);
+                            trace.raw.push('\n');
+                            trace.raw.push_str(&record.raw);
+                        }
+                        _ => {
+                            if let Some(trace) = self.trace.replace(record) {
+                                return Ok(Async::Ready(Some(trace)));
+                            }
+                        }
+                    }
+                }
+                Async::Ready(None) => self.done = true,
+                Async::NotReady => {
+                    if self.trace.is_none() {
+                        return Ok(Async::NotReady);
+                    }
+                    let flush = self
+                        .flush
+                        .get_or_insert_with(|| Delay::new(Instant::now() + FLUSH));
+                    try_ready!(flush.poll().map_err(|e| format_err!("Timer error: {}", e)));
+                    self.flush = None;
+                    return Ok(Async::Ready(self.trace.take()));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn continuation() {
+    let record = |message: &str| Record {
+        tag: "AndroidRuntime".into(),
+        process: "123".into(),
+        message: message.into(),
+        ..Default::default()
+    };
+    let trace = record("FATAL EXCEPTION: main");
+    assert!(continues(&trace, &record("Process: com.example, PID: 123")));
+    assert!(continues(
+        &trace,
+        &record("java.lang.IllegalStateException: Boom")
+    ));
+    assert!(continues(&trace, &record("kotlin.NotImplementedError")));
+    assert!(continues(
+        &trace,
+        &record("\tat com.example.Foo.<init>(Foo.java:42)")
+    ));
+    assert!(continues(
+        &trace,
+        &record("Caused by: java.lang.NullPointerException")
+    ));
+    assert!(continues(&trace, &record("\t... 12 more")));
+    assert!(!continues(&trace, &record("Starting activity")));
+    assert!(!continues(&trace, &record("look at this(")));
+
+    let mut other = record("\tat com.example.Foo.bar(Foo.java:1)");
+    other.thread = "456".into();
+    assert!(!continues(&trace, &other));
+
+    let debug = |message: &str| Record {
+        tag: TOMBSTONE_TAG.into(),
+        process: "456".into(),
+        message: message.into(),
+        ..Default::default()
+    };
+    let tombstone = debug("*** *** *** *** *** *** *** *** *** *** *** *** *** *** *** ***");
+    assert!(continues(&tombstone, &debug("signal 11 (SIGSEGV), code 1")));
+    assert!(continues(
+        &tombstone,
+        &debug("      #00 pc 000000000001a2b4  /system/lib64/libc.so (abort+20)")
+    ));
+    assert!(!continues(&tombstone, &tombstone));
+}
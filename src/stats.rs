@@ -0,0 +1,291 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::LogSink;
+use clap::{value_t, ArgMatches};
+use failure::Error;
+use futures::{Async, AsyncSink, Poll, Sink, StartSend};
+use rogcat::record::{Level, Record};
+use serde_json::json;
+use std::{
+    cmp::{max, Reverse},
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+use time::{at_utc, strftime, Timespec, Tm};
+
+/// Maximum number of rows of the rate histogram
+const RATE_ROWS: i64 = 20;
+
+/// Summary of the received records printed when the stream ends
+#[derive(Default)]
+struct Stats {
+    json: bool,
+    top: usize,
+    records: usize,
+    levels: HashMap<String, usize>,
+    tags: HashMap<String, usize>,
+    processes: HashMap<String, usize>,
+    messages: HashMap<String, usize>,
+    /// Records per second since epoch
+    seconds: BTreeMap<i64, usize>,
+    printed: bool,
+}
+
+pub fn try_from<'a>(args: &ArgMatches<'a>) -> Result<LogSink, Error> {
+    let top = if args.is_present("top") {
+        value_t!(args, "top", usize)?
+    } else {
+        10
+    };
+    Ok(Box::new(StatsSink(Stats {
+        json: args.is_present("json"),
+        top,
+        ..Default::default()
+    })))
+}
+
+/// Sort counts descending and take the first n
+fn top<K: Clone + Ord + Hash>(counts: &HashMap<K, usize>, n: usize) -> Vec<(K, usize)> {
+    let mut counts = counts
+        .iter()
+        .map(|(k, v)| (k.clone(), *v))
+        .collect::<Vec<_>>();
+    counts.sort_by(|a, b| (Reverse(a.1), &a.0).cmp(&(Reverse(b.1), &b.0)));
+    counts.truncate(n);
+    counts
+}
+
+fn time(second: i64) -> Tm {
+    at_utc(Timespec::new(second, 0))
+}
+
+fn format_time(second: i64) -> String {
+    strftime("%m-%d %H:%M:%S", &time(second)).unwrap_or_default()
+}
+
+impl Stats {
+    fn add(&mut self, record: &Record) {
+        self.records += 1;
+        *self.levels.entry(record.level.to_string()).or_default() += 1;
+        *self.tags.entry(record.tag.clone()).or_default() += 1;
+        *self.processes.entry(record.process.clone()).or_default() += 1;
+        *self.messages.entry(record.message.clone()).or_default() += 1;
+        if let Some(ref timestamp) = record.timestamp {
+            let mut tm = timestamp.tm;
+            tm.tm_utcoff = 0;
+            *self.seconds.entry(tm.to_timespec().sec).or_default() += 1;
+        }
+    }
+
+    /// Duration in seconds between the first and last record
+    fn duration(&self) -> i64 {
+        match (self.seconds.keys().next(), self.seconds.keys().last()) {
+            (Some(first), Some(last)) => last - first + 1,
+            _ => 0,
+        }
+    }
+
+    fn peak(&self) -> Option<(i64, usize)> {
+        self.seconds
+            .iter()
+            .max_by_key(|(s, c)| (*c, Reverse(*s)))
+            .map(|(s, c)| (*s, *c))
+    }
+
+    /// Records per time interval. The interval is chosen to get at most RATE_ROWS rows
+    fn rate(&self) -> (i64, Vec<(i64, usize)>) {
+        let first = match self.seconds.keys().next() {
+            Some(first) => *first,
+            None => return (0, vec![]),
+        };
+        let interval = max(1, (self.duration() + RATE_ROWS - 1) / RATE_ROWS);
+        let last = self.seconds.keys().last().cloned().unwrap_or(first);
+        let mut rate = (first..=last)
+            .step_by(interval as usize)
+            .map(|s| (s, 0))
+            .collect::<BTreeMap<i64, usize>>();
+        for (second, count) in &self.seconds {
+            *rate
+                .entry(first + (second - first) / interval * interval)
+                .or_default() += count;
+        }
+        (interval, rate.into_iter().collect())
+    }
+
+    fn print_json(&self) -> Result<(), Error> {
+        let list = |key: &str, counts: Vec<(String, usize)>| {
+            counts
+                .into_iter()
+                .map(|(k, c)| json!({ key: k, "records": c }))
+                .collect::<Vec<_>>()
+        };
+        let (interval, rate) = self.rate();
+        let summary = json!({
+            "records": self.records,
+            "duration": self.duration(),
+            "levels": top(&self.levels, usize::MAX).into_iter().collect::<BTreeMap<_, _>>(),
+            "tags": list("tag", top(&self.tags, self.top)),
+            "processes": list("process", top(&self.processes, self.top)),
+            "messages": list("message", top(&self.messages, self.top)),
+            "peak": self.peak().map(|(s, c)| json!({ "time": format_time(s), "records": c })),
+            "rate": {
+                "interval": interval,
+                "records": rate
+                    .iter()
+                    .map(|(s, c)| json!({ "time": format_time(*s), "records": c }))
+                    .collect::<Vec<_>>(),
+            },
+        });
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        Ok(())
+    }
+
+    fn print_table(&self) {
+        let duration = self.duration();
+        print!("Records: {}", self.records);
+        if duration > 0 {
+            print!(
+                " in {}s ({:.1}/s)",
+                duration,
+                self.records as f64 / duration as f64
+            );
+        }
+        println!();
+        if let Some((second, count)) = self.peak() {
+            println!("Peak: {}/s at {}", count, format_time(second));
+        }
+
+        let table = |title: &str, rows: Vec<(String, usize)>| {
+            if rows.is_empty() {
+                return;
+            }
+            let width = rows
+                .iter()
+                .map(|(k, _)| k.chars().count())
+                .max()
+                .unwrap_or(0);
+            println!("\n{}:", title);
+            for (k, v) in rows {
+                println!("  {:<width$}  {:>8}", k, v, width = width);
+            }
+        };
+
+        // Levels are listed by severity
+        let levels = [
+            Level::Assert,
+            Level::Fatal,
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Verbose,
+            Level::Trace,
+            Level::None,
+        ]
+        .iter()
+        .filter_map(|l| self.levels.get(&l.to_string()).map(|c| (l.to_string(), *c)))
+        .collect();
+        table("Levels", levels);
+        table("Tags", top(&self.tags, self.top));
+        table("Processes", top(&self.processes, self.top));
+
+        let (interval, rate) = self.rate();
+        if !rate.is_empty() {
+            let peak = rate.iter().map(|(_, c)| *c).max().unwrap_or(1);
+            println!("\nRecords per {}s:", interval);
+            for (second, count) in rate {
+                let bar = "#".repeat((count * 40).div_ceil(peak));
+                println!("  {}  {:>8} {}", format_time(second), count, bar);
+            }
+        }
+
+        let messages = top(&self.messages, self.top);
+        if !messages.is_empty() {
+            println!("\nMessages:");
+            for (message, count) in messages {
+                println!("  {:>8}  {}", count, message);
+            }
+        }
+    }
+
+    fn print(&mut self) -> Result<(), Error> {
+        if self.printed {
+            return Ok(());
+        }
+        self.printed = true;
+        if self.json {
+            self.print_json()
+        } else {
+            self.print_table();
+            Ok(())
+        }
+    }
+}
+
+/// Prints the summary at the end of the stream or when dropped on
+/// cancellation
+struct StatsSink(Stats);
+
+impl Sink for StatsSink {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        self.0.add(&record);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.0.print().map(Async::Ready)
+    }
+}
+
+impl Drop for StatsSink {
+    fn drop(&mut self) {
+        self.0.print().ok();
+    }
+}
+
+#[test]
+fn rate_interval() {
+    let mut stats = Stats::default();
+    for second in 0..100 {
+        stats.seconds.insert(1000 + second, 2);
+    }
+    assert_eq!(stats.duration(), 100);
+    let (interval, rate) = stats.rate();
+    assert_eq!(interval, 5);
+    assert_eq!(rate.len(), 20);
+    assert_eq!(rate[0], (1000, 10));
+}
+
+#[test]
+fn top_counts() {
+    let mut counts = HashMap::new();
+    counts.insert("a", 1);
+    counts.insert("b", 3);
+    counts.insert("c", 3);
+    assert_eq!(top(&counts, 2), vec![("b", 3), ("c", 3)]);
+}
@@ -0,0 +1,94 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! `--strict-parse` writes lines that no parser recognizes to a sidecar
+//! file instead of passing them on as untagged records.
+
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use std::{
+    fs::File,
+    io::{LineWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Sidecar file if `--strict-parse` is passed without a filename
+const DEFAULT_FILE: &str = "unparsed.log";
+
+/// Number of lines written to the sidecar file
+#[derive(Clone, Debug, Default)]
+pub struct Unparsed {
+    count: Arc<AtomicUsize>,
+    file: PathBuf,
+}
+
+impl Unparsed {
+    /// Print the number of unparsed lines if any
+    pub fn report(&self) {
+        let count = self.count.load(Ordering::Relaxed);
+        if count > 0 {
+            eprintln!(
+                "{} lines failed to parse and were written to {}",
+                count,
+                self.file.display()
+            );
+        }
+    }
+}
+
+pub struct Sidecar {
+    writer: LineWriter<File>,
+    unparsed: Unparsed,
+}
+
+impl Sidecar {
+    pub fn write(&mut self, line: &str) -> Result<(), Error> {
+        writeln!(self.writer, "{}", line)
+            .map_err(|e| format_err!("Failed to write {}: {}", self.unparsed.file.display(), e))?;
+        self.unparsed.count.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn unparsed(&self) -> Unparsed {
+        self.unparsed.clone()
+    }
+}
+
+/// Create the sidecar file if `--strict-parse` is passed
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Result<Option<Sidecar>, Error> {
+    if !args.is_present("strict_parse") {
+        return Ok(None);
+    }
+    let file = PathBuf::from(args.value_of("strict_parse").unwrap_or(DEFAULT_FILE));
+    let writer = File::create(&file)
+        .map(LineWriter::new)
+        .map_err(|e| format_err!("Failed to create {}: {}", file.display(), e))?;
+    Ok(Some(Sidecar {
+        writer,
+        unparsed: Unparsed {
+            count: Arc::new(AtomicUsize::new(0)),
+            file,
+        },
+    }))
+}
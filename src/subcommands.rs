@@ -20,23 +20,24 @@
 
 use crate::{
     cli::cli,
-    reader::stdin,
-    utils::{self, adb},
-    StreamData, DEFAULT_BUFFER,
+    diff, marker,
+    utils::{self, adb_command},
+    DEFAULT_BUFFER,
 };
 use clap::{crate_name, value_t, ArgMatches};
-use failure::{err_msg, Error};
-use futures::{
-    future::ok, stream::Stream, sync::oneshot, Async, AsyncSink, Future, Poll, Sink, StartSend,
-};
+use failure::{err_msg, format_err, Error};
+use futures::{future::ok, stream::Stream, sync::oneshot, Future};
 use indicatif::{ProgressBar, ProgressStyle};
 use rogcat::record::Level;
 use std::{
     borrow::ToOwned,
+    cmp::max,
     fs::{DirBuilder, File},
-    io::{BufReader, Write},
+    io::{BufRead, BufReader, Write},
     path::{Path, PathBuf},
     process::{exit, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
 use time::{now, strftime};
 use tokio::{io::lines, runtime::Runtime};
@@ -45,11 +46,17 @@ use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
 pub fn run(args: &ArgMatches) {
     match args.subcommand() {
-        ("bugreport", Some(sub_matches)) => bugreport(sub_matches),
+        ("bootloop", Some(sub_matches)) => crate::bootloop::run(args, sub_matches),
+        ("bugreport", Some(sub_matches)) if !sub_matches.is_present("extract") => {
+            bugreport(sub_matches)
+        }
         ("clear", Some(sub_matches)) => clear(sub_matches),
         ("completions", Some(sub_matches)) => completions(sub_matches),
         ("devices", _) => devices(),
-        ("log", Some(sub_matches)) => log(sub_matches),
+        ("diff", Some(sub_matches)) => diff::run(sub_matches),
+        ("log", Some(sub_matches)) => log(args, sub_matches),
+        ("log-level", Some(sub_matches)) => crate::loglevel::run(args, sub_matches),
+        ("run", Some(sub_matches)) => crate::run::run(args, sub_matches),
         (_, _) => (),
     }
 }
@@ -130,7 +137,8 @@ pub fn bugreport(args: &ArgMatches) {
         exit(1);
     }
 
-    let mut child = Command::new(adb().expect("Failed to find adb"))
+    let mut child = adb_command()
+        .expect("Failed to find adb")
         .arg("bugreport")
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -185,7 +193,8 @@ pub fn bugreport(args: &ArgMatches) {
 }
 
 pub fn devices() {
-    let mut child = Command::new(adb().expect("Failed to find adb"))
+    let mut child = adb_command()
+        .expect("Failed to find adb")
         .arg("devices")
         .stdout(Stdio::piped())
         .spawn_async()
@@ -213,6 +222,7 @@ pub fn devices() {
     );
 }
 
+/// Writes messages to the device log with the `log` command of the device shell
 struct Logger {
     tag: String,
     level: Level,
@@ -228,69 +238,110 @@ impl Logger {
             Level::Error | Level::Fatal | Level::Assert => "e",
         }
     }
-}
 
-impl Sink for Logger {
-    type SinkItem = String;
-    type SinkError = Error;
+    /// Shell command logging message
+    fn command(&self, message: &str) -> String {
+        format!(
+            "log -p {} -t {} {}",
+            Self::level(&self.level),
+            shell_quote(&self.tag),
+            shell_quote(message)
+        )
+    }
 
-    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        let child = Command::new(adb()?)
+    /// Log each line of stdin in order through a single shell on the device.
+    /// With rate the lines are spread to at most rate lines per second.
+    fn stream(&self, adb: &[String], rate: Option<f64>) -> Result<(), Error> {
+        let mut shell = Command::new(&adb[0])
+            .args(&adb[1..])
             .arg("shell")
-            .arg("log")
-            .arg("-p")
-            .arg(Self::level(&self.level))
-            .arg("-t")
-            .arg(format!("\"{}\"", &self.tag))
-            .arg(&item)
-            .stdout(Stdio::piped())
-            .output_async()
-            .map(|_| ())
-            .map_err(|_| ());
-        tokio::spawn(child);
-        Ok(AsyncSink::Ready)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| format_err!("Failed to run adb: {}", e))?;
+        let mut input = shell.stdin.take().expect("Failed to get adb stdin");
+        let delay = rate.map(|r| Duration::from_secs_f64(1.0 / r));
+        let mut next = Instant::now();
+        let stdin = std::io::stdin();
+        let mut stdin = stdin.lock();
+        let mut line = Vec::new();
+        while stdin.read_until(b'\n', &mut line)? > 0 {
+            let message = String::from_utf8_lossy(&line);
+            let message = message.trim_end_matches(&['\r', '\n'][..]);
+            if !message.is_empty() {
+                if let Some(delay) = delay {
+                    let now = Instant::now();
+                    if next > now {
+                        thread::sleep(next - now);
+                    }
+                    next = max(next, now) + delay;
+                }
+                writeln!(input, "{}", self.command(message))
+                    .and_then(|_| input.flush())
+                    .map_err(|e| format_err!("Failed to write to the device: {}", e))?;
+            }
+            line.clear();
+        }
+        writeln!(input, "exit").ok();
+        drop(input);
+        match shell.wait()? {
+            status if status.success() => Ok(()),
+            status => Err(format_err!("adb shell exited with {}", status)),
+        }
     }
+}
 
-    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
-        Ok(Async::Ready(()))
-    }
+/// Quote s for the shell of the device
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
 }
 
-/// Call something like adb shell log <message>
-pub fn log(args: &ArgMatches) {
-    let message = args.value_of("MESSAGE").unwrap_or("");
-    let tag = args.value_of("tag").unwrap_or("Rogcat").to_owned();
-    let level = Level::from(args.value_of("level").unwrap_or(""));
-    match message {
+/// Call something like adb shell log <message>. Lines of stdin are logged
+/// one by one if message is "-"
+pub fn log(args: &ArgMatches, log: &ArgMatches) {
+    let message = log.value_of("MESSAGE").unwrap_or("");
+    let tag = if log.is_present("marker") {
+        marker::TAG
+    } else {
+        log.value_of("tag").unwrap_or("Rogcat")
+    }
+    .to_owned();
+    let level = Level::from(log.value_of("level").unwrap_or(""));
+    let logger = Logger { tag, level };
+    let result = utils::device_cmd(args).and_then(|adb| match message {
         "-" => {
-            let sink = Logger { tag, level };
-            let stream = stdin()
-                .map(|d| match d {
-                    StreamData::Line(l) => l,
-                    _ => panic!("Received non line item during log"),
-                })
-                .forward(sink)
-                .map(|_| ())
-                .map_err(|_| ());
-            tokio::run(stream);
+            let rate = match log.value_of("rate") {
+                Some(rate) => match rate.parse::<f64>() {
+                    Ok(rate) if rate > 0.0 => Some(rate),
+                    _ => return Err(format_err!("Invalid rate {}", rate)),
+                },
+                None => None,
+            };
+            logger.stream(&adb, rate)
         }
         _ => {
-            let child = Command::new(adb().expect("Failed to find adb"))
+            let output = Command::new(&adb[0])
+                .args(&adb[1..])
                 .arg("shell")
-                .arg("log")
-                .arg("-p")
-                .arg(&Logger::level(&level))
-                .arg("-t")
-                .arg(&tag)
-                .arg(format!("\"{}\"", message))
-                .stdout(Stdio::piped())
-                .output_async()
-                .map(|_| ())
-                .map_err(|_| ());
-            tokio::run(child)
+                .arg(logger.command(message))
+                .stdin(Stdio::null())
+                .output()
+                .map_err(|e| format_err!("Failed to run adb: {}", e))?;
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(format_err!(
+                    "Failed to log: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ))
+            }
         }
-    }
+    });
 
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(1);
+    }
     exit(0);
 }
 
@@ -302,7 +353,8 @@ pub fn clear(args: &ArgMatches) {
         .or_else(|| utils::config_get("buffer"))
         .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_owned()).collect())
         .join(" -b ");
-    let child = Command::new(adb().expect("Failed to find adb"))
+    let child = adb_command()
+        .expect("Failed to find adb")
         .arg("logcat")
         .arg("-c")
         .arg("-b")
@@ -0,0 +1,197 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Symbolize the backtraces of native crashes with `--symbols DIR`. Frames
+//! of libraries found in the directories are resolved with `addr2line` and
+//! the functions and source lines are added below each frame.
+
+use crate::{utils::config_get, RecordStream};
+use clap::{values_t, ArgMatches};
+use failure::{format_err, Error};
+use futures::Stream;
+use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::record::Record;
+use std::{
+    collections::HashMap,
+    env,
+    fs::read_dir,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use which::which_in;
+
+lazy_static! {
+    /// Backtrace frame e.g `#00 pc 000000000001a2b4  /data/app/lib/arm64/libnative.so (foo+20)`
+    static ref FRAME: Regex = Regex::new(r"^\s*#\d+ pc ([0-9a-fA-F]+)\s+(/\S+)").unwrap();
+}
+
+/// Tools tried in this order unless configured with `addr2line`
+const ADDR2LINE: &[&str] = &["llvm-addr2line", "addr2line"];
+
+struct Symbolizer {
+    addr2line: PathBuf,
+    /// Files of the symbol directories by file name
+    files: HashMap<String, PathBuf>,
+    /// Resolved frames by file and address
+    cache: HashMap<(PathBuf, String), Vec<String>>,
+}
+
+/// Add the files below dir to files unless a file of the same name is known
+fn index(dir: &Path, files: &mut HashMap<String, PathBuf>) -> Result<(), Error> {
+    let entries =
+        read_dir(dir).map_err(|e| format_err!("Failed to read {}: {}", dir.display(), e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => index(&path, files)?,
+            Ok(t) if t.is_file() => {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                files.entry(name).or_insert(path);
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// File name of a library path on the device. Libraries loaded from a apk
+/// are named like `base.apk!libnative.so`
+fn library_name(path: &str) -> &str {
+    path.rsplit(['/', '!']).next().unwrap_or(path)
+}
+
+/// Format the function and location pairs printed by `addr2line -f -i`
+fn frames(output: &str) -> Vec<String> {
+    let lines = output.lines().collect::<Vec<_>>();
+    lines
+        .chunks(2)
+        .filter(|c| c.len() == 2 && !(c[0] == "??" && c[1].starts_with("??")))
+        .map(|c| format!("{} at {}", c[0], c[1]))
+        .collect()
+}
+
+impl Symbolizer {
+    /// Resolve address in file. Failures yield no frames
+    fn resolve(&mut self, file: &Path, address: &str) -> &[String] {
+        let addr2line = &self.addr2line;
+        self.cache
+            .entry((file.to_owned(), address.to_owned()))
+            .or_insert_with(|| {
+                Command::new(addr2line)
+                    .args(["-C", "-f", "-i", "-e"])
+                    .arg(file)
+                    .arg(format!("0x{}", address))
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::null())
+                    .output()
+                    .map(|o| frames(&String::from_utf8_lossy(&o.stdout)))
+                    .unwrap_or_default()
+            })
+    }
+
+    /// Add the resolved frames below the frame lines of the message
+    fn process(&mut self, record: &mut Record) {
+        if !record.message.contains(" pc ") {
+            return;
+        }
+        let mut message = String::with_capacity(record.message.len());
+        let mut raw = record.raw.clone();
+        for line in record.message.clone().lines() {
+            if !message.is_empty() {
+                message.push('\n');
+            }
+            message.push_str(line);
+            let (address, library) = match FRAME.captures(line) {
+                Some(c) => (c[1].to_owned(), c[2].to_owned()),
+                None => continue,
+            };
+            let file = match self.files.get(library_name(&library)) {
+                Some(file) => file.clone(),
+                None => continue,
+            };
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let symbolized = self
+                .resolve(&file, &address)
+                .iter()
+                .map(|f| format!("\n{}    {}", indent, f))
+                .collect::<String>();
+            if !symbolized.is_empty() {
+                message.push_str(&symbolized);
+                // Keep the raw line in sync for the raw output format
+                if let Some(n) = raw.find(line) {
+                    raw.insert_str(n + line.len(), &symbolized);
+                }
+            }
+        }
+        record.message = message;
+        record.raw = raw;
+    }
+}
+
+/// Symbolize records if `--symbols` is passed
+pub fn from_args<'a>(args: &ArgMatches<'a>, records: RecordStream) -> Result<RecordStream, Error> {
+    if !args.is_present("symbols") {
+        return Ok(records);
+    }
+    let mut files = HashMap::new();
+    for dir in values_t!(args.values_of("symbols"), String)? {
+        index(Path::new(&dir), &mut files)?;
+    }
+    let addr2line = match config_get::<String>("addr2line") {
+        Some(addr2line) => PathBuf::from(addr2line),
+        None => ADDR2LINE
+            .iter()
+            .find_map(|tool| which_in(tool, env::var_os("PATH"), env::current_dir().ok()?).ok())
+            .ok_or_else(|| format_err!("Cannot find {}", ADDR2LINE.join(" or ")))?,
+    };
+    let mut symbolizer = Symbolizer {
+        addr2line,
+        files,
+        cache: HashMap::new(),
+    };
+    Ok(Box::new(records.map(move |mut r| {
+        symbolizer.process(&mut r);
+        r
+    })))
+}
+
+#[test]
+fn symbolize() {
+    assert_eq!(
+        library_name("/data/app/com.example-1/lib/arm64/libfoo.so"),
+        "libfoo.so"
+    );
+    assert_eq!(
+        library_name("/data/app/com.example-1/base.apk!libfoo.so"),
+        "libfoo.so"
+    );
+    assert_eq!(
+        frames("foo(int)\njni/foo.cpp:42\nbar()\njni/bar.cpp:7\n??\n??:0\n"),
+        vec!["foo(int) at jni/foo.cpp:42", "bar() at jni/bar.cpp:7"]
+    );
+
+    let c = FRAME
+        .captures("      #01 pc 0000000000012345  /system/lib64/libc.so (__libc_init+88)")
+        .unwrap();
+    assert_eq!(&c[1], "0000000000012345");
+    assert_eq!(&c[2], "/system/lib64/libc.so");
+    assert!(FRAME.captures("#02 pc 00001234  [anon:dalvik]").is_none());
+}
@@ -0,0 +1,184 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! User defined record formats like `{time} [{level}] {tag}: {message}`.
+//! Placeholders take a alignment, width and precision after a colon:
+//! `{tag:>20}` pads to 20 characters on the left, `{message:.80}` truncates
+//! to 80 characters and `{tag:<8.8}` does both. `{{` and `}}` are literal
+//! braces. Names that are no record attribute select a field of the profile.
+
+use crate::record::Record;
+use failure::{format_err, Error};
+use time::strftime;
+
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder {
+        name: &'a str,
+        right: bool,
+        width: Option<usize>,
+        precision: Option<usize>,
+    },
+}
+
+fn spec<'a>(placeholder: &'a str) -> Result<Part<'a>, Error> {
+    let (name, spec) = match placeholder.find(':') {
+        Some(n) => (&placeholder[..n], &placeholder[n + 1..]),
+        None => (placeholder, ""),
+    };
+    if name.is_empty() {
+        return Err(format_err!(
+            "Missing name in placeholder {{{}}}",
+            placeholder
+        ));
+    }
+    let (right, spec) = match spec.chars().next() {
+        Some('>') => (true, &spec[1..]),
+        Some('<') => (false, &spec[1..]),
+        _ => (false, spec),
+    };
+    let (width, precision) = match spec.find('.') {
+        Some(n) => (&spec[..n], Some(&spec[n + 1..])),
+        None => (spec, None),
+    };
+    let number = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|e| format_err!("Invalid placeholder {{{}}}: {}", placeholder, e))
+    };
+    Ok(Part::Placeholder {
+        name,
+        right,
+        width: Some(width)
+            .filter(|w| !w.is_empty())
+            .map(number)
+            .transpose()?,
+        precision: precision.map(number).transpose()?,
+    })
+}
+
+fn parse(template: &str) -> Result<Vec<Part<'_>>, Error> {
+    let mut parts = vec![];
+    let mut rest = template;
+    while !rest.is_empty() {
+        if rest.starts_with("{{") {
+            parts.push(Part::Text("{"));
+            rest = &rest[2..];
+        } else if rest.starts_with("}}") {
+            parts.push(Part::Text("}"));
+            rest = &rest[2..];
+        } else if rest.starts_with('{') {
+            let end = rest
+                .find('}')
+                .ok_or_else(|| format_err!("Unclosed placeholder in template {}", template))?;
+            parts.push(spec(&rest[1..end])?);
+            rest = &rest[end + 1..];
+        } else if rest.starts_with('}') {
+            return Err(format_err!("Unmatched }} in template {}", template));
+        } else {
+            let end = rest.find(&['{', '}'][..]).unwrap_or(rest.len());
+            parts.push(Part::Text(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    Ok(parts)
+}
+
+/// Check the syntax of template
+pub fn validate(template: &str) -> Result<(), Error> {
+    parse(template).map(|_| ())
+}
+
+fn value(name: &str, record: &Record) -> String {
+    let timestamp = |format: &str, len: usize| {
+        record
+            .timestamp
+            .as_ref()
+            .and_then(|ts| strftime(format, ts).ok())
+            .map(|mut ts| {
+                ts.truncate(len);
+                ts
+            })
+            .unwrap_or_default()
+    };
+    match name {
+        "time" => timestamp("%H:%M:%S.%f", 12),
+        "date" => timestamp("%m-%d", 5),
+        "level" => record.level.to_string(),
+        "tag" => record.tag.clone(),
+        "pid" => record.process.clone(),
+        "tid" => record.thread.clone(),
+        "message" => record.message.clone(),
+        "raw" => record.raw.clone(),
+        "device" => record.device.clone().unwrap_or_default(),
+        "buffer" => record.buffer.clone().unwrap_or_default(),
+        "name" => record.process_name.clone().unwrap_or_default(),
+        "cpu" => record.cpu.map(|c| format!("{:.1}", c)).unwrap_or_default(),
+        field => record.fields.get(field).cloned().unwrap_or_default(),
+    }
+}
+
+/// Format record according to template
+pub fn render(template: &str, record: &Record) -> Result<String, Error> {
+    let mut line = String::new();
+    for part in parse(template)? {
+        match part {
+            Part::Text(text) => line.push_str(text),
+            Part::Placeholder {
+                name,
+                right,
+                width,
+                precision,
+            } => {
+                let mut value = value(name, record);
+                if let Some((n, _)) = precision.and_then(|p| value.char_indices().nth(p)) {
+                    value.truncate(n);
+                }
+                let pad = width.unwrap_or(0).saturating_sub(value.chars().count());
+                if right {
+                    line.push_str(&" ".repeat(pad));
+                    line.push_str(&value);
+                } else {
+                    line.push_str(&value);
+                    line.push_str(&" ".repeat(pad));
+                }
+            }
+        }
+    }
+    Ok(line)
+}
+
+#[test]
+fn templates() {
+    let record = crate::parser::Parser::default()
+        .parse_line("01-02 03:04:05.678  123  456 W Tag: hello world")
+        .unwrap();
+    let r = |t: &str| render(t, &record).unwrap();
+    assert_eq!(
+        r("{date} {time} [{level}] {tag}: {message}"),
+        "01-02 03:04:05.678 [W] Tag: hello world"
+    );
+    assert_eq!(r("{tag:6}|{tag:>6}|{message:.5}|"), "Tag   |   Tag|hello|");
+    assert_eq!(r("{message:<7.5}|{pid:>5.2}"), "hello  |   12");
+    assert_eq!(r("{{{tag}}} {request}"), "{Tag} ");
+    assert!(validate("{tag").is_err());
+    assert!(validate("tag}").is_err());
+    assert!(validate("{tag:x}").is_err());
+    assert!(validate("{}").is_err());
+}
@@ -19,9 +19,16 @@
 // SOFTWARE.
 
 use crate::{
-    profiles::Profile,
+    footer::Footer,
+    hosttime,
+    html::Html,
+    marker,
+    profiles::{Profile, Watch},
+    relative,
+    theme::{parse_color, Theme},
+    trace::Trace,
     utils::{config_get, terminal_width},
-    LogSink,
+    vitals, LogSink,
 };
 use clap::{values_t, ArgMatches};
 use failure::{err_msg, format_err, Error};
@@ -29,29 +36,56 @@ use futures::{Async, AsyncSink, Poll, Sink, StartSend};
 use regex::Regex;
 use rogcat::record::{Format, Level, Record};
 use std::{
-    cmp::{max, min},
+    borrow::ToOwned,
+    cmp::max,
+    collections::HashMap,
     convert::Into,
+    env,
     io::{stdout, BufWriter, Write},
+    mem::swap,
+    path::Path,
     str::FromStr,
 };
 use termcolor::{Buffer, BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
+use time::Tm;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 const DIMM_COLOR: Color = Color::Ansi256(243);
 
-/// Construct a terminal sink for format from args with give profile
-pub fn try_from<'a>(args: &ArgMatches<'a>, profile: &Profile) -> Result<LogSink, Error> {
-    let format = args
-        .value_of("format")
-        .ok_or_else(|| format_err!("Missing format argument"))
-        .and_then(|f| Format::from_str(f).map_err(err_msg))
-        .unwrap_or(Format::Human);
-
-    if format == Format::Html {
-        return Err(format_err!("HTML format is only valid for file output"));
-    }
+/// Construct a terminal sink for format from args with give profile. The
+/// human format follows updates of the profile.
+pub fn try_from(
+    args: &ArgMatches<'static>,
+    profile: &Profile,
+    watch: &Watch,
+) -> Result<LogSink, Error> {
+    let format = match args.value_of("output_format") {
+        Some(template) => Format::Template(template.to_owned()),
+        None => args
+            .value_of("format")
+            .ok_or_else(|| format_err!("Missing format argument"))
+            .and_then(|f| Format::from_str(f).map_err(err_msg))
+            .unwrap_or(Format::Human),
+    };
 
     let sink = match format {
-        Format::Human => Box::new(Human::from(args, profile, format)) as LogSink,
+        Format::Human => {
+            let mut human = Human::from(args, profile, format)?;
+            human.watch = Some((args.clone(), watch.clone(), 0));
+            // The footer is drawn with control codes and omitted on pipes
+            if args.is_present("stats_line") && atty::is(atty::Stream::Stdout) {
+                human.footer = Some(Footer::spawn(terminal_width().unwrap_or(80)));
+            }
+            Box::new(human) as LogSink
+        }
+        Format::Msgpack | Format::Protobuf if atty::is(atty::Stream::Stdout) => {
+            return Err(format_err!(
+                "Refusing to write binary {} to the terminal. Redirect stdout or pass -o",
+                format
+            ))
+        }
+        Format::Html => Box::new(HtmlSink::new(stdout())?) as LogSink,
+        Format::Trace => Box::new(TraceSink::new(stdout())?) as LogSink,
         format => Box::new(FormatSink::new(format, stdout())) as LogSink,
     };
 
@@ -60,48 +94,592 @@ pub fn try_from<'a>(args: &ArgMatches<'a>, profile: &Profile) -> Result<LogSink,
     })))
 }
 
+/// Color for a given string e.g a tag or pid
+#[cfg(target_os = "windows")]
+pub fn hashed_color(i: &str) -> Color {
+    let v = i.bytes().fold(42u8, |c, x| c ^ x) % 7;
+    match v {
+        0 => Color::Blue,
+        1 => Color::Green,
+        2 => Color::Red,
+        3 => Color::Cyan,
+        4 => Color::Magenta,
+        5 => Color::Yellow,
+        _ => Color::White,
+    }
+}
+
+/// Color for a given string e.g a tag or pid
+#[cfg(not(target_os = "windows"))]
+pub fn hashed_color(i: &str) -> Color {
+    // Some colors are hard to read on (at least) dark terminals
+    // and I consider some others as ugly.
+    Color::Ansi256(match i.bytes().fold(42u8, |c, x| c ^ x) {
+        c @ 0..=1 => c + 2,
+        c @ 16..=21 => c + 6,
+        c @ 52..=55 | c @ 126..=129 => c + 4,
+        c @ 163..=165 | c @ 200..=201 => c + 3,
+        c @ 207 => c + 1,
+        c @ 232..=240 => c + 9,
+        c => c,
+    })
+}
+
+/// Windows consoles that understand escape sequences e.g Windows Terminal or
+/// PowerShell. Virtual terminal processing is enabled on the way.
+#[cfg(target_os = "windows")]
+fn supports_ansi() -> bool {
+    crossterm::ansi_support::supports_ansi()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn supports_ansi() -> bool {
+    false
+}
+
+/// Color choice for a color setting. Piped output is colored with escape
+/// sequences only if forced because the Windows console API cannot color
+/// pipes. `NO_COLOR` disables colors in auto mode.
+fn color_choice(color: &str) -> ColorChoice {
+    let tty = atty::is(atty::Stream::Stdout);
+    match color {
+        "always" if !tty || supports_ansi() => ColorChoice::AlwaysAnsi,
+        "always" => ColorChoice::Always,
+        "never" => ColorChoice::Never,
+        _ if !tty || env::var_os("NO_COLOR").is_some() => ColorChoice::Never,
+        _ if supports_ansi() => ColorChoice::AlwaysAnsi,
+        _ => ColorChoice::Auto,
+    }
+}
+
+/// Timestamp format used if not configured otherwise
+const TIME_FORMAT: (&str, usize) = ("%H:%M:%S.%f", 12);
+
+/// Conversion of timestamps before display. Timestamps without a zone
+/// are taken as UTC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TimeZone {
+    /// As captured
+    Original,
+    Local,
+    Utc,
+}
+
+impl TimeZone {
+    fn convert(self, tm: &Tm) -> Tm {
+        if self == TimeZone::Original {
+            return *tm;
+        }
+        // Timestamps without year are placed in the current one to get the
+        // offset of the local time zone right
+        let mut tm = *tm;
+        let year = tm.tm_year;
+        if year == 0 {
+            tm.tm_year = time::now().tm_year;
+        }
+        // Tm::to_timespec takes any offset as the local one
+        let offset = i64::from(tm.tm_utcoff);
+        tm.tm_utcoff = 0;
+        let spec = time::Timespec::new(tm.to_timespec().sec - offset, tm.tm_nsec);
+        let mut converted = match self {
+            TimeZone::Local => time::at(spec),
+            _ => time::at_utc(spec),
+        };
+        if year == 0 {
+            converted.tm_year = 0;
+        }
+        converted
+    }
+}
+
+/// Expand a custom timestamp format. `%f` is the milliseconds part
+/// unlike strftime's nanoseconds. `%z` is the offset with a `+` for UTC.
+fn format_timestamp(format: &str, tm: &Tm) -> Result<String, Error> {
+    let offset = tm.tm_utcoff / 60;
+    let format = format
+        .replace("%f", &format!("{:03}", tm.tm_nsec / 1_000_000))
+        .replace(
+            "%z",
+            &format!(
+                "{}{:02}{:02}",
+                if offset < 0 { '-' } else { '+' },
+                offset.abs() / 60,
+                offset.abs() % 60
+            ),
+        );
+    time::strftime(&format, tm)
+        .map_err(|e| format_err!("Invalid timestamp format {}: {}", format, e))
+}
+
+/// Segments of text with foreground and background color
+type Cell = Vec<(String, Option<Color>, Option<Color>)>;
+
+/// Columns of the human format with optional width
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Column {
+    Buffer(Option<usize>),
+    Device(Option<usize>),
+    Time,
+    Tag(Option<usize>),
+    Name(Option<usize>),
+    Pid(Option<usize>),
+    Tid(Option<usize>),
+    Level,
+    Message(Option<usize>),
+    /// Field of the profile by index
+    Field(usize, Option<usize>),
+}
+
+impl FromStr for Column {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Column, Error> {
+        let mut split = s.trim().splitn(2, ':');
+        let name = split.next().unwrap_or_default();
+        let width = split
+            .next()
+            .map(|w| {
+                w.parse::<usize>()
+                    .map_err(|e| format_err!("Invalid width of column {}: {}", s, e))
+            })
+            .transpose()?;
+        let column = match name {
+            "buffer" => Column::Buffer(width),
+            "device" => Column::Device(width),
+            "time" => Column::Time,
+            "tag" => Column::Tag(width),
+            "name" => Column::Name(width),
+            "pid" => Column::Pid(width),
+            "tid" => Column::Tid(width),
+            "level" => Column::Level,
+            "msg" => Column::Message(width),
+            _ => return Err(format_err!("Invalid column {}", s)),
+        };
+        match column {
+            Column::Time | Column::Level if width.is_some() => {
+                Err(format_err!("Column {} has no width", name))
+            }
+            column => Ok(column),
+        }
+    }
+}
+
+/// Selection of the colors of a line
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ColorMode {
+    /// The entire line in the level color
+    Line,
+    /// Level badge and message in the level color
+    Level,
+    /// Level and message in the color of the tag
+    Tag,
+    /// No colors
+    Off,
+}
+
+impl FromStr for ColorMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ColorMode, Error> {
+        match s {
+            "line" => Ok(ColorMode::Line),
+            "level" => Ok(ColorMode::Level),
+            "tag" => Ok(ColorMode::Tag),
+            "off" => Ok(ColorMode::Off),
+            _ => Err(format_err!("Invalid color mode {}", s)),
+        }
+    }
+}
+
+/// Colors of the elements of a line
+struct Style {
+    /// Foreground and background of the level badge
+    level: (Option<Color>, Option<Color>),
+    message: Option<Color>,
+    /// Overrides the foreground of all cells without background
+    line: Option<Option<Color>>,
+}
+
+/// Appearance of records matching a highlight pattern. Highlights without
+/// action only color the timestamp
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Action {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    underline: bool,
+    /// Swap foreground and background of the whole line
+    invert: bool,
+    /// Ring the terminal bell
+    bell: bool,
+}
+
+impl Action {
+    /// Combine with the action of another matching highlight
+    fn merge(&mut self, other: &Action) {
+        self.fg = self.fg.or(other.fg);
+        self.bg = self.bg.or(other.bg);
+        self.bold |= other.bold;
+        self.underline |= other.underline;
+        self.invert |= other.invert;
+        self.bell |= other.bell;
+    }
+
+    fn spec(&self, fg: Option<Color>, bg: Option<Color>) -> ColorSpec {
+        let (fg, bg) = if self.invert {
+            (
+                Some(bg.unwrap_or(Color::Black)),
+                Some(fg.unwrap_or(Color::White)),
+            )
+        } else {
+            (fg, bg)
+        };
+        let mut spec = ColorSpec::new();
+        spec.set_fg(fg).set_bg(bg);
+        spec
+    }
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Action, Error> {
+        let mut action = Action::default();
+        for a in s.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+            match a {
+                "bold" => action.bold = true,
+                "underline" => action.underline = true,
+                "invert" => action.invert = true,
+                "bell" => action.bell = true,
+                _ if a.starts_with("fg:") => action.fg = Some(parse_color(&a[3..])?),
+                _ if a.starts_with("bg:") => action.bg = Some(parse_color(&a[3..])?),
+                _ => return Err(format_err!("Invalid highlight action {}", a)),
+            }
+        }
+        Ok(action)
+    }
+}
+
+/// Handling of messages exceeding the terminal width
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Wrap {
+    /// Split into chunks of the available width
+    Chars,
+    /// Break on word boundaries and indent continuation lines
+    Word,
+    /// Truncate with a ellipsis
+    None,
+}
+
+impl FromStr for Wrap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Wrap, Error> {
+        match s {
+            "chars" => Ok(Wrap::Chars),
+            "word" => Ok(Wrap::Word),
+            "none" => Ok(Wrap::None),
+            _ => Err(format_err!("Invalid wrap mode {}", s)),
+        }
+    }
+}
+
+/// All levels in display order
+const LEVELS: [Level; 9] = [
+    Level::None,
+    Level::Trace,
+    Level::Verbose,
+    Level::Debug,
+    Level::Info,
+    Level::Warn,
+    Level::Error,
+    Level::Fatal,
+    Level::Assert,
+];
+
+/// Rendering of the level column
+#[derive(Clone, Debug, PartialEq)]
+struct Levels {
+    glyphs: Vec<(Level, String)>,
+    /// Width of the widest glyph
+    width: usize,
+    /// Spaces on each side of a glyph
+    padding: usize,
+}
+
+impl Levels {
+    /// Levels of a style `letter` (default), `word` or `emoji` with
+    /// overrides of single glyphs
+    fn new(
+        style: Option<&str>,
+        overrides: &HashMap<String, String>,
+        padding: Option<usize>,
+    ) -> Result<Levels, Error> {
+        let mut glyphs = LEVELS
+            .iter()
+            .map(|level| {
+                let glyph = match (style.unwrap_or("letter"), level) {
+                    ("letter", level) => level.to_string(),
+                    ("word", Level::None) => "-".to_owned(),
+                    ("word", level) => format!("{:?}", level).to_uppercase(),
+                    ("emoji", level) => match level {
+                        Level::None => "  ",
+                        Level::Trace => "🔬",
+                        Level::Verbose => "💬",
+                        Level::Debug => "🐛",
+                        Level::Info => "💡",
+                        Level::Warn => "🔶",
+                        Level::Error => "🔥",
+                        Level::Fatal => "💀",
+                        Level::Assert => "💣",
+                    }
+                    .to_owned(),
+                    (style, _) => return Err(format_err!("Invalid level style {}", style)),
+                };
+                Ok((level.clone(), glyph))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        for (key, glyph) in overrides {
+            let level = match Level::from(key.as_str()) {
+                Level::None if key == "none" || key == "-" => Level::None,
+                Level::None => return Err(format_err!("Invalid level {}", key)),
+                level => level,
+            };
+            if let Some(entry) = glyphs.iter_mut().find(|(l, _)| *l == level) {
+                entry.1 = glyph.clone();
+            }
+        }
+
+        let width = glyphs.iter().map(|(_, g)| g.width()).max().unwrap_or(0);
+        Ok(Levels {
+            glyphs,
+            width,
+            padding: padding.unwrap_or(1),
+        })
+    }
+
+    /// Padded glyph of level
+    fn glyph(&self, level: &Level) -> String {
+        let glyph = self
+            .glyphs
+            .iter()
+            .find(|(l, _)| l == level)
+            .map(|(_, g)| g.as_str())
+            .unwrap_or("");
+        let padding = " ".repeat(self.padding);
+        format!("{}{}{}", padding, pad(glyph, self.width), padding)
+    }
+}
+
+/// Byte offset of the first character of text that exceeds width terminal
+/// columns. Wide characters like CJK take two columns. The first character
+/// always fits to guarantee progress when splitting.
+fn width_end(text: &str, width: usize) -> Option<usize> {
+    let mut columns = 0;
+    for (i, c) in text.char_indices() {
+        columns += c.width().unwrap_or(0);
+        if columns > width && i != 0 {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Longest prefix of text that fits into width columns
+fn take_width(text: &str, width: usize) -> &str {
+    &text[..width_end(text, width).unwrap_or(text.len())]
+}
+
+/// Pad text with spaces to width columns
+fn pad(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(text.width())))
+}
+
+/// Pad text with leading spaces to width columns
+fn pad_start(text: &str, width: usize) -> String {
+    format!("{}{}", " ".repeat(width.saturating_sub(text.width())), text)
+}
+
+/// Split message into chunks of at most width columns
+fn chunks(message: &str, width: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut rest = message;
+    while let Some(end) = width_end(rest, width) {
+        chunks.push(rest[..end].to_owned());
+        rest = &rest[end..];
+    }
+    chunks.push(rest.to_owned());
+    chunks
+}
+
+/// Truncate message to width columns including a trailing ellipsis
+fn truncate(message: &str, width: usize) -> String {
+    format!("{}…", take_width(message, width.saturating_sub(1)))
+}
+
+/// Split message into lines of at most width columns. Lines are broken after
+/// the last whitespace that fits. Words longer than width are split.
+fn word_wrap(message: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut rest = message;
+    loop {
+        let end = match width_end(rest, width) {
+            Some(end) => end,
+            None => {
+                if !rest.is_empty() || lines.is_empty() {
+                    lines.push(rest.to_owned());
+                }
+                return lines;
+            }
+        };
+        let split = if rest[end..].starts_with(char::is_whitespace) {
+            Some(end)
+        } else {
+            rest[..end].rfind(char::is_whitespace)
+        };
+        match split {
+            Some(split) if !rest[..split].trim_end().is_empty() => {
+                lines.push(rest[..split].trim_end().to_owned());
+                rest = rest[split..].trim_start();
+            }
+            _ => {
+                lines.push(rest[..end].to_owned());
+                rest = &rest[end..];
+            }
+        }
+    }
+}
+
+/// Parse a column list like "time,level,tag:20,msg". Names of profile fields
+/// are valid columns as well
+fn columns(s: &str, fields: &[String]) -> Result<Vec<Column>, Error> {
+    let columns = s
+        .split(',')
+        .map(|c| {
+            Column::from_str(c).or_else(|e| {
+                let mut split = c.trim().splitn(2, ':');
+                let name = split.next().unwrap_or_default();
+                match fields.iter().position(|f| f == name) {
+                    Some(index) => {
+                        let width = split
+                            .next()
+                            .map(|w| {
+                                w.parse::<usize>().map_err(|e| {
+                                    format_err!("Invalid width of column {}: {}", c, e)
+                                })
+                            })
+                            .transpose()?;
+                        Ok(Column::Field(index, width))
+                    }
+                    None => Err(e),
+                }
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    match columns.iter().position(|c| matches!(c, Column::Message(_))) {
+        Some(n) if n != columns.len() - 1 => Err(format_err!("Column msg must be the last column")),
+        _ => Ok(columns),
+    }
+}
+
 /// Human readable terminal output
 struct Human {
     writer: BufferWriter,
+    columns: Vec<Column>,
     date_format: Option<(&'static str, usize)>,
-    highlight: Vec<Regex>,
+    /// Custom strftime format of timestamps
+    timestamp_format: Option<String>,
+    time_zone: TimeZone,
+    buffer_width: usize,
+    device_width: usize,
+    footer: Option<Footer>,
+    /// Names of the profile fields and the widths of their columns
+    fields: Vec<(String, usize)>,
+    highlight: Vec<(Regex, Action)>,
+    levels: Levels,
+    name_width: usize,
     process_width: usize,
+    timestamp_width: usize,
     tag_width: Option<usize>,
     thread_width: usize,
     dimm_color: Option<Color>,
+    theme: Theme,
+    watch: Option<(ArgMatches<'static>, Watch, usize)>,
+    wrap: Wrap,
+    color_mode: ColorMode,
 }
 
 impl Human {
-    pub fn from<'a>(args: &ArgMatches<'a>, profile: &Profile, _: Format) -> Human {
+    pub fn from<'a>(args: &ArgMatches<'a>, profile: &Profile, _: Format) -> Result<Human, Error> {
         let mut hl = profile.highlight.clone();
         if args.is_present("highlight") {
             hl.extend(values_t!(args.values_of("highlight"), String).unwrap());
         }
-        let highlight = hl.iter().flat_map(|h| Regex::new(h)).collect();
+        hl.retain(|h| !profile.is_disabled("highlight", h));
+        let mut highlight = Vec::new();
+        for h in &hl {
+            let action = match profile.highlight_actions.get(h) {
+                Some(action) => action.parse()?,
+                None => Action::default(),
+            };
+            highlight.extend(Regex::new(h).map(|r| (r, action)));
+        }
 
-        let color = {
-            match args
-                .value_of("color")
-                .unwrap_or_else(|| config_get("terminal_color").unwrap_or_else(|| "auto"))
-            {
-                "always" => ColorChoice::Always,
-                "never" => ColorChoice::Never,
-                "auto" | _ => {
-                    if atty::is(atty::Stream::Stdout) {
-                        ColorChoice::Auto
-                    } else {
-                        ColorChoice::Never
-                    }
-                }
-            }
-        };
-        let no_dimm = args.is_present("no_dimm") || config_get("terminal_no_dimm").unwrap_or(false);
-        let tag_width = config_get("terminal_tag_width");
+        let color = color_choice(
+            args.value_of("color")
+                .unwrap_or_else(|| config_get("terminal_color").unwrap_or_else(|| "auto")),
+        );
+        let no_dimm = args.is_present("no_dimm")
+            || profile
+                .terminal_no_dimm
+                .or_else(|| config_get("terminal_no_dimm"))
+                .unwrap_or(false);
+        let tag_width = profile
+            .terminal_tag_width
+            .or_else(|| config_get("terminal_tag_width"));
+        let levels = Levels::new(
+            profile
+                .terminal_level_style
+                .clone()
+                .or_else(|| config_get("terminal_level_style"))
+                .as_deref(),
+            &profile.terminal_level_glyphs,
+            profile
+                .terminal_level_padding
+                .or_else(|| config_get("terminal_level_padding")),
+        )?;
         let hide_timestamp = args.is_present("hide_timestamp")
-            || config_get("terminal_hide_timestamp").unwrap_or(false);
-        let show_date =
-            args.is_present("show_date") || config_get("terminal_show_date").unwrap_or(false);
-        let date_format = if show_date {
+            || profile
+                .terminal_hide_timestamp
+                .or_else(|| config_get("terminal_hide_timestamp"))
+                .unwrap_or(false);
+        let show_date = args.is_present("show_date")
+            || profile
+                .terminal_show_date
+                .or_else(|| config_get("terminal_show_date"))
+                .unwrap_or(false);
+        let timestamp_format = args
+            .value_of("timestamp_format")
+            .map(ToOwned::to_owned)
+            .or_else(|| config_get("terminal_timestamp_format"));
+        if let Some(ref format) = timestamp_format {
+            format_timestamp(format, &time::now())?;
+        }
+        let time_zone = match args
+            .value_of("time_zone")
+            .map(ToOwned::to_owned)
+            .or_else(|| config_get("terminal_time_zone"))
+            .as_deref()
+        {
+            Some("local") => TimeZone::Local,
+            Some("utc") => TimeZone::Utc,
+            Some(zone) => return Err(format_err!("Invalid time zone {}", zone)),
+            None => TimeZone::Original,
+        };
+        let date_format = if timestamp_format.is_some() {
+            Some(TIME_FORMAT)
+        } else if show_date {
             if hide_timestamp {
                 Some(("%m-%d", 5))
             } else {
@@ -110,17 +688,115 @@ impl Human {
         } else if hide_timestamp {
             None
         } else {
-            Some(("%H:%M:%S.%f", 12))
+            Some(TIME_FORMAT)
+        };
+
+        let mut field_names = profile.fields.keys().cloned().collect::<Vec<_>>();
+        if args.value_of("host_timestamps") == Some("keep") {
+            field_names.push(hosttime::DEVICE_TIME.to_owned());
+        }
+        let mut columns = match args
+            .value_of("columns")
+            .map(ToOwned::to_owned)
+            .or_else(|| profile.terminal_columns.clone())
+            .or_else(|| config_get("terminal_columns"))
+        {
+            Some(c) => self::columns(&c, &field_names)?,
+            None => {
+                // Fields are shown in front of the level
+                let mut columns = vec![
+                    Column::Device(None),
+                    Column::Time,
+                    Column::Tag(None),
+                    Column::Name(None),
+                    Column::Pid(None),
+                    Column::Tid(None),
+                ];
+                columns.extend((0..field_names.len()).map(|i| Column::Field(i, None)));
+                columns.extend(vec![Column::Level, Column::Message(None)]);
+                columns
+            }
+        };
+        if date_format.is_none() {
+            columns.retain(|c| *c != Column::Time);
+        }
+
+        let wrap = if args.is_present("no_wrap") {
+            Wrap::None
+        } else {
+            match args
+                .value_of("wrap")
+                .map(ToOwned::to_owned)
+                .or_else(|| config_get("terminal_wrap"))
+            {
+                Some(wrap) => Wrap::from_str(&wrap)?,
+                None => Wrap::Chars,
+            }
+        };
+
+        let color_mode = match args
+            .value_of("color_mode")
+            .map(ToOwned::to_owned)
+            .or_else(|| config_get("terminal_color_mode"))
+        {
+            Some(mode) => ColorMode::from_str(&mode)?,
+            None => ColorMode::Level,
         };
 
-        Human {
+        let theme = match args.value_of("theme").or_else(|| profile.theme.as_deref()) {
+            Some(theme) => Theme::from_file(Path::new(theme))?,
+            None => Theme::default(),
+        };
+
+        Ok(Human {
             writer: BufferWriter::stdout(color),
+            columns,
             dimm_color: if no_dimm { None } else { Some(DIMM_COLOR) },
             highlight,
+            levels,
             date_format,
+            timestamp_format,
+            time_zone,
+            buffer_width: 0,
+            device_width: 0,
+            footer: None,
+            fields: field_names.into_iter().map(|n| (n, 0)).collect(),
             tag_width,
+            name_width: 0,
             process_width: 0,
+            timestamp_width: 0,
             thread_width: 0,
+            theme,
+            watch: None,
+            wrap,
+            color_mode,
+        })
+    }
+
+    /// Apply the highlights and terminal settings of a modified profile
+    fn reload(&mut self) {
+        let profile = match self.watch {
+            Some((_, ref watch, ref mut generation)) => watch.changed(generation),
+            None => None,
+        };
+        if let (Some(profile), Some((args, _, _))) = (profile, &self.watch) {
+            match Human::from(args, &profile, Format::Human) {
+                Ok(mut human) => {
+                    swap(&mut self.columns, &mut human.columns);
+                    swap(&mut self.fields, &mut human.fields);
+                    swap(&mut self.date_format, &mut human.date_format);
+                    swap(&mut self.timestamp_format, &mut human.timestamp_format);
+                    swap(&mut self.time_zone, &mut human.time_zone);
+                    swap(&mut self.dimm_color, &mut human.dimm_color);
+                    swap(&mut self.highlight, &mut human.highlight);
+                    swap(&mut self.levels, &mut human.levels);
+                    swap(&mut self.tag_width, &mut human.tag_width);
+                    swap(&mut self.theme, &mut human.theme);
+                    swap(&mut self.wrap, &mut human.wrap);
+                    swap(&mut self.color_mode, &mut human.color_mode);
+                }
+                Err(e) => eprintln!("Failed to reload terminal settings: {}", e),
+            }
         }
     }
 
@@ -136,141 +812,286 @@ impl Human {
         })
     }
 
-    #[cfg(target_os = "windows")]
-    fn hashed_color(i: &str) -> Color {
-        let v = i.bytes().fold(42u8, |c, x| c ^ x) % 7;
-        match v {
-            0 => Color::Blue,
-            1 => Color::Green,
-            2 => Color::Red,
-            3 => Color::Cyan,
-            4 => Color::Magenta,
-            5 => Color::Yellow,
-            _ => Color::White,
+    /// Colors for record according to the color mode and theme
+    fn style(&self, record: &Record) -> Style {
+        let level_color = self.theme.level(&record.level).or(match record.level {
+            Level::Info => Some(Color::Green),
+            Level::Warn => Some(Color::Yellow),
+            Level::Error | Level::Fatal | Level::Assert => Some(Color::Red),
+            _ => self.dimm_color,
+        });
+        // Set fg only if bg is set
+        let badge = (level_color.map(|_| Color::Black), level_color);
+        let message = self.theme.message(record);
+        match self.color_mode {
+            ColorMode::Off => (),
+            _ if record.context || vitals::is_vitals(record) => {
+                return Style {
+                    level: (Some(DIMM_COLOR), None),
+                    message: Some(DIMM_COLOR),
+                    line: Some(Some(DIMM_COLOR)),
+                }
+            }
+            _ => (),
+        }
+        match self.color_mode {
+            ColorMode::Level => Style {
+                level: badge,
+                message: message.or(level_color),
+                line: None,
+            },
+            ColorMode::Line => Style {
+                level: badge,
+                message: message.or(level_color),
+                line: Some(level_color),
+            },
+            ColorMode::Tag => {
+                let tag_color = self
+                    .theme
+                    .tag(&record.tag)
+                    .unwrap_or_else(|| hashed_color(&record.tag));
+                Style {
+                    level: (Some(tag_color), None),
+                    message: message.or(Some(tag_color)),
+                    line: None,
+                }
+            }
+            ColorMode::Off => Style {
+                level: (None, None),
+                message: None,
+                line: Some(None),
+            },
         }
-    }
-
-    #[cfg(not(target_os = "windows"))]
-    fn hashed_color(i: &str) -> Color {
-        // Some colors are hard to read on (at least) dark terminals
-        // and I consider some others as ugly.
-        Color::Ansi256(match i.bytes().fold(42u8, |c, x| c ^ x) {
-            c @ 0..=1 => c + 2,
-            c @ 16..=21 => c + 6,
-            c @ 52..=55 | c @ 126..=129 => c + 4,
-            c @ 163..=165 | c @ 200..=201 => c + 3,
-            c @ 207 => c + 1,
-            c @ 232..=240 => c + 9,
-            c => c,
-        })
     }
 
     fn print(&mut self, record: &Record) -> Result<(), Error> {
-        let timestamp = if let Some((format, len)) = self.date_format {
-            if let Some(ref ts) = record.timestamp {
-                let mut ts = time::strftime(format, &ts).expect("Date format error");
-                ts.truncate(len);
-                ts
-            } else {
-                " ".repeat(len)
-            }
-        } else {
-            String::new()
-        };
+        self.reload();
 
-        let tag_width = self.tag_width();
-        let tag_chars = record.tag.chars().count();
-        let tag = format!(
-            "{:>width$}",
-            record
-                .tag
-                .chars()
-                .take(min(tag_width, tag_chars))
-                .collect::<String>(),
-            width = tag_width
-        );
+        if marker::is_marker(record) {
+            let mut buffer = self.writer.buffer();
+            buffer.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Cyan)))?;
+            buffer.write_all(marker::line(record, terminal_width().unwrap_or(80)).as_bytes())?;
+            buffer.reset()?;
+            buffer.write_all(b"\n")?;
+            return self.writer.print(&buffer).map_err(Into::into);
+        }
 
-        self.process_width = max(self.process_width, record.process.chars().count());
-        let pid = if record.process.is_empty() {
-            " ".repeat(self.process_width)
-        } else {
-            format!("{:<width$}", record.process, width = self.process_width)
-        };
-        self.thread_width = max(self.thread_width, record.thread.chars().count());
-        let tid = if !record.thread.is_empty() {
-            format!(" {:>width$}", record.thread, width = self.thread_width)
-        } else if self.thread_width != 0 {
-            " ".repeat(self.thread_width + 1)
-        } else {
-            String::new()
-        };
+        let mut highlight: Option<Action> = None;
+        for (regex, action) in &self.highlight {
+            if regex.is_match(&record.tag) || regex.is_match(&record.message) {
+                highlight.get_or_insert_with(Action::default).merge(action);
+            }
+        }
+        let style = self.style(record);
+        let message_color = style.message;
+
+        let mut cells: Vec<Cell> = vec![];
+        let mut message = None;
+        let mut columns = self.columns.iter().cloned().peekable();
+        while let Some(column) = columns.next() {
+            match column {
+                Column::Buffer(width) => {
+                    let buffer = record.buffer.as_deref().unwrap_or("");
+                    self.buffer_width = max(self.buffer_width, buffer.width());
+                    let width = max(self.buffer_width, width.unwrap_or(0));
+                    let buffer = pad(buffer, width);
+                    cells.push(vec![(buffer, self.dimm_color, None)]);
+                }
+                Column::Device(width) => {
+                    // The device column is only present if records with device are received
+                    let device = record.device.as_deref().unwrap_or("");
+                    self.device_width = max(self.device_width, device.width());
+                    let width = max(self.device_width, width.unwrap_or(0));
+                    if width != 0 {
+                        let device = pad(device, width);
+                        let color = hashed_color(&device);
+                        cells.push(vec![(device, Some(color), None)]);
+                    }
+                }
+                Column::Time => {
+                    let (format, len) = self.date_format.unwrap_or(TIME_FORMAT);
+                    let tm = record
+                        .timestamp
+                        .as_ref()
+                        .map(|ts| self.time_zone.convert(ts));
+                    let timestamp = if let Some(offset) = record.relative {
+                        let ts = relative::format(offset);
+                        self.timestamp_width = max(self.timestamp_width, ts.width());
+                        pad(&ts, self.timestamp_width)
+                    } else {
+                        match (&self.timestamp_format, tm) {
+                            (Some(custom), Some(tm)) => {
+                                let ts = format_timestamp(custom, &tm)?;
+                                self.timestamp_width = max(self.timestamp_width, ts.width());
+                                pad(&ts, self.timestamp_width)
+                            }
+                            (Some(_), None) => " ".repeat(self.timestamp_width),
+                            (None, Some(tm)) => {
+                                let mut ts =
+                                    time::strftime(format, &tm).expect("Date format error");
+                                ts.truncate(len);
+                                ts
+                            }
+                            (None, None) => " ".repeat(len),
+                        }
+                    };
+                    let color = if highlight.is_some() {
+                        Some(Color::Yellow)
+                    } else {
+                        self.dimm_color
+                    };
+                    cells.push(vec![(timestamp, color, None)]);
+                }
+                Column::Tag(width) => {
+                    let width = width.unwrap_or_else(|| self.tag_width());
+                    let tag = pad_start(take_width(&record.tag, width), width);
+                    let color = self
+                        .theme
+                        .tag(&record.tag)
+                        .unwrap_or_else(|| hashed_color(&record.tag));
+                    cells.push(vec![(tag, Some(color), None)]);
+                }
+                Column::Name(width) => {
+                    // The name column is only present if process names are resolved
+                    let name = record.process_name.as_deref().unwrap_or("");
+                    self.name_width = max(self.name_width, name.width());
+                    let width = max(self.name_width, width.unwrap_or(0));
+                    if width != 0 {
+                        let name = pad_start(name, width);
+                        let color = hashed_color(&name);
+                        cells.push(vec![(name, Some(color), None)]);
+                    }
+                }
+                Column::Pid(_) | Column::Tid(_) => {
+                    // Adjacent pid and tid columns share the parentheses
+                    let mut group = vec![column];
+                    match (column, columns.peek()) {
+                        (Column::Pid(_), Some(Column::Tid(_)))
+                        | (Column::Tid(_), Some(Column::Pid(_))) => {
+                            group.extend(columns.next());
+                        }
+                        _ => (),
+                    }
+                    let mut cell = vec![("(".to_owned(), None, None)];
+                    for (i, column) in group.iter().enumerate() {
+                        let text = match column {
+                            Column::Pid(width) => {
+                                self.process_width =
+                                    max(self.process_width, record.process.width());
+                                let width = max(self.process_width, width.unwrap_or(0));
+                                pad(&record.process, width)
+                            }
+                            Column::Tid(width) => {
+                                self.thread_width = max(self.thread_width, record.thread.width());
+                                let width = max(self.thread_width, width.unwrap_or(0));
+                                pad_start(&record.thread, width)
+                            }
+                            _ => unreachable!(),
+                        };
+                        // Empty tids without width leave "()" for pid and tid
+                        let text = if i == 0 || text.is_empty() {
+                            text
+                        } else {
+                            format!(" {}", text)
+                        };
+                        let color = hashed_color(&text);
+                        cell.push((text, Some(color), None));
+                    }
+                    cell.push((")".to_owned(), None, None));
+                    cells.push(cell);
+                }
+                Column::Level => {
+                    let (fg, bg) = style.level;
+                    cells.push(vec![(self.levels.glyph(&record.level), fg, bg)]);
+                }
+                Column::Message(width) => message = Some(width),
+                Column::Field(index, width) => {
+                    let (ref name, ref mut field_width) = self.fields[index];
+                    let value = record.fields.get(name).map(String::as_str).unwrap_or("");
+                    *field_width = max(*field_width, value.width());
+                    let width = max(*field_width, width.unwrap_or(0));
+                    let value = pad(value, width);
+                    let color = hashed_color(&value);
+                    cells.push(vec![(value, Some(color), None)]);
+                }
+            }
+        }
 
-        let highlight = !self.highlight.is_empty()
-            && (self.highlight.iter().any(|r| r.is_match(&record.tag))
-                || self.highlight.iter().any(|r| r.is_match(&record.message)));
+        if let Some(color) = style.line {
+            for (_, fg, bg) in cells.iter_mut().flatten() {
+                if bg.is_none() {
+                    *fg = color;
+                }
+            }
+        }
 
-        let preamble_width = timestamp.chars().count()
-            + 1 // " "
-            + tag.chars().count()
-            + 2 // " ("
-            + pid.chars().count() + tid.chars().count()
-            + 2 // ") "
-            + 3; // level
+        let action = highlight.unwrap_or_default();
 
-        let timestamp_color = if highlight {
-            Some(Color::Yellow)
-        } else {
-            self.dimm_color
-        };
-        let tag_color = Self::hashed_color(&record.tag);
-        let pid_color = Self::hashed_color(&pid);
-        let tid_color = Self::hashed_color(&tid);
-        let level_color = match record.level {
-            Level::Info => Some(Color::Green),
-            Level::Warn => Some(Color::Yellow),
-            Level::Error | Level::Fatal | Level::Assert => Some(Color::Red),
-            _ => self.dimm_color,
-        };
+        // Cells are separated by a space
+        let preamble_width = cells
+            .iter()
+            .map(|c| c.iter().map(|(t, _, _)| t.width()).sum::<usize>() + 1)
+            .sum::<usize>()
+            .saturating_sub(1);
 
         let write_preamble = |buffer: &mut Buffer| -> Result<(), Error> {
-            let mut spec = ColorSpec::new();
-            buffer.set_color(spec.set_fg(timestamp_color))?;
-            buffer.write_all(timestamp.as_bytes())?;
-            buffer.write_all(b" ")?;
-
-            buffer.set_color(spec.set_fg(Some(tag_color)))?;
-            buffer.write_all(tag.as_bytes())?;
-            buffer.set_color(spec.set_fg(None))?;
-
-            buffer.write_all(b" (")?;
-            buffer.set_color(spec.set_fg(Some(pid_color)))?;
-            buffer.write_all(pid.as_bytes())?;
-            if !tid.is_empty() {
-                buffer.set_color(spec.set_fg(Some(tid_color)))?;
-                buffer.write_all(tid.as_bytes())?;
+            for (i, cell) in cells.iter().enumerate() {
+                if i != 0 {
+                    buffer.set_color(&action.spec(None, None))?;
+                    buffer.write_all(b" ")?;
+                }
+                for (text, fg, bg) in cell {
+                    buffer.set_color(&action.spec(*fg, *bg))?;
+                    buffer.write_all(text.as_bytes())?;
+                }
+                buffer.set_color(&ColorSpec::new())?;
             }
-            buffer.set_color(spec.set_fg(None))?;
-            buffer.write_all(b") ")?;
-
-            buffer.set_color(
-                spec.set_bg(level_color)
-                    .set_fg(level_color.map(|_| Color::Black)), // Set fg only if bg is set
-            )?;
-            write!(buffer, " {} ", record.level)?;
-            buffer.set_color(&ColorSpec::new())?;
-
             Ok(())
         };
 
-        let payload_len = terminal_width().unwrap_or(std::usize::MAX) - preamble_width - 3;
-        let message = &record.message;
-        let message_len = message.chars().count();
-        let chunks = message_len / payload_len + 1;
-
         let mut buffer = self.writer.buffer();
+        // The bell is not written to pipes
+        if action.bell && buffer.supports_color() {
+            buffer.write_all(b"\x07")?;
+        }
+
+        let payload_len = match message {
+            Some(Some(width)) => max(width, 1),
+            Some(None) => max(
+                terminal_width()
+                    .unwrap_or(usize::MAX)
+                    .saturating_sub(preamble_width + 3),
+                1,
+            ),
+            None => {
+                write_preamble(&mut buffer)?;
+                buffer.write_all(b"\n")?;
+                return self.writer.print(&buffer).map_err(Into::into);
+            }
+        };
+        // Lines of joined records are wrapped one by one
+        let wrap = |message: &str| -> Vec<String> {
+            match self.wrap {
+                Wrap::Chars => chunks(message, payload_len),
+                Wrap::Word => word_wrap(message, payload_len),
+                Wrap::None if message.width() > payload_len => vec![truncate(message, payload_len)],
+                Wrap::None => vec![message.to_owned()],
+            }
+        };
+        let lines = record
+            .message
+            .split('\n')
+            .flat_map(wrap)
+            .collect::<Vec<_>>();
+        let chunks = lines.len();
 
-        for i in 0..chunks {
-            write_preamble(&mut buffer)?;
+        for (i, line) in lines.iter().enumerate() {
+            // Continuation lines of word wrapped messages are indented
+            if i == 0 || self.wrap != Wrap::Word {
+                write_preamble(&mut buffer)?;
+            } else {
+                buffer.write_all(" ".repeat(preamble_width).as_bytes())?;
+            }
 
             let c = if chunks == 1 {
                 "   "
@@ -282,15 +1103,16 @@ impl Human {
                 " ├ "
             };
 
+            buffer.set_color(&action.spec(None, None))?;
             buffer.write_all(c.as_bytes())?;
-
-            let chunk = message
-                .chars()
-                .skip(i * payload_len)
-                .take(payload_len)
-                .collect::<String>();
-            buffer.set_color(ColorSpec::new().set_fg(level_color))?;
-            buffer.write_all(chunk.as_bytes())?;
+            buffer.set_color(
+                action
+                    .spec(action.fg.or(message_color), action.bg)
+                    .set_bold(action.bold)
+                    .set_underline(action.underline),
+            )?;
+            buffer.write_all(line.as_bytes())?;
+            buffer.reset()?;
             buffer.write_all(b"\n")?;
         }
 
@@ -307,12 +1129,15 @@ impl Drop for Human {
 
 struct FormatSink<T: Write> {
     format: Format,
+    /// Header of the format that is written before the first record
+    header: Option<&'static str>,
     sink: BufWriter<T>,
 }
 
 impl<T: Write> FormatSink<T> {
     fn new(format: Format, sink: T) -> FormatSink<T> {
         FormatSink {
+            header: format.header(),
             format,
             sink: BufWriter::new(sink),
         }
@@ -324,15 +1149,85 @@ impl<T: Write> Sink for FormatSink<T> {
     type SinkError = Error;
 
     fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        self.sink
-            .write_all(self.format.fmt_record(&record)?.as_bytes())?;
-        self.sink.write_all(&[b'\n'])?;
+        if let Some(header) = self.header.take() {
+            writeln!(self.sink, "{}", header)?;
+        }
+        self.sink.write_all(&self.format.encode_record(&record)?)?;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.sink.flush()?;
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Html page on stdout
+struct HtmlSink<T: Write> {
+    html: Html<BufWriter<T>>,
+    index: usize,
+}
+
+impl<T: Write> HtmlSink<T> {
+    fn new(sink: T) -> Result<HtmlSink<T>, Error> {
+        Ok(HtmlSink {
+            html: Html::new(BufWriter::new(sink))?,
+            index: 0,
+        })
+    }
+}
+
+impl<T: Write> Sink for HtmlSink<T> {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        self.html.write(&record, self.index)?;
+        self.index += 1;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.html.finish().map(Async::Ready)
+    }
+}
+
+/// Chrome trace on stdout
+struct TraceSink<T: Write> {
+    trace: Trace<BufWriter<T>>,
+    index: usize,
+}
+
+impl<T: Write> TraceSink<T> {
+    fn new(sink: T) -> Result<TraceSink<T>, Error> {
+        Ok(TraceSink {
+            trace: Trace::new(BufWriter::new(sink))?,
+            index: 0,
+        })
+    }
+}
+
+impl<T: Write> Sink for TraceSink<T> {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        self.trace.write(&record, self.index)?;
+        self.index += 1;
         Ok(AsyncSink::Ready)
     }
 
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
         Ok(Async::Ready(()))
     }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.trace.finish().map(Async::Ready)
+    }
 }
 
 impl Sink for Human {
@@ -340,10 +1235,154 @@ impl Sink for Human {
     type SinkError = Error;
 
     fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        self.print(&record).map(|_| AsyncSink::Ready)
+        match self.footer.clone() {
+            Some(footer) => footer.print(&record, || self.print(&record)),
+            None => self.print(&record),
+        }
+        .map(|_| AsyncSink::Ready)
     }
 
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
         Ok(Async::Ready(()))
     }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        // Keep the last footer above the prompt
+        if let Some(footer) = self.footer.take() {
+            footer.finish()?;
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+#[test]
+fn parse_columns() {
+    assert_eq!(
+        columns("time,level,tag:20,msg", &[]).unwrap(),
+        vec![
+            Column::Time,
+            Column::Level,
+            Column::Tag(Some(20)),
+            Column::Message(None)
+        ]
+    );
+    assert_eq!(
+        columns("pid:5, tid", &[]).unwrap(),
+        vec![Column::Pid(Some(5)), Column::Tid(None)]
+    );
+    assert!(columns("msg,tag", &[]).is_err());
+    assert!(columns("time:5", &[]).is_err());
+    assert!(columns("tag:x", &[]).is_err());
+    assert!(columns("foo", &[]).is_err());
+    assert!(columns("", &[]).is_err());
+
+    let fields = vec!["request".to_owned()];
+    assert_eq!(
+        columns("time,request:8,msg", &fields).unwrap(),
+        vec![
+            Column::Time,
+            Column::Field(0, Some(8)),
+            Column::Message(None)
+        ]
+    );
+    assert!(columns("request:x", &fields).is_err());
+    assert!(columns("response", &fields).is_err());
+}
+
+#[test]
+fn timestamp_formats() {
+    let tm = time::at_utc(time::Timespec::new(1_490_469_079, 52_000_000));
+    assert_eq!(
+        format_timestamp("%Y-%m-%d %H:%M:%S.%f %z", &tm).unwrap(),
+        "2017-03-25 19:11:19.052 +0000"
+    );
+
+    let mut zoned = tm;
+    zoned.tm_hour += 1;
+    zoned.tm_utcoff = 3600;
+    assert_eq!(
+        TimeZone::Utc.convert(&zoned).to_timespec(),
+        tm.to_timespec()
+    );
+    assert_eq!(TimeZone::Utc.convert(&zoned).tm_hour, 19);
+    assert_eq!(TimeZone::Original.convert(&zoned).tm_hour, 20);
+}
+
+#[test]
+fn parse_color_mode() {
+    assert_eq!(ColorMode::from_str("line").unwrap(), ColorMode::Line);
+    assert_eq!(ColorMode::from_str("off").unwrap(), ColorMode::Off);
+    assert!(ColorMode::from_str("rainbow").is_err());
+}
+
+#[test]
+fn wrap_words() {
+    assert_eq!(word_wrap("", 5), vec![""]);
+    assert_eq!(word_wrap("hello", 5), vec!["hello"]);
+    assert_eq!(
+        word_wrap("hello world foo", 8),
+        vec!["hello", "world", "foo"]
+    );
+    assert_eq!(word_wrap("a bb ccc dddd", 6), vec!["a bb", "ccc", "dddd"]);
+    assert_eq!(word_wrap("abcdefgh ij", 4), vec!["abcd", "efgh", "ij"]);
+    assert_eq!(word_wrap("ab  ", 2), vec!["ab"]);
+}
+
+#[test]
+fn wrap_wide_chars() {
+    assert_eq!(chunks("", 4), vec![""]);
+    assert_eq!(chunks("abcdef", 4), vec!["abcd", "ef"]);
+    assert_eq!(chunks("日本語です", 4), vec!["日本", "語で", "す"]);
+    assert_eq!(chunks("a日本", 2), vec!["a", "日", "本"]);
+    // Characters wider than the line get a own line
+    assert_eq!(chunks("日本", 1), vec!["日", "本"]);
+    assert_eq!(truncate("日本語です", 6), "日本…");
+    assert_eq!(truncate("abcdef", 4), "abc…");
+    assert_eq!(word_wrap("日本 語です", 4), vec!["日本", "語で", "す"]);
+    assert_eq!(pad("日本", 6), "日本  ");
+    assert_eq!(pad_start("日本", 6), "  日本");
+    assert_eq!(take_width("日本語", 5), "日本");
+}
+
+#[test]
+fn level_glyphs() {
+    let levels = Levels::new(None, &HashMap::new(), None).unwrap();
+    assert_eq!(levels.glyph(&Level::Warn), " W ");
+    assert_eq!(levels.glyph(&Level::None), " - ");
+
+    let levels = Levels::new(Some("word"), &HashMap::new(), Some(0)).unwrap();
+    assert_eq!(levels.glyph(&Level::Info), "INFO   ");
+    assert_eq!(levels.glyph(&Level::Verbose), "VERBOSE");
+
+    let levels = Levels::new(Some("emoji"), &HashMap::new(), None).unwrap();
+    assert!(levels.glyphs.iter().all(|(_, g)| g.width() == 2));
+
+    let overrides = vec![("E".to_owned(), "ERR".to_owned())]
+        .into_iter()
+        .collect();
+    let levels = Levels::new(None, &overrides, None).unwrap();
+    assert_eq!(levels.glyph(&Level::Error), " ERR ");
+    assert_eq!(levels.glyph(&Level::Warn), " W   ");
+
+    assert!(Levels::new(Some("fancy"), &HashMap::new(), None).is_err());
+    let overrides = vec![("X".to_owned(), "?".to_owned())].into_iter().collect();
+    assert!(Levels::new(None, &overrides, None).is_err());
+}
+
+#[test]
+fn highlight_actions() {
+    assert_eq!("".parse::<Action>().unwrap(), Action::default());
+    let action = "fg:red, bg:#0000ff,underline,bell"
+        .parse::<Action>()
+        .unwrap();
+    assert_eq!(action.fg, Some(Color::Red));
+    assert_eq!(action.bg, Some(Color::Rgb(0, 0, 255)));
+    assert!(action.underline && action.bell && !action.invert && !action.bold);
+    assert!("fg:nocolor".parse::<Action>().is_err());
+    assert!("blink".parse::<Action>().is_err());
+
+    let mut merged = "fg:red".parse::<Action>().unwrap();
+    merged.merge(&"fg:blue,invert".parse().unwrap());
+    assert_eq!(merged.fg, Some(Color::Red));
+    assert!(merged.invert);
 }
@@ -0,0 +1,672 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::tests::utils::*;
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    os::unix::fs::PermissionsExt,
+    thread,
+    time::{Duration, Instant},
+};
+
+const DEVICES: &str = r#"
+case "$1" in
+    devices)
+        echo "List of devices attached"
+        echo "A1	device usb:1-1 product:flame model:Pixel_4 device:flame"
+        echo "B2	device product:sdk model:Android_SDK_built_for_x86"
+        echo "C3	offline"
+        ;;
+    -s)
+        echo "03-01 02:19:45.207     1     2 I Tag: hello from $2"
+        ;;
+esac
+"#;
+
+#[test]
+fn all_devices() {
+    let args = svec!("--all-devices", "-d", "-f", "json");
+    let output = run_rogcat_with_adb(args, DEVICES).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert!(output
+        .1
+        .iter()
+        .any(|l| l.contains("\"device\":\"A1\"") && l.contains("hello from A1")));
+    assert!(output
+        .1
+        .iter()
+        .any(|l| l.contains("\"device\":\"B2\"") && l.contains("hello from B2")));
+}
+
+#[test]
+fn device_model() {
+    let args = svec!("--device-model", "^Pixel", "-d", "-f", "raw");
+    let output = run_rogcat_with_adb(args, DEVICES).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        output.1,
+        svec!("03-01 02:19:45.207     1     2 I Tag: hello from A1")
+    );
+
+    let args = svec!("--device-model", "Nexus", "-d");
+    let output = run_rogcat_with_adb(args, DEVICES).unwrap();
+    assert!(!output.0);
+
+    // Multiple devices without a terminal for the picker
+    let output = run_rogcat_with_adb(svec!("-d"), DEVICES).unwrap();
+    assert!(!output.0);
+
+    let args = svec!("--serial", "B2", "-d", "-f", "raw");
+    let output = run_rogcat_with_adb(args, DEVICES).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        output.1,
+        svec!("03-01 02:19:45.207     1     2 I Tag: hello from B2")
+    );
+}
+
+#[test]
+fn binary() {
+    // logger_entry v1 with pid 123 and tid 456 followed by the payload
+    let script = r#"printf '\011\000\000\000\173\000\000\000\310\001\000\000\000\000\000\000\000\000\000\000\004Tag\000Msg\000'"#;
+    let args = svec!("--binary", "-d", "-f", "csv");
+    let output = run_rogcat_with_adb(args, script).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert_eq!(output.1[0], rogcat::record::CSV_HEADER);
+    assert!(output.1[1].contains(",Msg,Info,Tag,123,456,"));
+}
+
+const PIDOF: &str = r#"
+case "$1" in
+    shell)
+        echo "42"
+        ;;
+    logcat)
+        echo "03-01 02:19:45.207    42    42 I Tag: app"
+        echo "03-01 02:19:45.208    43    43 I Tag: other"
+        echo "03-01 02:19:45.209  1000  1001 I ActivityManager: Start proc 44:com.example.app/u0a12 for activity"
+        echo "03-01 02:19:45.210    44    44 I Tag: restarted app"
+        ;;
+esac
+"#;
+
+#[test]
+fn adb_path() {
+    let script = r#"
+[ "$1 $2" = "-P 5038" ] || exit 1
+shift 2
+case "$1" in
+    exec-out)
+        [ "$2" = "echo" ] && echo "$3" && exit 0
+        echo "03-01 02:19:45.207     1     2 I Tag: exec-out"
+        ;;
+    logcat)
+        echo "03-01 02:19:45.207     1     2 I Tag: shell"
+        ;;
+esac
+"#;
+    let adb = fake_adb(script).unwrap().join("adb").display().to_string();
+    let args = svec!(
+        "--adb-path",
+        adb,
+        "--adb-args",
+        "-P 5038",
+        "-d",
+        "-f",
+        "raw"
+    );
+    let output = run_rogcat(args, None).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        output.1,
+        svec!("03-01 02:19:45.207     1     2 I Tag: exec-out")
+    );
+}
+
+#[test]
+fn native_adb() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let request = |stream: &mut TcpStream| {
+            let mut len = [0u8; 4];
+            stream.read_exact(&mut len).unwrap();
+            let len = usize::from_str_radix(std::str::from_utf8(&len).unwrap(), 16).unwrap();
+            let mut request = vec![0u8; len];
+            stream.read_exact(&mut request).unwrap();
+            String::from_utf8(request).unwrap()
+        };
+        let transport = request(&mut stream);
+        stream.write_all(b"OKAY").unwrap();
+        let service = request(&mut stream);
+        stream
+            .write_all(b"OKAY03-01 02:19:45.207     1     2 I Tag: native\n")
+            .unwrap();
+        (transport, service)
+    });
+
+    let native = format!("--native-adb={}", addr);
+    let args = svec!(native, "--serial", "A1", "-d", "-f", "raw");
+    let output = run_rogcat_with_adb(args, "exit 1").unwrap();
+    let (transport, service) = server.join().unwrap();
+    assert_eq!(transport, "host:transport:A1");
+    assert!(service.starts_with("exec:logcat -d -b"));
+    assert!(output.0);
+    assert_eq!(
+        output.1,
+        svec!("03-01 02:19:45.207     1     2 I Tag: native")
+    );
+}
+
+#[test]
+fn props() {
+    let script = r#"
+[ "$1 $2" = "shell getprop" ] || exit 1
+echo "[ro.product.model]: [Pixel 4]"
+echo "[sys.boot_completed]: [1]"
+"#;
+    let args = svec!("-f", "raw", "props");
+    let output = run_rogcat_with_adb(args, script).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert!(output.1[0].ends_with("I getprop: [ro.product.model]: [Pixel 4]"));
+    assert!(output.1[1].ends_with("I getprop: [sys.boot_completed]: [1]"));
+}
+
+#[test]
+fn props_watch() {
+    let script = r#"
+case "$1" in
+    shell)
+        n=$(cat "$(dirname "$0")/polls" 2>/dev/null || echo 0)
+        echo $((n + 1)) > "$(dirname "$0")/polls"
+        echo "[ro.product.model]: [Pixel 4]"
+        echo "[sys.boot_completed]: [$n]"
+        ;;
+    logcat)
+        sleep 5
+        ;;
+esac
+"#;
+    let args = svec!(
+        "-f",
+        "raw",
+        "--head",
+        "1",
+        "props",
+        "--watch",
+        "--interval",
+        "100ms"
+    );
+    let output = run_rogcat_with_adb(args, script).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 1);
+    assert!(output.1[0].ends_with("I getprop: [sys.boot_completed]: [1] (was [0])"));
+}
+
+const VITALS: &str = r#"
+case "$1" in
+    shell)
+        echo "  level: 87"
+        echo "  scale: 100"
+        echo "  temperature: 285"
+        echo "---"
+        echo "1804800"
+        ;;
+    logcat)
+        sleep 1
+        echo "03-01 02:19:45.207     1     2 I Tag: hello"
+        ;;
+esac
+"#;
+
+#[test]
+fn vitals() {
+    let args = svec!("--vitals=100ms", "-t", "Tag", "-f", "raw", "--head", "1");
+    let output = run_rogcat_with_adb(args, VITALS).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 1);
+    assert!(output.1[0].ends_with("V RogcatVitals: battery 87% 28.5°C cpu 1804 MHz"));
+}
+
+#[test]
+fn vitals_csv() {
+    let csv = tempdir().unwrap().join("vitals.csv");
+    let args = svec!(
+        "--vitals=100ms",
+        "--vitals-csv",
+        csv.display().to_string(),
+        "-d",
+        "-f",
+        "raw"
+    );
+    let output = run_rogcat_with_adb(args, VITALS).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        output.1,
+        svec!("03-01 02:19:45.207     1     2 I Tag: hello")
+    );
+    let content = file_content(&csv).unwrap();
+    assert_eq!(content[0], "time,battery,temperature,cpu_mhz");
+    assert!(content.len() > 1);
+    assert!(content[1].ends_with(",87,28.5,1804"));
+}
+
+#[test]
+fn with_cpu() {
+    let script = r#"
+case "$1" in
+    shell)
+        echo "  PID USER         PR  NI VIRT  RES  SHR S[%CPU] %MEM     TIME+ ARGS"
+        echo " 1234 u0_a42       10 -10 4.5G 200M 100M S 12.3   2.7   1:23.45 com.example.app"
+        ;;
+    logcat)
+        echo "03-01 02:19:45.207  1234  1234 I Tag: busy"
+        echo "03-01 02:19:45.208    42    42 I Tag: idle"
+        ;;
+esac
+"#;
+    let args = svec!("--with-cpu", "-d", "-f", "csv");
+    let output = run_rogcat_with_adb(args, script).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 3);
+    let fields = |line: &str| line.matches(',').count();
+    assert_eq!(fields(&output.1[0]), fields(&output.1[1]));
+    assert_eq!(fields(&output.1[1]), fields(&output.1[2]));
+    assert!(output.1[1].ends_with(",12.3"));
+    assert!(output.1[2].ends_with("idle,,,,,"));
+}
+
+#[test]
+fn correct_clock() {
+    let script = r#"
+case "$1" in
+    logcat)
+        echo "01-02 03:04:05.678  123  456 W Tag: hello"
+        echo "01-02 03:04:06.678  123  456 W Tag: world"
+        ;;
+esac
+"#;
+    let output = run_rogcat_with_adb(svec!("--correct-clock", "-d", "-f", "raw"), script).unwrap();
+    assert!(output.0);
+    assert!(!output.1[0].starts_with("01-02 03:04:05.678"));
+    assert!(output.1[0].ends_with("  123  456 W Tag: hello"));
+    assert!(!output.1[1].starts_with("01-02 03:04:06.678"));
+
+    let args = svec!("--correct-clock", "--host-timestamps");
+    let output = run_rogcat_with_adb(args, script).unwrap();
+    assert!(!output.0);
+}
+
+/// Record the arguments of adb in the file setprop next to the script
+const SETPROP: &str = r#"
+case "$1" in
+    shell)
+        echo "$@" >> "$(dirname "$0")/setprop"
+        ;;
+    logcat)
+        echo "03-01 02:19:45.207     1     2 I Tag: hello"
+        ;;
+esac
+"#;
+
+#[test]
+fn log_level() {
+    let dir = fake_adb(SETPROP).unwrap();
+    let path = format!("{}:{}", dir.display(), env::var("PATH").unwrap_or_default());
+    let env = [("PATH", path.as_str())];
+    let args = svec!("log-level", "MyTag", "d");
+    assert!(run_rogcat_with_env(args, None, &env).unwrap().0);
+    let args = svec!("log-level", "MyTag", "loud");
+    assert!(!run_rogcat_with_env(args, None, &env).unwrap().0);
+
+    // Levels of the profile are set before capturing
+    let profiles = tempfile_with_content(svec!(
+        "[profile.verbose]",
+        "log_levels = { MyTag = \"verbose\", Other = \"default\" }"
+    ))
+    .unwrap()
+    .display()
+    .to_string();
+    let args = svec!(
+        "--profiles-path",
+        profiles,
+        "-p",
+        "verbose",
+        "-d",
+        "-f",
+        "raw"
+    );
+    let output = run_rogcat_with_env(args, None, &env).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        output.1,
+        svec!("03-01 02:19:45.207     1     2 I Tag: hello")
+    );
+
+    assert_eq!(
+        file_content(&dir.join("setprop")).unwrap(),
+        svec!(
+            "shell setprop log.tag.MyTag DEBUG",
+            "shell setprop log.tag.MyTag VERBOSE",
+            "shell setprop log.tag.Other \"\""
+        )
+    );
+}
+
+/// Record the commands run by the device shell in the file shell
+const SHELL: &str = r#"
+[ "$1" = "shell" ] || exit 0
+if [ -n "$2" ]; then
+    echo "$2" >> "$(dirname "$0")/shell"
+else
+    cat >> "$(dirname "$0")/shell"
+fi
+"#;
+
+#[test]
+fn log() {
+    let dir = fake_adb(SHELL).unwrap();
+    let path = format!("{}:{}", dir.display(), env::var("PATH").unwrap_or_default());
+    let env = [("PATH", path.as_str())];
+    let args = svec!("log", "-t", "Test", "-l", "warn", "it's done");
+    assert!(run_rogcat_with_env(args, None, &env).unwrap().0);
+
+    let input = svec!("step 1", "", "step 2; reboot");
+    let args = svec!("log", "--marker", "--rate", "50", "-");
+    let start = Instant::now();
+    assert!(run_rogcat_with_env(args, Some(input), &env).unwrap().0);
+    // Two lines are spaced by 20ms
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    assert_eq!(
+        file_content(&dir.join("shell")).unwrap(),
+        svec!(
+            "log -p w -t 'Test' 'it'\\''s done'",
+            "log -p d -t 'RogcatMarker' 'step 1'",
+            "log -p d -t 'RogcatMarker' 'step 2; reboot'",
+            "exit"
+        )
+    );
+
+    let args = svec!("log", "--rate", "0", "-");
+    assert!(!run_rogcat_with_env(args, Some(svec!("a")), &env).unwrap().0);
+}
+
+#[test]
+fn pid_of() {
+    let args = svec!("--pid-of", "com.example.app", "-d", "-f", "raw");
+    let output = run_rogcat_with_adb(args, PIDOF).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert!(output.1[0].ends_with("Tag: app"));
+    assert!(output.1[1].ends_with("Tag: restarted app"));
+}
+
+#[test]
+fn dmesg() {
+    let script = r#"
+[ "$*" = "shell dmesg -r" ] || exit 1
+echo "<4>[    1.234567] usb 1-1: device descriptor read error"
+echo "<6>[    1.334567] NET: Registered protocol family 10""#;
+    let args = svec!("-l", "W", "-f", "csv", "dmesg", "-d");
+    let output = run_rogcat_with_adb(args, script).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert!(output.1[1]
+        .starts_with("01-01 00:00:01.234567000,device descriptor read error,Warn,usb 1-1,"));
+}
+
+const CRASHES: &str = r#"
+case "$1" in
+    logcat)
+        echo "03-01 02:19:45.207  1000  1001 E ActivityManager: ANR in com.example.app"
+        echo "03-01 02:19:45.208   123   123 F DEBUG   : Tombstone written to: /data/tombstones/tombstone_03"
+        ;;
+    pull)
+        echo "$2" > "$3"
+        ;;
+esac
+"#;
+
+#[test]
+fn capture_crashes() {
+    let dir = tempdir().unwrap();
+    let output = dir.join("log.txt").display().to_string();
+    let args = svec!("--capture-crashes", "-d", "-o", output);
+    let output = run_rogcat_with_adb(args, CRASHES).unwrap();
+    assert!(output.0);
+
+    let files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(files.len(), 3);
+    let anr = files.iter().find(|f| f.ends_with("-anr")).unwrap();
+    assert!(check_file_content(&dir.join(anr), svec!("/data/anr")).unwrap());
+    let tombstone = files.iter().find(|f| f.ends_with("-tombstone_03")).unwrap();
+    let content = svec!("/data/tombstones/tombstone_03");
+    assert!(check_file_content(&dir.join(tombstone), content).unwrap());
+}
+
+#[test]
+fn configured_trigger() {
+    let config = tempdir().unwrap();
+    let out = config.join("exec");
+    fs::create_dir(config.join("rogcat")).unwrap();
+    let trigger = format!(
+        "[[trigger]]\nmessage = \"^ANR in (\\\\S+)\"\nexec = \"echo $1 > {}\"",
+        out.display()
+    );
+    fs::write(config.join("rogcat").join("config.toml"), trigger).unwrap();
+
+    let adb = fake_adb(CRASHES).unwrap();
+    let path = format!("{}:{}", adb.display(), env::var("PATH").unwrap());
+    let env = [
+        ("PATH", path.as_str()),
+        ("XDG_CONFIG_HOME", config.to_str().unwrap()),
+    ];
+    let output = run_rogcat_with_env(svec!("-d"), None, &env).unwrap();
+    assert!(output.0);
+    assert!(check_file_content(&out, svec!("com.example.app")).unwrap());
+}
+
+const BUFFERS: &str = r#"
+[ "$*" = "logcat -d -D -b main -b crash" ] || exit 1
+echo "--------- beginning of main"
+echo "03-01 02:19:45.207     1     2 I Tag: main"
+echo "--------- switch to crash"
+echo "03-01 02:19:45.208     1     2 F Tag: crash"
+"#;
+
+#[test]
+fn buffers() {
+    let args = svec!(
+        "-b",
+        "main,crash",
+        "--dividers",
+        "-d",
+        "-f",
+        "json",
+        "--filter",
+        "tag == Tag"
+    );
+    let output = run_rogcat_with_adb(args, BUFFERS).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert!(output.1[0].contains("\"buffer\":\"main\""));
+    assert!(output.1[1].contains("\"buffer\":\"crash\""));
+}
+
+#[test]
+fn transport_ssh() {
+    // The fake ssh prints the remote command as a log line
+    let dir = fake_adb("exit 1").unwrap();
+    let ssh = dir.join("ssh");
+    fs::write(
+        &ssh,
+        "#!/bin/sh\necho \"03-01 02:19:45.207     1     2 I Ssh: $*\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", dir.display(), env::var("PATH").unwrap_or_default());
+    let args = svec!("--transport", "ssh://lab@host:2222", "-d", "-f", "raw");
+    let output = run_rogcat_with_env(args, None, &[("PATH", &path)]).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 1);
+    assert!(output.1[0]
+        .ends_with("-p 2222 lab@host adb logcat -d -b main -b events -b crash -b kernel"));
+}
+
+#[test]
+fn process_names() {
+    let script = r#"
+case "$1" in
+    shell)
+        echo "USER PID PPID VSZ RSS WCHAN ADDR S NAME"
+        echo "u0_a42 1234 1 0 0 0 0 S com.example.app"
+        ;;
+    logcat)
+        echo "03-01 02:19:45.207  1234  1235 I Tag: known"
+        echo "03-01 02:19:45.208  4321  4322 I Tag: unknown"
+        ;;
+esac
+"#;
+    let args = svec!("--process-names", "-d", "-f", "json");
+    let output = run_rogcat_with_adb(args, script).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert!(output.1[0].contains("\"process_name\":\"com.example.app\""));
+    assert!(output.1[1].contains("\"process_name\":null"));
+}
+
+#[test]
+fn reconnect() {
+    // adb exits after each line and is respawned after the backoff delay
+    let script = r#"echo "03-01 02:19:45.207     1     2 I Tag: hello""#;
+    let args = svec!("--reconnect=1s", "--head", "2", "-f", "raw");
+    let output = run_rogcat_with_adb(args, script).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+}
+
+#[test]
+fn run_command() {
+    let script = r#"
+[ "$*" = "logcat -v threadtime -T 1" ] || exit 1
+echo "03-01 02:19:45.207     1     2 I Test: running""#;
+    let dir = tempdir().unwrap();
+    let args = svec!(
+        "run",
+        "-d",
+        dir.display().to_string(),
+        "--",
+        "sh",
+        "-c",
+        "exit 3"
+    );
+    let output = run_rogcat_with_adb(args, script).unwrap();
+    assert!(!output.0);
+    let args = svec!("run", "-d", dir.display().to_string(), "--", "true");
+    let output = run_rogcat_with_adb(args, script).unwrap();
+    assert!(output.0);
+
+    let mut files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files.len(), 2);
+    assert!(files[0].starts_with("sh_-c_exit_3-") && files[0].ends_with("-fail.log"));
+    assert!(files[1].starts_with("true-") && files[1].ends_with("-pass.log"));
+    let content = file_content(&dir.join(&files[1])).unwrap();
+    assert_eq!(
+        content,
+        svec!("03-01 02:19:45.207     1     2 I Test: running")
+    );
+}
+
+const BOOT: &str = r#"
+state=$(dirname "$0")
+case "$1" in
+    shell)
+        if [ -e "$state/booted" ]; then echo 1; else touch "$state/booted"; echo 0; fi
+        ;;
+    logcat)
+        if [ "$2" = "-c" ]; then
+            [ -e "$state/booted" ] && touch "$state/cleared"
+        elif [ -e "$state/cleared" ]; then
+            echo "03-01 02:19:45.207     1     2 I Tag: booted"
+        fi
+        ;;
+esac
+"#;
+
+#[test]
+fn wait_for_boot() {
+    let args = svec!("--wait-for-boot=clear", "-d", "-f", "raw");
+    let output = run_rogcat_with_adb(args, BOOT).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        output.1,
+        svec!("03-01 02:19:45.207     1     2 I Tag: booted")
+    );
+}
+
+const BOOTLOOP: &str = r#"
+state=$(dirname "$0")
+boot=$(cat "$state/boot" 2>/dev/null || echo 1)
+case "$1" in
+    shell)
+        [ "$2" = "cat" ] && echo "id-$boot"
+        [ "$2" = "getprop" ] && echo "reason-$boot"
+        ;;
+    logcat)
+        echo "03-01 02:19:45.207     1     2 I Tag: boot $boot"
+        echo $((boot + 1)) > "$state/boot"
+        ;;
+esac
+"#;
+
+#[test]
+fn bootloop() {
+    let dir = tempdir().unwrap();
+    let args = svec!("bootloop", "-c", "2", "-d", dir.display().to_string());
+    let output = run_rogcat_with_adb(args, BOOTLOOP).unwrap();
+    assert!(output.0);
+
+    let mut files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files.len(), 2);
+    for (n, file) in files.iter().enumerate() {
+        let content = fs::read_to_string(file).unwrap();
+        assert!(content.starts_with(&format!("# rogcat boot: {}\n", n + 1)));
+        assert!(content.contains(&format!("# rogcat boot-reason: reason-{}\n", n + 1)));
+        assert!(content.ends_with(&format!("Tag: boot {}\n", n + 1)));
+    }
+}
@@ -50,3 +50,129 @@ fn multiple_files() {
     assert!(output.0);
     assert_eq!(output.1.len(), 6);
 }
+
+#[test]
+fn source_urls() {
+    let content = svec!("A", "B", "C");
+    let file = tempfile_with_content(content).unwrap();
+    let file = format!("file://{}", file.display());
+    let args = svec!("-i", file, "-i", "stdin:");
+    let output = run_rogcat(args, Some(svec!("D", "E"))).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 5);
+}
+
+#[test]
+fn json_lines() {
+    let input = svec!(
+        "01-02 03:04:05.678  123  456 W Tag: hello",
+        "01-02 03:04:05.679  123  456 I Tag: world"
+    );
+    let output = run_rogcat_with_input_file(svec!("-f", "json"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+
+    // Feed the json output back and compare the rendering
+    let args = svec!("-l", "W", "-f", "csv");
+    let json = run_rogcat_with_input_file(args, &output.1).unwrap();
+    let raw = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(json.0);
+    assert_eq!(json.1.len(), 2);
+    assert_eq!(json.1, raw.1);
+}
+
+#[test]
+fn host_timestamps() {
+    let input = svec!("01-02 03:04:05.678  123  456 W Tag: hello");
+    let output =
+        run_rogcat_with_input_file(svec!("--host-timestamps", "-f", "raw"), input).unwrap();
+    assert!(output.0);
+    assert!(output.1[0].ends_with("  123  456 W Tag: hello"));
+    assert!(!output.1[0].starts_with("01-02 03:04:05.678"));
+
+    let args = svec!("--host-timestamps=keep", "-f", "json");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert!(output.1[0].contains("\"device_time\":\"01-02 03:04:05.678\""));
+}
+
+#[test]
+fn correct_clock() {
+    let input = svec!(
+        "01-02 03:04:05.678  123  456 W Tag: hello",
+        "01-02 03:04:06.678  123  456 W Tag: world"
+    );
+    // Files are not captured live
+    let output = run_rogcat_with_input_file(svec!("--correct-clock"), input).unwrap();
+    assert!(!output.0);
+
+    let file = tempfile_with_content(input).expect("Failed to create input file");
+    let file = file.to_str().unwrap().to_owned();
+    let output = run_rogcat(svec!("--correct-clock", "replay", file), None).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn long_format() {
+    let input = svec!(
+        "[ 01-02 03:04:05.678  123:  456 W/Tag ]",
+        "hello",
+        "world",
+        "",
+        "[ 01-02 03:04:05.679  123:  456 I/Tag ]",
+        "single",
+        ""
+    );
+    let output = run_rogcat_with_input_file(svec!("-f", "json"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert!(output.1[0].contains("\"message\":\"hello\\nworld\""));
+    assert!(output.1[1].contains("\"message\":\"single\""));
+}
+
+#[test]
+fn follow() {
+    use std::{
+        fs::OpenOptions,
+        io::Write,
+        process::{Command, Stdio},
+        thread::sleep,
+        time::Duration,
+    };
+
+    let dir = tempdir().unwrap();
+    let a = dir.join("a.log");
+    let b = dir.join("b.log");
+    std::fs::write(
+        &a,
+        "01-02 03:04:05.001  123  456 I Foo: A1\n01-02 03:04:05.003  123  456 I Foo: A3\n",
+    )
+    .unwrap();
+    std::fs::write(&b, "01-02 03:04:05.002  123  456 I Foo: B2\n").unwrap();
+
+    let child = Command::new(find_rogcat_binary())
+        .args(["-i", a.to_str().unwrap(), "-i", b.to_str().unwrap()])
+        .args(["--follow", "--head", "4", "-f", "csv"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    sleep(Duration::from_millis(500));
+    let mut file = OpenOptions::new().append(true).open(&b).unwrap();
+    writeln!(file, "01-02 03:04:05.004  123  456 I Foo: B4").unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let lines = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    let messages = lines
+        .iter()
+        .skip(1)
+        .map(|l| l.split(',').nth(1).unwrap().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(messages, svec!("A1", "B2", "A3", "B4"));
+    assert!(lines[1].contains(",a.log,"));
+    assert!(lines[2].contains(",b.log,"));
+}
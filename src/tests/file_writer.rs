@@ -0,0 +1,427 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::tests::utils::*;
+use flate2::read::GzDecoder;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+fn gz_content(file: &Path) -> SVec {
+    BufReader::new(GzDecoder::new(File::open(file).unwrap()))
+        .lines()
+        .map(Result::unwrap)
+        .collect()
+}
+
+#[test]
+fn gzip() {
+    let input = svec!("A", "B", "C");
+    let dir = tempdir().unwrap();
+    let file = dir.join("log.txt.gz");
+    let args = svec!("-o", file.display().to_string());
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(gz_content(&file), input);
+}
+
+#[test]
+fn gzip_compress_option() {
+    let input = svec!("A", "B", "C");
+    let dir = tempdir().unwrap();
+    let file = dir.join("log.txt");
+    let args = svec!("-o", file.display().to_string(), "--compress", "gzip");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert!(!file.exists());
+    assert_eq!(gz_content(&dir.join("log.txt.gz")), input);
+}
+
+#[test]
+fn gzip_records_per_file() {
+    let input = svec!("A", "B", "C");
+    let dir = tempdir().unwrap();
+    let file = dir.join("log.txt.gz");
+    let args = svec!("-o", file.display().to_string(), "-n", "2");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(gz_content(&dir.join("log-000.txt.gz")), svec!("A", "B"));
+    assert_eq!(gz_content(&dir.join("log-001.txt.gz")), svec!("C"));
+}
+
+#[test]
+fn protobuf() {
+    let input = svec!(
+        "01-02 03:04:05.678  123  456 I Foo: A",
+        "01-02 03:04:05.679  123  456 W Bar: B"
+    );
+    let dir = tempdir().unwrap();
+    let file = dir.join("log.pb");
+    let args = svec!("-o", file.display().to_string(), "-f", "protobuf");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    let content = std::fs::read(&file).unwrap();
+    let mut buf = &content[..];
+    let a = rogcat::protobuf::decode_delimited(&mut buf).unwrap();
+    let b = rogcat::protobuf::decode_delimited(&mut buf).unwrap();
+    assert!(buf.is_empty());
+    assert_eq!((a.tag.as_str(), a.message.as_str()), ("Foo", "A"));
+    assert_eq!(b.level, rogcat::record::Level::Warn);
+    assert_eq!(b.raw, input[1]);
+}
+
+#[test]
+fn msgpack() {
+    let input = svec!(
+        "01-02 03:04:05.678  123  456 I Foo: A",
+        "01-02 03:04:05.679  123  456 W Bar: B"
+    );
+    let dir = tempdir().unwrap();
+    let file = dir.join("log.msgpack").display().to_string();
+    let args = svec!("-o", file, "-f", "msgpack");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+
+    // Captures are read back as records
+    let args = svec!("-i", file, "-f", "raw", "-l", "W");
+    let output = run_rogcat(args, None).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, svec!(input[1].clone()));
+}
+
+#[test]
+fn multiple_outputs() {
+    let input = svec!(
+        "01-02 03:04:05.678  123  456 I Foo: A",
+        "01-02 03:04:05.679  123  456 W Bar: B"
+    );
+    let dir = tempdir().unwrap();
+    let raw = dir.join("log.txt");
+    let json = dir.join("log.json");
+    let args = svec!(
+        "-o",
+        raw.display().to_string(),
+        "-o",
+        json.display().to_string(),
+        "-o",
+        "-"
+    );
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert_eq!(file_content(&raw).unwrap(), input);
+    let json = file_content(&json).unwrap();
+    assert_eq!(json.len(), 2);
+    assert!(json[1].starts_with('{') && json[1].contains("\"tag\":\"Bar\""));
+}
+
+#[test]
+fn output_format() {
+    let input = svec!(
+        "01-02 03:04:05.678  123  456 I Foo: A",
+        "01-02 03:04:05.679  123  456 W Bar: B"
+    );
+    let args = svec!("--output-format", "{time} [{level}] {tag:>4}: {message}");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        output.1,
+        svec!("03:04:05.678 [I]  Foo: A", "03:04:05.679 [W]  Bar: B")
+    );
+
+    let file = tempdir().unwrap().join("log");
+    let args = svec!(
+        "-o",
+        file.display().to_string(),
+        "--output-format",
+        "{tag}|{pid}"
+    );
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert!(check_file_content(&file, svec!("Foo|123", "Bar|123")).unwrap());
+
+    let output = run_rogcat_with_input_file(svec!("--output-format", "{tag"), input).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn split_by_tag() {
+    // More tags than files kept open
+    let mut input = (0..70)
+        .map(|i| format!("01-02 03:04:05.678  123  456 I Tag{}: A", i))
+        .collect::<Vec<_>>();
+    input.push("01-02 03:04:05.679  123  456 I Tag0: B".into());
+    input.push("01-02 03:04:05.679  789  789 I a/b: C".into());
+    let dir = tempdir().unwrap();
+    let out = dir.join("split");
+    let args = svec!("-o", out.display().to_string(), "--split-by", "tag");
+    let output = run_rogcat_with_input_file(args, &input).unwrap();
+    assert!(output.0);
+    assert_eq!(std::fs::read_dir(&out).unwrap().count(), 71);
+    assert_eq!(
+        file_content(&out.join("Tag0.log")).unwrap(),
+        svec!(input[0].clone(), input[70].clone())
+    );
+    assert_eq!(
+        file_content(&out.join("a_b.log")).unwrap(),
+        svec!(input[71].clone())
+    );
+
+    // Existing files are not overwritten
+    let args = svec!("-o", out.display().to_string(), "--split-by", "tag");
+    let output = run_rogcat_with_input_file(args, &input).unwrap();
+    assert!(!output.0);
+
+    let args = svec!("-o", out.display().to_string(), "--split-by", "pid");
+    let output = run_rogcat_with_input_file(args, &input).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        file_content(&out.join("789.log")).unwrap(),
+        svec!(input[71].clone())
+    );
+}
+
+#[test]
+fn marker() {
+    let input = svec!(
+        "01-02 03:04:05.678  123  456 I Foo: A",
+        "01-02 03:04:05.679  123  456 I RogcatMarker: step 1",
+        "01-02 03:04:05.680  123  456 I Bar: B"
+    );
+    let dir = tempdir().unwrap();
+    let file = dir.join("log.txt");
+    // Markers pass the filters
+    let args = svec!("-o", file.display().to_string(), "-t", "Foo");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    let content = file_content(&file).unwrap();
+    assert_eq!(content.len(), 2);
+    assert!(content[1].starts_with("━━━━ 03:04:05.679 step 1 ━"));
+    assert_eq!(content[1].chars().count(), 80);
+}
+
+#[test]
+fn max_file_size_keep() {
+    let input = svec!("A", "B", "C", "D", "E", "F", "G", "H", "I", "J");
+    let dir = tempdir().unwrap();
+    let file = dir.join("log.txt");
+    let args = svec!(
+        "-o",
+        file.display().to_string(),
+        "--max-file-size",
+        "4",
+        "--keep",
+        "2"
+    );
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert!(!dir.join("log-002.txt").exists());
+    assert_eq!(
+        file_content(&dir.join("log-003.txt")).unwrap(),
+        svec!("G", "H")
+    );
+    assert_eq!(
+        file_content(&dir.join("log-004.txt")).unwrap(),
+        svec!("I", "J")
+    );
+}
+
+#[test]
+fn filename_template() {
+    let input = svec!("A", "B", "C");
+    let dir = tempdir().unwrap();
+    let file = dir.join("%Y-log-{index}.txt");
+    let args = svec!(
+        "-o",
+        file.display().to_string(),
+        "-a",
+        "template",
+        "-n",
+        "2"
+    );
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    let year = time::strftime("%Y", &time::now()).unwrap();
+    let first = dir.join(format!("{}-log-000.txt", year));
+    let second = dir.join(format!("{}-log-001.txt", year));
+    assert_eq!(file_content(&first).unwrap(), svec!("A", "B"));
+    assert_eq!(file_content(&second).unwrap(), svec!("C"));
+}
+
+#[test]
+fn session_metadata() {
+    let input = svec!("A", "B", "C");
+    let dir = tempdir().unwrap();
+    let file = dir.join("log.txt");
+    let args = svec!("-o", file.display().to_string(), "--session-metadata");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(file_content(&file).unwrap(), input);
+    let meta = file_content(&dir.join("log.txt.meta.json")).unwrap();
+    assert!(meta.iter().any(|l| l.contains("\"version\"")));
+
+    let file = dir.join("header.txt");
+    let args = svec!(
+        "-o",
+        file.display().to_string(),
+        "--session-metadata=header",
+        "-m",
+        "A|C"
+    );
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    let content = file_content(&file).unwrap();
+    assert!(content[0].starts_with("# rogcat version: "));
+    assert!(content.contains(&"# rogcat message: A|C".to_owned()));
+    assert!(content.ends_with(svec!("A", "C")));
+
+    // Replaying skips the header
+    let output = run_rogcat(svec!("-i", file.display().to_string(), "-f", "raw"), None).unwrap();
+    assert_eq!(output.1, svec!("A", "C"));
+}
+
+#[test]
+fn jobs() {
+    let input = (0..10_000)
+        .map(|i| format!("01-02 03:04:05.000  1  1 I Foo: record {}", i))
+        .collect::<Vec<_>>();
+    let dir = tempdir().unwrap();
+    let file = dir.join("log.txt");
+    let args = svec!(
+        "-o",
+        file.display().to_string(),
+        "--jobs",
+        "4",
+        "-m",
+        "7$",
+        "-f",
+        "csv"
+    );
+    let output = run_rogcat_with_input_file(args, &input).unwrap();
+    assert!(output.0);
+    let content = file_content(&file).unwrap();
+    assert_eq!(content.len(), 1001);
+    assert_eq!(content[0], rogcat::record::CSV_HEADER);
+    assert!(content[1..]
+        .iter()
+        .enumerate()
+        .all(|(i, l)| l.contains(&format!(",record {},", i * 10 + 7))));
+
+    let args = svec!("--jobs", "4", "-C", "1");
+    let output = run_rogcat_with_input_file(args, &input).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn index() {
+    let input = (0..10)
+        .map(|i| format!("01-02 03:04:0{}.000  1  1 I Foo: record {}", i, i))
+        .collect::<Vec<_>>();
+    let dir = tempdir().unwrap();
+    let file = dir.join("log.txt");
+    let args = svec!("-o", file.display().to_string(), "--index=3");
+    let output = run_rogcat_with_input_file(args, &input).unwrap();
+    assert!(output.0);
+    assert_eq!(file_content(&file).unwrap(), input);
+    let index = file_content(&dir.join("log.txt.idx")).unwrap();
+    assert_eq!(index.len(), 5);
+    assert!(index[2].starts_with("3 123 "));
+
+    let args = svec!(
+        "replay",
+        "--no-delay",
+        "--since",
+        "01-02 03:04:07.500",
+        file.display().to_string()
+    );
+    let output = run_rogcat(args, None).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert!(output.1[0].ends_with("record 8"));
+
+    let args = svec!("-o", dir.join("log.gz").display().to_string(), "--index");
+    let output = run_rogcat_with_input_file(args, &input).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn fifo() {
+    use std::{io::Read, process::Command, thread};
+
+    let input = svec!("A", "B", "C");
+    let fifo = tempdir().unwrap().join("fifo");
+    assert!(Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap()
+        .success());
+    let reader = {
+        let fifo = fifo.clone();
+        thread::spawn(move || {
+            let mut content = String::new();
+            File::open(fifo)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        })
+    };
+    let args = svec!("-o", fifo.display().to_string());
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(reader.join().unwrap(), "A\nB\nC\n");
+
+    let args = svec!("-o", fifo.display().to_string(), "-n", "2");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn redact() {
+    let input = svec!(
+        "01-02 03:04:05.678  123  456 I Foo: mail to a@b.com",
+        "01-02 03:04:05.679  123  456 I Foo: nothing"
+    );
+    let dir = tempdir().unwrap();
+    let file = dir.join("log.txt");
+    let args = svec!("-o", file.display().to_string(), "--redact");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        file_content(&file).unwrap(),
+        svec!(
+            "01-02 03:04:05.678  123  456 I Foo: mail to •••",
+            "01-02 03:04:05.679  123  456 I Foo: nothing"
+        )
+    );
+}
+
+#[test]
+fn single_output_raw() {
+    let input = svec!("01-02 03:04:05.678  123  456 I Foo: A");
+    let file = tempdir().unwrap().join("log.json");
+    let args = svec!("-o", file.display().to_string());
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(file_content(&file).unwrap(), input);
+}
@@ -20,6 +20,42 @@
 
 use crate::tests::utils::*;
 
+#[test]
+fn dedupe() {
+    let input = svec!("A", "B 1", "B 1", "B 1", "B 2", "C", "C");
+    let output = run_rogcat_with_input_file(svec!("--dedupe", "-f", "raw"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        output.1,
+        svec!("A", "B 1", "B 1 ×2 repeats", "B 2", "C", "C ×1 repeats")
+    );
+
+    let output = run_rogcat_with_input_file(svec!("--dedupe=fuzzy", "-f", "raw"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        output.1,
+        svec!("A", "B 1", "B 2 ×3 repeats", "C", "C ×1 repeats")
+    );
+}
+
+#[test]
+fn snooze() {
+    let input = svec!(
+        "03-01 02:19:45.207  1234  1234 I Chatty  : A",
+        "03-01 02:19:45.208  1234  1234 I Foo     : B",
+        "03-01 02:19:45.209  1234  1234 I Chatty  : C"
+    );
+    let output =
+        run_rogcat_with_input_file(svec!("--snooze", "Chatty=1h", "-f", "raw"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert_eq!(output.1[0], input[1]);
+    assert!(output.1[1].ends_with("RogcatMarker: suppressed 2 records from Chatty"));
+
+    let output = run_rogcat_with_input_file(svec!("--snooze", "Chatty"), input).unwrap();
+    assert!(!output.0);
+}
+
 #[test]
 fn filter_message() {
     let input = svec!("A", "B", "C", "D", "EF", "FE");
@@ -114,3 +150,49 @@ fn filter_tag_ignorecase() {
     assert!(output.0);
     assert_eq!(output.1.len(), 1);
 }
+
+#[test]
+fn filter_tag_level() {
+    let input = svec!(
+        "D/Runtime: Mindroid runtime system node id: 1",
+        "W/Runtime: Mindroid runtime system node id: 1",
+        "D/Other: Mindroid runtime system node id: 1",
+        "I/Other: Mindroid runtime system node id: 1"
+    );
+    let output = run_rogcat_with_input_file(svec!("--tag-level", "Runtime:W"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 3);
+
+    let output =
+        run_rogcat_with_input_file(svec!("--tag-level", "Runtime:D", "-l", "I"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 3);
+
+    let output = run_rogcat_with_input_file(svec!("--tag-level", "Runtime"), input).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn filter_expression() {
+    let input = svec!(
+        "I/ActivityManager: Start proc",
+        "W/ActivityManager: ANR in com.example",
+        "I/Other: ANR",
+        "I/Other: Something"
+    );
+    let args = svec!(
+        "--filter",
+        "(tag == \"ActivityManager\" && level >= W) || message ~ \"^ANR\""
+    );
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+
+    let args = svec!("--filter", "tag == Other", "--filter", "level == I");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+
+    let output = run_rogcat_with_input_file(svec!("--filter", "tag =="), input).unwrap();
+    assert!(!output.0);
+}
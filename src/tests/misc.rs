@@ -19,6 +19,14 @@
 // SOFTWARE.
 
 use crate::tests::utils::*;
+use std::{
+    env,
+    fs::{self, File},
+    io::Write,
+    os::unix::fs::PermissionsExt,
+    time::{Duration, Instant},
+};
+use zip::{write::FileOptions, ZipWriter};
 
 #[test]
 fn head() {
@@ -27,3 +35,558 @@ fn head() {
     assert!(output.0);
     assert_eq!(output.1.len(), 2);
 }
+
+#[test]
+fn tail_and_sample() {
+    let input = svec!("A", "B", "C", "D", "E");
+    let output = run_rogcat_with_input_file(svec!("--tail", "2", "-f", "raw"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, svec!("D", "E"));
+
+    let output = run_rogcat_with_input_file(svec!("--sample", "1/2", "-f", "raw"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, svec!("A", "C", "E"));
+
+    let args = svec!("--sample", "1/2", "--head", "2", "-f", "raw");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, svec!("A", "C"));
+}
+
+#[test]
+fn script() {
+    let script = svec!(
+        "fn process(r) {",
+        "    if r.message == \"B\" { return false; }",
+        "    r.raw += \"!\";",
+        "    r",
+        "}"
+    );
+    let script = tempfile_with_content(script).unwrap();
+    let input = svec!("A", "B", "C");
+    let args = svec!("--script", script.display().to_string(), "-f", "raw");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, svec!("A!", "C!"));
+}
+
+#[test]
+fn join_stacktraces() {
+    let input = svec!(
+        "03-01 02:19:45.207  1234  1234 I Foo     : before",
+        "03-01 02:19:45.207  1234  1234 E AndroidRuntime: FATAL EXCEPTION: main",
+        "03-01 02:19:45.207  1234  1234 E AndroidRuntime: java.lang.IllegalStateException: Boom",
+        "03-01 02:19:45.207  1234  1234 E AndroidRuntime: \tat com.example.Foo.bar(Foo.java:42)",
+        "03-01 02:19:45.208  1234  1234 I Foo     : after"
+    );
+    let args = svec!("--join-stacktraces", "-m", "FATAL", "-f", "raw");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, input[1..4].to_vec());
+
+    let args = svec!("--join-stacktraces", "-f", "json");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 3);
+}
+
+#[test]
+fn symbolize_tombstone() {
+    let input = svec!(
+        "03-01 02:19:45.207  4321  4321 F DEBUG   : *** *** *** *** *** *** *** *** *** *** *** *** *** *** *** ***",
+        "03-01 02:19:45.207  4321  4321 F DEBUG   : signal 11 (SIGSEGV), code 1 (SEGV_MAPERR), fault addr 0x0",
+        "03-01 02:19:45.207  4321  4321 F DEBUG   : backtrace:",
+        "03-01 02:19:45.207  4321  4321 F DEBUG   :       #00 pc 0000000000001a2b  /data/app/lib/arm64/libnative.so (foo+20)",
+        "03-01 02:19:45.207  4321  4321 F DEBUG   :       #01 pc 0000000000012345  /system/lib64/libc.so (__libc_init+88)",
+        "03-01 02:19:45.208  1234  1234 I Foo     : after"
+    );
+    let input = tempfile_with_content(input).unwrap().display().to_string();
+    let symbols = tempdir().unwrap();
+    fs::create_dir(symbols.join("arm64")).unwrap();
+    fs::write(symbols.join("arm64").join("libnative.so"), "").unwrap();
+    let tools = tempdir().unwrap();
+    let addr2line = tools.join("addr2line");
+    fs::write(
+        &addr2line,
+        "#!/bin/sh\necho \"foo(int)\"\necho \"jni/foo.cpp:42\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&addr2line, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::copy(&addr2line, tools.join("llvm-addr2line")).unwrap();
+    let path = format!("{}:{}", tools.display(), env::var("PATH").unwrap());
+
+    let args = svec!(
+        "-i",
+        input,
+        "--join-stacktraces",
+        "--symbols",
+        symbols.display().to_string(),
+        "-f",
+        "raw"
+    );
+    let output = run_rogcat_with_env(args, None, &[("PATH", &path)]).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 7);
+    assert!(output.1[3].ends_with("libnative.so (foo+20)"));
+    assert_eq!(output.1[4], "    foo(int) at jni/foo.cpp:42");
+    assert!(output.1[5].ends_with("libc.so (__libc_init+88)"));
+
+    let args = svec!("-i", input, "--join-stacktraces", "-f", "json");
+    let output = run_rogcat(args, None).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+}
+
+#[test]
+fn strict_parse() {
+    let input = svec!(
+        "03-01 02:19:45.207  1234  1234 I Foo     : A",
+        "garbage",
+        "03-01 02:19:45.208  1234  1234 I Foo     : B"
+    );
+    let sidecar = tempdir().unwrap().join("rejects.log");
+    let args = svec!(format!("--strict-parse={}", sidecar.display()), "-f", "raw");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, svec!(input[0].clone(), input[2].clone()));
+    assert!(check_file_content(&sidecar, svec!("garbage")).unwrap());
+}
+
+#[test]
+fn budget() {
+    let input = svec!("A", "B", "C", "D");
+    let output =
+        run_rogcat_with_input_file(svec!("--max-records", "2", "-f", "raw"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, svec!("A", "B"));
+
+    let output = run_rogcat_with_input_file(svec!("--max-bytes", "5", "-f", "raw"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, svec!("A", "B"));
+
+    let output = run_rogcat_with_input_file(svec!("--max-bytes", "0"), input).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn queue() {
+    let input = (0..1000).map(|i| i.to_string()).collect::<Vec<_>>();
+    let args = svec!("--queue-size", "10", "-f", "raw");
+    let output = run_rogcat_with_input_file(args, &input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, input);
+
+    let args = svec!("--overflow", "drop-oldest", "-f", "raw", "--head", "1000");
+    let output = run_rogcat_with_input_file(args, &input).unwrap();
+    assert!(output.0);
+    assert!(!output.1.is_empty());
+}
+
+#[test]
+fn context() {
+    let input = svec!("A", "B", "C", "D", "E", "F");
+    let args = svec!("-m", "^C$", "-B", "1", "-A", "2", "-f", "raw");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, svec!("B", "C", "D", "E"));
+
+    let args = svec!("-m", "^[AF]$", "-C", "1", "-f", "raw");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert_eq!(output.1, svec!("A", "B", "E", "F"));
+}
+
+#[test]
+fn format_json_stdout() {
+    let input = svec!("A", "B", "C");
+    let output = run_rogcat_with_input_file(svec!("-f", "json"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 3);
+    let json: serde_json::Value = serde_json::from_str(&output.1[0]).unwrap();
+    assert_eq!(json["schema"], 2);
+    for field in &[
+        "timestamp",
+        "buffer",
+        "device",
+        "process_name",
+        "relative",
+        "cpu",
+        "fields",
+    ] {
+        assert!(json.get(field).is_some(), "{} missing", field);
+    }
+    assert!(json["cpu"].is_null());
+}
+
+#[test]
+fn format_html() {
+    let input = svec!("<A>", "B", "C");
+    let output = run_rogcat_with_input_file(svec!("-f", "html"), input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.iter().filter(|l| l.starts_with("<tr>")).count(), 3);
+    assert!(output.1.iter().any(|l| l.contains("&lt;A&gt;")));
+    assert_eq!(output.1.last().map(String::as_str), Some("</html>"));
+
+    let file = tempfile().unwrap();
+    let args = svec!("-f", "html", "-o", file.display().to_string());
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    let content = file_content(&file).unwrap();
+    assert_eq!(content.iter().filter(|l| l.starts_with("<tr>")).count(), 3);
+    assert_eq!(content.last().map(String::as_str), Some("</html>"));
+}
+
+#[test]
+fn replay() {
+    let input = svec!(
+        "01-01 00:00:00.000  1  1 I A: first",
+        "01-01 00:00:01.000  1  1 I A: second",
+        "01-01 00:00:02.000  1  1 I A: third"
+    );
+    let file = tempfile_with_content(input).unwrap();
+    let file = file.display().to_string();
+
+    let start = Instant::now();
+    let output = run_rogcat(svec!("replay", "--speed", "10x", file), None).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 3);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    let output = run_rogcat(svec!("replay", "--no-delay", file), None).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 3);
+
+    let output = run_rogcat(svec!("replay", "--speed", "0x", file), None).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn theme() {
+    let theme = tempfile_with_content(svec!("[tag]", "Tag = \"#ff0000\"")).unwrap();
+    let input = svec!("01-02 03:04:05.678  123  456 W Tag: hello");
+    let args = svec!("--color", "always", "--theme", theme.display().to_string());
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert!(output.1[0].contains("\x1B[38;2;255;0;0m"));
+
+    let theme = tempfile_with_content(svec!("[tag]", "Tag = \"nocolor\"")).unwrap();
+    let args = svec!("--theme", theme.display().to_string());
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn stats() {
+    let input = svec!(
+        "03-01 02:19:45.207  1000  1001 E ActivityManager: ANR in com.example.app",
+        "03-01 02:19:47.207  1000  1001 I ActivityManager: foo",
+        "03-01 02:19:47.208  1002  1002 I Foo: foo"
+    );
+    let file = tempfile_with_content(input).unwrap().display().to_string();
+
+    let output = run_rogcat(svec!("stats", file), None).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1[0], "Records: 3 in 3s (1.0/s)");
+
+    let output = run_rogcat(
+        svec!("-l", "I", "stats", "--json", "--top", "1", file),
+        None,
+    )
+    .unwrap();
+    assert!(output.0);
+    let json: serde_json::Value = serde_json::from_str(&output.1.join("\n")).unwrap();
+    assert_eq!(json["records"], 3);
+    assert_eq!(json["levels"]["I"], 2);
+    assert_eq!(json["tags"][0]["tag"], "ActivityManager");
+    assert_eq!(json["tags"].as_array().unwrap().len(), 1);
+    assert_eq!(json["messages"][0]["message"], "foo");
+    assert_eq!(json["rate"]["records"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn analyze_latency() {
+    let input = svec!(
+        "01-02 03:04:05.000  1  1 I Net: send 1",
+        "01-02 03:04:05.010  1  1 I Net: send 2",
+        "01-02 03:04:05.030  1  1 I Net: recv 2",
+        "01-02 03:04:05.250  1  1 I Net: recv 1",
+        "01-02 03:04:05.300  1  1 I Net: send 3"
+    );
+    let file = tempfile_with_content(input).unwrap().display().to_string();
+
+    let args = svec!(
+        "analyze",
+        "latency",
+        "--json",
+        "--between",
+        "Net:send (\\d+)",
+        "Net:recv (\\d+)",
+        file
+    );
+    let output = run_rogcat(args, None).unwrap();
+    assert!(output.0);
+    let json: serde_json::Value = serde_json::from_str(&output.1.join("\n")).unwrap();
+    assert_eq!(json[0]["pairs"], 2);
+    assert_eq!(json[0]["unpaired"], 1);
+    assert_eq!(json[0]["min"], 20.0);
+    assert_eq!(json[0]["max"], 250.0);
+
+    let output = run_rogcat(
+        svec!(
+            "analyze",
+            "latency",
+            "--between",
+            "Net:send",
+            "Net:recv",
+            file
+        ),
+        None,
+    )
+    .unwrap();
+    assert!(output.0);
+    assert_eq!(output.1[1], "  Pairs: 1 (1 unpaired starts)");
+}
+
+#[test]
+fn columns() {
+    let input = svec!("01-02 03:04:05.678  123  456 W Tag: hello");
+    let args = svec!("--columns", "level,pid,tag:4,msg", "--color", "never");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, svec!(" W  (123)  Tag   hello"));
+
+    // Records without pid and tid
+    let args = svec!("--columns", "level,pid,tid,msg", "--color", "never");
+    let output = run_rogcat_with_input_file(args, svec!("A")).unwrap();
+    assert_eq!(output.1, svec!(" -  ()   A"));
+
+    let args = svec!("--columns", "msg,tag");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn diff() {
+    let a = tempfile_with_content(svec!(
+        "01-02 03:04:05.678  123  456 I Boot: start",
+        "01-02 03:04:05.679  123  456 I Net: connected in 12ms",
+        "01-02 03:04:05.680  123  456 E Cam: failed"
+    ))
+    .unwrap();
+    let b = tempfile_with_content(svec!(
+        "01-02 04:00:00.000  777  778 I Boot: start",
+        "01-02 04:00:00.001  777  778 I Net: connected in 20ms"
+    ))
+    .unwrap();
+    let args = svec!("diff", a.display().to_string(), b.display().to_string());
+    let output = run_rogcat(args, None).unwrap();
+    assert!(!output.0);
+    assert!(output.1.contains(&"- E Cam: failed".to_owned()));
+    assert!(output.1.contains(&"+ I Net: connected in 20ms".to_owned()));
+
+    let args = svec!(
+        "diff",
+        "--ignore-numbers",
+        a.display().to_string(),
+        b.display().to_string()
+    );
+    let output = run_rogcat(args, None).unwrap();
+    assert_eq!(
+        output.1,
+        svec!(
+            "Only in ".to_owned() + a.display().to_string().as_str() + " (1):",
+            "- E Cam: failed"
+        )
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn on_match() {
+    let file = tempfile().unwrap();
+    let cmd = format!(
+        "B:echo \"$$ROGCAT_MESSAGE\" >> {0}; cat >> {0}",
+        file.display()
+    );
+    let input = svec!("A", "B", "C");
+    let output = run_rogcat_with_input_file(svec!("--on-match", cmd), input).unwrap();
+    assert!(output.0);
+    let content = file_content(&file).unwrap();
+    assert_eq!(content.len(), 2);
+    assert_eq!(content[0], "B");
+    assert!(content[1].contains("\"message\":\"B\""));
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn on_match_captures() {
+    let dir = tempdir().unwrap();
+    let file = dir.join("captures");
+    let injected = dir.join("injected");
+    let cmd = format!("^B (.*)$:echo \"$1\" >> {}", file.display());
+    let input = svec!(format!("B $(touch {})", injected.display()));
+    let output = run_rogcat_with_input_file(svec!("--on-match", cmd), input).unwrap();
+    assert!(output.0);
+    assert!(!injected.exists());
+    let content = file_content(&file).unwrap();
+    assert_eq!(content, svec!(format!("$(touch {})", injected.display())));
+}
+
+#[test]
+fn bugreport_extract() {
+    let report = "== dumpstate: 2019-03-01 02:19:45\n\
+                  ------ SYSTEM LOG (logcat -v threadtime -v printable -v uid -d *:v) ------\n\
+                  03-01 02:19:45.207 system  1000  1001 I Tag: hello\n\
+                  03-01 02:19:45.208 system  1000  1001 W Tag: world\n\
+                  ------ 0.640s was the duration of 'SYSTEM LOG' ------\n\
+                  ------ KERNEL LOG (dmesg) ------\n\
+                  <6>[    0.000000] Booting Linux\n";
+    let file = tempfile().unwrap();
+    let mut zip = ZipWriter::new(File::create(&file).unwrap());
+    zip.start_file("main_entry.txt", FileOptions::default())
+        .unwrap();
+    zip.write_all(b"bugreport-1.txt\n").unwrap();
+    zip.start_file("bugreport-1.txt", FileOptions::default())
+        .unwrap();
+    zip.write_all(report.as_bytes()).unwrap();
+    zip.finish().unwrap();
+
+    let args = svec!(
+        "-f",
+        "csv",
+        "-l",
+        "W",
+        "bugreport",
+        "--extract",
+        "logcat",
+        file.display().to_string()
+    );
+    let output = run_rogcat(args, None).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert!(output.1[1].contains(",world,Warn,Tag,1000,1001,"));
+
+    let args = svec!("bugreport", "--extract", "anr", file.display().to_string());
+    let output = run_rogcat(args, None).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn relative_timestamps() {
+    let input = svec!(
+        "01-02 03:04:05.000  123  456 I Foo: A",
+        "01-02 03:04:17.345  123  456 I Foo: B"
+    );
+    let args = svec!("--relative-timestamps", "-f", "json");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert!(output.1[0].contains("\"relative\":0.0"));
+    assert!(output.1[1].contains("\"relative\":12.345"));
+
+    let args = svec!("--relative-timestamps", "-f", "human", "--color", "never");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.1[0].starts_with("+000.000"));
+    assert!(output.1[1].starts_with("+012.345"));
+}
+
+#[test]
+fn exit_and_fail_on() {
+    let input = svec!(
+        "01-02 03:04:05.000  123  456 I Foo: A",
+        "01-02 03:04:05.100  123  456 I Foo: Test run complete",
+        "01-02 03:04:05.200  123  456 E Foo: FATAL EXCEPTION: main"
+    );
+    let args = svec!("--exit-on", "Test run complete", "-f", "raw");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, input[..2].to_vec());
+
+    let args = svec!("--fail-on", "FATAL EXCEPTION", "-f", "raw");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(!output.0);
+    assert_eq!(output.1, input.to_vec());
+
+    // Filtered records are matched as well
+    let args = svec!("--fail-on", "FATAL EXCEPTION", "-l", "info", "-m", "^A$");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(!output.0);
+
+    let args = svec!("--timeout", "500ms", "sleep 3");
+    let output = run_rogcat(args, None).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn junit_report() {
+    let input = svec!(
+        "01-02 03:04:05.000  123  456 E Foo: FATAL EXCEPTION: main",
+        "01-02 03:04:05.100  123  456 I Foo: <ok>",
+        "01-02 03:04:05.200  123  456 E Foo: FATAL EXCEPTION: worker"
+    );
+    let report = tempfile().unwrap();
+    let args = svec!(
+        "--fail-on",
+        "FATAL EXCEPTION",
+        "--fail-on",
+        "ANR in",
+        "--report",
+        report.to_str().unwrap(),
+        "-f",
+        "raw"
+    );
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(!output.0);
+    assert_eq!(output.1, input.to_vec());
+
+    let xml = file_content(&report).unwrap().join("\n");
+    assert!(xml.contains("tests=\"2\" failures=\"1\""));
+    assert!(xml.contains("<failure message=\"2 matching records\">"));
+    assert!(xml.contains("FATAL EXCEPTION: worker</failure>"));
+    assert!(xml.contains("<testcase classname=\"rogcat\" name=\"fail-on ANR in\"/>"));
+}
+
+#[test]
+fn stats_line_on_pipe() {
+    let input = svec!("01-02 03:04:05.000  123  456 I Foo: A");
+    let args = svec!("--stats-line", "--color", "never", "--columns", "msg");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    // No footer is drawn on pipes
+    assert_eq!(output.1, svec!("   A"));
+}
+
+#[test]
+fn sort_window() {
+    let input = svec!(
+        "01-02 03:04:05.000  123  456 I Foo: A",
+        "01-02 03:04:05.200  123  456 I Foo: C",
+        "01-02 03:04:05.100  123  456 I Foo: B"
+    );
+    let args = svec!("--sort-window", "1s", "-f", "raw");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(
+        output.1,
+        vec![input[0].clone(), input[2].clone(), input[1].clone()]
+    );
+
+    let args = svec!("--sort-window", "1x");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(!output.0);
+}
+
+#[test]
+fn transform_demangle() {
+    let input = svec!(
+        "01-02 03:04:05.678  123  456 I flutter: [ERROR:flutter/lib/ui/ui_dart_state.cc(157)] Unhandled Exception: boom",
+        "01-02 03:04:05.679  123  456 I Foo: [ERROR:x.cc(1)] untouched"
+    );
+    let args = svec!("--transform", "demangle", "-l", "error", "-f", "csv");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert!(output.1[1].contains(",Unhandled Exception: boom,Error,flutter:ui_dart_state.cc:157,"));
+
+    let args = svec!("--transform", "unknown");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(!output.0);
+}
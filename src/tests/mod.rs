@@ -20,8 +20,11 @@
 
 #[macro_use]
 pub(crate) mod utils;
+mod adb;
 mod file_reader;
+mod file_writer;
 mod filter;
 mod misc;
+mod network;
 mod profiles;
 mod system;
@@ -0,0 +1,511 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::tests::utils::*;
+use rogcat::record::Level;
+use std::{
+    cell::Cell,
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    process::{Command, Stdio},
+    thread::sleep,
+    time::Duration,
+};
+
+/// Find a free local port
+fn port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Start rogcat listening on url, call send until it suceeds and return
+/// the first n lines of stdout
+fn listen<F: Fn() -> bool>(url: &str, n: usize, send: F) -> Vec<String> {
+    let mut rogcat = Command::new(find_rogcat_binary())
+        .args(["-i", url, "-f", "raw", "--head", &n.to_string()])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run rogcat");
+
+    for _ in 0..50 {
+        if send() {
+            break;
+        }
+        sleep(Duration::from_millis(100));
+    }
+
+    let lines = BufReader::new(rogcat.stdout.take().unwrap())
+        .lines()
+        .take(n)
+        .map(Result::unwrap)
+        .collect();
+    rogcat.kill().ok();
+    rogcat.wait().ok();
+    lines
+}
+
+#[test]
+fn tcp_listen() {
+    let addr = format!("127.0.0.1:{}", port());
+    let url = format!("tcp://{}", addr);
+    let lines = listen(&url, 3, || {
+        TcpStream::connect(&addr)
+            .and_then(|mut s| s.write_all(b"A\nB\n"))
+            .and_then(|_| TcpStream::connect(&addr))
+            .and_then(|mut s| s.write_all(b"C\n"))
+            .is_ok()
+    });
+    assert_eq!(lines.len(), 3);
+    assert!(lines.contains(&"C".to_owned()));
+}
+
+#[test]
+fn udp_listen() {
+    let addr = format!("127.0.0.1:{}", port());
+    let url = format!("udp://{}", addr);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    // Datagrams get lost until rogcat is listening - resend for a second
+    let sent = Cell::new(0);
+    let lines = listen(&url, 3, || {
+        socket.send_to(b"A\r\nB", &addr).ok();
+        sent.set(sent.get() + 1);
+        sent.get() == 10
+    });
+    assert_eq!(&lines[..2], &["A".to_owned(), "B".to_owned()]);
+}
+
+#[test]
+fn forward() {
+    let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("tcp://{}", collector.local_addr().unwrap());
+    let receiver = std::thread::spawn(move || {
+        let (stream, _) = collector.accept().unwrap();
+        BufReader::new(stream)
+            .lines()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+    });
+
+    let input = svec!("A", "B");
+    let args = svec!("--forward", url, "--forward-format", "raw");
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1.len(), 2);
+    assert_eq!(receiver.join().unwrap(), input);
+}
+
+#[test]
+fn forward_stalled_collector() {
+    // Accept but never read
+    let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("tcp://{}", collector.local_addr().unwrap());
+    let _receiver = std::thread::spawn(move || {
+        let connection = collector.accept().unwrap();
+        sleep(Duration::from_secs(60));
+        drop(connection);
+    });
+
+    let line = "x".repeat(4096);
+    let input = (0..4000).map(|_| line.clone()).collect::<Vec<_>>();
+    let args = svec!("--forward", url, "--forward-format", "raw");
+    let start = std::time::Instant::now();
+    let output = run_rogcat_with_input_file(args, &input).unwrap();
+    assert!(output.0);
+    assert!(start.elapsed() < Duration::from_secs(30));
+}
+
+#[test]
+fn forward_syslog() {
+    let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("syslog://{}?proto=tcp", collector.local_addr().unwrap());
+    let receiver = std::thread::spawn(move || {
+        let (mut stream, _) = collector.accept().unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        received
+    });
+
+    let input = svec!("01-02 03:04:05.678  123  456 W Foo Bar: warn");
+    let args = svec!("--forward", url);
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    let received = receiver.join().unwrap();
+    let (len, message) = received.split_at(received.find(' ').unwrap());
+    let message = &message[1..];
+    assert_eq!(len.parse::<usize>().unwrap(), message.len());
+    assert!(message.starts_with("<12>1 "));
+    assert!(message.ends_with("-01-02T03:04:05.678000 - Foo_Bar 123 - - warn"));
+}
+
+#[test]
+fn forward_syslog_zone() {
+    let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("syslog://{}?proto=tcp", collector.local_addr().unwrap());
+    let receiver = std::thread::spawn(move || {
+        let (mut stream, _) = collector.accept().unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        received
+    });
+
+    let input = svec!("01-02 03:04:05.678 -0130  123  456 W Foo: warn");
+    let args = svec!("--forward", url);
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    let received = receiver.join().unwrap();
+    assert!(received.ends_with("-01-02T03:04:05.678000-01:30 - Foo 123 - - warn"));
+}
+
+/// Read a http request and return the path and body
+fn http_request(stream: &mut TcpStream) -> (String, String) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let path = line.split_whitespace().nth(1).unwrap().to_owned();
+    let mut length = 0;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+            length = value.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    (path, String::from_utf8(body).unwrap())
+}
+
+#[test]
+fn loki() {
+    let loki = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", loki.local_addr().unwrap());
+    let receiver = std::thread::spawn(move || {
+        // The first push fails and is retried
+        let (mut stream, _) = loki.accept().unwrap();
+        let first = http_request(&mut stream);
+        stream
+            .write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        let (mut stream, _) = loki.accept().unwrap();
+        let second = http_request(&mut stream);
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+            .unwrap();
+        assert_eq!(first, second);
+        second
+    });
+
+    let input = svec!(
+        "01-02 03:04:05.000  123  456 I Foo: info",
+        "01-02 03:04:05.100  123  456 W Foo: warn"
+    );
+    let args = svec!("--loki", url);
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+
+    let (path, body) = receiver.join().unwrap();
+    assert_eq!(path, "/loki/api/v1/push");
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let streams = body["streams"].as_array().unwrap();
+    assert_eq!(streams.len(), 2);
+    assert_eq!(streams[0]["stream"]["tag"], "Foo");
+    assert_eq!(streams[0]["stream"]["level"], "info");
+    assert_eq!(streams[0]["values"][0][1], input[0]);
+    assert_eq!(streams[1]["stream"]["level"], "warning");
+}
+
+/// Split a gRPC response body into messages
+fn grpc_messages(mut buf: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = vec![];
+    while buf.len() >= 5 {
+        let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+        if buf.len() < len + 5 {
+            break;
+        }
+        messages.push(buf[5..len + 5].to_vec());
+        buf = &buf[len + 5..];
+    }
+    messages
+}
+
+/// Write a HTTP/2 frame
+fn h2_frame(stream: &mut TcpStream, kind: u8, flags: u8, id: u32, payload: &[u8]) {
+    let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+    frame.extend(&[kind, flags]);
+    frame.extend(&id.to_be_bytes());
+    frame.extend(payload);
+    stream.write_all(&frame).unwrap();
+}
+
+/// Call method with a request message over a plain HTTP/2 connection and
+/// return the first n response messages
+fn grpc_call(addr: SocketAddr, method: &str, message: &[u8], n: usize) -> Vec<Vec<u8>> {
+    const DATA: u8 = 0;
+    const HEADERS: u8 = 1;
+    const SETTINGS: u8 = 4;
+    const END_STREAM: u8 = 1;
+    const END_HEADERS: u8 = 4;
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    stream
+        .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+        .unwrap();
+    h2_frame(&mut stream, SETTINGS, 0, 0, &[]);
+
+    // Literal header fields without indexing
+    let mut headers = vec![];
+    let authority = addr.to_string();
+    for (name, value) in &[
+        (":method", "POST"),
+        (":scheme", "http"),
+        (":path", method),
+        (":authority", &authority),
+        ("content-type", "application/grpc"),
+        ("te", "trailers"),
+    ] {
+        headers.push(0);
+        headers.push(name.len() as u8);
+        headers.extend(name.as_bytes());
+        headers.push(value.len() as u8);
+        headers.extend(value.as_bytes());
+    }
+    h2_frame(&mut stream, HEADERS, END_HEADERS, 1, &headers);
+    let mut data = vec![0];
+    data.extend(&(message.len() as u32).to_be_bytes());
+    data.extend(message);
+    h2_frame(&mut stream, DATA, END_STREAM, 1, &data);
+
+    let mut body = vec![];
+    while grpc_messages(&body).len() < n {
+        let mut header = [0u8; 9];
+        if stream.read_exact(&mut header).is_err() {
+            break;
+        }
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).unwrap();
+        match header[3] {
+            DATA => body.extend(payload),
+            SETTINGS if header[4] & 1 == 0 => h2_frame(&mut stream, SETTINGS, 1, 0, &[]),
+            _ => (),
+        }
+    }
+    grpc_messages(&body).into_iter().take(n).collect()
+}
+
+#[test]
+fn grpc_serve() {
+    let script = r#"
+case "$1" in
+    devices)
+        echo "List of devices attached"
+        echo "A1	device"
+        ;;
+    start-server)
+        ;;
+    exec-out)
+        exit 1
+        ;;
+    *)
+        while true; do
+            echo "01-02 03:04:05.000  123  456 I Foo: info"
+            echo "01-02 03:04:05.100  123  456 W Foo: warn"
+            sleep 0.1
+        done
+        ;;
+esac
+"#;
+    let dir = fake_adb(script).unwrap();
+    let path = format!("{}:{}", dir.display(), env::var("PATH").unwrap_or_default());
+    let mut rogcat = Command::new(find_rogcat_binary())
+        .args(["serve", "--grpc", "127.0.0.1:0"])
+        .env("PATH", path)
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run rogcat");
+    let mut line = String::new();
+    let mut stderr = BufReader::new(rogcat.stderr.take().unwrap());
+    stderr.read_line(&mut line).unwrap();
+    let addr = line.trim().rsplit(' ').next().unwrap().parse().unwrap();
+
+    // SubscribeRequest with a filter
+    let filter = b"level >= W";
+    let mut request = vec![0x0a, filter.len() as u8];
+    request.extend(filter);
+    let records = grpc_call(addr, "/rogcat.Rogcat/Subscribe", &request, 2);
+    assert_eq!(records.len(), 2);
+    for record in records {
+        let record = rogcat::protobuf::decode(&record).unwrap();
+        assert_eq!(record.level, Level::Warn);
+        assert_eq!(record.message, "warn");
+    }
+
+    // Reflection
+    let reflection = "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo";
+    let list_services = [0x3a, 0x01, b'*'];
+    let response = grpc_call(addr, reflection, &list_services, 1);
+    assert!(String::from_utf8_lossy(&response[0]).contains("rogcat.Rogcat"));
+    let mut symbol = vec![0x22, 13];
+    symbol.extend(b"rogcat.Rogcat");
+    let response = grpc_call(addr, reflection, &symbol, 1);
+    let response = String::from_utf8_lossy(&response[0]);
+    assert!(response.contains("service.proto") && response.contains("record.proto"));
+
+    rogcat.kill().ok();
+    rogcat.wait().ok();
+}
+
+#[test]
+fn http_serve() {
+    let script = r#"
+case "$1" in
+    devices)
+        echo "List of devices attached"
+        echo "A1	device"
+        ;;
+    start-server)
+        ;;
+    exec-out)
+        exit 1
+        ;;
+    *)
+        while true; do
+            echo "01-02 03:04:05.000  123  456 W Foo: warn"
+            sleep 0.1
+        done
+        ;;
+esac
+"#;
+    let dir = fake_adb(script).unwrap();
+    let path = format!("{}:{}", dir.display(), env::var("PATH").unwrap_or_default());
+    let mut rogcat = Command::new(find_rogcat_binary())
+        .args(["serve", "--http", "127.0.0.1:0"])
+        .env("PATH", path)
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run rogcat");
+    let mut line = String::new();
+    let mut stderr = BufReader::new(rogcat.stderr.take().unwrap());
+    stderr.read_line(&mut line).unwrap();
+    let addr: SocketAddr = line.trim().rsplit('/').next().unwrap().parse().unwrap();
+
+    // Page
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut page = String::new();
+    stream.read_to_string(&mut page).unwrap();
+    assert!(page.starts_with("HTTP/1.1 200 OK"));
+    assert!(page.contains("new WebSocket"));
+
+    // WebSocket
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    stream
+        .write_all(b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n")
+        .unwrap();
+    let mut client = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut response = String::new();
+    line.clear();
+    while reader.read_line(&mut line).unwrap() > 2 {
+        response.push_str(&line);
+        line.clear();
+    }
+    assert!(response.contains("101 Switching Protocols"));
+    assert!(response.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+    let mut read_frame = || {
+        let mut header = [0u8; 2];
+        reader.read_exact(&mut header).unwrap();
+        let len = match header[1] {
+            126 => {
+                let mut len = [0u8; 2];
+                reader.read_exact(&mut len).unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload).unwrap();
+        (header[0], payload)
+    };
+    let (opcode, payload) = read_frame();
+    assert_eq!(opcode, 0x81);
+    let record: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+    assert_eq!(record["schema"], 2);
+    assert_eq!(record["level"], "Warn");
+    assert_eq!(record["message"], "warn");
+
+    // Masked ping with payload "hi" is answered with a pong
+    client
+        .write_all(&[0x89, 0x82, 1, 2, 3, 4, b'h' ^ 1, b'i' ^ 2])
+        .unwrap();
+    let pong = std::iter::repeat_with(&mut read_frame)
+        .find(|(opcode, _)| *opcode != 0x81)
+        .unwrap();
+    assert_eq!(pong, (0x8A, b"hi".to_vec()));
+
+    // Close is answered with a close
+    client.write_all(&[0x88, 0x80, 1, 2, 3, 4]).unwrap();
+    let close = std::iter::repeat_with(&mut read_frame)
+        .find(|(opcode, _)| *opcode != 0x81)
+        .unwrap();
+    assert_eq!(close.0, 0x88);
+
+    rogcat.kill().ok();
+    rogcat.wait().ok();
+}
+
+#[test]
+fn output_url_format() {
+    let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = collector.local_addr().unwrap();
+    let receiver = std::thread::spawn(move || {
+        let (stream, _) = collector.accept().unwrap();
+        BufReader::new(stream)
+            .lines()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+    });
+
+    let input = svec!("A", "B");
+    let args = svec!("-o", format!("tcp://{}?format=raw", addr));
+    let output = run_rogcat_with_input_file(args, input).unwrap();
+    assert!(output.0);
+    assert_eq!(receiver.join().unwrap(), input);
+
+    for format in &["trace", "html", "human"] {
+        let args = svec!("-o", format!("tcp://{}?format={}", addr, format));
+        let output = run_rogcat_with_input_file(args, input).unwrap();
+        assert!(!output.0);
+    }
+}
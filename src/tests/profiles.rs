@@ -20,7 +20,15 @@
 
 use crate::tests::utils::*;
 use failure::Error;
-use std::string::ToString;
+use std::{
+    fs::write,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    process::{Command, Stdio},
+    string::ToString,
+    thread::sleep,
+    time::Duration,
+};
 
 const CONFIG: &str = "
 [profile.A]
@@ -34,10 +42,55 @@ message = [\"B\"]
 extends = [\"AB\"]
 message = [\"C\"]
 
+[profile.Base]
+regex = [\"^[AB]\"]
+terminal_columns = \"msg\"
+
+[profile.Single]
+extends = \"Base\"
+message = [\"A\"]
+
+[profile.Suppress]
+suppress = [\"^B\", \"tag:Spammy\"]
+
+[profile.Levels]
+terminal_columns = \"level,msg\"
+terminal_level_style = \"word\"
+terminal_level_padding = 0
+
+[profile.LevelGlyphs]
+extends = \"Levels\"
+terminal_level_glyphs = { warn = \"!!\", none = \"?\" }
+
+[profile.Rewrite]
+rewrite = [\"s/^\\\\[\\\\w+\\\\] //\"]
+
+[profile.RewriteMore]
+extends = \"Rewrite\"
+rewrite = [\"s/(\\\\w+)/<$1>/\"]
+
+[profile.Fields]
+fields = { request = \"request_id=(\\\\w+)\", user = \"user \\\\d+\" }
+terminal_columns = \"request,level,msg\"
+
+[profile.MoreFields]
+extends = \"Fields\"
+fields = { request = \"req=(\\\\w+)\" }
+
 [profile.Highlight]
 extends = [\"AB\"]
 highlight = [\"A\"]
 
+[profile.Work]
+preset = \"ui\"
+message = [\"A\"]
+
+[profile.Work.presets.ui]
+message = [\"B\"]
+
+[profile.Work.presets.crash]
+message = [\"C\"]
+
 # CicleA extends CircleB and CircleB extends CircleA -> invalid
 [profile.CircleA]
 extends = [\"CircleB\"]
@@ -133,3 +186,173 @@ fn highlight() {
     let output = run_rogcat_with_config_and_input_file(svec!("-p", "Highlight"), input).unwrap();
     assert_eq!(output.len(), 2);
 }
+
+#[test]
+fn extends_single() {
+    let input = svec!(
+        "01-02 03:04:05.678  123  456 W Tag: A",
+        "01-02 03:04:05.679  123  456 I Tag: B",
+        "01-02 03:04:05.679  123  456 I Tag: C"
+    );
+    let output = run_rogcat_with_config_and_input_file(svec!("-p", "Single"), input).unwrap();
+    // Regex and terminal settings of Base apply
+    assert_eq!(output.len(), 1);
+    assert_eq!(output[0].trim(), "A");
+}
+
+#[test]
+fn reload() {
+    let config = tempfile().unwrap();
+    write(&config, "[profile.R]\nmessage = [\"^A\"]\n").unwrap();
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let url = format!("tcp://{}", addr);
+    let config = config.display().to_string();
+    let args = svec!("-P", config, "-p", "R", "-i", url, "-f", "raw", "--head", "2");
+    let mut rogcat = Command::new(find_rogcat_binary())
+        .args(args)
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run rogcat");
+
+    let mut stream = (0..50)
+        .find_map(|_| {
+            sleep(Duration::from_millis(100));
+            TcpStream::connect(addr).ok()
+        })
+        .expect("Failed to connect");
+    stream.write_all(b"A1\nB1\n").unwrap();
+    sleep(Duration::from_millis(500));
+
+    // Modify the profile and wait for the reload
+    write(&config, "[profile.R]\nmessage = [\"^B\"]\n").unwrap();
+    sleep(Duration::from_millis(2500));
+    // The third record that passes terminates rogcat
+    stream.write_all(b"A2\nB2\nB3\n").unwrap();
+
+    let lines = BufReader::new(rogcat.stdout.take().unwrap())
+        .lines()
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+    rogcat.kill().ok();
+    rogcat.wait().ok();
+    assert_eq!(lines, svec!("A1", "B2"));
+}
+
+#[test]
+fn output() {
+    let dir = tempdir().unwrap();
+    let config = tempfile().unwrap();
+    let content = format!(
+        "[profile.O]\nmessage = [\"A\"]\n\n[[profile.O.output]]\nfile = \"{}/o-%Y-{{index}}.json\"\nformat = \"json\"\n",
+        dir.display()
+    );
+    write(&config, content).unwrap();
+    let input = tempfile_with_content(svec!("A", "B", "AA")).unwrap();
+    let args = svec!(
+        "-P",
+        config.display().to_string(),
+        "-p",
+        "O",
+        "-i",
+        input.display().to_string(),
+        "-f",
+        "raw"
+    );
+    let output = run_rogcat(args, None).unwrap();
+    assert!(output.0);
+    assert_eq!(output.1, svec!("A", "AA"));
+
+    let files = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(files.len(), 1);
+    let name = files[0].file_name().unwrap().to_string_lossy().into_owned();
+    assert!(name.starts_with("o-2") && name.ends_with("-000.json"));
+    let lines = file_content(&files[0]).unwrap();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains("\"raw\":\"AA\""));
+}
+
+#[test]
+fn suppress() {
+    let input = svec!(
+        "01-02 03:04:05.678  123  456 I Tag: A",
+        "01-02 03:04:05.679  123  456 I Tag: B",
+        "01-02 03:04:05.679  123  456 I Spammy: C",
+        "01-02 03:04:05.679  123  456 I Tag: D"
+    );
+    let output = run_rogcat_with_config_and_input_file(svec!("-p", "Suppress"), input).unwrap();
+    assert_eq!(output.len(), 2);
+    let args = svec!("-p", "Suppress", "--suppress", "msg:D");
+    let output = run_rogcat_with_config_and_input_file(args, input).unwrap();
+    assert_eq!(output.len(), 1);
+}
+
+#[test]
+fn level_style() {
+    let input = svec!("01-02 03:04:05.678  123  456 W Tag: A", "B");
+    let args = svec!("-p", "Levels", "-f", "human", "--color", "never");
+    let output = run_rogcat_with_config_and_input_file(args, input).unwrap();
+    assert_eq!(output, svec!("WARN      A", "-         B"));
+
+    let args = svec!("-p", "LevelGlyphs", "-f", "human", "--color", "never");
+    let output = run_rogcat_with_config_and_input_file(args, input).unwrap();
+    assert_eq!(output, svec!("!!        A", "?         B"));
+}
+
+#[test]
+fn rewrite() {
+    let input = svec!("[noise] A", "B");
+    let args = svec!("-p", "RewriteMore", "-f", "raw");
+    let output = run_rogcat_with_config_and_input_file(args, input).unwrap();
+    assert_eq!(output, svec!("<A>", "<B>"));
+    let args = svec!("-p", "Rewrite", "--rewrite", "s/A/Z/", "-f", "raw");
+    let output = run_rogcat_with_config_and_input_file(args, input).unwrap();
+    assert_eq!(output, svec!("Z", "B"));
+}
+
+#[test]
+fn fields() {
+    let input = svec!("request_id=a1 by user 7", "req=b2", "none");
+    let args = svec!("-p", "Fields", "--color", "never");
+    let output = run_rogcat_with_config_and_input_file(args, input).unwrap();
+    // Values are aligned in the field column
+    assert!(output[0].starts_with("a1 ") && output[0].ends_with(" request_id=a1 by user 7"));
+    assert!(output[1].starts_with("   ") && output[1].ends_with(" req=b2"));
+
+    let args = svec!("-p", "MoreFields", "-f", "json");
+    let output = run_rogcat_with_config_and_input_file(args, input).unwrap();
+    assert!(output[0].contains("\"fields\":{\"user\":\"user 7\"}"));
+    assert!(output[1].contains("\"fields\":{\"request\":\"b2\"}"));
+    assert!(output[2].contains("\"fields\":{}"));
+
+    let args = svec!("-p", "Fields", "-f", "csv");
+    let output = run_rogcat_with_config_and_input_file(args, input).unwrap();
+    assert_eq!(output.len(), 4);
+}
+
+#[test]
+fn presets() {
+    let input = svec!("A", "B", "C", "D");
+    let output = run_rogcat_with_config_and_input_file(svec!("-p", "Work"), input).unwrap();
+    assert_eq!(output.len(), 2);
+    assert!(output[1].ends_with(" B"));
+
+    let args = svec!("-p", "Work", "--preset", "crash");
+    let output = run_rogcat_with_config_and_input_file(args, input).unwrap();
+    assert_eq!(output.len(), 2);
+    assert!(output[1].ends_with(" C"));
+
+    let lines = CONFIG
+        .lines()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>();
+    let config = tempfile_with_content(&lines).unwrap().display().to_string();
+    let args = svec!("-P", config, "-p", "Work", "--preset", "nope");
+    let output = run_rogcat(args, None).unwrap();
+    assert!(!output.0);
+}
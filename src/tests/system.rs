@@ -30,7 +30,7 @@ fn help() {
         vec!["configuration", "--help"],
         vec!["devices", "--help"],
         vec!["log", "--help"],
-        vec!["profiles", "--help"],
+        vec!["props", "--help"],
     ];
 
     for set in args {
@@ -24,6 +24,7 @@ use std::{
     env,
     fs::{self, File},
     io::{prelude::*, BufReader},
+    os::unix::fs::PermissionsExt,
     path::PathBuf,
     process::{Command, Stdio},
 };
@@ -80,9 +81,35 @@ pub fn find_rogcat_binary() -> PathBuf {
 }
 
 pub fn run_rogcat(args: &[String], input: Option<&[String]>) -> Result<(bool, SVec), Error> {
+    run_rogcat_with_env(args, input, &[])
+}
+
+/// Create a fake adb executable running script and return the
+/// directory it is placed in
+pub fn fake_adb(script: &str) -> Result<PathBuf, Error> {
+    let dir = tempdir()?;
+    let adb = dir.join("adb");
+    File::create(&adb)?.write_all(format!("#!/bin/sh\n{}\n", script).as_bytes())?;
+    fs::set_permissions(&adb, fs::Permissions::from_mode(0o755))?;
+    Ok(dir)
+}
+
+/// Run rogcat with a fake adb placed in front of PATH
+pub fn run_rogcat_with_adb(args: &[String], script: &str) -> Result<(bool, SVec), Error> {
+    let dir = fake_adb(script)?;
+    let path = format!("{}:{}", dir.display(), env::var("PATH").unwrap_or_default());
+    run_rogcat_with_env(args, None, &[("PATH", &path)])
+}
+
+pub fn run_rogcat_with_env(
+    args: &[String],
+    input: Option<&[String]>,
+    env: &[(&str, &str)],
+) -> Result<(bool, SVec), Error> {
     let rogcat = find_rogcat_binary();
     let mut process = Command::new(format!("{}", rogcat.display()))
         .args(args)
+        .envs(env.iter().cloned())
         .stdin(Stdio::piped())
         .stderr(Stdio::piped())
         .stdout(Stdio::piped())
@@ -0,0 +1,185 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Color themes for the terminal output. A theme file maps levels, tags
+//! and message patterns to colors:
+//!
+//! ```toml
+//! [level]
+//! W = "#ffaf00"
+//! E = 196
+//!
+//! [tag]
+//! ActivityManager = "cyan"
+//!
+//! [regex]
+//! "^ANR" = "255,0,0"
+//! ```
+//!
+//! Colors are names (black, blue, green, red, cyan, magenta, yellow, white),
+//! 256 color numbers, `#rrggbb` or `r,g,b` truecolor values.
+
+use failure::{format_err, Error};
+use regex::Regex;
+use rogcat::record::{Level, Record};
+use serde::Deserialize;
+use std::{collections::HashMap, fs::read_to_string, path::Path, str::FromStr};
+use termcolor::Color;
+use toml::Value;
+
+/// Layout of a theme file
+#[derive(Debug, Default, Deserialize)]
+struct ThemeFile {
+    level: Option<HashMap<String, Value>>,
+    tag: Option<HashMap<String, Value>>,
+    regex: Option<HashMap<String, Value>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Theme {
+    level: Vec<(Level, Color)>,
+    tag: HashMap<String, Color>,
+    regex: Vec<(Regex, Color)>,
+}
+
+impl Theme {
+    pub fn from_file(file: &Path) -> Result<Theme, Error> {
+        let content = read_to_string(file)
+            .map_err(|e| format_err!("Failed to open theme {}: {}", file.display(), e))?;
+        content
+            .parse()
+            .map_err(|e| format_err!("Failed to parse theme {}: {}", file.display(), e))
+    }
+
+    /// Color of the level of record if configured
+    pub fn level(&self, level: &Level) -> Option<Color> {
+        self.level.iter().find(|(l, _)| l == level).map(|(_, c)| *c)
+    }
+
+    /// Color of tag if configured
+    pub fn tag(&self, tag: &str) -> Option<Color> {
+        self.tag.get(tag).cloned()
+    }
+
+    /// Color for the message of record if a pattern matches
+    pub fn message(&self, record: &Record) -> Option<Color> {
+        self.regex
+            .iter()
+            .find(|(r, _)| r.is_match(&record.message))
+            .map(|(_, c)| *c)
+    }
+}
+
+impl FromStr for Theme {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Theme, Error> {
+        let file: ThemeFile = toml::from_str(s)?;
+
+        let level = file
+            .level
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(l, c)| match Level::from(l.as_str()) {
+                Level::None => Err(format_err!("Invalid level {}", l)),
+                level => Ok((level, color(&c)?)),
+            })
+            .collect::<Result<_, Error>>()?;
+        let tag = file
+            .tag
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(t, c)| Ok((t, color(&c)?)))
+            .collect::<Result<_, Error>>()?;
+        let mut regex = file
+            .regex
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(r, c)| {
+                let regex =
+                    Regex::new(&r).map_err(|e| format_err!("Invalid regex {}: {}", r, e))?;
+                Ok((regex, color(&c)?))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        // Tables are unordered - keep the matching deterministic
+        regex.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+
+        Ok(Theme { level, tag, regex })
+    }
+}
+
+/// Parse a color value of a theme file
+fn color(value: &Value) -> Result<Color, Error> {
+    match value {
+        Value::Integer(n) if *n >= 0 && *n <= 255 => Ok(Color::Ansi256(*n as u8)),
+        Value::String(s) => parse_color(s),
+        v => Err(format_err!("Invalid color {}", v)),
+    }
+}
+
+/// Parse a color name, ANSI number or `#rrggbb` value
+pub fn parse_color(s: &str) -> Result<Color, Error> {
+    if s.starts_with('#') && s.len() == 7 && s.is_ascii() {
+        let component = |i: usize| {
+            u8::from_str_radix(&s[i..i + 2], 16)
+                .map_err(|e| format_err!("Invalid color {}: {}", s, e))
+        };
+        Ok(Color::Rgb(component(1)?, component(3)?, component(5)?))
+    } else {
+        Color::from_str(s).map_err(|e| format_err!("Invalid color {}: {}", s, e))
+    }
+}
+
+#[test]
+fn parse_theme() {
+    let theme: Theme = r##"
+        [level]
+        W = "#ffaf00"
+        error = 196
+
+        [tag]
+        ActivityManager = "cyan"
+
+        [regex]
+        "^ANR" = "255,0,0"
+    "##
+    .parse()
+    .unwrap();
+    assert_eq!(theme.level(&Level::Warn), Some(Color::Rgb(255, 175, 0)));
+    assert_eq!(theme.level(&Level::Error), Some(Color::Ansi256(196)));
+    assert_eq!(theme.level(&Level::Info), None);
+    assert_eq!(theme.tag("ActivityManager"), Some(Color::Cyan));
+    assert_eq!(theme.tag("Other"), None);
+    let record = Record {
+        message: "ANR in foo".into(),
+        ..Default::default()
+    };
+    assert_eq!(theme.message(&record), Some(Color::Rgb(255, 0, 0)));
+}
+
+#[test]
+fn parse_theme_errors() {
+    assert!("[level]\nX = 1".parse::<Theme>().is_err());
+    assert!("[tag]\nA = 256".parse::<Theme>().is_err());
+    assert!("[tag]\nA = \"#ffff\"".parse::<Theme>().is_err());
+    assert!("[tag]\nA = \"#gggggg\"".parse::<Theme>().is_err());
+    assert!("[tag]\nA = \"nocolor\"".parse::<Theme>().is_err());
+    assert!("[regex]\n\"(\" = 1".parse::<Theme>().is_err());
+}
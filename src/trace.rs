@@ -0,0 +1,160 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use failure::Error;
+use rogcat::record::Record;
+use serde_json::json;
+use std::{collections::HashSet, io::Write};
+
+const HEADER: &str = "{\"displayTimeUnit\":\"ms\",\"traceEvents\":[\n";
+const FOOTER: &str = "\n]}\n";
+
+/// Streaming writer of Chrome trace events that can be opened in Perfetto or
+/// chrome://tracing. Every record is an instant event on the track of its
+/// pid and tid with the tag as category
+pub struct Trace<W: Write> {
+    writer: W,
+    /// Microseconds of the first timestamp. Logcat timestamps lack the year
+    /// and events are relative to the start of the capture
+    origin: Option<i64>,
+    /// Time of the last record with a timestamp
+    last: i64,
+    /// Processes with a name event
+    named: HashSet<u64>,
+    empty: bool,
+    finished: bool,
+}
+
+/// Numeric pid or tid. Tracks of unparseable ids are merged into 0
+fn id(s: &str) -> u64 {
+    s.trim().parse().unwrap_or(0)
+}
+
+impl<W: Write> Trace<W> {
+    pub fn new(mut writer: W) -> Result<Trace<W>, Error> {
+        writer.write_all(HEADER.as_bytes())?;
+        Ok(Trace {
+            writer,
+            origin: None,
+            last: 0,
+            named: HashSet::new(),
+            empty: true,
+            finished: false,
+        })
+    }
+
+    fn event(&mut self, event: &serde_json::Value) -> Result<usize, Error> {
+        let mut line = if self.empty { "" } else { ",\n" }.to_owned();
+        line.push_str(&event.to_string());
+        self.writer.write_all(line.as_bytes())?;
+        self.empty = false;
+        Ok(line.len())
+    }
+
+    /// Write the events of a record and return the number of bytes written
+    pub fn write(&mut self, record: &Record, _index: usize) -> Result<usize, Error> {
+        let pid = id(&record.process);
+        let tid = id(&record.thread);
+        let mut written = 0;
+
+        if let Some(ref name) = record.process_name {
+            if self.named.insert(pid) {
+                written += self.event(&json!({
+                    "name": "process_name",
+                    "ph": "M",
+                    "pid": pid,
+                    "args": { "name": name },
+                }))?;
+            }
+        }
+
+        if let Some(ref timestamp) = record.timestamp {
+            let spec = timestamp.to_timespec();
+            let micros = spec.sec * 1_000_000 + i64::from(spec.nsec / 1000);
+            self.last = micros - *self.origin.get_or_insert(micros);
+        }
+
+        written += self.event(&json!({
+            "name": record.message,
+            "cat": record.tag,
+            "ph": "i",
+            "s": "t",
+            "ts": self.last,
+            "pid": pid,
+            "tid": tid,
+            "args": { "level": record.level.to_string(), "tag": record.tag },
+        }))?;
+        Ok(written)
+    }
+
+    /// Terminate the event list and flush the underlying writer
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if !self.finished {
+            self.finished = true;
+            self.writer.write_all(FOOTER.as_bytes())?;
+        }
+        self.writer.flush().map_err(Into::into)
+    }
+}
+
+impl<W: Write> Drop for Trace<W> {
+    fn drop(&mut self) {
+        self.finish().ok();
+    }
+}
+
+#[test]
+fn trace_events() {
+    use rogcat::record::{Level, Timestamp};
+
+    let record = |secs: i64, process: &str| Record {
+        timestamp: Some(Timestamp::new(time::at(time::Timespec::new(
+            1_500_000_000 + secs,
+            500_000,
+        )))),
+        message: "hello".to_owned(),
+        level: Level::Info,
+        tag: "Tag".to_owned(),
+        process: process.to_owned(),
+        thread: "2".to_owned(),
+        process_name: Some("com.example".to_owned()),
+        ..Default::default()
+    };
+
+    let mut buffer = vec![];
+    {
+        let mut trace = Trace::new(&mut buffer).unwrap();
+        trace.write(&record(0, "1"), 0).unwrap();
+        trace.write(&record(2, "1"), 1).unwrap();
+        trace.write(&record(3, "x"), 2).unwrap();
+    }
+    let trace: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+    let events = trace["traceEvents"].as_array().unwrap();
+    assert_eq!(events.len(), 5);
+    assert_eq!(events[0]["ph"], "M");
+    assert_eq!(events[0]["args"]["name"], "com.example");
+    assert_eq!(events[1]["ts"], 0);
+    assert_eq!(events[1]["cat"], "Tag");
+    assert_eq!(events[1]["pid"], 1);
+    assert_eq!(events[1]["tid"], 2);
+    assert_eq!(events[2]["ts"], 2_000_000);
+    assert_eq!(events[3]["pid"], 0);
+    assert_eq!(events[4]["ts"], 3_000_000);
+}
@@ -0,0 +1,379 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Triggers run actions when a record matches. Builtin triggers pull ANR traces
+//! and tombstones off the device. More triggers are configured in `config.toml`:
+//!
+//! ```toml
+//! [[trigger]]
+//! tag = "^ActivityManager$"
+//! message = "^ANR in (\\S+)"
+//! exec = "notify-send \"ANR in $1\""
+//!
+//! [[trigger]]
+//! message = "Dumping heap to (\\S+)"
+//! pull = "$1"
+//! ```
+//!
+//! Commands get the matching record as JSON on stdin and its fields in the
+//! environment variables `ROGCAT_TIMESTAMP`, `ROGCAT_LEVEL`, `ROGCAT_TAG`,
+//! `ROGCAT_PID`, `ROGCAT_TID`, `ROGCAT_MESSAGE` and `ROGCAT_DEVICE`. Capture
+//! groups are never expanded into the command text. They are passed as the
+//! positional parameters `$1` etc of the shell and in `ROGCAT_MATCH_1` etc or
+//! `ROGCAT_MATCH_NAME` for named groups. `$$` is accepted for `$` as before.
+
+use crate::utils::{adb_command, config_get};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use regex::{Captures, Regex};
+use rogcat::record::Record;
+use serde::Deserialize;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
+    thread::{self, JoinHandle},
+};
+use time::{now, strftime};
+
+/// Layout of a trigger in the config file
+#[derive(Debug, Deserialize)]
+struct TriggerConfig {
+    tag: Option<String>,
+    message: String,
+    pull: Option<String>,
+    exec: Option<String>,
+}
+
+#[derive(Debug)]
+enum Action {
+    /// Pull a file or directory from the device
+    Pull(String),
+    /// Run a shell command
+    Exec(String),
+}
+
+impl Action {
+    /// Shell command action. `$$` was the escape of `$` when capture groups
+    /// were expanded into the command and is still accepted
+    fn exec(cmd: &str) -> Action {
+        Action::Exec(cmd.replace("$$", "$"))
+    }
+}
+
+#[derive(Debug)]
+struct Trigger {
+    tag: Option<Regex>,
+    message: Regex,
+    actions: Vec<Action>,
+}
+
+impl Trigger {
+    fn new(tag: Option<&str>, message: &str, actions: Vec<Action>) -> Result<Trigger, Error> {
+        let regex =
+            |r: &str| Regex::new(r).map_err(|e| format_err!("Invalid trigger {}: {}", r, e));
+        Ok(Trigger {
+            tag: tag.map(regex).transpose()?,
+            message: regex(message)?,
+            actions,
+        })
+    }
+
+    fn matches<'a>(&self, record: &'a Record) -> Option<Captures<'a>> {
+        match self.tag {
+            Some(ref tag) if !tag.is_match(&record.tag) => None,
+            _ => self.message.captures(&record.message),
+        }
+    }
+
+    /// Environment variables of the capture groups e.g `ROGCAT_MATCH_1` or
+    /// `ROGCAT_MATCH_NAME` for named groups. Unmatched groups are empty.
+    fn groups(&self, captures: &Captures) -> Vec<(String, String)> {
+        self.message
+            .capture_names()
+            .enumerate()
+            .skip(1)
+            .map(|(i, name)| {
+                let key = name.map(str::to_uppercase).unwrap_or_else(|| i.to_string());
+                let value = captures
+                    .get(i)
+                    .map(|m| m.as_str().to_owned())
+                    .unwrap_or_default();
+                (format!("ROGCAT_MATCH_{}", key), value)
+            })
+            .collect()
+    }
+}
+
+/// Configured triggers and the actions in flight
+#[derive(Debug)]
+pub struct Triggers {
+    triggers: Vec<Trigger>,
+    directory: PathBuf,
+    pending: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// Split a `--on-match` argument like `regex:command` at the first colon
+/// that is not escaped with a backslash
+fn on_match(arg: &str) -> Result<(String, String), Error> {
+    let mut escaped = false;
+    for (i, c) in arg.char_indices() {
+        match c {
+            ':' if !escaped => {
+                let regex = arg[..i].replace("\\:", ":");
+                return Ok((regex, arg[i + 1..].to_owned()));
+            }
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    Err(format_err!(
+        "Invalid on-match {}. Use <regex>:<command>",
+        arg
+    ))
+}
+
+pub fn from_args<'a>(args: &ArgMatches<'a>) -> Result<Triggers, Error> {
+    let mut triggers = vec![];
+
+    for arg in args.values_of("on_match").into_iter().flatten() {
+        let (message, cmd) = on_match(arg)?;
+        triggers.push(Trigger::new(None, &message, vec![Action::exec(&cmd)])?);
+    }
+
+    if args.is_present("capture_crashes") {
+        triggers.push(Trigger::new(
+            Some("^ActivityManager$"),
+            "^ANR in ",
+            vec![Action::Pull("/data/anr".into())],
+        )?);
+        triggers.push(Trigger::new(
+            None,
+            r"^Tombstone written to: (\S+)",
+            vec![Action::Pull("$1".into())],
+        )?);
+    }
+
+    for t in config_get::<Vec<TriggerConfig>>("trigger").unwrap_or_default() {
+        let actions = t
+            .pull
+            .map(Action::Pull)
+            .into_iter()
+            .chain(t.exec.as_deref().map(Action::exec))
+            .collect::<Vec<_>>();
+        if actions.is_empty() {
+            return Err(format_err!("Trigger {} without pull or exec", t.message));
+        }
+        triggers.push(Trigger::new(t.tag.as_deref(), &t.message, actions)?);
+    }
+
+    // Captured files are placed next to the log output
+    let directory = args
+        .value_of("output")
+        .and_then(|o| Path::new(o).parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+
+    Ok(Triggers {
+        triggers,
+        directory,
+        pending: Mutex::new(vec![]),
+    })
+}
+
+impl Triggers {
+    /// Run the actions of the triggers that match record in the background
+    pub fn process(&self, record: &Record) {
+        for trigger in &self.triggers {
+            if let Some(captures) = trigger.matches(record) {
+                for action in &trigger.actions {
+                    let handle = match action {
+                        Action::Pull(remote) => {
+                            let mut expanded = String::new();
+                            captures.expand(remote, &mut expanded);
+                            self.pull(expanded, record.device.clone())
+                        }
+                        Action::Exec(cmd) => {
+                            exec(cmd.clone(), trigger.groups(&captures), record.clone())
+                        }
+                    };
+                    let mut pending = self.pending.lock().expect("Failed to lock triggers");
+                    // Finished actions are reaped to not grow with every match
+                    pending.retain(|h| !h.is_finished());
+                    pending.push(handle);
+                }
+            }
+        }
+    }
+
+    /// Wait for all actions to finish
+    pub fn wait(&self) {
+        let pending = self
+            .pending
+            .lock()
+            .expect("Failed to lock triggers")
+            .drain(..)
+            .collect::<Vec<_>>();
+        for handle in pending {
+            handle.join().ok();
+        }
+    }
+
+    fn pull(&self, remote: String, device: Option<String>) -> JoinHandle<()> {
+        let name = Path::new(&remote)
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_else(|| "capture".into());
+        let date = strftime("%m-%d_%H-%M-%S", &now()).unwrap_or_default();
+        let local = self.directory.join(format!("{}-{}", date, name));
+
+        thread::spawn(move || {
+            let result = adb_command().and_then(|mut cmd| {
+                if let Some(device) = device {
+                    cmd.arg("-s").arg(device);
+                }
+                let output = cmd.arg("pull").arg(&remote).arg(&local).output()?;
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    Err(format_err!(
+                        "{}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ))
+                }
+            });
+            match result {
+                Ok(_) => eprintln!("Pulled {} to {}", remote, local.display()),
+                Err(e) => eprintln!("Failed to pull {}: {}", remote, e),
+            }
+        })
+    }
+}
+
+/// Run cmd in a shell. The record is passed as JSON on stdin and in the
+/// environment. Capture groups are passed in the environment and as positional
+/// parameters of the shell but never spliced into cmd.
+fn exec(cmd: String, groups: Vec<(String, String)>, record: Record) -> JoinHandle<()> {
+    thread::spawn(move || {
+        #[cfg(not(target_os = "windows"))]
+        let mut command = Command::new("sh");
+        #[cfg(not(target_os = "windows"))]
+        command
+            .arg("-c")
+            .arg(&cmd)
+            .arg("sh")
+            .args(groups.iter().map(|(_, value)| value));
+        #[cfg(target_os = "windows")]
+        let mut command = Command::new("cmd");
+        #[cfg(target_os = "windows")]
+        command.arg("/C").arg(&cmd);
+
+        let timestamp = record
+            .timestamp
+            .as_ref()
+            .and_then(|t| strftime("%m-%d %H:%M:%S.%f", &t.tm).ok())
+            .unwrap_or_default();
+        command
+            .env("ROGCAT_TIMESTAMP", timestamp)
+            .env("ROGCAT_LEVEL", record.level.to_string())
+            .env("ROGCAT_TAG", &record.tag)
+            .env("ROGCAT_PID", &record.process)
+            .env("ROGCAT_TID", &record.thread)
+            .env("ROGCAT_MESSAGE", &record.message)
+            .env(
+                "ROGCAT_DEVICE",
+                record.device.as_deref().unwrap_or_default(),
+            )
+            .envs(groups)
+            .stdin(Stdio::piped());
+
+        let status = command.spawn().and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                let json = serde_json::to_string(&record).unwrap_or_default();
+                // The command might not read stdin at all
+                writeln!(stdin, "{}", json).ok();
+            }
+            child.wait()
+        });
+        match status {
+            Ok(s) if s.success() => (),
+            Ok(s) => eprintln!("Trigger command \"{}\" failed: {}", cmd, s),
+            Err(e) => eprintln!("Failed to run trigger command \"{}\": {}", cmd, e),
+        }
+    })
+}
+
+#[test]
+fn parse_on_match() {
+    assert_eq!(
+        on_match("^ANR in:adb shell screencap").unwrap(),
+        ("^ANR in".to_owned(), "adb shell screencap".to_owned())
+    );
+    assert_eq!(
+        on_match(r"written to\: (\S+):echo $1").unwrap(),
+        (r"written to: (\S+)".to_owned(), "echo $1".to_owned())
+    );
+    assert!(on_match("no command").is_err());
+}
+
+#[test]
+fn trigger_matches() {
+    let trigger = Trigger::new(Some("^DEBUG$"), r"^Tombstone written to: (\S+)", vec![]).unwrap();
+    let record = Record {
+        tag: "DEBUG".into(),
+        message: "Tombstone written to: /data/tombstones/tombstone_03".into(),
+        ..Default::default()
+    };
+    let captures = trigger.matches(&record).unwrap();
+    let mut expanded = String::new();
+    captures.expand("$1", &mut expanded);
+    assert_eq!(expanded, "/data/tombstones/tombstone_03");
+
+    let record = Record {
+        tag: "Other".into(),
+        ..record
+    };
+    assert!(trigger.matches(&record).is_none());
+}
+
+#[test]
+fn trigger_groups() {
+    let trigger = Trigger::new(None, r"^crash in (\S+) by (?P<cause>.*)$", vec![]).unwrap();
+    let record = Record {
+        message: "crash in app by $(reboot); rm -rf /".into(),
+        ..Default::default()
+    };
+    let captures = trigger.matches(&record).unwrap();
+    assert_eq!(
+        trigger.groups(&captures),
+        vec![
+            ("ROGCAT_MATCH_1".to_owned(), "app".to_owned()),
+            (
+                "ROGCAT_MATCH_CAUSE".to_owned(),
+                "$(reboot); rm -rf /".to_owned()
+            ),
+        ]
+    );
+    match Action::exec("echo $1 $$ROGCAT_TAG") {
+        Action::Exec(cmd) => assert_eq!(cmd, "echo $1 $ROGCAT_TAG"),
+        _ => unreachable!(),
+    }
+}
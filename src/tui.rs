@@ -0,0 +1,578 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{profiles::Profile, terminal::hashed_color, LogSink};
+use clap::{values_t, ArgMatches};
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute, queue,
+    style::{
+        Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor,
+    },
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use failure::{format_err, Error};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend};
+use regex::Regex;
+use rogcat::record::{Level, Record};
+use std::{
+    collections::VecDeque,
+    io::{stdout, Stdout, Write},
+    process::exit,
+    sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Number of records kept in the scrollback buffer and while paused
+const SCROLLBACK: usize = 100_000;
+/// Records queued for the ui thread
+const QUEUE: usize = 1024;
+/// Interval for polling input and new records
+const TICK: Duration = Duration::from_millis(50);
+
+/// Terminal ui sink. Records are passed to a ui thread that
+/// owns the terminal.
+struct Tui {
+    tx: Option<SyncSender<Record>>,
+    ui: Option<JoinHandle<()>>,
+}
+
+/// Construct a tui sink for given args and profile
+pub fn try_from<'a>(args: &ArgMatches<'a>, profile: &Profile) -> Result<LogSink, Error> {
+    let mut highlight = profile.highlight.clone();
+    if args.is_present("highlight") {
+        highlight.extend(values_t!(args.values_of("highlight"), String)?);
+    }
+    let highlight = highlight
+        .iter()
+        .map(|h| Regex::new(h).map_err(|e| format_err!("Invalid regex string: {}: {}", h, e)))
+        .collect::<Result<Vec<Regex>, Error>>()?;
+
+    let (tx, rx) = sync_channel(QUEUE);
+    let mut ui = Ui::new(rx, highlight);
+    ui.setup()?;
+    let ui = thread::Builder::new()
+        .name("tui".into())
+        .spawn(move || ui.run())?;
+
+    Ok(Box::new(Tui {
+        tx: Some(tx),
+        ui: Some(ui),
+    }))
+}
+
+impl Sink for Tui {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if let Some(ref tx) = self.tx {
+            tx.send(record)
+                .map_err(|_| format_err!("Terminal ui terminated"))?;
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+
+    /// The input stream ended. Keep the ui alive until the user quits.
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.tx.take();
+        if let Some(ui) = self.ui.take() {
+            ui.join().map_err(|_| format_err!("Terminal ui panicked"))?;
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Input line currently edited
+enum Prompt {
+    Search(String),
+    Filter(String),
+}
+
+struct Ui {
+    rx: Receiver<Record>,
+    records: VecDeque<Record>,
+    /// Records received while paused. The oldest are dropped if more
+    /// than the scrollback arrive
+    pending: VecDeque<Record>,
+    /// Records dropped from pending
+    dropped: usize,
+    paused: bool,
+    /// True when the input stream ended
+    eof: bool,
+    /// Lines scrolled up from the bottom. 0 follows the stream
+    scroll: usize,
+    filter: Option<Regex>,
+    search: Option<Regex>,
+    highlight: Vec<Regex>,
+    prompt: Option<Prompt>,
+    status: Option<String>,
+    stdout: Stdout,
+}
+
+impl Ui {
+    fn new(rx: Receiver<Record>, highlight: Vec<Regex>) -> Ui {
+        Ui {
+            rx,
+            records: VecDeque::new(),
+            pending: VecDeque::new(),
+            dropped: 0,
+            paused: false,
+            eof: false,
+            scroll: 0,
+            filter: None,
+            search: None,
+            highlight,
+            prompt: None,
+            status: None,
+            stdout: stdout(),
+        }
+    }
+
+    fn setup(&mut self) -> Result<(), Error> {
+        terminal::enable_raw_mode()?;
+        execute!(self.stdout, EnterAlternateScreen, Hide)?;
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        execute!(self.stdout, ResetColor, Show, LeaveAlternateScreen).ok();
+        terminal::disable_raw_mode().ok();
+    }
+
+    fn run(mut self) {
+        let result = self.event_loop();
+        self.teardown();
+        match result {
+            Ok(()) => exit(0),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1)
+            }
+        }
+    }
+
+    fn event_loop(&mut self) -> Result<(), Error> {
+        let mut dirty = true;
+        loop {
+            dirty |= self.receive();
+
+            if dirty {
+                self.draw()?;
+                dirty = false;
+            }
+
+            if event::poll(TICK)? {
+                match event::read()? {
+                    Event::Key(key) => {
+                        if !self.key(key)? {
+                            return Ok(());
+                        }
+                    }
+                    Event::Resize(_, _) => (),
+                    Event::Mouse(_) => continue,
+                }
+                dirty = true;
+            }
+        }
+    }
+
+    /// Drain the record channel. Returns true if something changed.
+    fn receive(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            match self.rx.try_recv() {
+                Ok(record) => {
+                    if self.paused {
+                        if self.pending.len() == SCROLLBACK {
+                            self.pending.pop_front();
+                            self.dropped += 1;
+                        }
+                        self.pending.push_back(record);
+                    } else {
+                        self.push(record);
+                    }
+                    changed = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    changed |= !self.eof;
+                    self.eof = true;
+                    break;
+                }
+            }
+        }
+        changed
+    }
+
+    fn push(&mut self, record: Record) {
+        if self.records.len() == SCROLLBACK {
+            self.records.pop_front();
+        }
+        // Keep the view steady if the user scrolled up
+        if self.scroll != 0 && self.matches_filter(&record) {
+            self.scroll += 1;
+        }
+        self.records.push_back(record);
+    }
+
+    fn matches_filter(&self, record: &Record) -> bool {
+        self.filter
+            .as_ref()
+            .map(|f| f.is_match(&record.tag) || f.is_match(&record.message))
+            .unwrap_or(true)
+    }
+
+    fn matches_search(&self, record: &Record) -> bool {
+        self.search
+            .as_ref()
+            .map(|s| s.is_match(&record.tag) || s.is_match(&record.message))
+            .unwrap_or(false)
+    }
+
+    /// Records passing the display filter
+    fn visible(&self) -> Vec<&Record> {
+        self.records
+            .iter()
+            .filter(|r| self.matches_filter(r))
+            .collect()
+    }
+
+    fn page(&self) -> usize {
+        terminal::size()
+            .map(|(_, h)| h as usize)
+            .unwrap_or(25)
+            .saturating_sub(1)
+            .max(1)
+    }
+
+    fn scroll_by(&mut self, lines: isize) {
+        let max = self.visible().len().saturating_sub(self.page());
+        let scroll = self.scroll as isize + lines;
+        self.scroll = (scroll.max(0) as usize).min(max);
+    }
+
+    /// Scroll to the next match of the search pattern. Searches
+    /// towards older records if backwards is set. A new search
+    /// starts at the last line on screen.
+    fn find(&mut self, backwards: bool, new: bool) {
+        if self.search.is_none() {
+            return;
+        }
+        let visible = self.visible();
+        let page = self.page();
+        let len = visible.len();
+        // Index of the last line currently shown
+        let bottom = len.saturating_sub(self.scroll + 1);
+        // The matching line is shown in the middle of the screen
+        let center = bottom.saturating_sub(page / 2);
+        let start = if new { (bottom + 1).min(len) } else { center };
+        let found = if backwards {
+            (0..start).rev().find(|i| self.matches_search(visible[*i]))
+        } else {
+            (center + 1..len).find(|i| self.matches_search(visible[*i]))
+        };
+        match found {
+            Some(i) => {
+                let bottom = (i + page / 2).min(len.saturating_sub(1));
+                self.scroll = len - 1 - bottom;
+                self.status = None;
+            }
+            None => self.status = Some("Pattern not found".into()),
+        }
+    }
+
+    /// Handle key event. Returns false if the ui shall quit.
+    fn key(&mut self, key: KeyEvent) -> Result<bool, Error> {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Ok(false);
+        }
+
+        if let Some(prompt) = self.prompt.take() {
+            let (mut input, search) = match prompt {
+                Prompt::Search(s) => (s, true),
+                Prompt::Filter(s) => (s, false),
+            };
+            match key.code {
+                KeyCode::Esc => return Ok(true),
+                KeyCode::Enter => {
+                    let regex = if input.is_empty() {
+                        None
+                    } else {
+                        match Regex::new(&input) {
+                            Ok(r) => Some(r),
+                            Err(e) => {
+                                self.status = Some(format!("Invalid regex: {}", e));
+                                return Ok(true);
+                            }
+                        }
+                    };
+                    if search {
+                        self.search = regex;
+                        self.find(true, true);
+                    } else {
+                        self.filter = regex;
+                        self.scroll = 0;
+                    }
+                    return Ok(true);
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => (),
+            }
+            self.prompt = Some(if search {
+                Prompt::Search(input)
+            } else {
+                Prompt::Filter(input)
+            });
+            return Ok(true);
+        }
+
+        self.status = None;
+        let page = self.page() as isize;
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Char('p') | KeyCode::Char(' ') => {
+                self.paused = !self.paused;
+                if !self.paused {
+                    for record in self.pending.drain(..).collect::<Vec<_>>() {
+                        self.push(record);
+                    }
+                    self.dropped = 0;
+                }
+            }
+            KeyCode::Char('/') => self.prompt = Some(Prompt::Search(String::new())),
+            KeyCode::Char('f') => {
+                let current = self
+                    .filter
+                    .as_ref()
+                    .map(|f| f.as_str().to_owned())
+                    .unwrap_or_default();
+                self.prompt = Some(Prompt::Filter(current))
+            }
+            KeyCode::Char('n') => self.find(true, false),
+            KeyCode::Char('N') => self.find(false, false),
+            KeyCode::Up | KeyCode::Char('k') => self.scroll_by(1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll_by(-1),
+            KeyCode::PageUp => self.scroll_by(page),
+            KeyCode::PageDown => self.scroll_by(-page),
+            KeyCode::Home | KeyCode::Char('g') => self.scroll_by(isize::MAX / 2),
+            KeyCode::End | KeyCode::Char('G') => self.scroll = 0,
+            _ => (),
+        }
+        Ok(true)
+    }
+
+    fn draw(&mut self) -> Result<(), Error> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let page = height.saturating_sub(1);
+
+        let visible = self.visible();
+        let end = visible.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(page);
+
+        let mut out = self.stdout.lock();
+        queue!(out, MoveTo(0, 0))?;
+        for (row, record) in visible[start..end].iter().enumerate() {
+            queue!(out, MoveTo(0, row as u16), Clear(ClearType::CurrentLine))?;
+            Self::draw_record(
+                &mut out,
+                record,
+                width,
+                self.matches_search(record),
+                self.highlight
+                    .iter()
+                    .any(|h| h.is_match(&record.tag) || h.is_match(&record.message)),
+            )?;
+        }
+        for row in end - start..page {
+            queue!(out, MoveTo(0, row as u16), Clear(ClearType::CurrentLine))?;
+        }
+
+        // Status line
+        let status = match self.prompt {
+            Some(Prompt::Search(ref s)) => format!("/{}", s),
+            Some(Prompt::Filter(ref s)) => format!("filter: {}", s),
+            None => {
+                let mut status = vec![];
+                if self.paused {
+                    let mut paused = format!("PAUSED ({} pending", self.pending.len());
+                    if self.dropped > 0 {
+                        paused.push_str(&format!(", {} dropped", self.dropped));
+                    }
+                    paused.push(')');
+                    status.push(paused);
+                }
+                if self.eof {
+                    status.push("EOF".to_owned());
+                }
+                if let Some(ref f) = self.filter {
+                    status.push(format!("filter: {}", f));
+                }
+                if let Some(ref s) = self.search {
+                    status.push(format!("search: {}", s));
+                }
+                if let Some(ref s) = self.status {
+                    status.push(s.clone());
+                }
+                status.push(format!("{}/{} records", visible.len(), self.records.len()));
+                status.push("q:quit p:pause /:search n/N:next/prev f:filter".to_owned());
+                status.join(" | ")
+            }
+        };
+        let status: String = status.chars().take(width).collect();
+        queue!(
+            out,
+            MoveTo(0, height.saturating_sub(1) as u16),
+            Clear(ClearType::CurrentLine),
+            SetAttribute(Attribute::Reverse),
+            Print(format!("{:<width$}", status, width = width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+        out.flush().map_err(Into::into)
+    }
+
+    fn draw_record<W: Write>(
+        out: &mut W,
+        record: &Record,
+        width: usize,
+        search: bool,
+        highlight: bool,
+    ) -> Result<(), Error> {
+        let timestamp = record
+            .timestamp
+            .as_ref()
+            .and_then(|ts| time::strftime("%H:%M:%S.%f", ts).ok())
+            .map(|mut ts| {
+                ts.truncate(12);
+                ts
+            })
+            .unwrap_or_else(|| " ".repeat(12));
+        let level_color = match record.level {
+            Level::Info => Some(Color::Green),
+            Level::Warn => Some(Color::Yellow),
+            Level::Error | Level::Fatal | Level::Assert => Some(Color::Red),
+            _ => None,
+        };
+
+        let mut columns = vec![
+            (timestamp, Some(Color::AnsiValue(243))),
+            (
+                format!("{:>20}", Self::truncate(&record.tag, 20)),
+                Some(color(&hashed_color(&record.tag))),
+            ),
+            (
+                format!("{:>6}", record.process),
+                Some(color(&hashed_color(&record.process))),
+            ),
+            (
+                format!("{:>6}", record.thread),
+                Some(color(&hashed_color(&record.thread))),
+            ),
+            (record.level.to_string(), level_color),
+            (record.message.replace('\n', " "), level_color),
+        ];
+
+        if search || highlight {
+            queue!(out, SetAttribute(Attribute::Bold))?;
+        }
+        if search {
+            queue!(out, SetBackgroundColor(Color::AnsiValue(237)))?;
+        }
+
+        let mut remaining = width;
+        for (text, fg) in columns.drain(..) {
+            if remaining == 0 {
+                break;
+            }
+            let text = Self::truncate(&text, remaining);
+            remaining = remaining.saturating_sub(text.chars().count() + 1);
+            match fg {
+                Some(c) => queue!(out, SetForegroundColor(c))?,
+                None => queue!(out, SetForegroundColor(Color::Reset))?,
+            }
+            queue!(out, Print(text))?;
+            if remaining > 0 {
+                queue!(out, Print(" "))?;
+            }
+        }
+        queue!(out, ResetColor, SetAttribute(Attribute::Reset))?;
+        Ok(())
+    }
+
+    fn truncate(s: &str, width: usize) -> String {
+        s.chars().take(width).collect()
+    }
+}
+
+impl Drop for Ui {
+    fn drop(&mut self) {
+        self.teardown();
+    }
+}
+
+/// Convert a termcolor color into its crossterm pendant
+fn color(c: &termcolor::Color) -> Color {
+    match *c {
+        termcolor::Color::Black => Color::Black,
+        termcolor::Color::Blue => Color::Blue,
+        termcolor::Color::Green => Color::Green,
+        termcolor::Color::Red => Color::Red,
+        termcolor::Color::Cyan => Color::Cyan,
+        termcolor::Color::Magenta => Color::Magenta,
+        termcolor::Color::Yellow => Color::Yellow,
+        termcolor::Color::White => Color::White,
+        termcolor::Color::Ansi256(c) => Color::AnsiValue(c),
+        termcolor::Color::Rgb(r, g, b) => Color::Rgb { r, g, b },
+        _ => Color::Reset,
+    }
+}
+
+#[test]
+fn pause_keeps_latest() {
+    let (tx, rx) = sync_channel(QUEUE);
+    let mut ui = Ui::new(rx, vec![]);
+    ui.paused = true;
+    let record = |n: usize| Record {
+        message: n.to_string(),
+        ..Default::default()
+    };
+    for n in 0..SCROLLBACK + 10 {
+        if tx.try_send(record(n)).is_err() {
+            ui.receive();
+            tx.send(record(n)).unwrap();
+        }
+    }
+    ui.receive();
+    assert_eq!(ui.pending.len(), SCROLLBACK);
+    assert_eq!(ui.dropped, 10);
+    assert_eq!(ui.pending[0].message, "10");
+    // The terminal was never set up
+    std::mem::forget(ui);
+}
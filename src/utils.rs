@@ -18,22 +18,346 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::adbserver;
+use clap::ArgMatches;
 use config::Config;
-use failure::Error;
+use failure::{format_err, Error};
 use lazy_static::lazy_static;
+use regex::Regex;
 use serde::Deserialize;
-use std::{convert::Into, env, path::PathBuf, sync::RwLock};
+use std::{
+    borrow::ToOwned,
+    collections::HashMap,
+    convert::Into,
+    env,
+    io::{stdin, BufRead},
+    path::PathBuf,
+    process::Command,
+    str::FromStr,
+    sync::{Mutex, RwLock},
+    time::Duration,
+};
+use url::Url;
 use which::which_in;
 
 lazy_static! {
     static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
+    /// Serial of the device selected by model or picked interactively
+    static ref SELECTED: Mutex<Option<String>> = Mutex::new(None);
+    /// Binary and arguments of `--adb-path` and `--adb-args`
+    static ref ADB: RwLock<(Option<PathBuf>, Vec<String>)> = RwLock::new((None, Vec::new()));
+}
+
+/// Parse a duration with optional ms, s, m, h or d suffix. Plain numbers are seconds
+pub fn interval(s: &str) -> Result<Duration, Error> {
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(n) => s.split_at(n),
+        None => (s, "s"),
+    };
+    let factor = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return Err(format_err!("Invalid interval {}. Use ms, s, m, h or d", s)),
+    };
+    let value = u64::from_str(value).map_err(|e| format_err!("Invalid interval {}: {}", s, e))?;
+    match value * factor {
+        0 => Err(format_err!("Invalid interval {}", s)),
+        millis => Ok(Duration::from_millis(millis)),
+    }
+}
+
+/// Set the adb binary and arguments from `--adb-path` and `--adb-args` or
+/// the configuration keys `adb_path` and `adb_args`
+pub fn adb_init<'a>(args: &ArgMatches<'a>) {
+    let path = args
+        .value_of("adb_path")
+        .map(ToOwned::to_owned)
+        .or_else(|| config_get("adb_path"))
+        .map(PathBuf::from);
+    let adb_args = args
+        .value_of("adb_args")
+        .map(ToOwned::to_owned)
+        .or_else(|| config_get("adb_args"))
+        .map(|a| a.split_whitespace().map(ToOwned::to_owned).collect())
+        .unwrap_or_default();
+    *ADB.write().expect("Failed to get adb lock") = (path, adb_args);
 }
 
 /// Find adb binary
 pub fn adb() -> Result<PathBuf, Error> {
+    if let Some(ref path) = ADB.read().expect("Failed to get adb lock").0 {
+        return Ok(path.clone());
+    }
     which_in("adb", env::var_os("PATH"), env::current_dir()?).map_err(Into::into)
 }
 
+/// Arguments passed to every adb invocation
+fn adb_args() -> Vec<String> {
+    ADB.read().expect("Failed to get adb lock").1.clone()
+}
+
+/// Command to run adb with the arguments of `--adb-args`
+pub fn adb_command() -> Result<Command, Error> {
+    let mut command = Command::new(adb()?);
+    command.args(adb_args());
+    Ok(command)
+}
+
+/// Command line to run adb. With `--transport ssh://user@host:port` adb is
+/// run on the remote host.
+pub fn adb_cmd<'a>(args: &ArgMatches<'a>) -> Result<Vec<String>, Error> {
+    let transport = match args.value_of("transport") {
+        Some(transport) => Url::parse(transport)
+            .map_err(|e| format_err!("Invalid transport {}: {}", transport, e))?,
+        None => {
+            let mut cmd = vec![adb()?.display().to_string()];
+            cmd.extend(adb_args());
+            adbserver::ensure(&cmd);
+            return Ok(cmd);
+        }
+    };
+    if transport.scheme() != "ssh" {
+        return Err(format_err!("Unsupported transport {}", transport.scheme()));
+    }
+    let host = transport
+        .host_str()
+        .ok_or_else(|| format_err!("Missing host in transport {}", transport))?;
+    let destination = match transport.username() {
+        "" => host.to_owned(),
+        user => format!("{}@{}", user, host),
+    };
+
+    // Detect dead connections and never prompt since stdin isn't forwarded
+    let mut cmd = [
+        "ssh",
+        "-T",
+        "-o",
+        "BatchMode=yes",
+        "-o",
+        "ServerAliveInterval=5",
+        "-o",
+        "ServerAliveCountMax=3",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect::<Vec<String>>();
+    if let Some(port) = transport.port() {
+        cmd.push("-p".into());
+        cmd.push(port.to_string());
+    }
+    cmd.push(destination);
+    // The adb path of the remote host if set
+    match ADB.read().expect("Failed to get adb lock").0 {
+        Some(ref path) => cmd.push(path.display().to_string()),
+        None => cmd.push("adb".into()),
+    }
+    cmd.extend(adb_args());
+    Ok(cmd)
+}
+
+/// A online device listed by `adb devices -l`
+#[derive(Debug, PartialEq)]
+pub struct Device {
+    pub serial: String,
+    pub model: Option<String>,
+}
+
+/// Devices listed by `adb devices -l` that are online
+pub fn list_devices(adb: &[String]) -> Result<Vec<Device>, Error> {
+    let output = Command::new(&adb[0])
+        .args(&adb[1..])
+        .args(["devices", "-l"])
+        .output()?;
+    if !output.status.success() {
+        return Err(format_err!(
+            "Failed to run adb devices: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(parse_devices(&String::from_utf8_lossy(&output.stdout)))
+}
+
+pub fn parse_devices(output: &str) -> Vec<Device> {
+    output
+        .lines()
+        .filter(|l| !l.starts_with("List of devices") && !l.starts_with("* daemon"))
+        .filter_map(|l| {
+            let mut s = l.split_whitespace();
+            match (s.next(), s.next()) {
+                (Some(serial), Some("device")) => Some(Device {
+                    serial: serial.to_owned(),
+                    model: s
+                        .find_map(|p| p.strip_prefix("model:"))
+                        .map(|m| m.replace('_', " ")),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Serials of the devices listed by `adb devices` that are online
+pub fn devices(adb: &[String]) -> Result<Vec<String>, Error> {
+    Ok(list_devices(adb)?.into_iter().map(|d| d.serial).collect())
+}
+
+/// Command line to run adb for a single device. The device is selected with
+/// `--serial`, by matching the model with `--device-model` or picked
+/// interactively if multiple devices are connected. Without a selection adb
+/// picks the device e.g from `ANDROID_SERIAL`.
+pub fn device_cmd<'a>(args: &ArgMatches<'a>) -> Result<Vec<String>, Error> {
+    let mut cmd = adb_cmd(args)?;
+    if let Some(serial) = select_device(args, || list_devices(&cmd))? {
+        cmd.push("-s".into());
+        cmd.push(serial);
+    }
+    Ok(cmd)
+}
+
+/// Serial of the device to use. `list` is called to get the devices to select from
+pub fn select_device<'a, F>(args: &ArgMatches<'a>, list: F) -> Result<Option<String>, Error>
+where
+    F: FnOnce() -> Result<Vec<Device>, Error>,
+{
+    if let Some(serial) = args.value_of("serial") {
+        return Ok(Some(serial.to_owned()));
+    }
+
+    let mut selected = SELECTED.lock().expect("Failed to lock device selection");
+    if selected.is_some() {
+        return Ok(selected.clone());
+    }
+
+    let model = args
+        .value_of("device_model")
+        .map(|m| Regex::new(m).map_err(|e| format_err!("Invalid device model {}: {}", m, e)))
+        .transpose()?;
+    if model.is_none() && env::var_os("ANDROID_SERIAL").is_some() {
+        return Ok(None);
+    }
+
+    // Failures are reported by the adb command that follows
+    let devices = match list() {
+        Ok(devices) => devices,
+        Err(_) if model.is_none() => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let devices = devices
+        .into_iter()
+        .filter(|d| match (&model, &d.model) {
+            (Some(regex), Some(m)) => regex.is_match(m),
+            (Some(_), None) => false,
+            (None, _) => true,
+        })
+        .collect::<Vec<_>>();
+
+    let serial = match devices.len() {
+        0 => match args.value_of("device_model") {
+            Some(m) => return Err(format_err!("No device with model matching {}", m)),
+            // Let adb wait for a device
+            None => return Ok(None),
+        },
+        1 if model.is_none() => return Ok(None),
+        1 => devices[0].serial.clone(),
+        _ if atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stderr) => {
+            pick_device(&devices)?
+        }
+        _ => {
+            return Err(format_err!(
+                "Multiple devices found: {}. Select one with --serial or --device-model",
+                devices
+                    .iter()
+                    .map(|d| d.serial.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        }
+    };
+    *selected = Some(serial.clone());
+    Ok(Some(serial))
+}
+
+/// Let the user choose one of devices on the terminal
+fn pick_device(devices: &[Device]) -> Result<String, Error> {
+    eprintln!("Multiple devices found:");
+    for (i, device) in devices.iter().enumerate() {
+        eprintln!(
+            "  {}) {} {}",
+            i + 1,
+            device.serial,
+            device.model.as_deref().unwrap_or_default()
+        );
+    }
+    eprint!("Select device [1-{}]: ", devices.len());
+
+    let mut line = String::new();
+    stdin().lock().read_line(&mut line)?;
+    line.trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|n| devices.get(n))
+        .map(|d| d.serial.clone())
+        .ok_or_else(|| format_err!("Invalid selection {}", line.trim()))
+}
+
+/// Process names by pid as listed by `adb shell ps`
+pub fn ps(adb: &[String]) -> Result<HashMap<String, String>, Error> {
+    let run = |args: &[&str]| -> Result<HashMap<String, String>, Error> {
+        let output = Command::new(&adb[0])
+            .args(&adb[1..])
+            .arg("shell")
+            .args(args)
+            .output()?;
+        Ok(parse_ps(&String::from_utf8_lossy(&output.stdout)))
+    };
+    // Toolbox ps of older Android versions lists all processes without -A
+    let names = run(&["ps", "-A"])?;
+    if names.len() > 1 {
+        Ok(names)
+    } else {
+        run(&["ps"])
+    }
+}
+
+/// Parse the output of ps. The name is the last column.
+fn parse_ps(output: &str) -> HashMap<String, String> {
+    let mut lines = output.lines();
+    let pid = match lines
+        .next()
+        .and_then(|header| header.split_whitespace().position(|c| c == "PID"))
+    {
+        Some(pid) => pid,
+        None => return HashMap::new(),
+    };
+    lines
+        .filter_map(|line| {
+            let columns = line.split_whitespace().collect::<Vec<_>>();
+            if columns.len() > pid + 1 {
+                Some((
+                    columns[pid].to_owned(),
+                    columns[columns.len() - 1].to_owned(),
+                ))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Pids of the processes of package. Empty if the package is not running
+pub fn pidof(package: &str) -> Result<Vec<String>, Error> {
+    let output = adb_command()?.args(["shell", "pidof", package]).output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .filter(|p| p.chars().all(|c| c.is_ascii_digit()))
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
 pub fn terminal_width() -> Option<usize> {
     match term_size::dimensions() {
         Some((width, _)) => Some(width),
@@ -65,3 +389,41 @@ pub fn config_init() {
         .merge(config::File::from(config_file))
         .ok();
 }
+
+#[test]
+fn parse_devices_output() {
+    let output = "List of devices attached\n\
+                  * daemon started successfully\n\
+                  A1             device usb:1-1 product:flame model:Pixel_4 device:flame transport_id:1\n\
+                  emulator-5554  device product:sdk model:Android_SDK_built_for_x86 transport_id:2\n\
+                  B2             offline transport_id:3\n\
+                  C3\tdevice\n";
+    assert_eq!(
+        parse_devices(output),
+        vec![
+            Device {
+                serial: "A1".into(),
+                model: Some("Pixel 4".into())
+            },
+            Device {
+                serial: "emulator-5554".into(),
+                model: Some("Android SDK built for x86".into())
+            },
+            Device {
+                serial: "C3".into(),
+                model: None
+            },
+        ]
+    );
+}
+
+#[test]
+fn parse_ps_output() {
+    let toybox = "USER PID PPID VSZ RSS WCHAN ADDR S NAME\n\
+                  root 1 0 10 2 0 0 S init\n\
+                  u0_a42 1234 567 10 2 0 0 S com.example.app\n";
+    let names = parse_ps(toybox);
+    assert_eq!(names.len(), 2);
+    assert_eq!(names["1234"], "com.example.app");
+    assert!(parse_ps("ps: bad -A").is_empty());
+}
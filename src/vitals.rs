@@ -0,0 +1,262 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Battery level, temperature and cpu frequencies of the device sampled with
+//! `--vitals`. Samples are injected as records or written to a csv file with
+//! `--vitals-csv` to correlate the log with the conditions of the device.
+
+use crate::{
+    marker,
+    utils::{device_cmd, interval},
+    RecordStream,
+};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::sync::mpsc::unbounded;
+use rogcat::record::{Level, Record, Timestamp};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// Tag of vitals records
+pub const TAG: &str = "RogcatVitals";
+
+/// Delay between samples if `--vitals` is passed without interval
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shell command printing the battery state and current cpu frequencies in kHz
+const COMMAND: &str =
+    "dumpsys battery; echo ---; cat /sys/devices/system/cpu/cpu*/cpufreq/scaling_cur_freq";
+
+/// Header of the csv file
+const CSV_HEADER: &str = "time,battery,temperature,cpu_mhz";
+
+/// True if record is a vitals sample
+pub fn is_vitals(record: &Record) -> bool {
+    record.tag == TAG
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Sample {
+    /// Battery level in percent
+    battery: Option<u32>,
+    /// Battery temperature in degree celsius
+    temperature: Option<f32>,
+    /// Current frequency of each cpu in MHz
+    cpu: Vec<u32>,
+}
+
+impl Sample {
+    /// Parse the output of COMMAND
+    fn parse(output: &str) -> Sample {
+        let mut sections = output.splitn(2, "---");
+        let battery = sections
+            .next()
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|l| {
+                let mut s = l.trim().splitn(2, ": ");
+                Some((s.next()?, s.next()?.trim()))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let value = |key: &str| battery.get(key).and_then(|v| v.parse::<u32>().ok());
+        let level = match (value("level"), value("scale")) {
+            (Some(level), Some(scale)) if scale > 0 => Some(level * 100 / scale),
+            (level, _) => level,
+        };
+        let cpu = sections
+            .next()
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|l| l.trim().parse::<u32>().ok())
+            .map(|khz| khz / 1000)
+            .collect();
+        Sample {
+            battery: level,
+            temperature: value("temperature").map(|t| t as f32 / 10.0),
+            cpu,
+        }
+    }
+
+    fn battery(&self) -> String {
+        self.battery.map(|b| b.to_string()).unwrap_or_default()
+    }
+
+    fn temperature(&self) -> String {
+        self.temperature
+            .map(|t| format!("{:.1}", t))
+            .unwrap_or_default()
+    }
+
+    fn cpu(&self) -> String {
+        self.cpu
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Dimmed one line summary e.g "battery 87% 28.5°C cpu 1804/2419 MHz"
+    fn message(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(battery) = self.battery {
+            parts.push(format!("battery {}%", battery));
+        }
+        if self.temperature.is_some() {
+            parts.push(format!("{}°C", self.temperature()));
+        }
+        if !self.cpu.is_empty() {
+            parts.push(format!("cpu {} MHz", self.cpu()));
+        }
+        parts.join(" ")
+    }
+
+    fn record(&self) -> Record {
+        let now = time::now();
+        let message = self.message();
+        let mut fields = BTreeMap::new();
+        fields.insert("battery".to_owned(), self.battery());
+        fields.insert("temperature".to_owned(), self.temperature());
+        fields.insert("cpu_mhz".to_owned(), self.cpu());
+        Record {
+            timestamp: Some(Timestamp::new(now)),
+            level: Level::Verbose,
+            tag: TAG.to_owned(),
+            raw: format!(
+                "{} V {}: {}",
+                time::strftime("%m-%d %H:%M:%S.%f", &now)
+                    .map(|mut t| {
+                        t.truncate(18);
+                        t
+                    })
+                    .unwrap_or_default(),
+                TAG,
+                message
+            ),
+            message,
+            fields,
+            ..Default::default()
+        }
+    }
+
+    fn csv(&self) -> String {
+        let time = time::strftime("%Y-%m-%d %H:%M:%S", &time::now()).unwrap_or_default();
+        format!(
+            "{},{},{},{}",
+            time,
+            self.battery(),
+            self.temperature(),
+            self.cpu().replace('/', " ")
+        )
+    }
+}
+
+/// Sample the device. None if the device is not reachable
+fn sample(adb: &[String]) -> Option<Sample> {
+    Command::new(&adb[0])
+        .args(&adb[1..])
+        .args(["shell", COMMAND])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| Sample::parse(&String::from_utf8_lossy(&output.stdout)))
+        .filter(|sample| *sample != Sample::default())
+}
+
+/// Inject samples into records or write them to the csv file if `--vitals`
+/// is passed
+pub fn from_args<'a>(args: &ArgMatches<'a>, records: RecordStream) -> Result<RecordStream, Error> {
+    if !args.is_present("vitals") {
+        return Ok(records);
+    }
+    let delay = match args.value_of("vitals") {
+        Some(i) => interval(i)?,
+        None => INTERVAL,
+    };
+    let adb = device_cmd(args)?;
+    let mut csv = match args.value_of("vitals_csv") {
+        Some(file) => {
+            let mut csv = File::create(file)
+                .map(BufWriter::new)
+                .map_err(|e| format_err!("Failed to create {}: {}", file, e))?;
+            writeln!(csv, "{}", CSV_HEADER)?;
+            csv.flush()?;
+            Some(csv)
+        }
+        None => None,
+    };
+
+    let (tx, rx) = unbounded();
+    thread::spawn(move || loop {
+        if let Some(sample) = sample(&adb) {
+            match csv {
+                Some(ref mut csv) => {
+                    if let Err(e) = writeln!(csv, "{}", sample.csv()).and_then(|_| csv.flush()) {
+                        eprintln!("Failed to write vitals: {}", e);
+                        break;
+                    }
+                }
+                None => {
+                    if tx.unbounded_send(sample.record()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        thread::sleep(delay);
+    });
+    Ok(marker::inject(records, rx))
+}
+
+#[test]
+fn parse_sample() {
+    let output = "Current Battery Service state:\n  \
+                  AC powered: false\n  \
+                  status: 3\n  \
+                  level: 87\n  \
+                  scale: 100\n  \
+                  temperature: 285\n\
+                  ---\n\
+                  1804800\n\
+                  2419200\n";
+    let sample = Sample::parse(output);
+    assert_eq!(
+        sample,
+        Sample {
+            battery: Some(87),
+            temperature: Some(28.5),
+            cpu: vec![1804, 2419],
+        }
+    );
+    assert_eq!(sample.message(), "battery 87% 28.5°C cpu 1804/2419 MHz");
+    assert!(sample.csv().ends_with(",87,28.5,1804 2419"));
+    assert!(is_vitals(&sample.record()));
+
+    // Devices without cpufreq
+    let sample = Sample::parse("  level: 50\n  scale: 200\n---\ncat: No such file\n");
+    assert_eq!(sample.message(), "battery 25%");
+}
@@ -0,0 +1,318 @@
+// Copyright © 2016 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::LogSink;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend};
+use rogcat::record::{Format, Record};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+const PAGE: &str = include_str!("web.html");
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Records queued per client before records are dropped
+const QUEUE: usize = 1024;
+/// Time a client gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Clients that don't take a frame within this time are disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest frame accepted from a client
+const MAX_PAYLOAD: u64 = 64 * 1024;
+
+/// Frames sent to a client
+enum Message {
+    Record(Arc<String>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+type Clients = Arc<Mutex<Vec<SyncSender<Message>>>>;
+
+/// Parse an address or a port that is served on all interfaces
+fn listen_addr(addr: &str) -> Result<SocketAddr, Error> {
+    if let Ok(port) = addr.parse::<u16>() {
+        return Ok(SocketAddr::from(([0, 0, 0, 0], port)));
+    }
+    addr.to_socket_addrs()
+        .map_err(|e| format_err!("Invalid address {}: {}", addr, e))?
+        .next()
+        .ok_or_else(|| format_err!("Invalid address {}", addr))
+}
+
+/// Value of Sec-WebSocket-Accept for a key
+fn accept_key(key: &str) -> String {
+    let mut sha1 = sha1::Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    base64::encode(&sha1.digest().bytes())
+}
+
+/// Unmasked server frame
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        n if n < 126 => frame.push(n as u8),
+        n if n <= 0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            frame.push(127);
+            frame.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Read a masked client frame and return its opcode and payload
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    let len = match header[1] & 0x7F {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        n => u64::from(n),
+    };
+    if len > MAX_PAYLOAD {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Frame too large",
+        ));
+    }
+    let mut mask = [0; 4];
+    if header[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+    Ok((header[0] & 0x0F, payload))
+}
+
+/// Answer pings and stop the client on close. Other frames are ignored
+fn receive<R: Read>(mut reader: R, tx: &SyncSender<Message>) {
+    loop {
+        match read_frame(&mut reader) {
+            Ok((0x9, payload)) => {
+                if tx.send(Message::Pong(payload)).is_err() {
+                    return;
+                }
+            }
+            Ok((0x8, _)) | Err(_) => {
+                tx.send(Message::Close).ok();
+                return;
+            }
+            Ok(_) => (),
+        }
+    }
+}
+
+/// Send the records as text messages until the client or the sink is gone
+fn stream(mut socket: TcpStream, messages: &Receiver<Message>) {
+    for message in messages {
+        let frame = match message {
+            Message::Record(json) => frame(0x1, json.as_bytes()),
+            Message::Pong(payload) => frame(0xA, &payload),
+            Message::Close => break,
+        };
+        if socket.write_all(&frame).is_err() {
+            // Unblock the receiver
+            socket.shutdown(Shutdown::Both).ok();
+            return;
+        }
+    }
+    socket.write_all(&frame(0x8, &[])).ok();
+    // Give the client a moment to answer the close
+    socket.set_read_timeout(Some(REQUEST_TIMEOUT)).ok();
+}
+
+/// Serve the page or upgrade to a WebSocket
+fn connection(socket: TcpStream, clients: &Clients) -> Result<(), Error> {
+    socket.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    socket.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut reader = BufReader::new(socket.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("/").to_owned();
+
+    let mut key = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let mut header = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (header.next(), header.next()) {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_owned());
+            }
+        }
+    }
+
+    let mut socket = socket;
+    match (path.as_str(), key) {
+        ("/ws", Some(key)) => {
+            write!(
+                socket,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&key)
+            )?;
+            // Client frames may take arbitrarily long
+            socket.set_read_timeout(None)?;
+            let (tx, rx) = sync_channel(QUEUE);
+            clients
+                .lock()
+                .expect("Failed to lock clients")
+                .push(tx.clone());
+            thread::spawn(move || receive(reader, &tx));
+            stream(socket, &rx);
+        }
+        ("/", _) | ("/index.html", _) => {
+            write!(
+                socket,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                PAGE.len(),
+                PAGE
+            )?;
+        }
+        _ => {
+            socket.write_all(
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Sink sending records to the connected browsers
+struct Broadcast {
+    clients: Clients,
+}
+
+impl Sink for Broadcast {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let mut clients = self.clients.lock().expect("Failed to lock clients");
+        if !clients.is_empty() {
+            let json = Arc::new(Format::Json.fmt_record(&record)?);
+            // Slow clients miss records and disconnected ones are removed
+            clients.retain(|tx| {
+                !matches!(
+                    tx.try_send(Message::Record(json.clone())),
+                    Err(TrySendError::Disconnected(_))
+                )
+            });
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.clients.lock().expect("Failed to lock clients").clear();
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Start the live view server if requested and return a sink for the records
+pub fn serve<'a>(args: &ArgMatches<'a>) -> Result<Option<LogSink>, Error> {
+    let addr = match args.value_of("http") {
+        Some(addr) => listen_addr(addr)?,
+        None => return Ok(None),
+    };
+    let listener =
+        TcpListener::bind(addr).map_err(|e| format_err!("Failed to listen on {}: {}", addr, e))?;
+    eprintln!("Serving live view on http://{}", listener.local_addr()?);
+
+    let clients = Clients::default();
+    let accept = clients.clone();
+    thread::spawn(move || {
+        for socket in listener.incoming().filter_map(Result::ok) {
+            let clients = accept.clone();
+            thread::spawn(move || {
+                if let Err(e) = connection(socket, &clients) {
+                    eprintln!("Live view connection failed: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok(Some(Box::new(Broadcast { clients })))
+}
+
+#[test]
+fn accept_key_rfc6455() {
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[test]
+fn frame_length() {
+    assert_eq!(frame(0x1, b"abc"), vec![0x81, 3, b'a', b'b', b'c']);
+    assert_eq!(frame(0x1, &[0; 200])[..4], [0x81, 126, 0, 200]);
+    assert_eq!(
+        frame(0x1, &[0; 70000])[..10],
+        [0x81, 127, 0, 0, 0, 0, 0, 1, 0x11, 0x70]
+    );
+}
+
+#[test]
+fn read_masked_frame() {
+    // RFC 6455 5.7: masked "Hello"
+    let data = [
+        0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+    ];
+    let (opcode, payload) = read_frame(&mut &data[..]).unwrap();
+    assert_eq!(opcode, 0x1);
+    assert_eq!(payload, b"Hello");
+
+    let ping = [0x89, 0x80, 1, 2, 3, 4];
+    assert_eq!(read_frame(&mut &ping[..]).unwrap(), (0x9, vec![]));
+
+    let large = [0x82, 0xFF, 0, 0, 0, 1, 0, 0, 0, 0];
+    assert!(read_frame(&mut &large[..]).is_err());
+}